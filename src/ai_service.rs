@@ -1,8 +1,20 @@
 use anyhow::{Context, Result};
-use reqwest::Client;
+use reqwest::{Certificate, Client};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::time::Duration;
-use tracing::error;
+use tracing::{error, warn};
+
+use crate::prompt_template::PromptTemplate;
+
+/// Persona used when a conversation does not define its own
+pub const DEFAULT_SYSTEM_PROMPT: &str =
+    "You are a helpful assistant replying over SMS. Keep answers short and plain-text.";
+
+const DEFAULT_API_URL: &str = "https://api.groq.com/openai/v1";
+
+/// Embedding model used unless `with_embedding_model` overrides it
+pub const DEFAULT_EMBEDDING_MODEL: &str = "nomic-embed-text-v1_5";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AIMessage {
@@ -10,10 +22,30 @@ pub struct AIMessage {
     pub content: String,
 }
 
+impl AIMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: "system".to_string(),
+            content: content.into(),
+        }
+    }
+}
+
+/// Per-call overrides for a completion; unset fields use the service
+/// defaults (configured model, temperature 0.7)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GenerationConfig {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+}
+
+/// Temperature for replies when nothing overrides it
+const DEFAULT_TEMPERATURE: f32 = 0.7;
+
 #[derive(Debug, Serialize)]
-struct GroqRequest {
-    model: String,
-    messages: Vec<AIMessage>,
+struct GroqRequest<'a> {
+    model: &'a str,
+    messages: &'a [AIMessage],
     temperature: f32,
     max_tokens: u32,
 }
@@ -28,59 +60,385 @@ struct Choice {
     message: AIMessage,
 }
 
+#[derive(Debug, Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+    index: usize,
+}
+
+impl EmbeddingResponse {
+    /// One vector per input, in input order (the API may reorder `data`)
+    fn into_vectors(mut self, expected: usize) -> Result<Vec<Vec<f32>>> {
+        if self.data.len() != expected {
+            anyhow::bail!(
+                "Embeddings API returned {} vectors for {} inputs",
+                self.data.len(),
+                expected
+            );
+        }
+        self.data.sort_by_key(|d| d.index);
+        Ok(self.data.into_iter().map(|d| d.embedding).collect())
+    }
+}
+
+/// -----------------------------
+/// Health Check Error
+/// -----------------------------
+/// Why `health_check` failed. Returned inside `anyhow::Error`; use
+/// `downcast_ref` to tell a bad key from an outage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AIHealthError {
+    /// The API key was rejected (401/403)
+    Unauthorized(u16),
+    /// The API answered with another non-success status
+    Status(u16),
+    /// The API could not be reached
+    Unreachable(String),
+}
+
+impl fmt::Display for AIHealthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AIHealthError::Unauthorized(status) => {
+                write!(f, "AI API rejected the API key (HTTP {status})")
+            }
+            AIHealthError::Status(status) => write!(f, "AI API returned HTTP {status}"),
+            AIHealthError::Unreachable(e) => write!(f, "AI API unreachable: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for AIHealthError {}
+
+/// -----------------------------
+/// Completion Error
+/// -----------------------------
+/// Returned inside `anyhow::Error` when a completion succeeded at the HTTP
+/// level but can't be used as a reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AIError {
+    /// The model answered with empty or whitespace-only content
+    EmptyResponse,
+    /// The API kept answering with this non-success status
+    Status(u16),
+}
+
+impl AIError {
+    /// Worth asking the next provider. A request the API rejected as
+    /// malformed or too large would fail the same way everywhere.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AIError::EmptyResponse => true,
+            AIError::Status(status) => !matches!(status, 400 | 413 | 422),
+        }
+    }
+}
+
+impl fmt::Display for AIError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AIError::EmptyResponse => write!(f, "AI returned an empty response"),
+            AIError::Status(status) => write!(f, "AI API returned {status}"),
+        }
+    }
+}
+
+impl std::error::Error for AIError {}
+
+/// Network errors and anything else outside `AIError` are retryable
+fn is_retryable(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<AIError>().is_none_or(AIError::is_retryable)
+}
+
+/// -----------------------------
+/// AI Provider
+/// -----------------------------
+/// An OpenAI-compatible endpoint and the model to use there, tried when
+/// the providers before it in the chain fail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIProvider {
+    pub api_url: String,
+    pub api_key: String,
+    pub model: String,
+}
+
 /// -----------------------------
 /// AI Service (Groq / OpenAI compatible)
 /// -----------------------------
 pub struct AIService {
     client: Client,
     model: String,
+    embedding_model: String,
     api_key: String,
+    api_url: String,
+    /// Tried in order when the primary fails (replies only)
+    fallbacks: Vec<AIProvider>,
+    /// Wraps the user message of replies
+    prompt_template: Option<PromptTemplate>,
 }
 
+/// Timeout for a single completion request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Timeout for the health check; readiness probes shouldn't hang
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl AIService {
     pub fn new(model: String, api_key: String) -> Self {
         let client = Client::builder()
-            .timeout(Duration::from_secs(30))
+            .timeout(REQUEST_TIMEOUT)
             .build()
             .expect("Failed to build HTTP client");
 
         Self {
             client,
             model,
+            embedding_model: DEFAULT_EMBEDDING_MODEL.to_string(),
             api_key,
+            api_url: DEFAULT_API_URL.to_string(),
+            fallbacks: Vec::new(),
+            prompt_template: None,
         }
     }
 
-    /// Generate AI response given the latest user message and conversation history
+    /// Trust an extra root CA for the AI API connection
+    pub fn with_root_certificate(mut self, cert: Certificate) -> Result<Self> {
+        self.client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .add_root_certificate(cert)
+            .build()
+            .context("Failed to build AI HTTP client")?;
+        Ok(self)
+    }
+
+    /// Point the client at a different OpenAI-compatible base URL
+    pub fn with_api_url(mut self, api_url: String) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Append a provider to the reply fallback chain
+    pub fn with_fallback_provider(mut self, mut provider: AIProvider) -> Self {
+        provider.api_url = provider.api_url.trim_end_matches('/').to_string();
+        self.fallbacks.push(provider);
+        self
+    }
+
+    /// Render reply prompts through `template` instead of sending the
+    /// user message as is
+    pub fn with_prompt_template(mut self, template: PromptTemplate) -> Self {
+        self.prompt_template = Some(template);
+        self
+    }
+
+    /// Model used by `embed`
+    pub fn with_embedding_model(mut self, model: String) -> Self {
+        self.embedding_model = model;
+        self
+    }
+
+    pub fn embedding_model(&self) -> &str {
+        &self.embedding_model
+    }
+
+    /// Cheap preflight: list models (no tokens spent) to confirm the API
+    /// is reachable and the key is accepted
+    pub async fn health_check(&self) -> Result<()> {
+        let response = self
+            .client
+            .get(format!("{}/models", self.api_url))
+            .bearer_auth(&self.api_key)
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| AIHealthError::Unreachable(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Err(AIHealthError::Unauthorized(status.as_u16()).into());
+        }
+        Err(AIHealthError::Status(status.as_u16()).into())
+    }
+
+    /// Generate AI response given the latest user message and conversation history.
+    /// Fails with `AIError::EmptyResponse` rather than returning a blank
+    /// reply, which SignalWire would reject.
+    ///
+    /// On a retryable failure the fallback providers are tried in order
+    /// (with their own model; a pinned model applies to the primary only).
+    /// The last error is returned once every provider has failed.
     pub async fn generate_response(
         &self,
         user_message: &str,
         history: &[AIMessage],
+        config: &GenerationConfig,
     ) -> Result<String> {
         // Defensive: limit history size (should already be done upstream)
-        let mut messages: Vec<AIMessage> = history
-            .iter()
-            .cloned()
-            .take(20)
-            .collect();
+        let history = &history[..history.len().min(20)];
+
+        let (mut messages, content): (Vec<AIMessage>, String) = match &self.prompt_template {
+            // The template carries the history itself; keep only system prompts
+            Some(template) if template.uses_history() => (
+                history.iter().filter(|m| m.role == "system").cloned().collect(),
+                template.render(user_message, history),
+            ),
+            Some(template) => (history.to_vec(), template.render(user_message, history)),
+            None => (history.to_vec(), user_message.to_string()),
+        };
 
         messages.push(AIMessage {
             role: "user".to_string(),
-            content: user_message.to_string(),
+            content,
         });
 
+        let model = config.model.as_deref().unwrap_or(&self.model);
+        let temperature = config.temperature.unwrap_or(DEFAULT_TEMPERATURE);
+
+        let providers = std::iter::once((self.api_url.as_str(), self.api_key.as_str(), model)).chain(
+            self.fallbacks
+                .iter()
+                .map(|p| (p.api_url.as_str(), p.api_key.as_str(), p.model.as_str())),
+        );
+
+        let mut last_error = None;
+        for (index, (api_url, api_key, model)) in providers.enumerate() {
+            let result = self
+                .complete_with(api_url, api_key, &messages, model, temperature, 500)
+                .await
+                .and_then(|reply| {
+                    if reply.trim().is_empty() {
+                        Err(AIError::EmptyResponse.into())
+                    } else {
+                        Ok(reply)
+                    }
+                });
+
+            match result {
+                Ok(reply) => {
+                    if index > 0 {
+                        warn!("AI reply served by fallback provider {index} ({api_url})");
+                    }
+                    return Ok(reply);
+                }
+                Err(e) if !is_retryable(&e) => return Err(e),
+                Err(e) => {
+                    warn!("AI provider {index} ({api_url}) failed: {e}");
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        let e = last_error.expect("provider chain always has the primary");
+        error!(
+            alert = "ai_providers_exhausted",
+            "All {} AI providers failed, last error: {e}",
+            self.fallbacks.len() + 1
+        );
+        Err(e)
+    }
+
+    /// Cheap one-shot completion producing a short conversation title
+    pub async fn generate_title(&self, first_message: &str) -> Result<String> {
+        let messages = vec![
+            AIMessage::system(
+                "Write a title of at most six words for a conversation that starts \
+                 with the user's message. Reply with the title only.",
+            ),
+            AIMessage {
+                role: "user".to_string(),
+                content: first_message.to_string(),
+            },
+        ];
+
+        let title = self
+            .complete_with(&self.api_url, &self.api_key, &messages, &self.model, 0.2, 16)
+            .await?;
+        let title: String = title
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'')
+            .chars()
+            .take(80)
+            .collect();
+
+        if title.is_empty() {
+            anyhow::bail!("AI returned an empty title");
+        }
+
+        Ok(title)
+    }
+
+    /// Embed each text with the configured embedding model; one vector per
+    /// input, in the same order. Not retried: callers batch and can retry.
+    pub async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let request = EmbeddingRequest {
+            model: &self.embedding_model,
+            input: texts,
+        };
+
+        let response = self
+            .client
+            .post(format!("{}/embeddings", self.api_url))
+            .bearer_auth(&self.api_key)
+            .header("User-Agent", "conversation-store/1.0")
+            .json(&request)
+            .send()
+            .await
+            .context("Embeddings request failed")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            error!("Embeddings API error {}: {}", status, body);
+            anyhow::bail!("Embeddings API returned {}", status);
+        }
+
+        let parsed: EmbeddingResponse = response
+            .json()
+            .await
+            .context("Failed to parse embeddings response JSON")?;
+
+        parsed.into_vectors(texts.len())
+    }
+
+    async fn complete_with(
+        &self,
+        api_url: &str,
+        api_key: &str,
+        messages: &[AIMessage],
+        model: &str,
+        temperature: f32,
+        max_tokens: u32,
+    ) -> Result<String> {
         let request = GroqRequest {
-            model: self.model.clone(),
+            model,
             messages,
-            temperature: 0.7,
-            max_tokens: 500,
+            temperature,
+            max_tokens,
         };
 
         // Simple retry loop for transient failures
         for attempt in 1..=2 {
             let response = self.client
-                .post("https://api.groq.com/openai/v1/chat/completions")
-                .header("Authorization", format!("Bearer {}", self.api_key))
+                .post(format!("{}/chat/completions", api_url))
+                .header("Authorization", format!("Bearer {}", api_key))
                 .header("Content-Type", "application/json")
                 .header("User-Agent", "conversation-store/1.0")
                 .json(&request)
@@ -109,7 +467,7 @@ impl AIService {
                     error!("Groq API error {}: {}", status, body);
 
                     if attempt == 2 {
-                        anyhow::bail!("Groq API failed after retries: {}", status);
+                        return Err(AIError::Status(status.as_u16()).into());
                     }
                 }
 
@@ -126,3 +484,116 @@ impl AIService {
         unreachable!("Retry loop should always return");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::serve;
+    use axum::{http::StatusCode, routing::get, Router};
+
+    #[tokio::test]
+    async fn test_health_check_tells_auth_from_network_errors() {
+        let router = Router::new().route("/models", get(|| async { StatusCode::UNAUTHORIZED }));
+        let ai = AIService::new("m".into(), "bad-key".into()).with_api_url(serve(router).await);
+
+        let err = ai.health_check().await.unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&AIHealthError::Unauthorized(401)));
+
+        // Nothing listens on port 1
+        let ai = AIService::new("m".into(), "key".into()).with_api_url("http://127.0.0.1:1".into());
+        let err = ai.health_check().await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AIHealthError::Unreachable(_))), "{err}");
+    }
+
+    #[test]
+    fn test_embeddings_response_parses_in_input_order() {
+        let json = r#"{
+            "object": "list",
+            "data": [
+                {"object": "embedding", "index": 1, "embedding": [0.5, -1.0]},
+                {"object": "embedding", "index": 0, "embedding": [0.25, 2]}
+            ],
+            "model": "nomic-embed-text-v1_5",
+            "usage": {"prompt_tokens": 4, "total_tokens": 4}
+        }"#;
+
+        let parsed: EmbeddingResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(parsed.into_vectors(2).unwrap(), vec![vec![0.25, 2.0], vec![0.5, -1.0]]);
+
+        let parsed: EmbeddingResponse = serde_json::from_str(json).unwrap();
+        assert!(parsed.into_vectors(3).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_empty_completion_is_an_error() {
+        let (url, _) = crate::test_support::fake_groq("  \n ").await;
+        let ai = AIService::new("m".into(), "key".into()).with_api_url(url);
+
+        let err = ai.generate_response("hi", &[], &GenerationConfig::default()).await.unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&AIError::EmptyResponse));
+    }
+
+    #[tokio::test]
+    async fn test_failing_primary_falls_back_to_secondary() {
+        let primary = crate::test_support::fake_ai_failing().await;
+        let (url, captured) = crate::test_support::fake_groq("From the backup").await;
+        let ai = primary.with_fallback_provider(AIProvider {
+            api_url: url,
+            api_key: "backup-key".into(),
+            model: "backup-model".into(),
+        });
+
+        let config = GenerationConfig {
+            model: Some("pinned".into()),
+            ..Default::default()
+        };
+        let reply = ai.generate_response("hi", &[], &config).await.unwrap();
+        assert_eq!(reply, "From the backup");
+        assert_eq!(captured.lock().unwrap()[0]["model"], "backup-model");
+
+        // Without a fallback the primary's error surfaces
+        let err = crate::test_support::fake_ai_failing()
+            .await
+            .generate_response("hi", &[], &config)
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&AIError::Status(500)));
+    }
+
+    #[tokio::test]
+    async fn test_prompt_template_wraps_the_user_message() {
+        let (url, captured) = crate::test_support::fake_groq("Happy to help").await;
+        let template = PromptTemplate::parse("Customer said: {{message}}. Respond politely.").unwrap();
+        let ai = AIService::new("m".into(), "key".into())
+            .with_api_url(url)
+            .with_prompt_template(template);
+
+        let history = [AIMessage::system("Be brief")];
+        let reply = ai
+            .generate_response("where is my order", &history, &GenerationConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(reply, "Happy to help");
+
+        let messages = &captured.lock().unwrap()[0]["messages"];
+        assert_eq!(messages[0]["content"], "Be brief");
+        assert_eq!(messages[1]["role"], "user");
+        assert_eq!(messages[1]["content"], "Customer said: where is my order. Respond politely.");
+    }
+
+    #[test]
+    fn test_rejected_requests_are_not_retried_elsewhere() {
+        assert!(AIError::Status(503).is_retryable());
+        assert!(AIError::Status(429).is_retryable());
+        assert!(AIError::EmptyResponse.is_retryable());
+        assert!(!AIError::Status(400).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_health_check_ok() {
+        let router = Router::new().route("/models", get(|| async { "{\"data\": []}" }));
+        let ai = AIService::new("m".into(), "key".into()).with_api_url(serve(router).await);
+
+        ai.health_check().await.unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::storage::NotFound;
+
+/// -----------------------------
+/// API Error
+/// -----------------------------
+/// Failure of an `/api` handler, sent as
+/// `{ "error": { "code": "...", "message": "..." } }` with its status.
+/// Internal errors are logged in full but answered with a generic
+/// message, so nothing about the store or upstreams leaks to clients.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+/// Response body of every failed `/api` request
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorDetail,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorDetail {
+    /// Stable, machine-readable (`not_found`, `invalid_request`, ...)
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            code: "not_found",
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            code: "invalid_request",
+            message: message.into(),
+        }
+    }
+
+    /// A daily usage cap or rate limit stops the request
+    pub fn too_many_requests(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            code: "rate_limited",
+            message: message.into(),
+        }
+    }
+
+    /// An upstream (AI, carrier) failed; `err` is logged, not returned
+    pub fn upstream(context: &str, err: anyhow::Error) -> Self {
+        error!("{context}: {err}");
+        Self {
+            status: StatusCode::BAD_GATEWAY,
+            code: "upstream_failed",
+            message: "An upstream service failed".into(),
+        }
+    }
+
+    /// `err` is logged with `context`; the client only learns it failed.
+    /// A store `NotFound` is still answered with 404.
+    pub fn internal(context: &str, err: anyhow::Error) -> Self {
+        if let Some(not_found) = err.downcast_ref::<NotFound>() {
+            return Self::not_found(not_found.to_string());
+        }
+
+        error!("{context}: {err}");
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            code: "internal",
+            message: "Internal server error".into(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::bad_request(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::bad_request(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::bad_request(rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorResponse {
+            error: ErrorDetail {
+                code: self.code.to_string(),
+                message: self.message,
+            },
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use serde_json::{json, Value};
+
+    async fn body_json(error: ApiError) -> (StatusCode, Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_not_found_and_bad_request_shapes() {
+        let (status, body) = body_json(ApiError::not_found("Conversation c1 not found")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body,
+            json!({ "error": { "code": "not_found", "message": "Conversation c1 not found" } })
+        );
+
+        let (status, body) = body_json(ApiError::bad_request("Invalid cursor")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, json!({ "error": { "code": "invalid_request", "message": "Invalid cursor" } }));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_json_body_is_a_400() {
+        async fn echo(body: Result<Json<Value>, JsonRejection>) -> Result<Json<Value>, ApiError> {
+            Ok(body?)
+        }
+        let router = axum::Router::new().route("/echo", axum::routing::post(echo));
+        let url = crate::test_support::serve(router).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{url}/echo"))
+            .header("content-type", "application/json")
+            .body("{not json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "invalid_request");
+        assert!(body["error"]["message"].as_str().unwrap().contains("JSON"));
+    }
+
+    #[tokio::test]
+    async fn test_internal_errors_are_not_leaked() {
+        let err = anyhow::anyhow!("Turso returned 500: table messages is locked");
+        let (status, body) = body_json(ApiError::internal("Failed to list", err)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["error"]["message"], "Internal server error");
+
+        // Unless the store says what was missing
+        let err = anyhow::Error::new(NotFound::conversation("c1")).context("Failed to store message");
+        let (status, body) = body_json(ApiError::internal("Failed to store", err)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["message"], "Conversation c1 not found");
+    }
+}
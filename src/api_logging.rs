@@ -0,0 +1,340 @@
+use anyhow::Result;
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::Value;
+use std::str::FromStr;
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// Longest body text that ends up in a log line
+const LOGGED_BODY_LEN: usize = 2048;
+/// Largest body buffered for logging; bigger ones (and streamed ones,
+/// like the zip export) pass through untouched
+const MAX_BUFFERED_BODY: u64 = 64 * 1024;
+/// Separators allowed inside a phone number (`+1 (555) 123-4567`)
+const PHONE_FORMATTING: [char; 5] = [' ', '-', '.', '(', ')'];
+/// JSON keys whose values are message text
+const CONTENT_KEYS: [&str; 3] = ["content", "body", "Body"];
+
+/// -----------------------------
+/// Verbosity
+/// -----------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogVerbosity {
+    Off,
+    /// Method, path, status and duration
+    #[default]
+    Basic,
+    /// Basic plus request/response bodies
+    Bodies,
+}
+
+impl FromStr for LogVerbosity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(LogVerbosity::Off),
+            "basic" => Ok(LogVerbosity::Basic),
+            "bodies" => Ok(LogVerbosity::Bodies),
+            other => anyhow::bail!("Unknown API log verbosity: {other}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ApiLogConfig {
+    pub verbosity: LogVerbosity,
+    /// Hide message text and phone numbers in logged bodies
+    pub redact: bool,
+}
+
+impl Default for ApiLogConfig {
+    fn default() -> Self {
+        Self {
+            verbosity: LogVerbosity::Basic,
+            redact: true,
+        }
+    }
+}
+
+/// -----------------------------
+/// Middleware
+/// -----------------------------
+/// Logs `/api/*` requests. Unlike `TraceLayer`, bodies are logged with
+/// message text and phone numbers redacted. Only text and JSON bodies
+/// with a known length up to `MAX_BUFFERED_BODY` are read; anything else
+/// is streamed through and logged by its content type.
+///
+/// Use with `axum::middleware::from_fn_with_state(config, log_api_requests)`.
+pub async fn log_api_requests(
+    State(config): State<ApiLogConfig>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if config.verbosity == LogVerbosity::Off || !request.uri().path().starts_with("/api/") {
+        return next.run(request).await;
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let started = Instant::now();
+
+    if config.verbosity == LogVerbosity::Basic {
+        let response = next.run(request).await;
+        info!(
+            %method,
+            %path,
+            status = response.status().as_u16(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "API request"
+        );
+        return response;
+    }
+
+    let (parts, body) = request.into_parts();
+    let (request_body, request_text) = buffer(&parts.headers, body, config.redact).await;
+
+    let response = next.run(Request::from_parts(parts, request_body)).await;
+
+    let (parts, body) = response.into_parts();
+    let (response_body, response_text) = buffer(&parts.headers, body, config.redact).await;
+
+    info!(
+        %method,
+        %path,
+        status = parts.status.as_u16(),
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        request_body = %request_text,
+        response_body = %response_text,
+        "API request"
+    );
+
+    Response::from_parts(parts, response_body)
+}
+
+/// The body to pass on and its loggable text. Bodies that aren't text or
+/// JSON, have no length or are too large are passed on unread.
+async fn buffer(headers: &HeaderMap, body: Body, redact: bool) -> (Body, String) {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    match length {
+        Some(0) => return (body, String::new()),
+        Some(length) if length <= MAX_BUFFERED_BODY && is_text(&content_type) => {}
+        Some(length) => return (body, format!("[{content_type}, {length} bytes]")),
+        None => return (body, format!("[{content_type}, streamed]")),
+    }
+
+    match to_bytes(body, MAX_BUFFERED_BODY as usize).await {
+        Ok(bytes) => {
+            let text = loggable(&bytes, redact);
+            (Body::from(bytes), text)
+        }
+        Err(e) => {
+            // Content-Length lied; the body is gone either way
+            warn!("Failed to buffer body for logging: {e}");
+            (Body::from(Bytes::new()), String::new())
+        }
+    }
+}
+
+fn is_text(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime == "application/json"
+        || mime.ends_with("+json")
+        || mime == "application/x-www-form-urlencoded"
+}
+
+/// Body as a (possibly redacted and truncated) string
+fn loggable(body: &[u8], redact: bool) -> String {
+    let text = match serde_json::from_slice::<Value>(body) {
+        Ok(mut json) if redact => {
+            redact_json(&mut json);
+            json.to_string()
+        }
+        _ if redact => redact_phone_numbers(&String::from_utf8_lossy(body)),
+        _ => String::from_utf8_lossy(body).into_owned(),
+    };
+
+    match text.char_indices().nth(LOGGED_BODY_LEN) {
+        Some((i, _)) => format!("{}…", &text[..i]),
+        None => text,
+    }
+}
+
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if CONTENT_KEYS.contains(&key.as_str()) && value.is_string() {
+                    *value = Value::String("[redacted]".into());
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        Value::String(s) => *s = redact_phone_numbers(s),
+        _ => {}
+    }
+}
+
+/// Replace phone numbers with `[phone]`: a `+` then 7-15 digits, or
+/// 10-15 digits without one (`15551234567`, `(555) 123-4567`), possibly
+/// formatted. Digits inside a longer word or id are left alone.
+fn redact_phone_numbers(text: &str) -> String {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (start, c) = chars[i];
+        let after_word = i > 0 && chars[i - 1].1.is_alphanumeric();
+        if after_word || !(c == '+' || c == '(' || c.is_ascii_digit()) {
+            i += 1;
+            continue;
+        }
+
+        // Longest run of digits and formatting, ending on a digit
+        let plus = c == '+';
+        let mut j = i + usize::from(plus);
+        let (mut digits, mut counted, mut end) = (0, 0, None);
+        while let Some(&(_, c)) = chars.get(j) {
+            if c.is_ascii_digit() {
+                digits += 1;
+                counted = digits;
+                end = Some(j + 1);
+            } else if !PHONE_FORMATTING.contains(&c) {
+                break;
+            }
+            j += 1;
+        }
+
+        let Some(end) = end else {
+            i += 1;
+            continue;
+        };
+        let before_word = chars.get(end).is_some_and(|(_, c)| c.is_alphanumeric());
+        let is_phone = match plus {
+            true => (7..=15).contains(&counted),
+            false => (10..=15).contains(&counted),
+        };
+
+        if is_phone && !before_word {
+            out.push_str(&text[copied..start]);
+            out.push_str("[phone]");
+            copied = chars.get(end).map_or(text.len(), |(pos, _)| *pos);
+        }
+        i = end;
+    }
+
+    out.push_str(&text[copied..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{capture_logs, serve};
+    use axum::{
+        middleware,
+        routing::{get, post},
+        Json, Router,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_redact_phone_numbers() {
+        assert_eq!(
+            redact_phone_numbers("from +15551234567 to +44 and 1+1"),
+            "from [phone] to +44 and 1+1"
+        );
+        assert_eq!(
+            redact_phone_numbers("call 15551234567, (555) 123-4567 or +1 555.123.4567!"),
+            "call [phone], [phone] or [phone]!"
+        );
+        // Short numbers and digits inside ids stay
+        assert_eq!(
+            redact_phone_numbers("code 12345 for conv-a1b2 msg_15551234567x"),
+            "code 12345 for conv-a1b2 msg_15551234567x"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_binary_and_streamed_bodies_are_not_buffered() {
+        let config = ApiLogConfig {
+            verbosity: LogVerbosity::Bodies,
+            redact: true,
+        };
+        let router = Router::new()
+            .route(
+                "/api/export",
+                get(|| async {
+                    let chunks = futures_util::stream::iter([Ok::<_, std::io::Error>(Bytes::from_static(b"PK\x03\x04"))]);
+                    ([(header::CONTENT_TYPE, "application/zip")], Body::from_stream(chunks))
+                }),
+            )
+            .layer(middleware::from_fn_with_state(config, log_api_requests));
+        let url = serve(router).await;
+        let (_guard, logs) = capture_logs();
+
+        let response = reqwest::get(format!("{url}/api/export")).await.unwrap();
+        assert_eq!(response.bytes().await.unwrap().as_ref(), b"PK\x03\x04");
+
+        let logs = logs();
+        assert!(logs.contains("[application/zip, streamed]"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn test_logged_request_hides_phone_numbers() {
+        let config = ApiLogConfig {
+            verbosity: LogVerbosity::Bodies,
+            redact: true,
+        };
+        let router = Router::new()
+            .route(
+                "/api/conversations",
+                post(|Json(body): Json<Value>| async move {
+                    Json(json!({ "id": "c1", "title": body["title"] }))
+                }),
+            )
+            .layer(middleware::from_fn_with_state(config, log_api_requests));
+        let url = serve(router).await;
+        let (_guard, logs) = capture_logs();
+
+        let response: Value = reqwest::Client::new()
+            .post(format!("{url}/api/conversations"))
+            .json(&json!({ "title": "SMS: +15551234567", "content": "my secret" }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        // Redaction only affects the log, not the response
+        assert_eq!(response["title"], "SMS: +15551234567");
+
+        let logs = logs();
+        assert!(logs.contains("path=/api/conversations"), "{logs}");
+        assert!(logs.contains("status=200"), "{logs}");
+        assert!(logs.contains("[phone]"), "{logs}");
+        assert!(!logs.contains("15551234567"), "{logs}");
+        assert!(!logs.contains("my secret"), "{logs}");
+    }
+}
@@ -1,24 +1,199 @@
 use anyhow::{Context, Result};
 use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::ai_service::{AIProvider, DEFAULT_EMBEDDING_MODEL, DEFAULT_SYSTEM_PROMPT};
+use crate::api_logging::LogVerbosity;
+use crate::batcher::OverflowPolicy;
+use crate::consumers::{
+    ConsumerConfig, StartStrategy, TopicTarget, DEFAULT_MAX_CONSECUTIVE_ERRORS, DEFAULT_MAX_IN_FLIGHT_AI,
+    DEFAULT_POLL_PER_PARTITION,
+};
+use crate::signalwire::{FromNumberStrategy, SignalWireClient};
+use crate::store::{ContentOverflowPolicy, DEFAULT_MAX_CONTENT_BYTES};
+use crate::branding::ReplyBranding;
+use crate::markdown::MarkdownPolicy;
+use crate::prompt_template::PromptTemplate;
+use crate::usage_caps::UsageCaps;
+
+/// Comma-separated env var; `None` when unset or empty
+fn env_list(key: &str) -> Option<Vec<String>> {
+    env::var(key)
+        .ok()
+        .map(|v| {
+            v.split(',')
+                .map(|n| n.trim().to_string())
+                .filter(|n| !n.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|items| !items.is_empty())
+}
+
+/// Parse an optional env var, falling back to `default` when unset or invalid
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
 
 #[derive(Debug, Clone)]
 pub struct AppConfig {
     // --- Server ---
     pub port: String,
+    /// `/api/*` request logging: off | basic | bodies
+    pub api_log_verbosity: LogVerbosity,
+    /// Redact message text and phone numbers in logged bodies
+    pub api_log_redact: bool,
+    /// Browser origins allowed to call `/api/*` (`*` for any); no CORS when unset
+    pub allowed_origins: Option<Vec<String>>,
+    /// Requests per minute to `/api/audit/outbound` (0 = no limit)
+    pub audit_rate_limit_per_min: u32,
+
+    /// Extra root CA (PEM) trusted by the Turso and AI clients
+    pub ca_cert_path: Option<String>,
 
     // --- Turso ---
     pub turso_db_url: String,
     pub turso_auth_token: String,
+    /// Read replica for the HTTP API's listings and stats; unset means the primary
+    pub turso_read_url: Option<String>,
+    /// Turso requests slower than this are logged as warnings
+    pub turso_slow_query_ms: u64,
+    /// Longest message content stored, in bytes
+    pub turso_max_content_bytes: usize,
+    pub turso_content_overflow: ContentOverflowPolicy,
 
     // --- AI ---
     pub groq_model: String,
     pub groq_api_key: String,
+    /// Provider tried when Groq fails (set by AI_FALLBACK_API_URL + AI_FALLBACK_API_KEY)
+    pub ai_fallback: Option<AIProvider>,
+    /// Model for `AIService::embed`
+    pub embedding_model: String,
+    pub ai_system_prompt: String,
+    /// Wraps user messages before they reach the AI (`{{message}}`, `{{history}}`)
+    pub ai_prompt_template: Option<PromptTemplate>,
+    /// Stored with assistant replies unless the conversation sets its own
+    pub assistant_sender_label: Option<String>,
+    /// Cut each history message sent to the AI to this many characters (no limit when unset)
+    pub ai_history_max_chars: Option<usize>,
+    /// Generate conversation titles from the first message (extra AI call)
+    pub auto_title_enabled: bool,
+    /// Cap on concurrent AI completions (provider rate limit)
+    pub ai_max_in_flight: usize,
+    /// Per-number daily limits (unlimited when unset)
+    pub daily_ai_call_cap: Option<u32>,
+    pub daily_sms_cap: Option<u32>,
+    /// Sent once per number and day when a cap is hit
+    pub daily_cap_notice: Option<String>,
+    /// Sent before every assistant reply, e.g. `[Acme]`
+    pub reply_prefix: Option<String>,
+    /// Sent on its own line after every assistant reply
+    pub reply_suffix: Option<String>,
+    /// Store replies with the prefix/suffix instead of as generated
+    pub reply_store_branded: bool,
+    /// Markdown in AI replies: keep, strip when sending, or strip before storing
+    pub reply_markdown: MarkdownPolicy,
 
     // --- SignalWire ---
     pub signalwire_project_id: String,
     pub signalwire_auth_token: String,
     pub signalwire_space_url: String,
     pub signalwire_from_number: String,
+    pub signalwire_breaker_threshold: u32,
+    pub signalwire_breaker_cooldown_secs: u64,
+    /// Non-production safety net: only text these numbers
+    pub signalwire_allowed_recipients: Option<Vec<String>>,
+    /// Sender pool; empty means only `signalwire_from_number`
+    pub signalwire_from_numbers: Vec<String>,
+    pub signalwire_from_strategy: FromNumberStrategy,
+    /// Public URL of this server (e.g. `https://sms.example.com`); every
+    /// send asks for delivery updates at `{url}/sms/status`
+    pub public_base_url: Option<String>,
+    /// Reject carrier webhooks whose signature doesn't match the auth token
+    pub webhook_signature_validation: bool,
+    /// Hold each reply until the previous one is confirmed delivered
+    pub sequential_delivery_enabled: bool,
+    pub sequential_delivery_timeout_secs: u64,
+
+    // --- Inbound ---
+    /// Inbound SMS containing any of these (case-insensitive) are dropped
+    pub inbound_blocked_patterns: Vec<String>,
+
+    /// Keep each raw inbound webhook body (for debugging carrier quirks)
+    pub raw_webhook_audit: bool,
+    pub raw_webhook_retention_days: u64,
+    /// Acknowledge but drop webhooks whose MessageSid was received this recently (0 = off)
+    pub webhook_replay_window_secs: u64,
+    /// Most SIDs remembered for replay protection
+    pub webhook_replay_cache_size: usize,
+    /// Carrier webhooks handled at once; more are answered 503 (0 = no cap)
+    pub webhook_max_in_flight: usize,
+
+    /// Trim/collapse whitespace in SMS bodies before storing and sending
+    pub normalize_bodies: bool,
+    /// Active conversations one number may start; past it, new inbound SMS
+    /// join the number's latest conversation (unset/0 = no cap)
+    pub max_conversations_per_number: Option<usize>,
+
+    // --- Batcher ---
+    pub batch_max_size: usize,
+    pub batch_flush_ms: u64,
+    pub batch_max_buffer: usize,
+    pub batch_overflow_policy: OverflowPolicy,
+    /// Grow batch size / flush interval under bursts, up to these limits
+    pub batch_adaptive: bool,
+    pub batch_adaptive_max_size: usize,
+    pub batch_adaptive_max_flush_ms: u64,
+
+    // --- Retention ---
+    /// Purge conversations idle for this many days (disabled when unset)
+    pub purge_max_age_days: Option<u64>,
+    pub purge_interval_secs: u64,
+    /// Run `ConversationStore::maintenance` this often (disabled when unset)
+    pub maintenance_interval_secs: Option<u64>,
+    /// Also VACUUM during maintenance (expensive, blocks writes)
+    pub maintenance_vacuum: bool,
+    /// Poll for due scheduled SMS this often (disabled when 0)
+    pub scheduled_sms_poll_secs: u64,
+
+    // --- Readiness ---
+    /// `/health/ready` also verifies the Groq API key
+    pub ready_check_ai: bool,
+
+    // --- Greeting ---
+    pub greeting_enabled: bool,
+    /// Sent once, the first time a number texts in
+    pub greeting_message: String,
+
+    // --- Consumers ---
+    /// Where consumers start reading (replay after a bug)
+    pub consumer_start_strategy: StartStrategy,
+    /// `<stream>/<topic>` targets both consumers read; invalid entries are skipped
+    pub consumer_topics: Vec<TopicTarget>,
+    /// Ceiling for the idle backoff between empty polls
+    pub consumer_idle_backoff_max_ms: u64,
+    /// Commit offsets on poll (at-most-once) instead of after processing
+    pub consumer_auto_commit: bool,
+    /// Wait this long for a message before checking on Iggy and re-polling
+    pub consumer_poll_timeout_secs: u64,
+    /// Messages per poll for each partition of a topic
+    pub consumer_poll_per_partition: u32,
+    /// Fixed messages per poll instead of scaling with partitions
+    pub consumer_poll_batch: Option<u32>,
+    /// Polling errors in a row before a consumer exits (0 = never)
+    pub consumer_max_consecutive_errors: u32,
+    /// Log consumer events (message stored, AI reply, SMS sent)
+    pub event_logging: bool,
+    /// Run the AI consumer; off = store-only mode (inbound SMS are
+    /// stored, nothing is generated or sent)
+    pub ai_consumer_enabled: bool,
+
+    // --- Producer ---
+    /// Stop the demo producer after this many messages (runs forever when unset)
+    pub producer_max_messages: Option<u64>,
 }
 
 impl AppConfig {
@@ -28,16 +203,68 @@ impl AppConfig {
 
         Ok(Self {
             port: env::var("PORT").unwrap_or_else(|_| "3001".into()),
+            api_log_verbosity: env_or("API_LOG_VERBOSITY", LogVerbosity::Basic),
+            api_log_redact: env_or("API_LOG_REDACT", true),
+            allowed_origins: env_list("ALLOWED_ORIGINS"),
+            audit_rate_limit_per_min: env_or("AUDIT_RATE_LIMIT_PER_MIN", 60),
+
+            ca_cert_path: env::var("CA_CERT_PATH").ok().filter(|p| !p.is_empty()),
 
             turso_db_url: env::var("TURSO_DATABASE_URL")
                 .context("TURSO_DATABASE_URL missing")?,
             turso_auth_token: env::var("TURSO_AUTH_TOKEN")
                 .context("TURSO_AUTH_TOKEN missing")?,
+            turso_read_url: env::var("TURSO_READ_URL").ok().filter(|u| !u.is_empty()),
+            turso_slow_query_ms: env_or("TURSO_SLOW_QUERY_MS", 500),
+            turso_max_content_bytes: env_or("TURSO_MAX_CONTENT_BYTES", DEFAULT_MAX_CONTENT_BYTES),
+            turso_content_overflow: env_or("TURSO_CONTENT_OVERFLOW", ContentOverflowPolicy::Truncate),
 
             groq_model: env::var("GROQ_MODEL")
                 .unwrap_or_else(|_| "llama-3.3-70b-versatile".into()),
             groq_api_key: env::var("GROQ_API_KEY")
                 .context("GROQ_API_KEY missing")?,
+            ai_fallback: match (
+                env::var("AI_FALLBACK_API_URL").ok().filter(|u| !u.is_empty()),
+                env::var("AI_FALLBACK_API_KEY").ok().filter(|k| !k.is_empty()),
+            ) {
+                (Some(api_url), Some(api_key)) => Some(AIProvider {
+                    api_url,
+                    api_key,
+                    model: env::var("AI_FALLBACK_MODEL")
+                        .unwrap_or_else(|_| "llama-3.1-8b-instant".into()),
+                }),
+                _ => None,
+            },
+            embedding_model: env::var("EMBEDDING_MODEL")
+                .unwrap_or_else(|_| DEFAULT_EMBEDDING_MODEL.into()),
+            ai_system_prompt: env::var("AI_SYSTEM_PROMPT")
+                .unwrap_or_else(|_| DEFAULT_SYSTEM_PROMPT.into()),
+            ai_prompt_template: env::var("AI_PROMPT_TEMPLATE")
+                .ok()
+                .filter(|t| !t.is_empty())
+                .map(|t| PromptTemplate::parse(&t))
+                .transpose()
+                .context("Invalid AI_PROMPT_TEMPLATE")?,
+            assistant_sender_label: env::var("ASSISTANT_SENDER_LABEL").ok().filter(|l| !l.is_empty()),
+            ai_history_max_chars: env::var("AI_HISTORY_MAX_CHARS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|chars| *chars > 0),
+            auto_title_enabled: env_or("AUTO_TITLE_ENABLED", false),
+            ai_max_in_flight: env_or("AI_MAX_IN_FLIGHT", DEFAULT_MAX_IN_FLIGHT_AI),
+            daily_ai_call_cap: env::var("DAILY_AI_CALL_CAP")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|cap| *cap > 0),
+            daily_sms_cap: env::var("DAILY_SMS_CAP")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|cap| *cap > 0),
+            daily_cap_notice: env::var("DAILY_CAP_NOTICE").ok().filter(|n| !n.is_empty()),
+            reply_prefix: env::var("REPLY_PREFIX").ok().filter(|p| !p.is_empty()),
+            reply_suffix: env::var("REPLY_SUFFIX").ok().filter(|s| !s.is_empty()),
+            reply_store_branded: env_or("REPLY_STORE_BRANDED", false),
+            reply_markdown: env_or("REPLY_MARKDOWN", MarkdownPolicy::Send),
 
             signalwire_project_id: env::var("SIGNALWIRE_PROJECT_ID")
                 .context("SIGNALWIRE_PROJECT_ID missing")?,
@@ -47,6 +274,141 @@ impl AppConfig {
                 .context("SIGNALWIRE_SPACE_URL missing")?,
             signalwire_from_number: env::var("SIGNALWIRE_FROM_NUMBER")
                 .context("SIGNALWIRE_FROM_NUMBER missing")?,
+            signalwire_breaker_threshold: env_or("SIGNALWIRE_BREAKER_THRESHOLD", 5),
+            signalwire_breaker_cooldown_secs: env_or("SIGNALWIRE_BREAKER_COOLDOWN_SECS", 30),
+            signalwire_allowed_recipients: env_list("SIGNALWIRE_ALLOWED_RECIPIENTS"),
+            signalwire_from_numbers: env_list("SIGNALWIRE_FROM_NUMBERS").unwrap_or_default(),
+            signalwire_from_strategy: env_or("SIGNALWIRE_FROM_STRATEGY", FromNumberStrategy::Sticky),
+            public_base_url: env::var("PUBLIC_BASE_URL")
+                .ok()
+                .map(|url| url.trim_end_matches('/').to_string())
+                .filter(|url| !url.is_empty()),
+            webhook_signature_validation: env_or("WEBHOOK_SIGNATURE_VALIDATION", true),
+            sequential_delivery_enabled: env_or("SEQUENTIAL_DELIVERY_ENABLED", false),
+            sequential_delivery_timeout_secs: env_or("SEQUENTIAL_DELIVERY_TIMEOUT_SECS", 60),
+
+            inbound_blocked_patterns: env_list("INBOUND_BLOCKED_PATTERNS").unwrap_or_default(),
+
+            raw_webhook_audit: env_or("RAW_WEBHOOK_AUDIT", false),
+            raw_webhook_retention_days: env_or("RAW_WEBHOOK_RETENTION_DAYS", 7),
+            webhook_replay_window_secs: env_or("WEBHOOK_REPLAY_WINDOW_SECS", 300),
+            webhook_replay_cache_size: env_or("WEBHOOK_REPLAY_CACHE_SIZE", 10_000),
+            webhook_max_in_flight: env_or("WEBHOOK_MAX_IN_FLIGHT", 512),
+
+            normalize_bodies: env_or("NORMALIZE_BODIES", true),
+            max_conversations_per_number: env::var("MAX_CONVERSATIONS_PER_NUMBER")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|cap| *cap > 0),
+
+            batch_max_size: env_or("BATCH_MAX_SIZE", 100),
+            batch_flush_ms: env_or("BATCH_FLUSH_MS", 2),
+            batch_max_buffer: env_or("BATCH_MAX_BUFFER", 10_000),
+            batch_overflow_policy: env_or("BATCH_OVERFLOW_POLICY", OverflowPolicy::Reject),
+            batch_adaptive: env_or("BATCH_ADAPTIVE", false),
+            batch_adaptive_max_size: env_or("BATCH_ADAPTIVE_MAX_SIZE", 1000),
+            batch_adaptive_max_flush_ms: env_or("BATCH_ADAPTIVE_MAX_FLUSH_MS", 20),
+
+            purge_max_age_days: env::var("PURGE_MAX_AGE_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|days| *days > 0),
+            purge_interval_secs: env_or("PURGE_INTERVAL_SECS", 3600),
+            maintenance_interval_secs: env::var("MAINTENANCE_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|secs| *secs > 0),
+            maintenance_vacuum: env_or("MAINTENANCE_VACUUM", false),
+            scheduled_sms_poll_secs: env_or("SCHEDULED_SMS_POLL_SECS", 15),
+
+            ready_check_ai: env_or("READY_CHECK_AI", false),
+
+            greeting_enabled: env_or("GREETING_ENABLED", false),
+            greeting_message: env::var("GREETING_MESSAGE").unwrap_or_else(|_| {
+                "Thanks for reaching out! Reply with your question and we'll get right back to you."
+                    .into()
+            }),
+
+            consumer_start_strategy: env_or("CONSUMER_START_STRATEGY", StartStrategy::Next),
+            consumer_topics: env_list("CONSUMER_TOPICS")
+                .map(|targets| targets.iter().filter_map(|t| t.parse().ok()).collect::<Vec<_>>())
+                .filter(|targets| !targets.is_empty())
+                .unwrap_or_else(|| vec![TopicTarget::default()]),
+            consumer_idle_backoff_max_ms: env_or("CONSUMER_IDLE_BACKOFF_MAX_MS", 1000),
+            consumer_auto_commit: env_or("CONSUMER_AUTO_COMMIT", false),
+            consumer_poll_timeout_secs: env_or("CONSUMER_POLL_TIMEOUT_SECS", 30),
+            consumer_poll_per_partition: env_or("CONSUMER_POLL_PER_PARTITION", DEFAULT_POLL_PER_PARTITION),
+            consumer_poll_batch: env::var("CONSUMER_POLL_BATCH")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|n| *n > 0),
+            consumer_max_consecutive_errors: env_or(
+                "CONSUMER_MAX_CONSECUTIVE_ERRORS",
+                DEFAULT_MAX_CONSECUTIVE_ERRORS,
+            ),
+            event_logging: env_or("EVENT_LOGGING", false),
+            ai_consumer_enabled: env_or("ENABLE_AI_CONSUMER", true),
+
+            producer_max_messages: env::var("PRODUCER_MAX_MESSAGES")
+                .ok()
+                .and_then(|v| v.parse().ok()),
         })
     }
+
+    /// Settings shared by both consumer groups
+    pub fn consumer_config(&self) -> ConsumerConfig {
+        ConsumerConfig {
+            start_strategy: self.consumer_start_strategy,
+            topics: self.consumer_topics.clone(),
+            idle_backoff_max: Duration::from_millis(self.consumer_idle_backoff_max_ms),
+            auto_commit: self.consumer_auto_commit,
+            poll_timeout: Duration::from_secs(self.consumer_poll_timeout_secs.max(1)),
+            poll_per_partition: self.consumer_poll_per_partition,
+            poll_batch_length: self.consumer_poll_batch,
+            max_consecutive_errors: self.consumer_max_consecutive_errors,
+        }
+    }
+
+    pub fn usage_caps(&self) -> UsageCaps {
+        UsageCaps {
+            max_ai_calls: self.daily_ai_call_cap,
+            max_sms: self.daily_sms_cap,
+            notice: self.daily_cap_notice.clone(),
+        }
+    }
+
+    /// SignalWire client with the configured breaker, sender pool and allow-list
+    pub fn signalwire_client(&self) -> SignalWireClient {
+        let mut client = SignalWireClient::new(
+            self.signalwire_project_id.clone(),
+            self.signalwire_auth_token.clone(),
+            self.signalwire_space_url.clone(),
+            self.signalwire_from_number.clone(),
+        )
+        .with_body_normalization(self.normalize_bodies)
+        .with_circuit_breaker(
+            self.signalwire_breaker_threshold,
+            Duration::from_secs(self.signalwire_breaker_cooldown_secs),
+        );
+
+        if !self.signalwire_from_numbers.is_empty() {
+            client = client.with_from_numbers(self.signalwire_from_numbers.clone(), self.signalwire_from_strategy);
+        }
+        if let Some(allowed) = &self.signalwire_allowed_recipients {
+            client = client.with_allow_list(allowed.clone());
+        }
+        if let Some(base) = &self.public_base_url {
+            client = client.with_status_callback(format!("{base}/sms/status"));
+        }
+
+        client
+    }
+
+    pub fn reply_branding(&self) -> ReplyBranding {
+        ReplyBranding {
+            prefix: self.reply_prefix.clone(),
+            suffix: self.reply_suffix.clone(),
+            store_branded: self.reply_store_branded,
+        }
+    }
 }
@@ -0,0 +1,483 @@
+use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+
+use crate::message_broker::{SMSMessage, SmsPublisher};
+
+/// -----------------------------
+/// Overflow Policy
+/// -----------------------------
+/// What `add_message` does once the buffer reaches `max_buffer_len`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Refuse the message so the caller can signal 503 and the carrier retries
+    Reject,
+    /// Drop the incoming message and log a warning
+    DropNewest,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(OverflowPolicy::Reject),
+            "drop" | "drop_newest" => Ok(OverflowPolicy::DropNewest),
+            other => anyhow::bail!("Unknown overflow policy: {other}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BatcherConfig {
+    /// Flush as soon as this many messages are buffered
+    pub max_batch_size: usize,
+    /// Flush whatever is buffered at this interval
+    pub flush_interval: Duration,
+    /// Hard cap on buffered messages (guards against unbounded growth)
+    pub max_buffer_len: usize,
+    pub overflow_policy: OverflowPolicy,
+    /// Let batch size and flush interval follow the load (fixed when `None`)
+    pub adaptive: Option<AdaptiveLimits>,
+}
+
+impl Default for BatcherConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 100,
+            flush_interval: Duration::from_millis(2),
+            max_buffer_len: 10_000,
+            overflow_policy: OverflowPolicy::Reject,
+            adaptive: None,
+        }
+    }
+}
+
+/// -----------------------------
+/// Adaptive Flush
+/// -----------------------------
+/// Upper bounds for adaptive batching. The configured `max_batch_size` and
+/// `flush_interval` are the lower bounds and the starting point.
+///
+/// Every batch that fills up before the timer fires doubles both values
+/// (bursts get bigger batches); a timer flush of a mostly empty batch
+/// halves them again (trickles get low latency).
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveLimits {
+    pub max_batch_size: usize,
+    pub max_flush_interval: Duration,
+}
+
+/// Current effective settings, for monitoring
+#[derive(Debug, Clone, Serialize)]
+pub struct BatcherStats {
+    pub buffered: usize,
+    pub effective_batch_size: usize,
+    pub effective_flush_interval_ms: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tuning {
+    batch_size: usize,
+    flush_interval: Duration,
+}
+
+/// Buffer is at capacity and the policy is `Reject`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferFull;
+
+impl fmt::Display for BufferFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "message buffer is full")
+    }
+}
+
+impl std::error::Error for BufferFull {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddOutcome {
+    Queued,
+    Dropped,
+}
+
+/// -----------------------------
+/// Message Batcher
+/// -----------------------------
+/// Buffers inbound SMS and publishes them in batches.
+/// Messages from a failed flush are kept for the next attempt. They count
+/// against `max_buffer_len` while the flush is in flight, so putting them
+/// back can never grow the buffer past its cap.
+///
+/// Call `shutdown` before the process exits: Drop can't flush (it isn't
+/// async), it can only log what is about to be lost.
+pub struct MessageBatcher<P: SmsPublisher> {
+    publisher: Arc<P>,
+    config: BatcherConfig,
+    buffer: Mutex<Vec<SMSMessage>>,
+    /// Taken out of `buffer` by a flush that hasn't finished yet; only
+    /// changed while `buffer` is locked
+    in_flight: AtomicUsize,
+    tuning: std::sync::Mutex<Tuning>,
+}
+
+impl<P: SmsPublisher + 'static> MessageBatcher<P> {
+    pub fn new(publisher: Arc<P>, config: BatcherConfig) -> Self {
+        let tuning = Tuning {
+            batch_size: config.max_batch_size,
+            flush_interval: config.flush_interval,
+        };
+
+        Self {
+            publisher,
+            config,
+            buffer: Mutex::new(Vec::new()),
+            in_flight: AtomicUsize::new(0),
+            tuning: std::sync::Mutex::new(tuning),
+        }
+    }
+
+    fn tuning(&self) -> Tuning {
+        *self.tuning.lock().unwrap()
+    }
+
+    pub async fn stats(&self) -> BatcherStats {
+        let tuning = self.tuning();
+
+        BatcherStats {
+            buffered: self.len().await,
+            effective_batch_size: tuning.batch_size,
+            effective_flush_interval_ms: tuning.flush_interval.as_secs_f64() * 1000.0,
+        }
+    }
+
+    /// Grow (`busy`) or shrink the effective settings within the limits
+    fn adapt(&self, busy: bool) {
+        let Some(limits) = self.config.adaptive else {
+            return;
+        };
+
+        let mut tuning = self.tuning.lock().unwrap();
+        let (size, interval) = if busy {
+            (
+                (tuning.batch_size * 2).min(limits.max_batch_size),
+                (tuning.flush_interval * 2).min(limits.max_flush_interval),
+            )
+        } else {
+            (
+                (tuning.batch_size / 2).max(self.config.max_batch_size),
+                (tuning.flush_interval / 2).max(self.config.flush_interval),
+            )
+        };
+
+        if size != tuning.batch_size || interval != tuning.flush_interval {
+            debug!("Batcher now flushes at {} messages / {:?}", size, interval);
+            tuning.batch_size = size;
+            tuning.flush_interval = interval;
+        }
+    }
+
+    pub async fn len(&self) -> usize {
+        self.buffer.lock().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.buffer.lock().await.is_empty()
+    }
+
+    /// Buffer a message, flushing immediately once a full batch is ready
+    pub async fn add_message(&self, sms: SMSMessage) -> Result<AddOutcome, BufferFull> {
+        let ready = {
+            let mut buffer = self.buffer.lock().await;
+
+            let held = buffer.len() + self.in_flight.load(Ordering::SeqCst);
+            if held >= self.config.max_buffer_len {
+                match self.config.overflow_policy {
+                    OverflowPolicy::Reject => {
+                        warn!("Batcher buffer full ({held}), rejecting {}", sms.id);
+                        return Err(BufferFull);
+                    }
+                    OverflowPolicy::DropNewest => {
+                        warn!("Batcher buffer full ({held}), dropping {}", sms.id);
+                        return Ok(AddOutcome::Dropped);
+                    }
+                }
+            }
+
+            buffer.push(sms);
+            buffer.len() >= self.tuning().batch_size
+        };
+
+        if ready {
+            // Filled before the timer fired: the buffer is under pressure
+            self.adapt(true);
+
+            if let Err(e) = self.flush().await {
+                error!("Batch flush failed: {e}");
+            }
+        }
+
+        Ok(AddOutcome::Queued)
+    }
+
+    /// Publish everything currently buffered
+    pub async fn flush(&self) -> Result<()> {
+        let batch = {
+            let mut buffer = self.buffer.lock().await;
+            self.in_flight.fetch_add(buffer.len(), Ordering::SeqCst);
+            std::mem::take(&mut *buffer)
+        };
+
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let result = self.publisher.publish_sms_batch(batch.clone()).await;
+
+        let mut buffer = self.buffer.lock().await;
+        self.in_flight.fetch_sub(batch.len(), Ordering::SeqCst);
+        if result.is_err() {
+            // Put the batch back in front of anything that arrived meanwhile
+            let newer = std::mem::replace(&mut *buffer, batch);
+            buffer.extend(newer);
+        }
+
+        result
+    }
+
+    /// Final flush on the way out. Logs how many messages were still
+    /// buffered; if they can't be published they are reported as lost.
+    pub async fn shutdown(&self) -> Result<()> {
+        let buffered = self.len().await;
+        if buffered == 0 {
+            return Ok(());
+        }
+
+        info!("Flushing {buffered} buffered messages before shutdown");
+        self.flush().await.inspect_err(|e| {
+            error!("Final batch flush failed, {buffered} buffered messages will be lost: {e}");
+        })
+    }
+
+    /// Periodic flush loop; spawn once per batcher
+    pub async fn run_flush_loop(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(self.tuning().flush_interval).await;
+
+            if self.len().await < self.tuning().batch_size / 4 {
+                self.adapt(false);
+            }
+
+            if let Err(e) = self.flush().await {
+                error!("Batch flush failed: {e}");
+            }
+        }
+    }
+}
+
+impl<P: SmsPublisher> Drop for MessageBatcher<P> {
+    fn drop(&mut self) {
+        let buffered = self.buffer.get_mut().len();
+        if buffered > 0 {
+            error!("Batcher dropped with {buffered} unpublished messages; they are lost");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FailingPublisher;
+
+    impl SmsPublisher for FailingPublisher {
+        async fn publish_sms_batch(&self, _messages: Vec<SMSMessage>) -> Result<()> {
+            anyhow::bail!("broker down")
+        }
+    }
+
+    fn sms(id: usize) -> SMSMessage {
+        SMSMessage {
+            id: id.to_string(),
+            from: crate::PhoneNumber::parse("+15550001111").unwrap(),
+            to: crate::PhoneNumber::parse("+15552223333").unwrap(),
+            body: "hi".into(),
+            timestamp: 0,
+            conversation_id: "conv".into(),
+            provider_sid: None,
+            in_reply_to: None,
+            received_at: None,
+        }
+    }
+
+    fn batcher(policy: OverflowPolicy) -> MessageBatcher<FailingPublisher> {
+        MessageBatcher::new(
+            Arc::new(FailingPublisher),
+            BatcherConfig {
+                max_batch_size: 2,
+                max_buffer_len: 3,
+                overflow_policy: policy,
+                ..BatcherConfig::default()
+            },
+        )
+    }
+
+    #[derive(Default)]
+    struct RecordingPublisher {
+        batch_sizes: std::sync::Mutex<Vec<usize>>,
+    }
+
+    impl SmsPublisher for RecordingPublisher {
+        async fn publish_sms_batch(&self, messages: Vec<SMSMessage>) -> Result<()> {
+            self.batch_sizes.lock().unwrap().push(messages.len());
+            Ok(())
+        }
+    }
+
+    fn adaptive_batcher(publisher: Arc<RecordingPublisher>) -> Arc<MessageBatcher<RecordingPublisher>> {
+        Arc::new(MessageBatcher::new(
+            publisher,
+            BatcherConfig {
+                max_batch_size: 4,
+                flush_interval: Duration::from_millis(1),
+                adaptive: Some(AdaptiveLimits {
+                    max_batch_size: 64,
+                    max_flush_interval: Duration::from_millis(20),
+                }),
+                ..BatcherConfig::default()
+            },
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_flushes_what_is_buffered() {
+        let publisher = Arc::new(RecordingPublisher::default());
+        let recording = MessageBatcher::new(publisher.clone(), BatcherConfig::default());
+        recording.add_message(sms(1)).await.unwrap();
+        recording.add_message(sms(2)).await.unwrap();
+        assert!(publisher.batch_sizes.lock().unwrap().is_empty());
+
+        recording.shutdown().await.unwrap();
+        assert_eq!(*publisher.batch_sizes.lock().unwrap(), [2]);
+        assert!(recording.is_empty().await);
+
+        // Nothing left means nothing to publish
+        recording.shutdown().await.unwrap();
+        assert_eq!(publisher.batch_sizes.lock().unwrap().len(), 1);
+
+        let (_guard, logs) = crate::test_support::capture_logs();
+        let failing = batcher(OverflowPolicy::Reject);
+        failing.add_message(sms(1)).await.unwrap();
+        assert!(failing.shutdown().await.is_err());
+        drop(failing);
+        assert!(logs().contains("1 unpublished messages"), "{}", logs());
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_batches_grow_under_bursts() {
+        let bursty = Arc::new(RecordingPublisher::default());
+        let batcher = adaptive_batcher(bursty.clone());
+        for i in 0..300 {
+            batcher.add_message(sms(i)).await.unwrap();
+        }
+        assert_eq!(batcher.stats().await.effective_batch_size, 64);
+
+        let trickle = Arc::new(RecordingPublisher::default());
+        let batcher = adaptive_batcher(trickle.clone());
+        let flusher = tokio::spawn(batcher.clone().run_flush_loop());
+        for i in 0..5 {
+            batcher.add_message(sms(i)).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        flusher.abort();
+        assert_eq!(batcher.stats().await.effective_batch_size, 4);
+
+        let largest = |p: &RecordingPublisher| p.batch_sizes.lock().unwrap().iter().copied().max();
+        assert!(largest(&bursty) > largest(&trickle));
+        assert_eq!(largest(&bursty), Some(64));
+    }
+
+    #[tokio::test]
+    async fn test_reject_policy_applies_backpressure() {
+        let batcher = batcher(OverflowPolicy::Reject);
+
+        for i in 0..3 {
+            assert_eq!(batcher.add_message(sms(i)).await, Ok(AddOutcome::Queued));
+        }
+
+        // Failed flushes keep messages buffered, so the cap is reached
+        assert_eq!(batcher.add_message(sms(3)).await, Err(BufferFull));
+        assert_eq!(batcher.len().await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_drop_policy_discards_overflow() {
+        let batcher = batcher(OverflowPolicy::DropNewest);
+
+        for i in 0..3 {
+            batcher.add_message(sms(i)).await.unwrap();
+        }
+
+        assert_eq!(batcher.add_message(sms(3)).await, Ok(AddOutcome::Dropped));
+        assert_eq!(batcher.len().await, 3);
+    }
+
+    /// Fails every batch, but only once released
+    #[derive(Default)]
+    struct StallingPublisher {
+        release: tokio::sync::Notify,
+    }
+
+    impl SmsPublisher for StallingPublisher {
+        async fn publish_sms_batch(&self, _messages: Vec<SMSMessage>) -> Result<()> {
+            self.release.notified().await;
+            anyhow::bail!("broker down")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requeued_batch_never_overflows_the_buffer() {
+        for policy in [OverflowPolicy::Reject, OverflowPolicy::DropNewest] {
+            let publisher = Arc::new(StallingPublisher::default());
+            let batcher = Arc::new(MessageBatcher::new(
+                publisher.clone(),
+                BatcherConfig {
+                    max_batch_size: 100,
+                    max_buffer_len: 3,
+                    overflow_policy: policy,
+                    ..BatcherConfig::default()
+                },
+            ));
+            for i in 0..2 {
+                batcher.add_message(sms(i)).await.unwrap();
+            }
+
+            let flush = tokio::spawn({
+                let batcher = batcher.clone();
+                async move { batcher.flush().await }
+            });
+            while !batcher.is_empty().await {
+                tokio::task::yield_now().await;
+            }
+
+            // The two in flight still hold their place in the buffer
+            assert_eq!(batcher.add_message(sms(2)).await, Ok(AddOutcome::Queued));
+            let overflow = batcher.add_message(sms(3)).await;
+            match policy {
+                OverflowPolicy::Reject => assert_eq!(overflow, Err(BufferFull)),
+                OverflowPolicy::DropNewest => assert_eq!(overflow, Ok(AddOutcome::Dropped)),
+            }
+
+            publisher.release.notify_one();
+            assert!(flush.await.unwrap().is_err());
+
+            let ids: Vec<String> = batcher.buffer.lock().await.iter().map(|m| m.id.clone()).collect();
+            assert_eq!(ids, ["0", "1", "2"]);
+        }
+    }
+}
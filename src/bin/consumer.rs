@@ -1,14 +1,17 @@
 use anyhow::Result;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::{error, info};
 
 use conversation_store::{
     app_config::AppConfig,
     consumers::{AIConsumer, TursoConsumer},
-    infra::iggy::connect_iggy,
+    events::{noop_sink, EventSink, LoggingSink},
+    infra::{http::load_root_certificate, iggy::connect_iggy},
     store::ConversationStore,
+    storage::ConversationStorage,
     ai_service::AIService,
-    signalwire::SignalWireClient,
 };
 
 #[tokio::main]
@@ -24,15 +27,28 @@ async fn main() -> Result<()> {
     // =====================================================
     let config = Arc::new(AppConfig::load()?);
 
+    let root_cert = config
+        .ca_cert_path
+        .as_deref()
+        .map(load_root_certificate)
+        .transpose()?;
+
     // =====================================================
     // Initialize Turso store
     // =====================================================
-    let store = Arc::new(
+    let mut store =
         ConversationStore::new(
             config.turso_db_url.clone(),
             config.turso_auth_token.clone(),
         )
-    );
+        .with_slow_query_threshold(Duration::from_millis(config.turso_slow_query_ms))
+        .with_content_limit(config.turso_max_content_bytes, config.turso_content_overflow);
+
+    if let Some(cert) = &root_cert {
+        store = store.with_root_certificate(cert.clone())?;
+    }
+
+    let store = Arc::new(store);
 
     store.initialize().await?;
     info!("✓ Turso initialized");
@@ -40,72 +56,226 @@ async fn main() -> Result<()> {
     // =====================================================
     // Initialize AI service
     // =====================================================
-    let ai_service = Arc::new(
+    let mut ai_service =
         AIService::new(
             config.groq_model.clone(),
             config.groq_api_key.clone(),
         )
-    );
+        .with_embedding_model(config.embedding_model.clone());
+
+    if let Some(cert) = root_cert {
+        ai_service = ai_service.with_root_certificate(cert)?;
+        info!("✓ Trusting extra CA certificate");
+    }
+
+    if let Some(fallback) = &config.ai_fallback {
+        info!("✓ AI fallback provider: {} ({})", fallback.api_url, fallback.model);
+        ai_service = ai_service.with_fallback_provider(fallback.clone());
+    }
+
+    if let Some(template) = &config.ai_prompt_template {
+        info!("✓ AI prompt template enabled");
+        ai_service = ai_service.with_prompt_template(template.clone());
+    }
+
+    let ai_service = Arc::new(ai_service);
 
     // =====================================================
     // Initialize SignalWire
     // =====================================================
-    let signalwire = Arc::new(
-        SignalWireClient::new(
-            config.signalwire_project_id.clone(),
-            config.signalwire_auth_token.clone(),
-            config.signalwire_space_url.clone(),
-            config.signalwire_from_number.clone(),
-        )
-    );
+    if !config.signalwire_from_numbers.is_empty() {
+        info!(
+            "✓ Sending from {} numbers ({:?})",
+            config.signalwire_from_numbers.len(),
+            config.signalwire_from_strategy
+        );
+    }
+
+    if let Some(allowed) = &config.signalwire_allowed_recipients {
+        info!("⚠️ SignalWire allow-list active ({} numbers)", allowed.len());
+    }
+
+    let signalwire = Arc::new(config.signalwire_client());
 
     // =====================================================
     // Dedicated Iggy clients (IMPORTANT)
     // =====================================================
-    let turso_client = connect_iggy().await?;
-    info!("✓ Turso consumer connected to Iggy");
-
-    let ai_client = connect_iggy().await?;
-    info!("✓ AI consumer connected to Iggy");
+    let (turso_client, ai_client) = connect_clients(&config, connect_iggy).await?;
 
     // =====================================================
     // Create consumers
     // =====================================================
-    let turso_consumer =
-        TursoConsumer::new(
-            turso_client,
-            store.clone(),
-        )
-        .await?;
+    let consumer_config = config.consumer_config();
+    info!(
+        "✓ Delivery semantics: {:?} (auto_commit={})",
+        consumer_config.delivery_semantics(),
+        consumer_config.auto_commit
+    );
+
+    let events: Arc<dyn EventSink> = if config.event_logging {
+        Arc::new(LoggingSink)
+    } else {
+        noop_sink()
+    };
+
+    let mut turso_consumer = TursoConsumer::new(store.clone())
+        .with_config(consumer_config.clone())
+        .with_usage_caps(config.usage_caps())
+        .with_event_sink(events.clone());
+
+    if config.greeting_enabled {
+        turso_consumer =
+            turso_consumer.with_greeting(signalwire.clone(), config.greeting_message.clone());
+    }
 
     let ai_consumer =
         AIConsumer::new(
-            ai_client,
             store.clone(),
             ai_service.clone(),
             signalwire.clone(),
         )
-        .await?;
+        .with_default_system_prompt(config.ai_system_prompt.clone())
+        .with_sender_label(config.assistant_sender_label.clone())
+        .with_history_max_chars(config.ai_history_max_chars)
+        .with_auto_title(config.auto_title_enabled)
+        .with_max_in_flight_ai(config.ai_max_in_flight)
+        .with_usage_caps(config.usage_caps())
+        .with_reply_branding(config.reply_branding())
+        .with_markdown_policy(config.reply_markdown)
+        .with_body_normalization(config.normalize_bodies)
+        .with_event_sink(events)
+        .with_config(consumer_config)
+        .with_sequential_delivery(
+            config
+                .sequential_delivery_enabled
+                .then(|| Duration::from_secs(config.sequential_delivery_timeout_secs)),
+        );
 
     info!("✓ Consumers initialized");
 
     // =====================================================
     // Run consumers (PARALLEL)
     // =====================================================
-    tokio::try_join!(
+    run_consumers(
         async {
             info!("→ Turso consumer started");
-            turso_consumer.start().await
+            turso_consumer.start(turso_client).await
         },
-        async {
+        ai_client.map(|client| async {
             info!("→ AI consumer started");
-            ai_consumer.start().await
-        },
+            ai_consumer.start(client).await
+        }),
     )
+    .await
     .map_err(|e| {
         error!("Consumer crashed: {e}");
         e
-    })?;
+    })
+}
+
+/// Connect one Iggy client per consumer. The AI consumer gets none when
+/// `ENABLE_AI_CONSUMER` is off (store-only mode), so it is never started.
+async fn connect_clients<C, F, Fut>(config: &AppConfig, connect: F) -> Result<(C, Option<C>)>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<C>>,
+{
+    let turso_client = connect().await?;
+    info!("✓ Turso consumer connected to Iggy");
+
+    let ai_client = if config.ai_consumer_enabled {
+        let client = connect().await?;
+        info!("✓ AI consumer connected to Iggy");
+        Some(client)
+    } else {
+        info!("⚠️ AI consumer disabled: store-only mode");
+        None
+    };
+
+    Ok((turso_client, ai_client))
+}
+
+/// Run the Turso consumer alongside the AI consumer, or alone when the
+/// AI consumer is disabled (`None`). Returns as soon as either fails.
+async fn run_consumers<T, A>(turso: T, ai: Option<A>) -> Result<()>
+where
+    T: Future<Output = Result<()>>,
+    A: Future<Output = Result<()>>,
+{
+    match ai {
+        Some(ai) => tokio::try_join!(turso, ai).map(|_| ()),
+        None => turso.await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    /// Loads the config from the environment, connects the clients it asks
+    /// for and runs the consumers; returns (clients connected, AI ran)
+    async fn start_from_env() -> (usize, bool) {
+        let config = AppConfig::load().unwrap();
+        let connected = AtomicUsize::new(0);
+        let (turso_client, ai_client) = connect_clients(&config, || async {
+            Ok(connected.fetch_add(1, Ordering::SeqCst))
+        })
+        .await
+        .unwrap();
+
+        let ai_ran = AtomicBool::new(false);
+        run_consumers(
+            async move {
+                assert_eq!(turso_client, 0);
+                Ok(())
+            },
+            ai_client.map(|_| async {
+                ai_ran.store(true, Ordering::SeqCst);
+                Ok(())
+            }),
+        )
+        .await
+        .unwrap();
+
+        (connected.load(Ordering::SeqCst), ai_ran.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn test_store_only_mode_starts_no_ai_consumer() {
+        for (key, value) in [
+            ("TURSO_DATABASE_URL", "libsql://test.turso.io"),
+            ("TURSO_AUTH_TOKEN", "token"),
+            ("GROQ_API_KEY", "key"),
+            ("SIGNALWIRE_PROJECT_ID", "project"),
+            ("SIGNALWIRE_AUTH_TOKEN", "token"),
+            ("SIGNALWIRE_SPACE_URL", "test.signalwire.com"),
+            ("SIGNALWIRE_FROM_NUMBER", "+15550000000"),
+        ] {
+            std::env::set_var(key, value);
+        }
+
+        std::env::set_var("ENABLE_AI_CONSUMER", "false");
+        assert_eq!(start_from_env().await, (1, false));
+
+        std::env::set_var("ENABLE_AI_CONSUMER", "true");
+        assert_eq!(start_from_env().await, (2, true));
+    }
+
+    #[tokio::test]
+    async fn test_both_consumers_run_and_a_crash_is_reported() {
+        let turso_ran = AtomicBool::new(false);
+
+        let result = run_consumers(
+            async {
+                turso_ran.store(true, Ordering::SeqCst);
+                Ok(())
+            },
+            Some(async { Err(anyhow::anyhow!("iggy went away")) }),
+        )
+        .await;
 
-    Ok(())
+        assert!(result.is_err());
+        assert!(turso_ran.load(Ordering::SeqCst));
+    }
 }
@@ -1,30 +1,152 @@
 use anyhow::Result;
 use axum::{
-    extract::{Form, State},
-    routing::{get, post},
-    Router,
-    http::StatusCode,
+    body::Body,
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        FromRef, Form, Path, Query, RawForm, State,
+    },
+    middleware,
+    response::IntoResponse,
+    routing::{get, post, put},
+    Json, Router,
+    http::{header, StatusCode},
 };
+use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tower_http::trace::TraceLayer;
-use tracing::{error, info};
-use chrono::Utc;
-use serde::Deserialize;
+use tracing::{error, info, warn};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
 
-use conversation_store::message_broker::{MessageBroker, SMSMessage};
+use conversation_store::batcher::{
+    AdaptiveLimits, AddOutcome, BatcherConfig, BatcherStats, MessageBatcher,
+};
+use conversation_store::message_broker::{
+    ConsumerGroupInfo, MessageBroker, PartitionStat, SMSMessage, SmsPublisher,
+};
+use conversation_store::inbound_filter::InboundFilter;
+use conversation_store::replay_guard::ReplayGuard;
+use conversation_store::normalize::normalize_body;
+use conversation_store::ai_service::AIService;
+use conversation_store::consumers::{
+    generate_assistant_reply, merge_split_sms_conversations, regenerate_assistant_reply,
+    ConsumerConfig, DeliverySemantics, NotAUserMessage, ReplyContext, ReplyGuards, UsageCapReached,
+};
+use conversation_store::signalwire::{CircuitStats, SendOutcome, SignalWireClient};
+use conversation_store::outbound_audit::send_audited;
+use conversation_store::scheduler::send_due_scheduled;
+use conversation_store::usage_caps::{UsageCaps, UsageKind};
+use conversation_store::models::{AggregateStats, AuditCursor, ConversationCursor, DailyCount, MessageCursor, OutboundAudit, Page};
+use conversation_store::store::DEFAULT_STATS_DAYS;
+use conversation_store::{
+    Conversation, ConversationStorage, ConversationStore, Message, MessageRole, PhoneNumber,
+};
+use conversation_store::infra::http::load_root_certificate;
 use conversation_store::infra::iggy::connect_iggy;
+use conversation_store::export::{export_filename, stream_export_zip_paged, EXPORT_PAGE_SIZE};
+use conversation_store::api_error::{ApiError, ErrorDetail, ErrorResponse};
+use conversation_store::storage::NotFound;
+use conversation_store::api_logging::{log_api_requests, ApiLogConfig};
+use conversation_store::concurrency_limit::{limit_concurrency, ConcurrencyLimit};
+use conversation_store::rate_limit::{limit_rate, RateLimit};
+use conversation_store::webhook_signature::{verify_webhook_signature, WebhookSigner};
 use conversation_store::app_config::AppConfig;
 use conversation_store::broker_config::BrokerConfig;
 
+/// -----------------------------
+/// OpenAPI
+/// -----------------------------
+/// Spec for the JSON API and health probes, served at `/api/openapi.json`
+/// with a Swagger UI at `/api/docs`. Webhooks are for the carrier only
+/// and left out.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "SMS Service API"),
+    paths(
+        health,
+        ready,
+        list_conversations,
+        create_conversation,
+        get_conversation,
+        export_conversation_zip,
+        mark_conversation_read,
+        set_conversation_context,
+        set_conversation_ai_enabled,
+        set_conversation_ai_settings,
+        set_conversation_sender_label,
+        list_messages,
+        post_message,
+        get_message,
+        regenerate_reply,
+        stats,
+        outbound_audit,
+    ),
+    components(schemas(
+        Conversation,
+        Message,
+        MessageRole,
+        Page<Conversation>,
+        Page<Message>,
+        ReadState,
+        AggregateStats,
+        DailyCount,
+        OutboundAudit,
+        ErrorResponse,
+        ErrorDetail,
+    )),
+    tags(
+        (name = "health", description = "Liveness and readiness probes"),
+        (name = "conversations", description = "Conversation threads"),
+        (name = "messages", description = "Messages within a conversation"),
+        (name = "stats", description = "Aggregate counts for analytics"),
+        (name = "audit", description = "Compliance records of outbound SMS"),
+    )
+)]
+struct ApiDoc;
+
 /// -----------------------------
 /// Health
 /// -----------------------------
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "health",
+    responses((status = 200, description = "Process is up", body = String))
+)]
 async fn health() -> &'static str {
     "OK"
 }
+
+/// Ready to serve: dependencies answer. The AI key is only checked when
+/// `READY_CHECK_AI` is set, since it costs a request to the provider.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready to serve", body = String),
+        (status = 503, description = "A dependency is failing", body = String),
+    )
+)]
+async fn ready(State(state): State<AppState>) -> (StatusCode, String) {
+    if state.config.ready_check_ai {
+        if let Err(e) = state.ai.health_check().await {
+            error!("Readiness: {e}");
+            return (StatusCode::SERVICE_UNAVAILABLE, e.to_string());
+        }
+    }
+
+    (StatusCode::OK, "READY".into())
+}
 /// -----------------------------
 /// Incoming SMS
 /// -----------------------------
+/// SignalWire/Twilio (LaML) webhook form payload.
+/// Fields not listed here are ignored.
 #[derive(Debug, Deserialize)]
 struct IncomingSMS {
     #[serde(rename = "From")]
@@ -33,6 +155,87 @@ struct IncomingSMS {
     to: String,
     #[serde(rename = "Body")]
     body: String,
+    #[serde(rename = "MessageSid")]
+    message_sid: Option<String>,
+    #[serde(rename = "AccountSid")]
+    account_sid: Option<String>,
+    #[serde(rename = "NumSegments")]
+    num_segments: Option<u32>,
+    #[serde(rename = "SmsStatus")]
+    sms_status: Option<String>,
+    /// Provider SID of the message being replied to (RCS/iMessage-style threading)
+    #[serde(rename = "InReplyTo")]
+    in_reply_to: Option<String>,
+}
+
+/// Why an inbound webhook didn't become an `SMSMessage`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum InboundError {
+    /// `From` or `To` isn't a usable phone number
+    InvalidNumber(String),
+    /// Nothing left of the body after normalization
+    BlankBody,
+}
+
+impl fmt::Display for InboundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InboundError::InvalidNumber(e) => write!(f, "invalid phone number: {e}"),
+            InboundError::BlankBody => write!(f, "blank message body"),
+        }
+    }
+}
+
+impl IncomingSMS {
+    /// Validate the webhook fields and build the message to publish:
+    /// normalize the body (when `normalize`), parse both numbers, resolve
+    /// the conversation (`InReplyTo`, the sender's latest once they're at
+    /// `max_per_number`, or a new one), and stamp a fresh id and
+    /// `received_at`.
+    async fn into_sms_message<S: ConversationStorage>(
+        self,
+        store: &S,
+        normalize: bool,
+        max_per_number: Option<usize>,
+        received_at: DateTime<Utc>,
+    ) -> Result<SMSMessage, InboundError> {
+        let body = if normalize { normalize_body(&self.body) } else { self.body };
+        if normalize && body.is_empty() {
+            return Err(InboundError::BlankBody);
+        }
+
+        let from = PhoneNumber::parse(&self.from).map_err(|e| InboundError::InvalidNumber(e.to_string()))?;
+        let to = PhoneNumber::parse(&self.to).map_err(|e| InboundError::InvalidNumber(e.to_string()))?;
+
+        let conversation_id =
+            resolve_conversation_id(store, &from, self.in_reply_to.as_deref(), max_per_number).await;
+
+        Ok(SMSMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            from,
+            to,
+            body,
+            timestamp: received_at.timestamp(),
+            conversation_id,
+            provider_sid: self.message_sid,
+            in_reply_to: self.in_reply_to,
+            received_at: Some(received_at),
+        })
+    }
+}
+
+/// -----------------------------
+/// Delivery Status
+/// -----------------------------
+/// Status callback for an outbound message
+#[derive(Debug, Deserialize)]
+struct DeliveryStatus {
+    #[serde(rename = "MessageSid")]
+    message_sid: String,
+    #[serde(rename = "MessageStatus")]
+    message_status: String,
+    #[serde(rename = "ErrorCode")]
+    error_code: Option<String>,
 }
 
 /// -----------------------------
@@ -41,36 +244,1296 @@ struct IncomingSMS {
 #[derive(Clone)]
 struct AppState {
     broker: Arc<MessageBroker>,
+    batcher: Arc<MessageBatcher<MessageBroker>>,
+    inbound_filter: Arc<InboundFilter>,
+    /// Unset when replay protection is disabled
+    replay_guard: Option<Arc<ReplayGuard>>,
+    store: Arc<ConversationStore>,
+    ai: Arc<AIService>,
+    /// Sends regenerated replies on request
+    signalwire: Arc<SignalWireClient>,
+    /// Shared AI slots and daily caps for API-driven replies
+    guards: ReplyGuards,
+    config: Arc<AppConfig>,
+}
+
+/// Lets handlers that only need the store take `State<Arc<S>>`, so they
+/// can be served over an `InMemoryStore` in tests
+impl FromRef<AppState> for Arc<ConversationStore> {
+    fn from_ref(state: &AppState) -> Self {
+        state.store.clone()
+    }
+}
+
+impl AppState {
+    fn reply_context(&self) -> ReplyContext<'_, ConversationStore> {
+        ReplyContext {
+            store: self.store.as_ref(),
+            ai: &self.ai,
+            guards: &self.guards,
+            default_system_prompt: &self.config.ai_system_prompt,
+            default_sender_label: self.config.assistant_sender_label.as_deref(),
+            history_max_chars: self.config.ai_history_max_chars,
+            normalize_bodies: self.config.normalize_bodies,
+        }
+    }
+}
+
+/// Maps a failed API-driven reply: unknown ids are 404s, a used-up cap
+/// is a 429, a non-user message a 400 and anything else an upstream failure
+fn reply_error(context: &str, e: anyhow::Error) -> ApiError {
+    if let Some(not_user) = e.downcast_ref::<NotAUserMessage>() {
+        return ApiError::bad_request(not_user.to_string());
+    }
+    if let Some(capped) = e.downcast_ref::<UsageCapReached>() {
+        return ApiError::too_many_requests(capped.to_string());
+    }
+    match e.downcast_ref::<NotFound>() {
+        Some(not_found) => ApiError::not_found(not_found.to_string()),
+        None => ApiError::upstream(context, e),
+    }
+}
+
+/// -----------------------------
+/// Conversations API
+/// -----------------------------
+#[derive(Debug, Deserialize, ToSchema)]
+struct CreateConversationReq {
+    title: Option<String>,
+    /// Custom AI persona for this conversation
+    system_prompt: Option<String>,
+    /// Pin a different model / temperature (0 to 2) for this conversation's replies
+    ai_model: Option<String>,
+    ai_temperature: Option<f32>,
+    /// Label stored with this conversation's assistant replies (e.g. "Agent Bot")
+    sender_label: Option<String>,
+    /// Customer profile given to the AI with the system prompt, e.g.
+    /// `{"name": "Ada", "plan": "pro"}` (at most 4 KB of JSON); not part
+    /// of the transcript
+    #[schema(value_type = Option<Object>)]
+    context: Option<serde_json::Value>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/conversations",
+    tag = "conversations",
+    request_body = CreateConversationReq,
+    responses(
+        (status = 201, description = "Conversation created", body = Conversation),
+        (status = 400, description = "Invalid request body", body = ErrorResponse),
+    )
+)]
+async fn create_conversation(
+    State(state): State<AppState>,
+    req: Result<Json<CreateConversationReq>, JsonRejection>,
+) -> Result<(StatusCode, Json<Conversation>), ApiError> {
+    let Json(req) = req?;
+    check_ai_settings(req.ai_model.as_deref(), req.ai_temperature)?;
+    check_sender_label(req.sender_label.as_deref())?;
+    check_context(req.context.as_ref())?;
+    let internal = |e| ApiError::internal("Failed to create conversation", e);
+
+    let mut conversation = state
+        .store
+        .create_conversation(req.title, req.system_prompt)
+        .await
+        .map_err(internal)?;
+
+    if req.ai_model.is_some() || req.ai_temperature.is_some() {
+        state
+            .store
+            .set_conversation_ai_settings(&conversation.id, req.ai_model.as_deref(), req.ai_temperature)
+            .await
+            .map_err(internal)?;
+        conversation.ai_model = req.ai_model;
+        conversation.ai_temperature = req.ai_temperature;
+    }
+
+    if req.sender_label.is_some() {
+        state
+            .store
+            .set_conversation_sender_label(&conversation.id, req.sender_label.as_deref())
+            .await
+            .map_err(internal)?;
+        conversation.sender_label = req.sender_label;
+    }
+
+    if req.context.is_some() {
+        state
+            .store
+            .set_conversation_context(&conversation.id, req.context.as_ref())
+            .await
+            .map_err(internal)?;
+        conversation.context = req.context;
+    }
+
+    Ok((StatusCode::CREATED, Json(conversation)))
+}
+
+/// Highest sampling temperature the AI providers accept
+const MAX_AI_TEMPERATURE: f32 = 2.0;
+
+/// 400 for a blank model or a temperature outside `0..=MAX_AI_TEMPERATURE`
+fn check_ai_settings(model: Option<&str>, temperature: Option<f32>) -> Result<(), ApiError> {
+    if model.is_some_and(|m| m.trim().is_empty()) {
+        return Err(ApiError::bad_request("ai_model must not be blank"));
+    }
+    if temperature.is_some_and(|t| !(0.0..=MAX_AI_TEMPERATURE).contains(&t)) {
+        return Err(ApiError::bad_request(format!(
+            "ai_temperature must be between 0 and {MAX_AI_TEMPERATURE}"
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SetAiSettingsReq {
+    /// Model for this conversation's replies; `null` goes back to the default
+    ai_model: Option<String>,
+    /// 0 to 2; `null` goes back to the default
+    ai_temperature: Option<f32>,
+}
+
+/// Pin (or clear) the AI model and temperature for this conversation,
+/// including ones opened automatically by inbound SMS
+#[utoipa::path(
+    put,
+    path = "/api/conversations/{id}/ai-settings",
+    tag = "conversations",
+    params(("id" = String, Path, description = "Conversation id")),
+    request_body = SetAiSettingsReq,
+    responses(
+        (status = 200, description = "Updated conversation", body = Conversation),
+        (status = 400, description = "Invalid request body or temperature", body = ErrorResponse),
+        (status = 404, description = "No such conversation", body = ErrorResponse),
+    )
+)]
+async fn set_conversation_ai_settings(
+    State(state): State<AppState>,
+    path: Result<Path<String>, PathRejection>,
+    req: Result<Json<SetAiSettingsReq>, JsonRejection>,
+) -> Result<Json<Conversation>, ApiError> {
+    let Path(id) = path?;
+    let Json(req) = req?;
+    check_ai_settings(req.ai_model.as_deref(), req.ai_temperature)?;
+    let context = format!("Failed to update AI settings of {id}");
+    let internal = |e| ApiError::internal(&context, e);
+
+    let mut conversation = state
+        .store
+        .get_conversation(&id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::not_found(format!("Conversation {id} not found")))?;
+
+    state
+        .store
+        .set_conversation_ai_settings(&id, req.ai_model.as_deref(), req.ai_temperature)
+        .await
+        .map_err(internal)?;
+    conversation.ai_model = req.ai_model;
+    conversation.ai_temperature = req.ai_temperature;
+
+    Ok(Json(conversation))
+}
+
+/// Longest sender label, in characters
+const MAX_SENDER_LABEL_CHARS: usize = 64;
+
+/// 400 for a blank or overlong sender label
+fn check_sender_label(label: Option<&str>) -> Result<(), ApiError> {
+    match label {
+        Some(label) if label.trim().is_empty() => Err(ApiError::bad_request("sender_label must not be blank")),
+        Some(label) if label.chars().count() > MAX_SENDER_LABEL_CHARS => Err(ApiError::bad_request(format!(
+            "sender_label must be at most {MAX_SENDER_LABEL_CHARS} characters"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SetSenderLabelReq {
+    /// Label for this conversation's assistant replies; `null` goes back
+    /// to `ASSISTANT_SENDER_LABEL`
+    sender_label: Option<String>,
+}
+
+/// Change the label stored with this conversation's future assistant replies
+#[utoipa::path(
+    put,
+    path = "/api/conversations/{id}/sender-label",
+    tag = "conversations",
+    params(("id" = String, Path, description = "Conversation id")),
+    request_body = SetSenderLabelReq,
+    responses(
+        (status = 200, description = "Updated conversation", body = Conversation),
+        (status = 400, description = "Invalid request body or label", body = ErrorResponse),
+        (status = 404, description = "No such conversation", body = ErrorResponse),
+    )
+)]
+async fn set_conversation_sender_label(
+    State(state): State<AppState>,
+    path: Result<Path<String>, PathRejection>,
+    req: Result<Json<SetSenderLabelReq>, JsonRejection>,
+) -> Result<Json<Conversation>, ApiError> {
+    let Path(id) = path?;
+    let Json(req) = req?;
+    check_sender_label(req.sender_label.as_deref())?;
+    let context = format!("Failed to update sender label of {id}");
+    let internal = |e| ApiError::internal(&context, e);
+
+    let mut conversation = state
+        .store
+        .get_conversation(&id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::not_found(format!("Conversation {id} not found")))?;
+
+    state
+        .store
+        .set_conversation_sender_label(&id, req.sender_label.as_deref())
+        .await
+        .map_err(internal)?;
+    conversation.sender_label = req.sender_label;
+
+    Ok(Json(conversation))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SetAiEnabledReq {
+    /// `false` hands the conversation to a human agent
+    enabled: bool,
+}
+
+/// Turn AI replies on or off for this conversation (human takeover).
+/// Inbound messages are stored either way.
+#[utoipa::path(
+    post,
+    path = "/api/conversations/{id}/ai",
+    tag = "conversations",
+    params(("id" = String, Path, description = "Conversation id")),
+    request_body = SetAiEnabledReq,
+    responses(
+        (status = 200, description = "Updated conversation", body = Conversation),
+        (status = 400, description = "Invalid request body", body = ErrorResponse),
+        (status = 404, description = "No such conversation", body = ErrorResponse),
+    )
+)]
+async fn set_conversation_ai_enabled(
+    State(state): State<AppState>,
+    path: Result<Path<String>, PathRejection>,
+    req: Result<Json<SetAiEnabledReq>, JsonRejection>,
+) -> Result<Json<Conversation>, ApiError> {
+    let Path(id) = path?;
+    let Json(req) = req?;
+    let context = format!("Failed to update AI replies of {id}");
+    let internal = |e| ApiError::internal(&context, e);
+
+    let mut conversation = state
+        .store
+        .get_conversation(&id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::not_found(format!("Conversation {id} not found")))?;
+
+    state
+        .store
+        .set_conversation_ai_enabled(&id, req.enabled)
+        .await
+        .map_err(internal)?;
+    conversation.ai_enabled = req.enabled;
+
+    Ok(Json(conversation))
+}
+
+/// Largest customer context, in bytes of JSON. It goes into every
+/// system prompt of the conversation, so it costs tokens on each reply.
+const MAX_CONTEXT_BYTES: usize = 4096;
+
+/// 400 for a context larger than `MAX_CONTEXT_BYTES`
+fn check_context(context: Option<&serde_json::Value>) -> Result<(), ApiError> {
+    let size = context.map_or(0, |c| c.to_string().len());
+    if size > MAX_CONTEXT_BYTES {
+        return Err(ApiError::bad_request(format!(
+            "context is {size} bytes, at most {MAX_CONTEXT_BYTES} allowed"
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SetContextReq {
+    /// New customer context (at most 4 KB of JSON); `null` clears it
+    #[schema(value_type = Option<Object>)]
+    context: Option<serde_json::Value>,
+}
+
+/// Replace the customer context the AI sees for this conversation
+#[utoipa::path(
+    put,
+    path = "/api/conversations/{id}/context",
+    tag = "conversations",
+    params(("id" = String, Path, description = "Conversation id")),
+    request_body = SetContextReq,
+    responses(
+        (status = 200, description = "Updated conversation", body = Conversation),
+        (status = 400, description = "Invalid request body or context too large", body = ErrorResponse),
+        (status = 404, description = "No such conversation", body = ErrorResponse),
+    )
+)]
+async fn set_conversation_context(
+    State(state): State<AppState>,
+    path: Result<Path<String>, PathRejection>,
+    req: Result<Json<SetContextReq>, JsonRejection>,
+) -> Result<Json<Conversation>, ApiError> {
+    let Path(id) = path?;
+    let Json(req) = req?;
+    check_context(req.context.as_ref())?;
+    let error_context = format!("Failed to set context of {id}");
+    let internal = |e| ApiError::internal(&error_context, e);
+
+    let mut conversation = state
+        .store
+        .get_conversation(&id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::not_found(format!("Conversation {id} not found")))?;
+
+    state
+        .store
+        .set_conversation_context(&id, req.context.as_ref())
+        .await
+        .map_err(internal)?;
+    conversation.context = req.context;
+
+    Ok(Json(conversation))
+}
+
+const DEFAULT_PAGE_LIMIT: u32 = 50;
+const MAX_PAGE_LIMIT: u32 = 200;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListConversationsQuery {
+    /// Page size (default 50, at most 200)
+    limit: Option<u32>,
+    #[serde(default)]
+    offset: u32,
+    #[serde(default)]
+    include_archived: bool,
+    /// `next_cursor` from a previous page; takes precedence over `offset`
+    cursor: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/conversations",
+    tag = "conversations",
+    params(ListConversationsQuery),
+    responses(
+        (status = 200, description = "One page, most recently updated first", body = Page<Conversation>),
+        (status = 400, description = "Invalid query or cursor", body = ErrorResponse),
+    )
+)]
+async fn list_conversations(
+    State(state): State<AppState>,
+    query: Result<Query<ListConversationsQuery>, QueryRejection>,
+) -> Result<Json<Page<Conversation>>, ApiError> {
+    let Query(query) = query?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT);
+
+    let cursor = match query.cursor.as_deref() {
+        Some(raw) => Some(ConversationCursor::decode(raw).ok_or_else(|| ApiError::bad_request("Invalid cursor"))?),
+        None => None,
+    };
+
+    let page = async {
+        match &cursor {
+            Some(cursor) => {
+                state
+                    .store
+                    .list_conversations_after(query.include_archived, limit, Some(cursor))
+                    .await
+            }
+            None => {
+                let items = state
+                    .store
+                    .list_conversations(query.include_archived, limit, query.offset)
+                    .await?;
+                Ok((items, None))
+            }
+        }
+    };
+
+    let ((items, next), total) = tokio::try_join!(
+        page,
+        state.store.count_conversations(query.include_archived),
+    )
+    .map_err(|e| ApiError::internal("Failed to list conversations", e))?;
+
+    // Offset callers can switch to the cursor from any page
+    let next = match cursor {
+        Some(_) => next,
+        None => ((i64::from(query.offset) + items.len() as i64) < total)
+            .then(|| items.last().map(ConversationCursor::after))
+            .flatten(),
+    };
+
+    Ok(Json(Page {
+        items,
+        total,
+        limit,
+        offset: Some(if cursor.is_some() { 0 } else { query.offset }),
+        next_cursor: next.map(|c| c.encode()),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/conversations/{id}",
+    tag = "conversations",
+    params(("id" = String, Path, description = "Conversation id")),
+    responses(
+        (status = 200, body = Conversation),
+        (status = 404, description = "No such conversation", body = ErrorResponse),
+    )
+)]
+async fn get_conversation<S: ConversationStorage>(
+    State(store): State<Arc<S>>,
+    path: Result<Path<String>, PathRejection>,
+) -> Result<Json<Conversation>, ApiError> {
+    let Path(id) = path?;
+    store
+        .get_conversation(&id)
+        .await
+        .map_err(|e| ApiError::internal(&format!("Failed to load conversation {id}"), e))?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("Conversation {id} not found")))
+}
+
+/// Zip of `conversation.json`, `transcript.txt` and `metadata.json`,
+/// streamed to the client as it is compressed. Messages are read a page
+/// at a time, so huge conversations are never loaded whole.
+#[utoipa::path(
+    get,
+    path = "/api/conversations/{id}/export.zip",
+    tag = "conversations",
+    params(("id" = String, Path, description = "Conversation id")),
+    responses(
+        (status = 200, description = "Zip bundle", content_type = "application/zip", body = Vec<u8>),
+        (status = 404, description = "No such conversation", body = ErrorResponse),
+    )
+)]
+async fn export_conversation_zip(
+    State(state): State<AppState>,
+    path: Result<Path<String>, PathRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Path(id) = path?;
+    let conversation = state
+        .store
+        .get_conversation(&id)
+        .await
+        .map_err(|e| ApiError::internal(&format!("Failed to load conversation {id}"), e))?
+        .ok_or_else(|| ApiError::not_found(format!("Conversation {id} not found")))?;
+
+    let headers = [
+        (header::CONTENT_TYPE, "application/zip".to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", export_filename(&id)),
+        ),
+    ];
+
+    Ok((
+        headers,
+        Body::from_stream(stream_export_zip_paged(state.store.clone(), conversation, EXPORT_PAGE_SIZE)),
+    ))
+}
+
+/// -----------------------------
+/// Messages API
+/// -----------------------------
+#[derive(Debug, Deserialize, ToSchema)]
+struct PostMessageReq {
+    #[serde(default = "default_role", deserialize_with = "MessageRole::deserialize_known")]
+    role: MessageRole,
+    content: String,
+    /// Arbitrary JSON stored alongside the message
+    #[schema(value_type = Option<Object>)]
+    metadata: Option<serde_json::Value>,
+}
+
+fn default_role() -> MessageRole {
+    MessageRole::User
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListMessagesQuery {
+    /// Only messages with this role (`user`, `assistant`)
+    #[serde(default, deserialize_with = "MessageRole::deserialize_known_opt")]
+    role: Option<MessageRole>,
+    /// Only messages created at or after this RFC 3339 timestamp
+    since: Option<DateTime<Utc>>,
+    /// Page size (default 50, at most 200)
+    limit: Option<u32>,
+    /// `next_cursor` from a previous page
+    cursor: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/conversations/{id}/messages",
+    tag = "messages",
+    params(("id" = String, Path, description = "Conversation id"), ListMessagesQuery),
+    responses(
+        (status = 200, description = "One page of messages, oldest first", body = Page<Message>),
+        (status = 400, description = "Invalid filter or cursor", body = ErrorResponse),
+    )
+)]
+async fn list_messages<S: ConversationStorage>(
+    State(store): State<Arc<S>>,
+    path: Result<Path<String>, PathRejection>,
+    query: Result<Query<ListMessagesQuery>, QueryRejection>,
+) -> Result<Json<Page<Message>>, ApiError> {
+    let Path(id) = path?;
+    let Query(query) = query?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT);
+
+    let cursor = match query.cursor.as_deref() {
+        Some(raw) => Some(MessageCursor::decode(raw).ok_or_else(|| ApiError::bad_request("Invalid cursor"))?),
+        None => None,
+    };
+
+    message_page(store.as_ref(), &id, query.role.as_ref(), query.since, limit, cursor.as_ref())
+        .await
+        .map(Json)
+        .map_err(|e| ApiError::internal(&format!("Failed to list messages for {id}"), e))
+}
+
+/// Up to `limit` messages after `cursor`, oldest first, read off the
+/// `(created_at, id)` keyset with `role` and `since` applied in SQL
+async fn message_page<S: ConversationStorage>(
+    store: &S,
+    conversation_id: &str,
+    role: Option<&MessageRole>,
+    since: Option<DateTime<Utc>>,
+    limit: u32,
+    cursor: Option<&MessageCursor>,
+) -> Result<Page<Message>> {
+    // One extra row tells whether another page follows
+    let (mut items, total) = if role.is_none() && since.is_none() {
+        tokio::try_join!(
+            store.get_conversation_messages_after(conversation_id, cursor, limit + 1),
+            store.count_conversation_messages(conversation_id),
+        )?
+    } else {
+        tokio::try_join!(
+            store.get_conversation_messages_filtered(conversation_id, role, since, cursor, limit + 1),
+            store.count_conversation_messages_filtered(conversation_id, role, since),
+        )?
+    };
+
+    let has_more = items.len() > limit as usize;
+    items.truncate(limit as usize);
+    let next_cursor = has_more
+        .then(|| items.last().map(|m| MessageCursor::after(m).encode()))
+        .flatten();
+
+    Ok(Page {
+        items,
+        total,
+        limit,
+        offset: None,
+        next_cursor,
+    })
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PostMessageQuery {
+    /// Also generate (and return) the AI reply. Only for user messages,
+    /// and not once the conversation's number has opted out.
+    #[serde(default)]
+    generate: bool,
+}
+
+/// Whether an AI reply to a `role` message may be generated in
+/// `conversation`: only user messages are answered, and numbers that
+/// opted out get no more AI replies, as over SMS
+async fn check_can_generate<S: ConversationStorage>(
+    store: &S,
+    conversation: &Conversation,
+    role: &MessageRole,
+) -> Result<(), ApiError> {
+    if *role != MessageRole::User {
+        return Err(ApiError::bad_request("Only user messages can be answered with generate=true"));
+    }
+
+    if let Some(number) = conversation.from_number.as_deref() {
+        let opted_out = store
+            .is_opted_out(number)
+            .await
+            .map_err(|e| ApiError::internal("Failed to check opt-out", e))?;
+        if opted_out {
+            return Err(ApiError::bad_request(format!("{number} has opted out")));
+        }
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/conversations/{id}/messages",
+    tag = "messages",
+    params(("id" = String, Path, description = "Conversation id"), PostMessageQuery),
+    request_body = PostMessageReq,
+    responses(
+        (status = 201, description = "The stored message, or the AI reply with `generate=true`", body = Message),
+        (status = 400, description = "Invalid request body, or a reply that can't be generated", body = ErrorResponse),
+        (status = 404, description = "No such conversation", body = ErrorResponse),
+        (status = 429, description = "The conversation's number is out of AI replies for today", body = ErrorResponse),
+        (status = 502, description = "AI reply failed", body = ErrorResponse),
+    )
+)]
+async fn post_message(
+    State(state): State<AppState>,
+    path: Result<Path<String>, PathRejection>,
+    query: Result<Query<PostMessageQuery>, QueryRejection>,
+    req: Result<Json<PostMessageReq>, JsonRejection>,
+) -> Result<(StatusCode, Json<Message>), ApiError> {
+    let Path(id) = path?;
+    let (Query(query), Json(req)) = (query?, req?);
+
+    let conversation = state
+        .store
+        .get_conversation(&id)
+        .await
+        .map_err(|e| ApiError::internal("Failed to load conversation", e))?
+        .ok_or_else(|| ApiError::not_found(format!("Conversation {id} not found")))?;
+
+    if query.generate {
+        check_can_generate(state.store.as_ref(), &conversation, &req.role).await?;
+
+        let (_, reply) = generate_assistant_reply(&state.reply_context(), &id, req.content, req.metadata)
+            .await
+            .map_err(|e| reply_error(&format!("Failed to generate reply in {id}"), e))?;
+
+        return Ok((StatusCode::CREATED, Json(reply)));
+    }
+
+    let message = state
+        .store
+        .store_message_with_metadata(id, req.role, req.content, req.metadata)
+        .await
+        .map_err(|e| ApiError::internal("Failed to store message", e))?;
+
+    Ok((StatusCode::CREATED, Json(message)))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ReadState {
+    last_read_at: DateTime<Utc>,
+    unread_count: i64,
+}
+
+/// Mark the conversation read as of now
+#[utoipa::path(
+    post,
+    path = "/api/conversations/{id}/read",
+    tag = "conversations",
+    params(("id" = String, Path, description = "Conversation id")),
+    responses(
+        (status = 200, body = ReadState),
+        (status = 404, description = "No such conversation", body = ErrorResponse),
+    )
+)]
+async fn mark_conversation_read<S: ConversationStorage>(
+    State(store): State<Arc<S>>,
+    path: Result<Path<String>, PathRejection>,
+) -> Result<Json<ReadState>, ApiError> {
+    let Path(id) = path?;
+    let context = format!("Failed to mark {id} read");
+    let internal = |e| ApiError::internal(&context, e);
+
+    // `NotFound` (a 404) for an unknown conversation
+    let last_read_at = store.clock().now();
+    store.mark_read(&id, last_read_at).await.map_err(internal)?;
+    let unread_count = store.unread_count(&id).await.map_err(internal)?;
+
+    Ok(Json(ReadState {
+        last_read_at,
+        unread_count,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/messages/{id}",
+    tag = "messages",
+    params(("id" = String, Path, description = "Message id")),
+    responses(
+        (status = 200, body = Message),
+        (status = 404, description = "No such message", body = ErrorResponse),
+    )
+)]
+async fn get_message<S: ConversationStorage>(
+    State(store): State<Arc<S>>,
+    path: Result<Path<String>, PathRejection>,
+) -> Result<Json<Message>, ApiError> {
+    let Path(id) = path?;
+    store
+        .get_message(&id)
+        .await
+        .map_err(|e| ApiError::internal(&format!("Failed to load message {id}"), e))?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("Message {id} not found")))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RegenerateQuery {
+    /// Also send the new reply by SMS to the conversation's number
+    #[serde(default)]
+    send: bool,
+}
+
+/// Generate a new AI reply to a stored user message, from the history
+/// before it (e.g. after a prompt change). Earlier replies are kept.
+#[utoipa::path(
+    post,
+    path = "/api/messages/{id}/regenerate",
+    tag = "messages",
+    params(("id" = String, Path, description = "Id of a user message"), RegenerateQuery),
+    responses(
+        (status = 201, description = "The new assistant reply", body = Message),
+        (status = 400, description = "Not a user message, or no number to send to", body = ErrorResponse),
+        (status = 404, description = "No such message", body = ErrorResponse),
+        (status = 429, description = "The number is out of AI replies or SMS for today", body = ErrorResponse),
+        (status = 502, description = "AI reply or SMS send failed", body = ErrorResponse),
+    )
+)]
+async fn regenerate_reply(
+    State(state): State<AppState>,
+    path: Result<Path<String>, PathRejection>,
+    query: Result<Query<RegenerateQuery>, QueryRejection>,
+) -> Result<(StatusCode, Json<Message>), ApiError> {
+    let Path(id) = path?;
+    let Query(query) = query?;
+
+    // Find the recipient up front so a send that can't happen costs no AI call
+    let recipient = match query.send {
+        true => Some(sms_recipient(state.store.as_ref(), &id).await?),
+        false => None,
+    };
+
+    let reply = regenerate_assistant_reply(&state.reply_context(), &id)
+        .await
+        .map_err(|e| reply_error(&format!("Failed to regenerate reply to {id}"), e))?;
+
+    if let Some(to) = recipient {
+        send_regenerated_reply(&state, &reply, &to).await?;
+    }
+
+    Ok((StatusCode::CREATED, Json(reply)))
+}
+
+/// Number of the SMS conversation that message `id` belongs to, as
+/// stored when the conversation was created; renaming it doesn't matter
+async fn sms_recipient<S: ConversationStorage>(store: &S, id: &str) -> Result<PhoneNumber, ApiError> {
+    let internal = |e| ApiError::internal(&format!("Failed to load message {id}"), e);
+
+    let message = store
+        .get_message(id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::not_found(format!("Message {id} not found")))?;
+    let conversation = store
+        .get_conversation(&message.conversation_id)
+        .await
+        .map_err(internal)?;
+
+    let to = conversation
+        .and_then(|c| c.from_number)
+        .map(|number| PhoneNumber::from_stored(&number))
+        .ok_or_else(|| ApiError::bad_request(format!("Conversation {} has no SMS number", message.conversation_id)))?;
+
+    if store.is_opted_out(to.as_str()).await.map_err(internal)? {
+        return Err(ApiError::bad_request(format!("{to} has opted out")));
+    }
+    Ok(to)
+}
+
+async fn send_regenerated_reply(state: &AppState, reply: &Message, to: &PhoneNumber) -> Result<(), ApiError> {
+    let body = state
+        .config
+        .reply_branding()
+        .outbound_text(&state.config.reply_markdown.outbound_text(&reply.content));
+
+    state
+        .guards
+        .claim(state.store.as_ref(), to.as_str(), UsageKind::OutboundSms)
+        .await
+        .map_err(|e| reply_error(&format!("Failed to send regenerated reply {}", reply.id), e))?;
+
+    let sid = match send_audited(&*state.store, &state.signalwire, Some(&reply.conversation_id), to, &body)
+        .await
+        .map_err(|e| ApiError::upstream(&format!("Failed to send regenerated reply {}", reply.id), e))?
+    {
+        SendOutcome::Sent(sid) => sid,
+        SendOutcome::NotAllowed => return Ok(()),
+    };
+
+    let internal = |e| ApiError::internal("Failed to record regenerated reply send", e);
+    state.store.mark_sms_sent(&reply.conversation_id, &reply.id).await.map_err(internal)?;
+    state.store.record_outbound_sent(&reply.id, &sid).await.map_err(internal)?;
+    Ok(())
+}
+
+/// -----------------------------
+/// Stats API
+/// -----------------------------
+/// Totals, messages per role and per-day message counts (last 30 days)
+#[utoipa::path(
+    get,
+    path = "/api/stats",
+    tag = "stats",
+    responses((status = 200, body = AggregateStats))
+)]
+async fn stats(State(state): State<AppState>) -> Result<Json<AggregateStats>, ApiError> {
+    state
+        .store
+        .aggregate_stats(DEFAULT_STATS_DAYS)
+        .await
+        .map(Json)
+        .map_err(|e| ApiError::internal("Failed to compute stats", e))
+}
+
+/// -----------------------------
+/// Audit API
+/// -----------------------------
+const DEFAULT_AUDIT_LIMIT: u32 = 100;
+const MAX_AUDIT_LIMIT: u32 = 500;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct OutboundAuditQuery {
+    /// RFC 3339 time; records from then on are returned
+    since: DateTime<Utc>,
+    /// Most records returned (default 100, at most 500)
+    limit: Option<u32>,
+    /// `next_cursor` from a previous page
+    cursor: Option<String>,
+}
+
+/// Outbound SMS attempts since `since`, oldest first. Rate limited per
+/// `AUDIT_RATE_LIMIT_PER_MIN`.
+#[utoipa::path(
+    get,
+    path = "/api/audit/outbound",
+    tag = "audit",
+    params(OutboundAuditQuery),
+    responses(
+        (status = 200, body = Page<OutboundAudit>),
+        (status = 400, description = "Missing or invalid query", body = ErrorResponse),
+        (status = 429, description = "Rate limit reached", body = ErrorResponse),
+    )
+)]
+async fn outbound_audit(
+    State(state): State<AppState>,
+    query: Result<Query<OutboundAuditQuery>, QueryRejection>,
+) -> Result<Json<Page<OutboundAudit>>, ApiError> {
+    let Query(query) = query?;
+    let limit = query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT).clamp(1, MAX_AUDIT_LIMIT);
+
+    let cursor = match query.cursor.as_deref() {
+        Some(raw) => Some(AuditCursor::decode(raw).ok_or_else(|| ApiError::bad_request("Invalid cursor"))?),
+        None => None,
+    };
+
+    audit_page(state.store.as_ref(), query.since, limit, cursor.as_ref())
+        .await
+        .map(Json)
+        .map_err(|e| ApiError::internal("Failed to read outbound audit", e))
+}
+
+/// Up to `limit` audit records after `cursor`, read off the
+/// `(created_at, id)` keyset
+async fn audit_page<S: ConversationStorage>(
+    store: &S,
+    since: DateTime<Utc>,
+    limit: u32,
+    cursor: Option<&AuditCursor>,
+) -> Result<Page<OutboundAudit>> {
+    // One extra row tells whether another page follows
+    let (mut items, total) = tokio::try_join!(
+        store.outbound_audit_since(since, cursor, limit + 1),
+        store.count_outbound_audit_since(since),
+    )?;
+
+    let has_more = items.len() > limit as usize;
+    items.truncate(limit as usize);
+    let next_cursor = has_more
+        .then(|| items.last().map(|a| AuditCursor::after(a).encode()))
+        .flatten();
+
+    Ok(Page {
+        items,
+        total,
+        limit,
+        offset: None,
+        next_cursor,
+    })
+}
+
+/// -----------------------------
+/// CORS
+/// -----------------------------
+/// CORS for the `/api` routes (the carrier webhooks never need it).
+/// `*` allows any origin; otherwise only the listed ones get the
+/// `Access-Control-Allow-Origin` header. Answers preflight requests.
+fn cors_layer(origins: &[String]) -> CorsLayer {
+    let allow_origin = if origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.iter().filter_map(|o| match o.parse() {
+            Ok(origin) => Some(origin),
+            Err(_) => {
+                warn!("Ignoring invalid CORS origin {o:?}");
+                None
+            }
+        }))
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(Any)
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+}
+
+/// -----------------------------
+/// Broker API
+/// -----------------------------
+async fn broker_stats(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<PartitionStat>>, ApiError> {
+    state
+        .broker
+        .partition_stats()
+        .await
+        .map(Json)
+        .map_err(|e| ApiError::internal("Failed to read partition stats", e))
+}
+
+async fn batcher_stats(State(state): State<AppState>) -> Json<BatcherStats> {
+    Json(state.batcher.stats().await)
+}
+
+/// SignalWire circuit breaker: open means sends are being skipped
+async fn signalwire_circuit(State(state): State<AppState>) -> Json<CircuitStats> {
+    Json(state.signalwire.circuit_stats())
+}
+
+/// What the consumers are configured to do, so operators can check
+/// at-most-once vs at-least-once without reading env files.
+///
+/// The consumers run in their own process, so the settings are read from
+/// the server's environment (the same `CONSUMER_*` variables): they match
+/// the consumers only when both are deployed with the same env. `groups`
+/// comes from Iggy and is always live.
+#[derive(Debug, Serialize)]
+struct BrokerConfigView {
+    delivery_semantics: DeliverySemantics,
+    auto_commit: bool,
+    start_strategy: String,
+    groups: Vec<ConsumerGroupInfo>,
+}
+
+fn broker_config_view(config: &ConsumerConfig, groups: Vec<ConsumerGroupInfo>) -> BrokerConfigView {
+    BrokerConfigView {
+        delivery_semantics: config.delivery_semantics(),
+        auto_commit: config.auto_commit,
+        start_strategy: format!("{:?}", config.start_strategy),
+        groups,
+    }
+}
+
+async fn broker_config(
+    State(state): State<AppState>,
+) -> Result<Json<BrokerConfigView>, ApiError> {
+    let groups = state
+        .broker
+        .consumer_groups()
+        .await
+        .map_err(|e| ApiError::internal("Failed to read consumer groups", e))?;
+
+    Ok(Json(broker_config_view(&state.config.consumer_config(), groups)))
 }
 
 /// -----------------------------
 /// SMS Webhook
 /// -----------------------------
+/// How long the webhook waits on the store to resolve a conversation
+/// before it starts a new one, so a slow Turso doesn't hold up the ack
+const RESOLVE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A reply to a message we know joins that message's conversation, and a
+/// number a human has taken over stays in that conversation (otherwise a
+/// new thread would have AI replies on again); anything else starts a new
+/// one, unless `from` already has `max_per_number` active conversations:
+/// then it joins the most recently updated of them. Conversations are
+/// created by the consumer, so a burst from one number can briefly
+/// overshoot the cap.
+async fn resolve_conversation_id<S: ConversationStorage>(
+    store: &S,
+    from: &PhoneNumber,
+    in_reply_to: Option<&str>,
+    max_per_number: Option<usize>,
+) -> String {
+    let lookup = store.resolve_inbound_conversation(from.as_str(), in_reply_to, max_per_number);
+    match tokio::time::timeout(RESOLVE_TIMEOUT, lookup).await {
+        Ok(Ok(Some(conversation_id))) => return conversation_id,
+        Ok(Ok(None)) => {}
+        Ok(Err(e)) => error!("Failed to resolve the conversation of {from}: {e}"),
+        Err(_) => warn!("Resolving the conversation of {from} timed out, starting a new one"),
+    }
+
+    format!("sms_{}", uuid::Uuid::new_v4())
+}
+
+/// Read as the raw form body (not `Form`) so it can be kept for audit
 async fn sms_webhook(
     State(state): State<AppState>,
-    Form(sms): Form<IncomingSMS>,
+    RawForm(raw): RawForm,
 ) -> Result<StatusCode, StatusCode> {
-    info!("SMS from {} → {}", sms.from, sms.body);
-
-    let msg = SMSMessage {
-         id: uuid::Uuid::new_v4().to_string(),
-        from: sms.from.clone(),
-        to: sms.to,
-        body: sms.body,
-        timestamp: Utc::now().timestamp(),
-        conversation_id: format!("sms_{}", uuid::Uuid::new_v4()),
+    // Taken before any store round trip, so latency counts from arrival
+    let received_at = state.store.clock().now();
+
+    // Before anything can reject it: malformed and filtered payloads are
+    // the ones worth debugging
+    let raw_webhook_id = match state.config.raw_webhook_audit {
+        true => keep_raw_webhook(state.store.as_ref(), &String::from_utf8_lossy(&raw)).await,
+        false => None,
     };
 
-    state
-        .broker
-        .publish_sms(msg)
+    let sms: IncomingSMS = serde_urlencoded::from_bytes(&raw).map_err(|e| {
+        warn!("Malformed SMS webhook: {e}");
+        StatusCode::UNPROCESSABLE_ENTITY
+    })?;
+
+    info!(
+        "SMS from {} → {} | sid={:?} account={:?} segments={:?} status={:?}",
+        sms.from, sms.body, sms.message_sid, sms.account_sid, sms.num_segments, sms.sms_status
+    );
+
+    let replay_key = state.replay_guard.as_deref().zip(sms.message_sid.clone());
+    if let Some((guard, sid)) = &replay_key {
+        if is_replay(guard, sid) {
+            // Acknowledged so the carrier stops retrying, but not enqueued again
+            return Ok(StatusCode::OK);
+        }
+    }
+
+    let result = enqueue_inbound(
+        &state.inbound_filter,
+        state.config.normalize_bodies,
+        state.config.max_conversations_per_number,
+        state.store.as_ref(),
+        &state.batcher,
+        sms,
+        Receipt { received_at, raw_webhook_id },
+    )
+    .await;
+
+    // The carrier retries failed webhooks with the same SID
+    if let (Err(_), Some((guard, sid))) = (&result, &replay_key) {
+        guard.forget(sid);
+    }
+    result
+}
+
+/// Whether this `MessageSid` was already received within the replay window
+fn is_replay(guard: &ReplayGuard, sid: &str) -> bool {
+    let replay = !guard.check(sid, std::time::Instant::now());
+    if replay {
+        warn!("Ignoring replayed SMS webhook (sid={sid})");
+    }
+    replay
+}
+
+/// Keep the raw webhook body for auditing; its id, or `None` if that
+/// failed, which is never worth failing the webhook over
+async fn keep_raw_webhook<S: ConversationStorage>(store: &S, payload: &str) -> Option<i64> {
+    store
+        .store_raw_webhook(payload)
         .await
-        .map_err(|e| {
-            error!("Failed to publish SMS: {e}");
+        .inspect_err(|e| error!("Failed to store raw webhook: {e}"))
+        .ok()
+}
+
+/// How an inbound webhook arrived
+#[derive(Debug, Clone, Copy)]
+struct Receipt {
+    /// When the request came in, to the millisecond
+    received_at: DateTime<Utc>,
+    /// Raw payload row, when it was kept for auditing
+    raw_webhook_id: Option<i64>,
+}
+
+/// Buffer one inbound SMS for publishing. Messages the filter blocks, and
+/// bodies that normalize to nothing, are acknowledged (so the carrier
+/// doesn't retry) but never enqueued.
+/// The receipt's raw webhook, when given, is linked to the resolved conversation.
+async fn enqueue_inbound<S: ConversationStorage, P: SmsPublisher + 'static>(
+    filter: &InboundFilter,
+    normalize: bool,
+    max_per_number: Option<usize>,
+    store: &S,
+    batcher: &MessageBatcher<P>,
+    sms: IncomingSMS,
+    receipt: Receipt,
+) -> Result<StatusCode, StatusCode> {
+    let (from, sid) = (sms.from.clone(), sms.message_sid.clone());
+
+    // Malformed numbers are rejected here, before they reach the pipeline
+    let msg = match sms.into_sms_message(store, normalize, max_per_number, receipt.received_at).await {
+        Ok(msg) => msg,
+        Err(InboundError::BlankBody) => {
+            info!("Ignoring blank inbound SMS from {from} (sid={sid:?})");
+            return Ok(StatusCode::OK);
+        }
+        Err(e) => {
+            warn!("Inbound SMS rejected: {e}");
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    // Audit only; never worth failing the webhook over
+    if let Some(id) = receipt.raw_webhook_id {
+        if let Err(e) = store.set_raw_webhook_conversation(id, &msg.conversation_id).await {
+            error!("Failed to link raw webhook {id}: {e}");
+        }
+    }
+
+    if let Some(pattern) = filter.blocked_by(&msg.body) {
+        warn!("Inbound SMS from {from} blocked by pattern {pattern:?} (sid={sid:?})");
+        return Ok(StatusCode::OK);
+    }
+
+    match batcher.add_message(msg).await {
+        Ok(AddOutcome::Queued) => Ok(StatusCode::OK),
+        Ok(AddOutcome::Dropped) => {
+            error!("Inbound SMS dropped: batcher buffer full");
+            Ok(StatusCode::OK)
+        }
+        // Carrier retries on 503
+        Err(e) => {
+            error!("Failed to enqueue SMS: {e}");
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+/// -----------------------------
+/// Status Webhook
+/// -----------------------------
+async fn sms_status_webhook(
+    State(state): State<AppState>,
+    Form(status): Form<DeliveryStatus>,
+) -> StatusCode {
+    info!(
+        "Delivery status {} → {} (error={:?})",
+        status.message_sid, status.message_status, status.error_code
+    );
+
+    match state
+        .store
+        .update_delivery_status(&status.message_sid, &status.message_status)
+        .await
+    {
+        Ok(matched) => {
+            if !matched {
+                // Not one of our replies (or sent before tracking existed)
+                info!("No tracked reply for {}", status.message_sid);
+            }
+            StatusCode::OK
+        }
+        // Carrier retries on 5xx
+        Err(e) => {
+            error!("Failed to record delivery status: {e}");
             StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        }
+    }
+}
+
+/// -----------------------------
+/// Retention
+/// -----------------------------
+async fn run_purge_loop(store: Arc<ConversationStore>, max_age_days: u64, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
 
-    Ok(StatusCode::OK)
+        let cutoff = store.clock().now() - chrono::Duration::days(max_age_days as i64);
+        match store.purge_conversations_older_than(cutoff).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Purged {removed} conversations idle since {cutoff}"),
+            Err(e) => error!("Conversation purge failed: {e}"),
+        }
+    }
+}
+
+async fn run_raw_webhook_purge_loop(store: Arc<ConversationStore>, max_age_days: u64, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        let cutoff = store.clock().now() - chrono::Duration::days(max_age_days as i64);
+        match store.purge_raw_webhooks_older_than(cutoff).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Purged {removed} raw webhooks received before {cutoff}"),
+            Err(e) => error!("Raw webhook purge failed: {e}"),
+        }
+    }
+}
+
+/// First run after one full `interval`, not at startup
+async fn run_maintenance_loop(store: Arc<ConversationStore>, vacuum: bool, interval: Duration) {
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+
+    loop {
+        ticker.tick().await;
+
+        match store.maintenance(vacuum).await {
+            Ok(()) => info!("Database maintenance done (vacuum={vacuum})"),
+            Err(e) => error!("Database maintenance failed: {e}"),
+        }
+    }
+}
+
+/// -----------------------------
+/// Scheduled SMS
+/// -----------------------------
+async fn run_scheduled_sms_loop(
+    store: Arc<ConversationStore>,
+    signalwire: Arc<SignalWireClient>,
+    caps: UsageCaps,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        match send_due_scheduled(store.as_ref(), &signalwire, &caps, store.clock().now()).await {
+            Ok(0) => {}
+            Ok(sent) => info!("Sent {sent} scheduled messages"),
+            Err(e) => error!("Scheduled SMS poll failed: {e}"),
+        }
+    }
 }
 
 /// -----------------------------
@@ -85,6 +1548,50 @@ async fn main() -> Result<()> {
 
     info!("Starting SMS Server");
 
+    // -----------------------------
+    // TURSO
+    // -----------------------------
+    let mut store =
+        ConversationStore::new(config.turso_db_url.clone(), config.turso_auth_token.clone())
+            .with_slow_query_threshold(Duration::from_millis(config.turso_slow_query_ms))
+            .with_content_limit(config.turso_max_content_bytes, config.turso_content_overflow);
+
+    if let Some(read_url) = &config.turso_read_url {
+        store = store.with_read_url(read_url.clone());
+        info!("✓ Reads served from Turso replica");
+    }
+
+    if let Some(path) = &config.ca_cert_path {
+        store = store.with_root_certificate(load_root_certificate(path)?)?;
+        info!("✓ Trusting extra CA certificate");
+    }
+
+    let store = Arc::new(store);
+    store.initialize().await?;
+    info!("✓ Turso initialized");
+
+    // -----------------------------
+    // AI (for `?generate=true`)
+    // -----------------------------
+    let mut ai = AIService::new(config.groq_model.clone(), config.groq_api_key.clone())
+        .with_embedding_model(config.embedding_model.clone());
+
+    if let Some(path) = &config.ca_cert_path {
+        ai = ai.with_root_certificate(load_root_certificate(path)?)?;
+    }
+
+    if let Some(fallback) = &config.ai_fallback {
+        info!("✓ AI fallback provider: {} ({})", fallback.api_url, fallback.model);
+        ai = ai.with_fallback_provider(fallback.clone());
+    }
+
+    if let Some(template) = &config.ai_prompt_template {
+        info!("✓ AI prompt template enabled");
+        ai = ai.with_prompt_template(template.clone());
+    }
+
+    let ai = Arc::new(ai);
+
     // -----------------------------
     // IGGY
     // -----------------------------
@@ -105,21 +1612,822 @@ async fn main() -> Result<()> {
 
     info!("✓ MessageBroker ready");
 
+    let batcher = Arc::new(MessageBatcher::new(
+        broker.clone(),
+        BatcherConfig {
+            max_batch_size: config.batch_max_size,
+            flush_interval: Duration::from_millis(config.batch_flush_ms),
+            max_buffer_len: config.batch_max_buffer,
+            overflow_policy: config.batch_overflow_policy,
+            adaptive: config.batch_adaptive.then(|| AdaptiveLimits {
+                max_batch_size: config.batch_adaptive_max_size,
+                max_flush_interval: Duration::from_millis(config.batch_adaptive_max_flush_ms),
+            }),
+        },
+    ));
+    tokio::spawn(batcher.clone().run_flush_loop());
+
+    let inbound_filter = Arc::new(InboundFilter::new(config.inbound_blocked_patterns.clone()));
+    if !inbound_filter.is_empty() {
+        info!("✓ Inbound filter enabled");
+    }
+
+    let replay_guard = (config.webhook_replay_window_secs > 0).then(|| {
+        info!("✓ Ignoring webhook SIDs seen in the last {}s", config.webhook_replay_window_secs);
+        Arc::new(ReplayGuard::new(
+            Duration::from_secs(config.webhook_replay_window_secs),
+            config.webhook_replay_cache_size,
+        ))
+    });
+
+    // Legacy rows stored before numbers were normalized; in the background
+    // so a large table doesn't hold up startup
+    tokio::spawn({
+        let store = store.clone();
+        async move {
+            match merge_split_sms_conversations(store.as_ref()).await {
+                Ok(0) => {}
+                Ok(merged) => info!("✓ Merged {merged} conversations split by number format"),
+                Err(e) => error!("Failed to merge split conversations: {e}"),
+            }
+        }
+    });
+
+    if config.raw_webhook_audit {
+        info!("✓ Keeping raw inbound webhooks for {} days", config.raw_webhook_retention_days);
+        tokio::spawn(run_raw_webhook_purge_loop(
+            store.clone(),
+            config.raw_webhook_retention_days,
+            Duration::from_secs(config.purge_interval_secs),
+        ));
+    }
+
+    if let Some(days) = config.purge_max_age_days {
+        info!("✓ Purging conversations idle for {days}+ days");
+        tokio::spawn(run_purge_loop(
+            store.clone(),
+            days,
+            Duration::from_secs(config.purge_interval_secs),
+        ));
+    }
+
+    if let Some(secs) = config.maintenance_interval_secs {
+        info!("✓ Database maintenance every {secs}s (vacuum={})", config.maintenance_vacuum);
+        tokio::spawn(run_maintenance_loop(
+            store.clone(),
+            config.maintenance_vacuum,
+            Duration::from_secs(secs),
+        ));
+    }
+
+    let signalwire = Arc::new(config.signalwire_client());
+
+    if config.scheduled_sms_poll_secs > 0 {
+        info!("✓ Sending scheduled SMS (polling every {}s)", config.scheduled_sms_poll_secs);
+        tokio::spawn(run_scheduled_sms_loop(
+            store.clone(),
+            signalwire.clone(),
+            config.usage_caps(),
+            Duration::from_secs(config.scheduled_sms_poll_secs),
+        ));
+    }
+
     // -----------------------------
     // HTTP SERVER
     // -----------------------------
+    let mut webhooks = Router::new()
+        .route("/sms/webhook", post(sms_webhook))
+        .route("/sms/status", post(sms_status_webhook));
+
+    if config.webhook_signature_validation {
+        info!("✓ Webhook signatures checked");
+        webhooks = webhooks.route_layer(middleware::from_fn_with_state(
+            WebhookSigner::new(&config.signalwire_auth_token, config.public_base_url.as_deref()),
+            verify_webhook_signature,
+        ));
+    } else {
+        warn!("Webhook signatures are NOT checked (WEBHOOK_SIGNATURE_VALIDATION=false)");
+    }
+    if config.public_base_url.is_none() {
+        warn!("PUBLIC_BASE_URL unset: sends carry no StatusCallback, so delivery updates rely on the number's own callback");
+    }
+
+    if config.webhook_max_in_flight > 0 {
+        info!("✓ At most {} webhooks in flight", config.webhook_max_in_flight);
+        webhooks = webhooks.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(config.webhook_max_in_flight),
+            limit_concurrency,
+        ));
+    }
+
     let app = Router::new()
         .route("/", get(health))
         .route("/health", get(health))
-        .route("/sms/webhook", post(sms_webhook))
+        .route("/health/ready", get(ready))
+        .merge(webhooks);
+
+    let mut audit = get(outbound_audit);
+    if config.audit_rate_limit_per_min > 0 {
+        info!("✓ Outbound audit limited to {} requests per minute", config.audit_rate_limit_per_min);
+        audit = audit.route_layer(middleware::from_fn_with_state(
+            RateLimit::per_minute(config.audit_rate_limit_per_min),
+            limit_rate,
+        ));
+    }
+
+    let mut api = Router::new()
+        .route(
+            "/api/conversations",
+            get(list_conversations).post(create_conversation),
+        )
+        .route("/api/conversations/{id}", get(get_conversation::<ConversationStore>))
+        .route(
+            "/api/conversations/{id}/export.zip",
+            get(export_conversation_zip),
+        )
+        .route(
+            "/api/conversations/{id}/messages",
+            get(list_messages::<ConversationStore>).post(post_message),
+        )
+        .route("/api/conversations/{id}/read", post(mark_conversation_read::<ConversationStore>))
+        .route("/api/conversations/{id}/context", put(set_conversation_context))
+        .route("/api/conversations/{id}/ai", post(set_conversation_ai_enabled))
+        .route("/api/conversations/{id}/ai-settings", put(set_conversation_ai_settings))
+        .route("/api/conversations/{id}/sender-label", put(set_conversation_sender_label))
+        .route("/api/messages/{id}", get(get_message::<ConversationStore>))
+        .route("/api/messages/{id}/regenerate", post(regenerate_reply))
+        .route("/api/stats", get(stats))
+        .route("/api/audit/outbound", audit)
+        .route("/api/broker/stats", get(broker_stats))
+        .route("/api/broker/config", get(broker_config))
+        .route("/api/batcher/stats", get(batcher_stats))
+        .route("/api/signalwire/circuit", get(signalwire_circuit))
+        .merge(SwaggerUi::new("/api/docs").url("/api/openapi.json", ApiDoc::openapi()));
+
+    if let Some(origins) = &config.allowed_origins {
+        info!("✓ CORS enabled for {:?}", origins);
+        api = api.layer(cors_layer(origins));
+    }
+
+    let app = app
+        .merge(api)
+        .layer(middleware::from_fn_with_state(
+            ApiLogConfig {
+                verbosity: config.api_log_verbosity,
+                redact: config.api_log_redact,
+            },
+            log_api_requests,
+        ))
         .layer(TraceLayer::new_for_http())
-        .with_state(AppState { broker });
+        .with_state(AppState {
+            broker,
+            batcher: batcher.clone(),
+            inbound_filter,
+            replay_guard,
+            store,
+            ai,
+            signalwire,
+            guards: ReplyGuards::new(config.ai_max_in_flight, config.usage_caps()),
+            config: config.clone(),
+        });
 
     let addr = format!("0.0.0.0:{}", config.port);
     info!("Listening on {addr}");
 
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    // Webhooks have stopped; publish whatever is still buffered
+    batcher.shutdown().await?;
+    info!("Shut down");
 
     Ok(())
 }
+
+/// Ctrl-C, or SIGTERM from the orchestrator
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("Shutdown signal received, draining");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::FromRequest, http::Request};
+    use conversation_store::consumers::default_sms_title;
+
+    #[tokio::test]
+    async fn test_full_signalwire_webhook_deserializes() {
+        let body = "MessageSid=SM123abc&SmsSid=SM123abc&AccountSid=AC456def\
+            &From=%2B15551234567&To=%2B15557654321&Body=Hello+there%21\
+            &NumMedia=0&NumSegments=2&SmsStatus=received&ApiVersion=2010-04-01";
+
+        let request = Request::builder()
+            .method("POST")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap();
+
+        let Form(sms) = Form::<IncomingSMS>::from_request(request, &())
+            .await
+            .unwrap();
+
+        assert_eq!(sms.from, "+15551234567");
+        assert_eq!(sms.to, "+15557654321");
+        assert_eq!(sms.body, "Hello there!");
+        assert_eq!(sms.message_sid.as_deref(), Some("SM123abc"));
+        assert_eq!(sms.account_sid.as_deref(), Some("AC456def"));
+        assert_eq!(sms.num_segments, Some(2));
+        assert_eq!(sms.sms_status.as_deref(), Some("received"));
+    }
+
+    struct NullPublisher;
+
+    impl SmsPublisher for NullPublisher {
+        async fn publish_sms_batch(&self, _messages: Vec<SMSMessage>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Keeps every published SMS for inspection
+    #[derive(Default)]
+    struct RecordingPublisher(std::sync::Mutex<Vec<SMSMessage>>);
+
+    impl SmsPublisher for RecordingPublisher {
+        async fn publish_sms_batch(&self, messages: Vec<SMSMessage>) -> Result<()> {
+            self.0.lock().unwrap().extend(messages);
+            Ok(())
+        }
+    }
+
+    fn incoming(body: &str) -> IncomingSMS {
+        IncomingSMS {
+            from: "+15551234567".into(),
+            to: "+15557654321".into(),
+            body: body.into(),
+            message_sid: None,
+            account_sid: None,
+            num_segments: None,
+            sms_status: None,
+            in_reply_to: None,
+        }
+    }
+
+    fn receipt(raw_webhook_id: Option<i64>) -> Receipt {
+        Receipt { received_at: Utc::now(), raw_webhook_id }
+    }
+
+    #[tokio::test]
+    async fn test_incoming_sms_converts_with_validation() {
+        let store = conversation_store::InMemoryStore::new();
+        let at = DateTime::parse_from_rfc3339("2026-03-01T12:00:00Z").unwrap().with_timezone(&Utc);
+
+        let mut sms = incoming("  Hi\r\nthere  ");
+        sms.message_sid = Some("SM1".into());
+        let msg = sms.into_sms_message(&store, true, None, at).await.unwrap();
+        assert_eq!(msg.from.as_str(), "+15551234567");
+        assert_eq!(msg.to.as_str(), "+15557654321");
+        assert_eq!(msg.body, "Hi\nthere");
+        assert_eq!(msg.timestamp, at.timestamp());
+        assert_eq!(msg.received_at, Some(at));
+        assert_eq!(msg.provider_sid.as_deref(), Some("SM1"));
+        assert!(msg.conversation_id.starts_with("sms_"));
+        assert!(!msg.id.is_empty());
+
+        // Each message gets its own id and conversation
+        let other = incoming("Hi").into_sms_message(&store, true, None, at).await.unwrap();
+        assert_ne!(other.id, msg.id);
+        assert_ne!(other.conversation_id, msg.conversation_id);
+
+        // Unnormalized bodies pass through verbatim, even blank ones
+        let raw = incoming(" \t ").into_sms_message(&store, false, None, at).await.unwrap();
+        assert_eq!(raw.body, " \t ");
+
+        let err = incoming(" \r\n ").into_sms_message(&store, true, None, at).await.unwrap_err();
+        assert_eq!(err, InboundError::BlankBody);
+
+        let mut bad = incoming("Hi");
+        bad.from = "not a number".into();
+        let err = bad.into_sms_message(&store, true, None, at).await.unwrap_err();
+        assert!(matches!(err, InboundError::InvalidNumber(_)), "{err}");
+    }
+
+    #[test]
+    fn test_replayed_sid_is_detected() {
+        let guard = ReplayGuard::new(Duration::from_secs(300), 100);
+
+        assert!(!is_replay(&guard, "SM1"));
+        assert!(is_replay(&guard, "SM1"));
+        assert!(!is_replay(&guard, "SM2"));
+
+        // A failed webhook's retry is let through
+        guard.forget("SM2");
+        assert!(!is_replay(&guard, "SM2"));
+    }
+
+    #[tokio::test]
+    async fn test_blocked_inbound_sms_is_not_enqueued() {
+        let filter = InboundFilter::new(vec!["free bitcoin".into()]);
+        let store = conversation_store::InMemoryStore::new();
+        let batcher = MessageBatcher::new(Arc::new(NullPublisher), BatcherConfig::default());
+
+        let status = enqueue_inbound(&filter, true, None, &store, &batcher, incoming("FREE BITCOIN, click here"), receipt(None))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(batcher.stats().await.buffered, 0);
+
+        enqueue_inbound(&filter, true, None, &store, &batcher, incoming("Where is my order?"), receipt(None))
+            .await
+            .unwrap();
+        assert_eq!(batcher.stats().await.buffered, 1);
+    }
+
+    #[tokio::test]
+    async fn test_inbound_bodies_are_normalized_and_blank_ones_ignored() {
+        let filter = InboundFilter::default();
+        let store = conversation_store::InMemoryStore::new();
+        let batcher = MessageBatcher::new(Arc::new(NullPublisher), BatcherConfig::default());
+
+        let status = enqueue_inbound(&filter, true, None, &store, &batcher, incoming(" \r\n\t\t\r\n"), receipt(None))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(batcher.stats().await.buffered, 0);
+
+        enqueue_inbound(&filter, true, None, &store, &batcher, incoming("Hi\r\n\r\n\r\nthere\t\t!  "), receipt(None))
+            .await
+            .unwrap();
+        assert_eq!(batcher.stats().await.buffered, 1);
+
+        // Opted out: sent on as received
+        enqueue_inbound(&filter, false, None, &store, &batcher, incoming(" \t "), receipt(None))
+            .await
+            .unwrap();
+        assert_eq!(batcher.stats().await.buffered, 2);
+    }
+
+    #[tokio::test]
+    async fn test_raw_payload_is_kept_only_when_auditing() {
+        let filter = InboundFilter::default();
+        let store = conversation_store::InMemoryStore::new();
+        let batcher = MessageBatcher::new(Arc::new(NullPublisher), BatcherConfig::default());
+        let raw = "From=%2B15551234567&To=%2B15557654321&Body=hi";
+
+        enqueue_inbound(&filter, true, None, &store, &batcher, incoming("hi"), receipt(None))
+            .await
+            .unwrap();
+        assert!(store.raw_webhooks().is_empty());
+
+        let id = keep_raw_webhook(&store, raw).await;
+        enqueue_inbound(&filter, true, None, &store, &batcher, incoming("hi"), receipt(id))
+            .await
+            .unwrap();
+        let kept = store.raw_webhooks();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].0, raw);
+        // Linked once the conversation is known
+        assert!(kept[0].1.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_raw_payload_is_kept_for_rejected_and_filtered_messages() {
+        let filter = InboundFilter::new(vec!["free money".to_string()]);
+        let store = conversation_store::InMemoryStore::new();
+        let batcher = MessageBatcher::new(Arc::new(NullPublisher), BatcherConfig::default());
+
+        let mut invalid = incoming("hi");
+        invalid.from = "not a number!".into();
+        let id = keep_raw_webhook(&store, "From=not+a+number%21&Body=hi").await;
+        let status = enqueue_inbound(&filter, true, None, &store, &batcher, invalid, receipt(id)).await;
+        assert_eq!(status, Err(StatusCode::BAD_REQUEST));
+
+        let id = keep_raw_webhook(&store, "Body=FREE+MONEY").await;
+        let status = enqueue_inbound(&filter, true, None, &store, &batcher, incoming("FREE MONEY"), receipt(id)).await;
+        assert_eq!(status, Ok(StatusCode::OK));
+
+        let kept = store.raw_webhooks();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0], ("From=not+a+number%21&Body=hi".to_string(), None));
+        assert_eq!(kept[1].0, "Body=FREE+MONEY");
+        assert_eq!(batcher.stats().await.buffered, 0);
+    }
+
+    #[tokio::test]
+    async fn test_cors_allows_only_listed_origins() {
+        let app: Router = Router::new()
+            .route("/api/ping", get(|| async { "ok" }))
+            .layer(cors_layer(&["https://dash.example.com".into()]));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/ping", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let allow_origin = |resp: &reqwest::Response| {
+            resp.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        let resp = client.get(&url).header(header::ORIGIN, "https://dash.example.com").send().await.unwrap();
+        assert_eq!(allow_origin(&resp).as_deref(), Some("https://dash.example.com"));
+
+        let resp = client.get(&url).header(header::ORIGIN, "https://evil.example.com").send().await.unwrap();
+        assert_eq!(allow_origin(&resp), None);
+
+        let preflight = client
+            .request(reqwest::Method::OPTIONS, &url)
+            .header(header::ORIGIN, "https://dash.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .send()
+            .await
+            .unwrap();
+        assert!(preflight.status().is_success());
+        assert_eq!(allow_origin(&preflight).as_deref(), Some("https://dash.example.com"));
+    }
+
+    #[test]
+    fn test_openapi_spec_is_valid_and_lists_api_paths() {
+        let json = ApiDoc::openapi().to_json().unwrap();
+        let spec: utoipa::openapi::OpenApi = serde_json::from_str(&json).unwrap();
+
+        let paths: Vec<&str> = spec.paths.paths.keys().map(String::as_str).collect();
+        for expected in [
+            "/health",
+            "/health/ready",
+            "/api/conversations",
+            "/api/conversations/{id}",
+            "/api/conversations/{id}/messages",
+            "/api/conversations/{id}/read",
+            "/api/conversations/{id}/ai-settings",
+            "/api/conversations/{id}/sender-label",
+            "/api/messages/{id}",
+            "/api/audit/outbound",
+        ] {
+            assert!(paths.contains(&expected), "missing {expected} in {paths:?}");
+        }
+
+        let schemas = &spec.components.as_ref().unwrap().schemas;
+        assert!(schemas.contains_key("Conversation"));
+        assert!(schemas.contains_key("Message"));
+        assert!(json.contains("\"openapi\":\"3."));
+    }
+
+    #[test]
+    fn test_ai_settings_are_validated() {
+        assert!(check_ai_settings(None, None).is_ok());
+        assert!(check_ai_settings(Some("llama-3.1-8b-instant"), Some(0.0)).is_ok());
+        assert!(check_ai_settings(None, Some(2.0)).is_ok());
+
+        for (model, temperature) in [(None, Some(2.5)), (None, Some(-0.1)), (None, Some(f32::NAN)), (Some(" "), None)] {
+            let err = check_ai_settings(model, temperature).unwrap_err();
+            assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn test_sender_labels_are_validated() {
+        assert!(check_sender_label(None).is_ok());
+        assert!(check_sender_label(Some("Agent Bot")).is_ok());
+
+        for label in ["", "  ", &"x".repeat(MAX_SENDER_LABEL_CHARS + 1)] {
+            let err = check_sender_label(Some(label)).unwrap_err();
+            assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn test_oversized_context_is_rejected() {
+        assert!(check_context(None).is_ok());
+        assert!(check_context(Some(&serde_json::json!({"name": "Ada", "plan": "pro"}))).is_ok());
+
+        let huge = serde_json::json!({"notes": "x".repeat(MAX_CONTEXT_BYTES)});
+        let err = check_context(Some(&huge)).unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_broker_config_reflects_delivery_semantics() {
+        let groups = vec![ConsumerGroupInfo {
+            group_id: 1,
+            name: "sms-ai-consumer-group".into(),
+            members: Vec::new(),
+        }];
+
+        let view = broker_config_view(&ConsumerConfig::default(), groups.clone());
+        let json = serde_json::to_value(&view).unwrap();
+        assert_eq!(json["delivery_semantics"], "at_least_once");
+        assert_eq!(json["auto_commit"], false);
+        assert_eq!(json["groups"][0]["name"], "sms-ai-consumer-group");
+
+        let auto = ConsumerConfig {
+            auto_commit: true,
+            ..Default::default()
+        };
+        let json = serde_json::to_value(broker_config_view(&auto, groups)).unwrap();
+        assert_eq!(json["delivery_semantics"], "at_most_once");
+    }
+
+    #[tokio::test]
+    async fn test_in_reply_to_joins_referenced_conversation() {
+        let store = conversation_store::InMemoryStore::new();
+        store
+            .store_message_with_provider_sid(
+                "conv-original".into(),
+                MessageRole::Assistant,
+                "How can I help?".into(),
+                Some("SM_reply".into()),
+            )
+            .await
+            .unwrap();
+
+        let body = "MessageSid=SM_in&From=%2B15551234567&To=%2B15557654321\
+            &Body=Thanks&InReplyTo=SM_reply";
+        let request = Request::builder()
+            .method("POST")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap();
+        let Form(sms) = Form::<IncomingSMS>::from_request(request, &())
+            .await
+            .unwrap();
+
+        let from = PhoneNumber::parse(&sms.from).unwrap();
+        let conversation_id = resolve_conversation_id(&store, &from, sms.in_reply_to.as_deref(), None).await;
+        assert_eq!(conversation_id, "conv-original");
+
+        // Unknown or missing references start a new conversation
+        let fresh = resolve_conversation_id(&store, &from, Some("SM_unknown"), None).await;
+        assert!(fresh.starts_with("sms_"));
+        assert!(resolve_conversation_id(&store, &from, None, None).await.starts_with("sms_"));
+    }
+
+    #[tokio::test]
+    async fn test_taken_over_number_stays_in_the_human_conversation() {
+        let filter = InboundFilter::default();
+        let store = conversation_store::InMemoryStore::new();
+        let publisher = Arc::new(RecordingPublisher::default());
+        let batcher = MessageBatcher::new(publisher.clone(), BatcherConfig::default());
+
+        enqueue_inbound(&filter, true, None, &store, &batcher, incoming("I need a person"), receipt(None))
+            .await
+            .unwrap();
+        batcher.flush().await.unwrap();
+        let first = publisher.0.lock().unwrap()[0].conversation_id.clone();
+
+        // The consumer creates the conversation, then an agent takes it over
+        store
+            .ensure_sms_conversation(&first, &default_sms_title("+15551234567"), "+15551234567")
+            .await
+            .unwrap();
+        store.set_conversation_ai_enabled(&first, false).await.unwrap();
+
+        enqueue_inbound(&filter, true, None, &store, &batcher, incoming("Still there?"), receipt(None))
+            .await
+            .unwrap();
+        batcher.flush().await.unwrap();
+        assert_eq!(publisher.0.lock().unwrap()[1].conversation_id, first);
+
+        // Handed back to the AI: new messages start new conversations again
+        store.set_conversation_ai_enabled(&first, true).await.unwrap();
+        enqueue_inbound(&filter, true, None, &store, &batcher, incoming("Thanks"), receipt(None))
+            .await
+            .unwrap();
+        batcher.flush().await.unwrap();
+        assert_ne!(publisher.0.lock().unwrap()[2].conversation_id, first);
+    }
+
+    #[tokio::test]
+    async fn test_numbers_at_the_cap_reuse_their_latest_conversation() {
+        let store = conversation_store::InMemoryStore::new();
+        let from = PhoneNumber::parse("+15551234567").unwrap();
+        let title = default_sms_title(from.as_str());
+
+        store.ensure_sms_conversation("sms_old", &title, from.as_str()).await.unwrap();
+        // Under the cap: a new conversation
+        let fresh = resolve_conversation_id(&store, &from, None, Some(2)).await;
+        assert!(fresh.starts_with("sms_") && fresh != "sms_old");
+
+        store.ensure_sms_conversation("sms_new", &title, from.as_str()).await.unwrap();
+        // Renamed conversations still count
+        store.update_conversation_title("sms_new", "Billing question").await.unwrap();
+        let reused = resolve_conversation_id(&store, &from, None, Some(2)).await;
+        assert_eq!(reused, "sms_new");
+        assert_eq!(store.count_conversations(true).await.unwrap(), 2);
+
+        // Archived conversations don't count toward the cap
+        store.set_conversation_archived("sms_new", true).await.unwrap();
+        let fresh = resolve_conversation_id(&store, &from, None, Some(2)).await;
+        assert!(fresh != "sms_old" && fresh != "sms_new");
+
+        // Nor do other numbers' conversations
+        let other = PhoneNumber::parse("+15559990000").unwrap();
+        assert_ne!(resolve_conversation_id(&store, &other, None, Some(1)).await, "sms_old");
+    }
+
+    #[tokio::test]
+    async fn test_a_hung_store_does_not_hold_up_resolution() {
+        // Accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+        let store = ConversationStore::new(url, "token".into());
+        let from = PhoneNumber::parse("+15551234567").unwrap();
+
+        let started = std::time::Instant::now();
+        let conversation_id = resolve_conversation_id(&store, &from, Some("SM_reply"), Some(1)).await;
+        assert!(conversation_id.starts_with("sms_"));
+        assert!(started.elapsed() < RESOLVE_TIMEOUT * 2, "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_regenerated_replies_go_to_the_stored_number() {
+        let store = conversation_store::InMemoryStore::new();
+        let from = PhoneNumber::parse("+15551234567").unwrap();
+
+        store.ensure_sms_conversation("sms_1", &default_sms_title(from.as_str()), from.as_str()).await.unwrap();
+        store.update_conversation_title("sms_1", "Billing question").await.unwrap();
+        let question = store.store_message("sms_1".into(), MessageRole::User, "Hi".into()).await.unwrap();
+        assert_eq!(sms_recipient(&store, &question.id).await.unwrap(), from);
+
+        // API-only conversations have nowhere to send to
+        let api = store.create_conversation(Some("SMS: +15559990000".into()), None).await.unwrap();
+        let message = store.store_message(api.id, MessageRole::User, "Hi".into()).await.unwrap();
+        let err = sms_recipient(&store, &message.id).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+        store.set_opted_out(from.as_str()).await.unwrap();
+        let err = sms_recipient(&store, &question.id).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_generation_is_refused_for_non_user_messages_and_opted_out_numbers() {
+        let store = conversation_store::InMemoryStore::new();
+        store.ensure_sms_conversation("sms_1", "SMS: +15551234567", "+15551234567").await.unwrap();
+        let conversation = store.get_conversation("sms_1").await.unwrap().unwrap();
+
+        assert!(check_can_generate(&store, &conversation, &MessageRole::User).await.is_ok());
+        let err = check_can_generate(&store, &conversation, &MessageRole::Assistant).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+        store.set_opted_out("+15551234567").await.unwrap();
+        let err = check_can_generate(&store, &conversation, &MessageRole::User).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+        // API conversations have no number to opt out
+        let api = store.create_conversation(None, None).await.unwrap();
+        assert!(check_can_generate(&store, &api, &MessageRole::User).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_long_thread_is_paged_completely() {
+        let store = conversation_store::InMemoryStore::new();
+        let at = Utc::now();
+        let messages: Vec<Message> = (0..100)
+            .map(|i| Message {
+                id: format!("m{i:03}"),
+                // Pairs share a timestamp, so the id has to break ties
+                created_at: at + chrono::Duration::seconds(i / 2),
+                ..Message::new("conv-long".into(), MessageRole::User, format!("#{i}"))
+            })
+            .collect();
+        store.store_messages_batch(messages).await.unwrap();
+
+        let mut seen = Vec::new();
+        let mut cursor: Option<MessageCursor> = None;
+        let mut pages = 0;
+        loop {
+            let page = message_page(&store, "conv-long", None, None, 25, cursor.as_ref()).await.unwrap();
+            assert_eq!(page.total, 100);
+            assert_eq!(page.items.len(), 25);
+            seen.extend(page.items.into_iter().map(|m| m.id));
+            pages += 1;
+
+            let Some(next) = page.next_cursor else { break };
+            cursor = Some(MessageCursor::decode(&next).unwrap());
+        }
+
+        assert_eq!(pages, 4);
+        let expected: Vec<String> = (0..100).map(|i| format!("m{i:03}")).collect();
+        assert_eq!(seen, expected);
+
+        // Filtered listings page the same way
+        let first = message_page(&store, "conv-long", Some(&MessageRole::User), None, 60, None).await.unwrap();
+        let cursor = MessageCursor::decode(first.next_cursor.as_deref().unwrap()).unwrap();
+        let rest = message_page(&store, "conv-long", Some(&MessageRole::User), None, 60, Some(&cursor)).await.unwrap();
+        assert_eq!((first.items.len(), rest.items.len()), (60, 40));
+        assert_eq!(rest.total, 100);
+        assert!(rest.next_cursor.is_none());
+
+        // Keyset pages carry no offset
+        let envelope = serde_json::to_value(&rest).unwrap();
+        assert!(envelope.get("offset").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handler_errors_are_json_error_responses() {
+        type Store = conversation_store::InMemoryStore;
+        let store = Arc::new(Store::new());
+        let conversation = store.create_conversation(None, None).await.unwrap();
+
+        let app: Router = Router::new()
+            .route("/api/conversations/{id}", get(get_conversation::<Store>))
+            .route("/api/conversations/{id}/messages", get(list_messages::<Store>))
+            .route("/api/conversations/{id}/read", post(mark_conversation_read::<Store>))
+            .route("/api/messages/{id}", get(get_message::<Store>))
+            .with_state(store);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let error = |path: String| {
+            let request = client.get(format!("{url}{path}"));
+            async move {
+                let resp = request.send().await.unwrap();
+                let status = resp.status().as_u16();
+                let body: serde_json::Value = resp.json().await.unwrap();
+                (status, body["error"]["code"].as_str().unwrap().to_string())
+            }
+        };
+
+        let resp = client.get(format!("{url}/api/conversations/{}", conversation.id)).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        assert_eq!(error("/api/conversations/missing".into()).await, (404, "not_found".into()));
+        assert_eq!(error("/api/messages/missing".into()).await, (404, "not_found".into()));
+
+        let read = |id: &str| client.post(format!("{url}/api/conversations/{id}/read")).send();
+        assert_eq!(read(&conversation.id).await.unwrap().status(), reqwest::StatusCode::OK);
+        let resp = read("missing").await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "not_found");
+        // Path rejections come back in the same envelope as handler errors
+        assert_eq!(error("/api/conversations/%FF".into()).await, (400, "invalid_request".into()));
+        assert_eq!(
+            error(format!("/api/conversations/{}/messages?cursor=zz", conversation.id)).await,
+            (400, "invalid_request".into())
+        );
+        assert_eq!(
+            error(format!("/api/conversations/{}/messages?role=foo", conversation.id)).await,
+            (400, "invalid_request".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_posted_message_with_unknown_role_is_rejected() {
+        let post = |body: &'static str| {
+            Request::post("/api/conversations/c1/messages")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let Json(req) = Json::<PostMessageReq>::from_request(post(r#"{"content":"hi"}"#), &()).await.unwrap();
+        assert_eq!(req.role, MessageRole::User);
+
+        let rejection = Json::<PostMessageReq>::from_request(post(r#"{"role":"foo","content":"hi"}"#), &())
+            .await
+            .unwrap_err();
+        let resp = ApiError::from(rejection).into_response();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "invalid_request");
+        assert!(body["error"]["message"].as_str().unwrap().contains("unknown role `foo`"));
+    }
+}
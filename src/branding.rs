@@ -0,0 +1,77 @@
+/// -----------------------------
+/// Reply Branding
+/// -----------------------------
+/// Text wrapped around every assistant reply, e.g. `[Acme]` before it and
+/// `Reply STOP to opt out.` on its own line after it. Branding is part of
+/// what is sent, so it counts toward the message's segments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplyBranding {
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+    /// Store the branded text instead of the reply as generated
+    pub store_branded: bool,
+}
+
+impl ReplyBranding {
+    pub fn is_empty(&self) -> bool {
+        self.prefix.is_none() && self.suffix.is_none()
+    }
+
+    /// `<prefix> <reply>\n<suffix>`, leaving out whichever part is unset
+    pub fn apply(&self, reply: &str) -> String {
+        let mut branded = String::with_capacity(reply.len());
+
+        if let Some(prefix) = &self.prefix {
+            branded.push_str(prefix);
+            branded.push(' ');
+        }
+        branded.push_str(reply);
+        if let Some(suffix) = &self.suffix {
+            branded.push('\n');
+            branded.push_str(suffix);
+        }
+
+        branded
+    }
+
+    /// What to store for a freshly generated reply
+    pub fn stored_text(&self, reply: String) -> String {
+        if self.store_branded {
+            self.apply(&reply)
+        } else {
+            reply
+        }
+    }
+
+    /// What to send for a stored reply (branded once, whichever the mode)
+    pub fn outbound_text(&self, stored: &str) -> String {
+        if self.store_branded {
+            stored.to_string()
+        } else {
+            self.apply(stored)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_and_suffix_are_optional() {
+        let branding = ReplyBranding {
+            prefix: Some("[Acme]".into()),
+            suffix: Some("Reply STOP to opt out.".into()),
+            store_branded: false,
+        };
+        assert_eq!(branding.apply("Hi"), "[Acme] Hi\nReply STOP to opt out.");
+
+        let suffix_only = ReplyBranding {
+            prefix: None,
+            ..branding
+        };
+        assert_eq!(suffix_only.apply("Hi"), "Hi\nReply STOP to opt out.");
+        assert_eq!(ReplyBranding::default().apply("Hi"), "Hi");
+        assert!(ReplyBranding::default().is_empty());
+    }
+}
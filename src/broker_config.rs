@@ -1,3 +1,10 @@
+/// Consumer group that stores inbound SMS in Turso
+pub const TURSO_CONSUMER_GROUP: &str = "sms-turso-consumer-group";
+/// Consumer group that generates and sends AI replies
+pub const AI_CONSUMER_GROUP: &str = "sms-ai-consumer-group";
+/// Every group reading the SMS topic, for broker stats
+pub const CONSUMER_GROUPS: [&str; 2] = [TURSO_CONSUMER_GROUP, AI_CONSUMER_GROUP];
+
 #[derive(Clone)]
 pub struct BrokerConfig {
     pub stream: &'static str,
@@ -0,0 +1,106 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+/// Seconds a rejected client is asked to wait before retrying
+const RETRY_AFTER_SECS: &str = "1";
+
+/// -----------------------------
+/// Concurrency Limit
+/// -----------------------------
+/// Caps the requests in flight through the routes it wraps. Over the cap
+/// a request is answered `503` with `Retry-After` straight away instead of
+/// queueing, so a burst of carrier deliveries is redelivered later rather
+/// than piling up in memory.
+///
+/// Use with `axum::middleware::from_fn_with_state(limit, limit_concurrency)`.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimit {
+    permits: Arc<Semaphore>,
+}
+
+impl ConcurrencyLimit {
+    /// At most `max` requests at once (at least 1)
+    pub fn new(max: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max.max(1))),
+        }
+    }
+
+    /// Requests that could start right now
+    pub fn available(&self) -> usize {
+        self.permits.available_permits()
+    }
+}
+
+pub async fn limit_concurrency(
+    State(limit): State<ConcurrencyLimit>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Ok(_permit) = limit.permits.try_acquire_owned() else {
+        warn!("Rejecting {} {}: too many requests in flight", request.method(), request.uri().path());
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, RETRY_AFTER_SECS)],
+        )
+            .into_response();
+    };
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::post, Router};
+    use tokio::sync::watch;
+
+    #[tokio::test]
+    async fn test_requests_over_the_limit_get_503() {
+        let (release, released) = watch::channel(false);
+        let limit = ConcurrencyLimit::new(2);
+
+        let router = Router::new()
+            .route(
+                "/sms/webhook",
+                post(move || {
+                    let mut released = released.clone();
+                    async move {
+                        released.wait_for(|r| *r).await.unwrap();
+                        StatusCode::OK
+                    }
+                }),
+            )
+            .route_layer(middleware::from_fn_with_state(limit.clone(), limit_concurrency));
+        let url = crate::test_support::serve(router).await;
+        let client = reqwest::Client::new();
+
+        let in_flight: Vec<_> = (0..2)
+            .map(|_| tokio::spawn(client.post(format!("{url}/sms/webhook")).send()))
+            .collect();
+        while limit.available() > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let rejected = client.post(format!("{url}/sms/webhook")).send().await.unwrap();
+        assert_eq!(rejected.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(rejected.headers()["retry-after"], "1");
+
+        release.send(true).unwrap();
+        for request in in_flight {
+            assert_eq!(request.await.unwrap().unwrap().status(), reqwest::StatusCode::OK);
+        }
+
+        // Capacity is back once they finish
+        let accepted = client.post(format!("{url}/sms/webhook")).send().await.unwrap();
+        assert_eq!(accepted.status(), reqwest::StatusCode::OK);
+        assert_eq!(limit.available(), 2);
+    }
+}
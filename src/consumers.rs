@@ -1,212 +1,2633 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
 use futures_util::StreamExt;
 use iggy::clients::client::IggyClient;
 use iggy::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
-use tracing::{error, info};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Semaphore, SemaphorePermit};
+use tracing::{debug, error, info, warn};
 
-use crate::{ConversationStore, MessageRole};
-use crate::ai_service::{AIMessage, AIService};
-use crate::message_broker::SMSMessage;
-use crate::signalwire::SignalWireClient;
+use crate::{Conversation, ConversationStorage, ConversationStore, Message, MessageRole};
+use crate::ai_service::{AIError, AIMessage, AIService, GenerationConfig, DEFAULT_SYSTEM_PROMPT};
+use crate::message_broker::{header_value, SMSMessage, HEADER_CONVERSATION_ID};
+use crate::dead_letter::{decode_or_dead_letter, validated_sms, IggyDeadLetterQueue};
+use crate::messages::{canned, CannedKey, Locale};
+use crate::branding::ReplyBranding;
+use crate::markdown::MarkdownPolicy;
+use crate::events::{noop_sink, EventSink};
+use crate::segments::segment_count;
+use crate::outbound_audit::send_audited;
+use crate::usage_caps::{claim_usage, record_usage, usage_date, UsageCaps, UsageKind};
+use crate::broker_config::{AI_CONSUMER_GROUP, TURSO_CONSUMER_GROUP};
+use crate::storage::NotFound;
+use crate::normalize::normalize_body;
+use crate::phone_number::normalize_number;
+use crate::signalwire::{
+    SendOutcome, SignalWireClient, SignalWireError, ERROR_UNSUBSCRIBED,
+};
 
 /// =============================
 /// CONSTANTS
 /// =============================
 const STREAM_NAME: &str = "sms_stream";
 const TOPIC_NAME: &str = "sms_incoming";
+const HISTORY_WINDOW: usize = 10;
+/// First sleep after an empty poll
+const IDLE_BACKOFF_MIN: Duration = Duration::from_millis(25);
+/// How long a consumer stream is driven before the poll counts as empty;
+/// about one round of Iggy's own `poll_interval`
+const EMPTY_POLL_WINDOW: Duration = Duration::from_millis(100);
+/// Default ceiling for `IdleBackoff`
+pub const DEFAULT_IDLE_BACKOFF_MAX: Duration = Duration::from_secs(1);
+/// Default cap on concurrent AI completions
+pub const DEFAULT_MAX_IN_FLIGHT_AI: usize = 4;
+/// Default wait for the next polled message before checking on Iggy
+pub const DEFAULT_POLL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Default messages per poll for each partition of a topic
+pub const DEFAULT_POLL_PER_PARTITION: u32 = 10;
+/// Default polling errors in a row before a consumer gives up
+pub const DEFAULT_MAX_CONSECUTIVE_ERRORS: u32 = 10;
+/// Sleep after a polling error before polling again
+const POLL_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Carrier statuses after which no further callback is expected
+pub fn is_final_delivery_status(status: &str) -> bool {
+    matches!(status, "delivered" | "undelivered" | "failed")
+}
+
+/// Conversation persona, or `default` when none is set
+pub fn resolve_system_prompt<'a>(conversation: Option<&'a Conversation>, default: &'a str) -> &'a str {
+    conversation
+        .and_then(|c| c.system_prompt.as_deref())
+        .unwrap_or(default)
+}
+
+/// System prompt sent to the AI: the persona, plus the conversation's
+/// customer context when it has one. The context only ever travels here,
+/// so it never shows up in the transcript.
+pub fn render_system_prompt(conversation: Option<&Conversation>, default: &str) -> String {
+    let prompt = resolve_system_prompt(conversation, default);
+
+    match conversation.and_then(|c| c.context.as_ref()) {
+        Some(context) => format!("{prompt}\n\nCustomer context (JSON): {context}"),
+        None => prompt.to_string(),
+    }
+}
+
+/// Conversation's label for assistant replies, or `default`
+pub fn resolve_sender_label(conversation: Option<&Conversation>, default: Option<&str>) -> Option<String> {
+    conversation
+        .and_then(|c| c.sender_label.as_deref())
+        .or(default)
+        .map(str::to_string)
+}
+
+/// The conversation's pinned model / temperature, if any
+pub fn generation_config(conversation: Option<&Conversation>) -> GenerationConfig {
+    GenerationConfig {
+        model: conversation.and_then(|c| c.ai_model.clone()),
+        temperature: conversation.and_then(|c| c.ai_temperature),
+    }
+}
+
+/// Build the AI context: persona first, then the most recent turns.
+/// Messages with unknown roles are left out.
+pub fn build_ai_history(system_prompt: &str, messages: Vec<Message>) -> Vec<AIMessage> {
+    let messages: Vec<Message> = messages
+        .into_iter()
+        .filter(|m| !matches!(m.role, MessageRole::Unknown(_)))
+        .collect();
+    let skip = messages.len().saturating_sub(HISTORY_WINDOW);
+
+    std::iter::once(AIMessage::system(system_prompt))
+        .chain(messages.into_iter().skip(skip).map(|m| AIMessage {
+            role: m.role.as_str().to_string(),
+            content: m.content,
+        }))
+        .collect()
+}
+
+/// -----------------------------
+/// Reply Guards
+/// -----------------------------
+/// Limits every AI reply goes through, whether it answers an SMS or an
+/// API call: a bound on concurrent AI completions and the per-number
+/// daily caps. Clones share the permits.
+#[derive(Debug, Clone)]
+pub struct ReplyGuards {
+    ai_permits: Arc<Semaphore>,
+    usage_caps: UsageCaps,
+}
+
+impl Default for ReplyGuards {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_IN_FLIGHT_AI, UsageCaps::default())
+    }
+}
+
+impl ReplyGuards {
+    /// At most `max_in_flight_ai` (at least 1) AI completions at once
+    pub fn new(max_in_flight_ai: usize, usage_caps: UsageCaps) -> Self {
+        Self {
+            ai_permits: Arc::new(Semaphore::new(max_in_flight_ai.max(1))),
+            usage_caps,
+        }
+    }
+
+    pub fn usage_caps(&self) -> &UsageCaps {
+        &self.usage_caps
+    }
+
+    /// Wait for a free AI slot; hold the permit for the call
+    pub async fn ai_permit(&self) -> Result<SemaphorePermit<'_>> {
+        Ok(self.ai_permits.acquire().await?)
+    }
+
+    /// Count one `kind` for `number`, or fail with `UsageCapReached` if
+    /// today's cap is used up
+    pub async fn claim<S: ConversationStorage>(&self, store: &S, number: &str, kind: UsageKind) -> Result<()> {
+        if claim_usage(store, &self.usage_caps, number, kind).await {
+            Ok(())
+        } else {
+            Err(UsageCapReached(number.to_string()).into())
+        }
+    }
+}
+
+/// A number's daily cap is used up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageCapReached(pub String);
+
+impl fmt::Display for UsageCapReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} has reached today's usage cap", self.0)
+    }
+}
+
+impl std::error::Error for UsageCapReached {}
+
+/// What an API-driven reply needs: the AI, the guards it shares with SMS
+/// replies, and the persona, label and history defaults SMS replies use.
+/// `history_max_chars` cuts each history message as
+/// `AIConsumer::with_history_max_chars` does.
+pub struct ReplyContext<'a, S> {
+    pub store: &'a S,
+    pub ai: &'a AIService,
+    pub guards: &'a ReplyGuards,
+    pub default_system_prompt: &'a str,
+    pub default_sender_label: Option<&'a str>,
+    pub history_max_chars: Option<usize>,
+    /// Store replies through `normalize_body`, as they'll be sent
+    pub normalize_bodies: bool,
+}
+
+impl<S: ConversationStorage> ReplyContext<'_, S> {
+    /// One AI completion for `conversation`, within the guards. Counted
+    /// against the conversation's SMS number, if it has one.
+    async fn complete(&self, conversation: Option<&Conversation>, content: &str, history: &[AIMessage]) -> Result<String> {
+        if let Some(number) = conversation.and_then(|c| c.from_number.as_deref()) {
+            self.guards.claim(self.store, number, UsageKind::AiCompletion).await?;
+        }
+
+        let reply = {
+            let _permit = self.guards.ai_permit().await?;
+            self.ai
+                .generate_response(content, history, &generation_config(conversation))
+                .await?
+        };
+        Ok(if self.normalize_bodies { normalize_body(&reply) } else { reply })
+    }
+}
+
+/// One API-driven chat turn: store `content` (with `metadata`) as a user
+/// message, then generate, store and return the assistant reply. Uses the
+/// same persona and history window as SMS replies.
+pub async fn generate_assistant_reply<S: ConversationStorage>(
+    ctx: &ReplyContext<'_, S>,
+    conversation_id: &str,
+    content: String,
+    metadata: Option<serde_json::Value>,
+) -> Result<(Message, Message)> {
+    let store = ctx.store;
+    let conversation = store.get_conversation(conversation_id).await?;
+    let system_prompt = render_system_prompt(conversation.as_ref(), ctx.default_system_prompt);
+
+    // History without the new message; `generate_response` appends it
+    let history = build_ai_history(
+        &system_prompt,
+        store
+            .get_recent_messages(conversation_id, HISTORY_WINDOW as u32, ctx.history_max_chars)
+            .await?,
+    );
+
+    let message = store
+        .store_message_with_metadata(conversation_id.to_string(), MessageRole::User, content, metadata)
+        .await?;
+
+    let reply = ctx.complete(conversation.as_ref(), &message.content, &history).await?;
+
+    let reply = Message::new(conversation_id.to_string(), MessageRole::Assistant, reply)
+        .with_sender_label(resolve_sender_label(conversation.as_ref(), ctx.default_sender_label));
+    let reply = store.insert_message(reply).await?;
+
+    Ok((message, reply))
+}
+
+/// Only user messages can have their reply regenerated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotAUserMessage(pub String);
+
+impl fmt::Display for NotAUserMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Message {} is not a user message", self.0)
+    }
+}
+
+impl std::error::Error for NotAUserMessage {}
+
+/// Generate a fresh reply to the stored user message `message_id`, from
+/// the history that preceded it, and store it as a new assistant message.
+/// Earlier replies are kept. Fails with `NotFound` for an unknown id,
+/// `NotAUserMessage` for anything but a user message and `UsageCapReached`
+/// when the conversation's number is out of AI replies for today.
+pub async fn regenerate_assistant_reply<S: ConversationStorage>(
+    ctx: &ReplyContext<'_, S>,
+    message_id: &str,
+) -> Result<Message> {
+    let store = ctx.store;
+    let message = store
+        .get_message(message_id)
+        .await?
+        .ok_or_else(|| NotFound::message(message_id))?;
+    if !message.is_from_user() {
+        return Err(NotAUserMessage(message.id).into());
+    }
+
+    let conversation = store.get_conversation(&message.conversation_id).await?;
+    let system_prompt = render_system_prompt(conversation.as_ref(), ctx.default_system_prompt);
+
+    let mut messages = store.get_conversation_messages(&message.conversation_id).await?;
+    let position = messages.iter().position(|m| m.id == message.id).unwrap_or(messages.len());
+    messages.truncate(position);
+    let history = build_ai_history(&system_prompt, messages);
+
+    let reply = ctx.complete(conversation.as_ref(), &message.content, &history).await?;
+
+    let reply = Message::new(message.conversation_id, MessageRole::Assistant, reply)
+        .with_sender_label(resolve_sender_label(conversation.as_ref(), ctx.default_sender_label));
+    store.insert_message(reply).await
+}
+
+/// When the webhook for `sms` arrived: `received_at` (milliseconds) when
+/// set, else the whole-second `timestamp`, else now if that is missing
+/// (0) or out of range
+pub fn sms_received_at(sms: &SMSMessage) -> DateTime<Utc> {
+    sms.received_at
+        .or_else(|| (sms.timestamp > 0).then(|| DateTime::from_timestamp(sms.timestamp, 0)).flatten())
+        .unwrap_or_else(Utc::now)
+}
+
+/// Id of the assistant reply to an inbound SMS. Deterministic, so a
+/// retried message finds the reply (and send record) of the first attempt.
+pub fn reply_message_id(inbound_id: &str) -> String {
+    format!("reply_{inbound_id}")
+}
+
+/// AI failures worth redelivering the message for: network errors, rate
+/// limits and server errors. A rejected request or an empty completion
+/// would fail the same way again, so those get the canned fallback.
+fn is_transient_ai_error(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<AIError>() {
+        Some(AIError::EmptyResponse) => false,
+        Some(err) => err.is_retryable(),
+        None => true,
+    }
+}
+
+/// Title given to conversations created from inbound SMS
+pub fn default_sms_title(from: &str) -> String {
+    format!("SMS: {}", from)
+}
+
+/// The number named by a `default_sms_title`, if `title` is one
+pub fn sms_title_number(title: &str) -> Option<&str> {
+    title.strip_prefix("SMS: ")
+}
+
+/// `(from_id, into_id)` pairs for SMS conversations split only by how the
+/// number was written: each conversation stored under a non-canonical form
+/// (a legacy `15551234567` row) pairs with the oldest conversation of the
+/// same number in canonical form. Conversations already in canonical form
+/// are separate threads (archived, or past the per-number cap) and are
+/// never merged with each other.
+pub fn duplicate_sms_conversations(conversations: &[Conversation]) -> Vec<(String, String)> {
+    let mut canonical: BTreeMap<String, &Conversation> = BTreeMap::new();
+    let mut legacy = Vec::new();
+
+    for conversation in conversations {
+        let number = conversation
+            .from_number
+            .as_deref()
+            .or_else(|| conversation.title.as_deref().and_then(sms_title_number));
+        let Some(number) = number else { continue };
+
+        let normalized = normalize_number(number);
+        if normalized == number {
+            let oldest = canonical.entry(normalized).or_insert(conversation);
+            if conversation.created_at < oldest.created_at {
+                *oldest = conversation;
+            }
+        } else {
+            legacy.push((normalized, conversation));
+        }
+    }
+
+    legacy
+        .into_iter()
+        .filter_map(|(number, conversation)| {
+            canonical
+                .get(&number)
+                .map(|into| (conversation.id.clone(), into.id.clone()))
+        })
+        .collect()
+}
+
+/// Merge every split `duplicate_sms_conversations` finds, archived
+/// conversations included. Returns how many were merged.
+pub async fn merge_split_sms_conversations<S: ConversationStorage>(store: &S) -> Result<usize> {
+    let mut conversations = Vec::new();
+    let mut cursor = None;
+    loop {
+        let (page, next) = store.list_conversations_after(true, 500, cursor.as_ref()).await?;
+        conversations.extend(page);
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    let pairs = duplicate_sms_conversations(&conversations);
+    for (from_id, into_id) in &pairs {
+        store.merge_conversations(from_id, into_id).await?;
+        info!("Merged conversation {from_id} into {into_id}");
+    }
+    Ok(pairs.len())
+}
+
+/// -----------------------------
+/// Start Strategy
+/// -----------------------------
+/// Where a consumer starts reading. Anything other than `Next` replays
+/// messages, e.g. to reprocess after a bug.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StartStrategy {
+    /// Resume after the group's committed offset
+    #[default]
+    Next,
+    /// From the start of each partition
+    First,
+    /// From this offset in each partition
+    Offset(u64),
+    /// From the first message at or after this Unix time (seconds)
+    Timestamp(i64),
+}
+
+impl StartStrategy {
+    pub fn polling_strategy(self) -> PollingStrategy {
+        match self {
+            StartStrategy::Next => PollingStrategy::next(),
+            StartStrategy::First => PollingStrategy::first(),
+            StartStrategy::Offset(offset) => PollingStrategy::offset(offset),
+            StartStrategy::Timestamp(secs) => {
+                // Iggy timestamps are microseconds
+                let micros = (secs.max(0) as u64).saturating_mul(1_000_000);
+                PollingStrategy::timestamp(IggyTimestamp::from(micros))
+            }
+        }
+    }
+}
+
+/// Accepts `next`, `first`, `offset:<n>` or `timestamp:<unix secs>`
+impl FromStr for StartStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+
+        match s.split_once(':') {
+            None if s == "next" => Ok(StartStrategy::Next),
+            None if s == "first" => Ok(StartStrategy::First),
+            Some(("offset", v)) => Ok(StartStrategy::Offset(v.trim().parse()?)),
+            Some(("timestamp", v)) => Ok(StartStrategy::Timestamp(v.trim().parse()?)),
+            _ => anyhow::bail!("Unknown start strategy: {s}"),
+        }
+    }
+}
+
+/// -----------------------------
+/// Delivery Semantics
+/// -----------------------------
+/// What a crash between polling and processing costs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliverySemantics {
+    /// Offsets are committed when polled: a crash loses the in-flight message
+    AtMostOnce,
+    /// Offsets are committed after processing: a crash replays it
+    AtLeastOnce,
+}
+
+/// -----------------------------
+/// Topic Targets
+/// -----------------------------
+/// One `(stream, topic)` a consumer reads from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicTarget {
+    pub stream: String,
+    pub topic: String,
+}
+
+/// The inbound SMS topic
+impl Default for TopicTarget {
+    fn default() -> Self {
+        Self {
+            stream: STREAM_NAME.to_string(),
+            topic: TOPIC_NAME.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TopicTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.stream, self.topic)
+    }
+}
+
+/// Accepts `<stream>/<topic>`
+impl FromStr for TopicTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().split_once('/') {
+            Some((stream, topic)) if !stream.trim().is_empty() && !topic.trim().is_empty() => Ok(Self {
+                stream: stream.trim().to_string(),
+                topic: topic.trim().to_string(),
+            }),
+            _ => anyhow::bail!("Expected <stream>/<topic>, got: {s}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsumerConfig {
+    pub start_strategy: StartStrategy,
+    /// Topics to consume, polled in turn (the SMS topic by default)
+    pub topics: Vec<TopicTarget>,
+    /// Longest sleep between empty polls
+    pub idle_backoff_max: Duration,
+    /// Let Iggy commit offsets as messages are polled instead of after
+    /// they are processed
+    pub auto_commit: bool,
+    /// Longest wait for the next message before the loop checks that Iggy
+    /// still answers and polls again
+    pub poll_timeout: Duration,
+    /// Messages per poll for each partition of the topic
+    pub poll_per_partition: u32,
+    /// Fixed messages per poll, ignoring the partition count
+    pub poll_batch_length: Option<u32>,
+    /// Polling errors in a row after which the consumer stops with an
+    /// error; 0 keeps retrying forever
+    pub max_consecutive_errors: u32,
+}
+
+impl Default for ConsumerConfig {
+    fn default() -> Self {
+        Self {
+            start_strategy: StartStrategy::default(),
+            topics: vec![TopicTarget::default()],
+            idle_backoff_max: DEFAULT_IDLE_BACKOFF_MAX,
+            auto_commit: false,
+            poll_timeout: DEFAULT_POLL_TIMEOUT,
+            poll_per_partition: DEFAULT_POLL_PER_PARTITION,
+            poll_batch_length: None,
+            max_consecutive_errors: DEFAULT_MAX_CONSECUTIVE_ERRORS,
+        }
+    }
+}
+
+impl ConsumerConfig {
+    pub fn delivery_semantics(&self) -> DeliverySemantics {
+        if self.auto_commit {
+            DeliverySemantics::AtMostOnce
+        } else {
+            DeliverySemantics::AtLeastOnce
+        }
+    }
+
+    /// Messages fetched per poll of a topic with `partitions` partitions:
+    /// the fixed length if set, else `poll_per_partition` for each one, so
+    /// throughput grows with parallelism. Always at least 1.
+    pub fn poll_batch_length(&self, partitions: u32) -> u32 {
+        self.poll_batch_length
+            .unwrap_or_else(|| self.poll_per_partition.saturating_mul(partitions.max(1)))
+            .max(1)
+    }
+
+    fn iggy_auto_commit(&self) -> AutoCommit {
+        if self.auto_commit {
+            AutoCommit::When(AutoCommitWhen::PollingMessages)
+        } else {
+            AutoCommit::Disabled // 🔒 manual commit
+        }
+    }
+}
+
+/// -----------------------------
+/// Idle Backoff
+/// -----------------------------
+/// Sleep between polls that grows on consecutive empty results and snaps
+/// back once something arrives: responsive under load, quiet when idle.
+///
+/// Paces the consumer loops (see `next_or_reconnect`) and the loops we
+/// poll ourselves (delivery confirmations).
+#[derive(Debug, Clone)]
+pub struct IdleBackoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl IdleBackoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        let max = max.max(min);
+        Self { min, max, current: min }
+    }
+
+    /// The poll came back empty: how long to sleep before the next one
+    pub fn on_empty(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    /// The poll returned something; start small again
+    pub fn on_messages(&mut self) {
+        self.current = self.min;
+    }
+}
+
+/// -----------------------------
+/// Error Threshold
+/// -----------------------------
+/// Counts polling errors in a row, stalls (polls that time out while Iggy
+/// doesn't answer) included. Once there are `max` of them the consumer
+/// returns the last one, so its task exits and the orchestrator restarts
+/// the pod, rather than the consumer looking up while it makes no
+/// progress. Any successful poll resets the count; `max` 0 never gives
+/// up. (Errors processing a message already stop the consumer.)
+#[derive(Debug, Clone)]
+pub struct ErrorThreshold {
+    max: u32,
+    consecutive: u32,
+}
+
+impl ErrorThreshold {
+    pub fn new(max: u32) -> Self {
+        Self { max, consecutive: 0 }
+    }
+
+    /// Errors since the last success
+    pub fn consecutive(&self) -> u32 {
+        self.consecutive
+    }
+
+    pub fn on_success(&mut self) {
+        self.consecutive = 0;
+    }
+
+    /// Count a failed poll by the `consumer` consumer; `Err` once it is
+    /// the `max`th in a row
+    pub fn on_error(&mut self, consumer: &str, e: impl fmt::Display) -> Result<()> {
+        self.consecutive += 1;
+
+        if self.max > 0 && self.consecutive >= self.max {
+            anyhow::bail!("{consumer} consumer stopping after {} polling errors in a row: {e}", self.consecutive);
+        }
+
+        error!("{consumer} polling error ({} in a row): {e}", self.consecutive);
+        Ok(())
+    }
+}
+
+/// -----------------------------
+/// Poll Timeout
+/// -----------------------------
+/// Result of waiting for the next item of a consumer stream
+#[derive(Debug, PartialEq, Eq)]
+pub enum PollOutcome<T> {
+    Item(T),
+    /// The stream finished; the consumer loop should stop
+    Ended,
+    /// Nothing arrived within the timeout
+    TimedOut,
+}
+
+/// Next item of `stream`, or `TimedOut` after `timeout`. The stream is left
+/// intact, so the caller can simply poll again.
+pub async fn next_with_timeout<S>(stream: &mut S, timeout: Duration) -> PollOutcome<S::Item>
+where
+    S: futures_util::Stream + Unpin,
+{
+    match tokio::time::timeout(timeout, stream.next()).await {
+        Ok(Some(item)) => PollOutcome::Item(item),
+        Ok(None) => PollOutcome::Ended,
+        Err(_) => PollOutcome::TimedOut,
+    }
+}
+
+/// Next item of a consumer stream, `None` once it ends.
+///
+/// The stream is driven for `EMPTY_POLL_WINDOW` at a time; a window
+/// without messages is an empty poll, after which the loop sleeps for
+/// `idle` (growing while the topic stays quiet) without driving the
+/// stream, so no polls reach Iggy meanwhile. A message resets `idle`.
+///
+/// A topic that stays quiet for `timeout` may also mean Iggy is gone
+/// (its consumer stream only yields messages), so `ping` before calling
+/// it a stall: an idle topic is just polled again. A stall counts toward
+/// `errors`, so a server that stays away stops the `consumer`; meanwhile
+/// the stream, which may never wake up again, is replaced by one from
+/// `reconnect`, which resumes from the committed offsets.
+pub async fn next_or_reconnect<S, P, R>(
+    stream: &mut S,
+    timeout: Duration,
+    idle: &mut IdleBackoff,
+    errors: &mut ErrorThreshold,
+    consumer: &str,
+    ping: impl Fn() -> P,
+    reconnect: impl Fn() -> R,
+) -> Result<Option<S::Item>>
+where
+    S: futures_util::Stream + Unpin,
+    P: Future<Output = Result<()>>,
+    R: Future<Output = Result<S>>,
+{
+    let mut quiet_since = Instant::now();
+
+    loop {
+        match next_with_timeout(stream, EMPTY_POLL_WINDOW.min(timeout)).await {
+            PollOutcome::Item(item) => {
+                idle.on_messages();
+                return Ok(Some(item));
+            }
+            PollOutcome::Ended => return Ok(None),
+            PollOutcome::TimedOut => {}
+        }
+
+        if quiet_since.elapsed() < timeout {
+            tokio::time::sleep(idle.on_empty()).await;
+            continue;
+        }
+        quiet_since = Instant::now();
+
+        let stall = match tokio::time::timeout(timeout, ping()).await {
+            Ok(Ok(())) => {
+                debug!("{consumer} consumer: no messages in {timeout:?}, Iggy is up");
+                continue;
+            }
+            Ok(Err(e)) => format!("no messages in {timeout:?} and Iggy ping failed: {e}"),
+            Err(_) => format!("Iggy stalled (no ping answer in {timeout:?})"),
+        };
+        errors.on_error(consumer, stall)?;
+
+        match reconnect().await {
+            Ok(fresh) => {
+                *stream = fresh;
+                info!("{consumer} consumer re-initialized");
+            }
+            Err(e) => errors.on_error(consumer, format!("failed to re-initialize: {e}"))?,
+        }
+    }
+}
+
+/// `IggyClient::ping` for `next_or_reconnect`
+async fn ping(client: &IggyClient) -> Result<()> {
+    Ok(client.ping().await?)
+}
+
+/// -----------------------------
+/// Fair Interleave
+/// -----------------------------
+/// Merges one stream per topic, yielding `(index, item)`. Polling starts
+/// after the stream that yielded last, so a busy topic can't starve the
+/// others. Ends once every stream has ended.
+pub struct FairInterleave<S> {
+    streams: Vec<S>,
+    ended: Vec<bool>,
+    next: usize,
+}
+
+impl<S> FairInterleave<S> {
+    pub fn new(streams: Vec<S>) -> Self {
+        let ended = vec![false; streams.len()];
+        Self { streams, ended, next: 0 }
+    }
+
+    /// The stream an item came from, e.g. to commit its offset
+    pub fn get_mut(&mut self, index: usize) -> &mut S {
+        &mut self.streams[index]
+    }
+}
+
+impl<S: futures_util::Stream + Unpin> futures_util::Stream for FairInterleave<S> {
+    type Item = (usize, S::Item);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let count = this.streams.len();
+        let mut all_ended = true;
+
+        for step in 0..count {
+            let index = (this.next + step) % count;
+            if this.ended[index] {
+                continue;
+            }
+
+            match this.streams[index].poll_next_unpin(cx) {
+                Poll::Ready(Some(item)) => {
+                    this.next = (index + 1) % count;
+                    return Poll::Ready(Some((index, item)));
+                }
+                Poll::Ready(None) => this.ended[index] = true,
+                Poll::Pending => all_ended = false,
+            }
+        }
+
+        if all_ended {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// One initialised group consumer per configured topic
+async fn topic_consumers(
+    client: &IggyClient,
+    group: &str,
+    config: &ConsumerConfig,
+) -> Result<FairInterleave<IggyConsumer>> {
+    let mut consumers = Vec::with_capacity(config.topics.len());
+    for target in &config.topics {
+        let partitions = match topic_partition_count(client, target).await {
+            Ok(partitions) => partitions,
+            Err(e) => {
+                warn!("{group}: partition count of {target} unknown ({e}), assuming 1");
+                1
+            }
+        };
+        let batch_length = config.poll_batch_length(partitions);
+        info!("{group}: polling {batch_length} messages at a time from {target} ({partitions} partitions)");
+
+        let mut consumer = group_consumer(client, group, config, target, batch_length)?;
+        consumer.init().await?;
+        consumers.push(consumer);
+    }
+    Ok(FairInterleave::new(consumers))
+}
+
+fn group_consumer(
+    client: &IggyClient,
+    group: &str,
+    config: &ConsumerConfig,
+    target: &TopicTarget,
+    batch_length: u32,
+) -> Result<IggyConsumer> {
+    Ok(client
+        .consumer_group(group, &target.stream, &target.topic)?
+        .batch_length(batch_length)
+        .auto_commit(config.iggy_auto_commit())
+        .create_consumer_group_if_not_exists()
+        .auto_join_consumer_group()
+        .polling_strategy(config.start_strategy.polling_strategy())
+        .poll_interval(IggyDuration::new(Duration::from_millis(50)))
+        .build())
+}
+
+async fn topic_partition_count(client: &IggyClient, target: &TopicTarget) -> Result<u32> {
+    let topic = client
+        .get_topic(&Identifier::named(&target.stream)?, &Identifier::named(&target.topic)?)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Topic not found"))?;
+
+    Ok(topic.partitions_count)
+}
+
+fn topic_list(topics: &[TopicTarget]) -> String {
+    topics.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+}
 
 /// =============================
 /// Turso Consumer (stores USER msgs)
 /// =============================
-pub struct TursoConsumer {
-    consumer: IggyConsumer,
-    store: Arc<ConversationStore>,
-}
-
-impl TursoConsumer {
-    pub async fn new(
-        client: Arc<IggyClient>,
-        store: Arc<ConversationStore>,
-    ) -> Result<Self> {
-        let mut consumer = client
-            .consumer_group(
-                "sms-turso-consumer-group",
-                STREAM_NAME,
-                TOPIC_NAME,
-            )?
-            .auto_commit(AutoCommit::Disabled) //manual commit
-            .create_consumer_group_if_not_exists()
-            .auto_join_consumer_group()
-            .polling_strategy(PollingStrategy::next())
-            .poll_interval(IggyDuration::new(Duration::from_millis(50)))
-            .build();
+pub struct TursoConsumer<S: ConversationStorage = ConversationStore> {
+    store: Arc<S>,
+    config: ConsumerConfig,
+    /// Welcome text for a conversation's first message, and how to send it
+    greeting: Option<(Arc<SignalWireClient>, String)>,
+    /// Greetings count toward the outbound SMS cap
+    usage_caps: UsageCaps,
+    events: Arc<dyn EventSink>,
+}
 
-        consumer.init().await?;
-        info!("✓ SMS Turso consumer initialized");
+impl<S: ConversationStorage> TursoConsumer<S> {
+    pub fn new(store: Arc<S>) -> Self {
+        Self {
+            store,
+            config: ConsumerConfig::default(),
+            greeting: None,
+            usage_caps: UsageCaps::default(),
+            events: noop_sink(),
+        }
+    }
+
+    pub fn with_config(mut self, config: ConsumerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Text `message` to a number once, on the first inbound message it
+    /// sends. Every inbound SMS can start a new conversation, so this is
+    /// tracked per number, not per conversation. The greeting is sent only,
+    /// not stored, so it doesn't count as the AI's first reply.
+    pub fn with_greeting(mut self, signalwire: Arc<SignalWireClient>, message: String) -> Self {
+        self.greeting = Some((signalwire, message));
+        self
+    }
+
+    /// Count greetings as outbound SMS under these caps (the same ones
+    /// the AI consumer enforces)
+    pub fn with_usage_caps(mut self, caps: UsageCaps) -> Self {
+        self.usage_caps = caps;
+        self
+    }
 
-        Ok(Self { consumer, store })
+    /// Notify `sink` of every stored message
+    pub fn with_event_sink(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.events = sink;
+        self
     }
 
-    pub async fn start(mut self) -> Result<()> {
+    pub async fn start(self, client: Arc<IggyClient>) -> Result<()> {
+        info!(
+            "Turso consumer start strategy: {:?}, delivery: {:?}, topics: {}",
+            self.config.start_strategy,
+            self.config.delivery_semantics(),
+            topic_list(&self.config.topics)
+        );
+        let mut consumers = topic_consumers(&client, TURSO_CONSUMER_GROUP, &self.config).await?;
+        let mut errors = ErrorThreshold::new(self.config.max_consecutive_errors);
+        let mut idle = IdleBackoff::new(IDLE_BACKOFF_MIN, self.config.idle_backoff_max);
+        // Both groups see every payload; only this one dead-letters
+        let dead_letters = IggyDeadLetterQueue::connect(client.clone(), &self.config.topics).await?;
         info!("→ SMS Turso consumer started");
 
-        while let Some(result) = self.consumer.next().await {
+        loop {
+            let next = next_or_reconnect(
+                &mut consumers,
+                self.config.poll_timeout,
+                &mut idle,
+                &mut errors,
+                "Turso",
+                || ping(&client),
+                || topic_consumers(&client, TURSO_CONSUMER_GROUP, &self.config),
+            );
+            let Some((topic, result)) = next.await? else { break };
+
             let msg = match result {
-                Ok(m) => m,
+                Ok(m) => {
+                    errors.on_success();
+                    m
+                }
                 Err(e) => {
-                    error!("Turso polling error: {e}");
+                    errors.on_error("Turso", e)?;
+                    tokio::time::sleep(POLL_ERROR_BACKOFF).await;
                     continue;
                 }
             };
 
-            let offset = msg.message.header.offset;
-
-            let sms: SMSMessage =
-                serde_json::from_slice(&msg.message.payload)?;
+            let _offset = msg.message.header.offset;
+            debug!("Polled message from {}", self.config.topics[topic]);
 
-            info!(
-                "📥 User SMS | conv={} | from={} | body={}",
-                sms.conversation_id,
-                sms.from,
-                sms.body
-            );
+            let now = self.store.clock().now();
+            let Some(sms) = decode_or_dead_letter(&msg.message, &self.config.topics[topic], &dead_letters, now).await else {
+                continue;
+            };
 
-            self.store
-                .store_message(
-                    sms.conversation_id,
-                    MessageRole::User,
-                    sms.body,
-                )
-                .await?;
+            self.process_message(sms).await?;
 
             // ACK AFTER DB WRITE
-            // self.consumer
+            // consumer
             //     .store_offset(offset + 1, None)
             //     .await?;
         }
 
         Ok(())
     }
+
+    /// Persist one inbound SMS as a user message
+    pub async fn process_message(&self, sms: SMSMessage) -> Result<()> {
+        info!(
+            "📥 User SMS | conv={} | from={} | body={}",
+            sms.conversation_id,
+            sms.from,
+            sms.body
+        );
+
+        self.store
+            .ensure_sms_conversation(
+                &sms.conversation_id,
+                &default_sms_title(sms.from.as_str()),
+                sms.from.as_str(),
+            )
+            .await?;
+
+        self.send_greeting(&sms).await?;
+
+        let received_at = sms_received_at(&sms);
+        let message = self
+            .store
+            .store_message_at(
+                sms.conversation_id,
+                MessageRole::User,
+                sms.body,
+                sms.provider_sid,
+                received_at,
+            )
+            .await?;
+
+        self.events.on_message_stored(&message);
+        Ok(())
+    }
+
+    /// Recorded under a `greeting:<number>` key in the processed-message
+    /// set, so it survives restarts and doesn't depend on which consumer
+    /// created the conversation. Marked only after a send, so a failure
+    /// retries on the next message.
+    async fn send_greeting(&self, sms: &SMSMessage) -> Result<()> {
+        let Some((signalwire, greeting)) = &self.greeting else {
+            return Ok(());
+        };
+
+        let key = format!("greeting:{}", sms.from);
+        if self.store.is_message_processed(&key).await?
+            || self.store.is_opted_out(sms.from.as_str()).await?
+        {
+            return Ok(());
+        }
+
+        match send_audited(&*self.store, signalwire, Some(&sms.conversation_id), &sms.from, greeting).await {
+            Ok(_) => {
+                info!("👋 Greeted {} in {}", sms.from, sms.conversation_id);
+                record_usage(&*self.store, &self.usage_caps, sms.from.as_str(), UsageKind::OutboundSms).await;
+                self.store.mark_message_processed(&key).await
+            }
+            Err(e) => {
+                warn!("Greeting to {} failed: {e}", sms.from);
+                Ok(())
+            }
+        }
+    }
 }
 
 /// =============================
 /// AI Consumer (reply + send SMS)
 /// =============================
-pub struct AIConsumer {
-    consumer: IggyConsumer,
-    store: Arc<ConversationStore>,
+/// Replies for the same conversation are serialized through a per-conversation
+/// lock, so a second message never reads history that is missing the first
+/// reply. The lock is in-process only: it guards one consumer instance and
+/// does not coordinate across instances in the consumer group.
+pub struct AIConsumer<S: ConversationStorage = ConversationStore> {
+    store: Arc<S>,
     ai: Arc<AIService>,
     signalwire: Arc<SignalWireClient>,
+    default_system_prompt: String,
+    auto_title: bool,
+    sequential_delivery: Option<Duration>,
+    config: ConsumerConfig,
+    conversation_locks: DashMap<String, Arc<Mutex<()>>>,
+    /// Concurrent AI calls (to stay under the provider's rate limit) and daily caps
+    guards: ReplyGuards,
+    branding: ReplyBranding,
+    markdown: MarkdownPolicy,
+    /// Store replies through `normalize_body`, so the stored text is the
+    /// text the SignalWire client sends
+    normalize_bodies: bool,
+    /// Stored with replies of conversations without their own label
+    sender_label: Option<String>,
+    /// Cut each history message sent to the AI to this many characters
+    history_max_chars: Option<usize>,
+    events: Arc<dyn EventSink>,
 }
 
-impl AIConsumer {
-    pub async fn new(
-        client: Arc<IggyClient>,
-        store: Arc<ConversationStore>,
+impl<S: ConversationStorage> AIConsumer<S> {
+    pub fn new(
+        store: Arc<S>,
         ai: Arc<AIService>,
         signalwire: Arc<SignalWireClient>,
-    ) -> Result<Self> {
-        let mut consumer = client
-            .consumer_group(
-                "sms-ai-consumer-group",
-                STREAM_NAME,
-                TOPIC_NAME,
-            )?
-            .auto_commit(AutoCommit::Disabled) // 🔒 REQUIRED
-            .create_consumer_group_if_not_exists()
-            .auto_join_consumer_group()
-            .polling_strategy(PollingStrategy::next())
-            .poll_interval(IggyDuration::new(Duration::from_millis(50)))
-            .build();
-
-        consumer.init().await?;
-        info!("✓ SMS AI consumer initialized");
-
-        Ok(Self {
-            consumer,
+    ) -> Self {
+        Self {
             store,
             ai,
             signalwire,
-        })
+            default_system_prompt: DEFAULT_SYSTEM_PROMPT.to_string(),
+            auto_title: false,
+            sequential_delivery: None,
+            config: ConsumerConfig::default(),
+            conversation_locks: DashMap::new(),
+            guards: ReplyGuards::default(),
+            branding: ReplyBranding::default(),
+            markdown: MarkdownPolicy::default(),
+            normalize_bodies: true,
+            sender_label: None,
+            history_max_chars: None,
+            events: noop_sink(),
+        }
     }
 
-    pub async fn start(mut self) -> Result<()> {
-        info!("→ SMS AI consumer started");
+    /// Persona used for conversations without their own `system_prompt`
+    pub fn with_default_system_prompt(mut self, prompt: String) -> Self {
+        self.default_system_prompt = prompt;
+        self
+    }
+
+    /// Generate a conversation title from the first message (one extra AI call)
+    pub fn with_auto_title(mut self, enabled: bool) -> Self {
+        self.auto_title = enabled;
+        self
+    }
+
+    /// Hold each reply until the previous one in the conversation reaches a
+    /// final delivery status (or `timeout` passes). Blocks this consumer while
+    /// waiting, so only enable it where strict ordering matters.
+    pub fn with_sequential_delivery(mut self, timeout: Option<Duration>) -> Self {
+        self.sequential_delivery = timeout;
+        self
+    }
+
+    pub fn with_config(mut self, config: ConsumerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Maximum AI completions running at once (at least 1)
+    pub fn with_max_in_flight_ai(mut self, permits: usize) -> Self {
+        self.guards = ReplyGuards::new(permits, self.guards.usage_caps.clone());
+        self
+    }
+
+    /// Stop AI replies to a number once it hits a daily cap. Usage is only
+    /// tracked while some cap is set.
+    pub fn with_usage_caps(mut self, caps: UsageCaps) -> Self {
+        self.guards.usage_caps = caps;
+        self
+    }
+
+    /// Wrap every reply (AI or fallback) in a prefix/suffix when sent
+    pub fn with_reply_branding(mut self, branding: ReplyBranding) -> Self {
+        self.branding = branding;
+        self
+    }
+
+    /// Strip markdown from replies when sending (or before storing)
+    pub fn with_markdown_policy(mut self, policy: MarkdownPolicy) -> Self {
+        self.markdown = policy;
+        self
+    }
+
+    /// Store replies exactly as generated instead of normalizing them (on
+    /// by default); match the SignalWire client's setting
+    pub fn with_body_normalization(mut self, enabled: bool) -> Self {
+        self.normalize_bodies = enabled;
+        self
+    }
+
+    /// Label replies (e.g. "AI") in conversations without their own `sender_label`
+    pub fn with_sender_label(mut self, label: Option<String>) -> Self {
+        self.sender_label = label;
+        self
+    }
+
+    /// Cut each earlier message to `max_chars` characters (in the store
+    /// query) before it goes into the AI context. Stored messages and
+    /// transcripts keep their full content.
+    pub fn with_history_max_chars(mut self, max_chars: Option<usize>) -> Self {
+        self.history_max_chars = max_chars;
+        self
+    }
+
+    /// Notify `sink` of generated replies and sends
+    pub fn with_event_sink(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.events = sink;
+        self
+    }
+
+    pub async fn start(self, client: Arc<IggyClient>) -> Result<()> {
+        info!(
+            "AI consumer start strategy: {:?}, delivery: {:?}, topics: {}",
+            self.config.start_strategy,
+            self.config.delivery_semantics(),
+            topic_list(&self.config.topics)
+        );
+        let mut consumers = topic_consumers(&client, AI_CONSUMER_GROUP, &self.config).await?;
+        let mut errors = ErrorThreshold::new(self.config.max_consecutive_errors);
+        let mut idle = IdleBackoff::new(IDLE_BACKOFF_MIN, self.config.idle_backoff_max);
+        info!("→ SMS AI consumer started");
+
+        loop {
+            let next = next_or_reconnect(
+                &mut consumers,
+                self.config.poll_timeout,
+                &mut idle,
+                &mut errors,
+                "AI",
+                || ping(&client),
+                || topic_consumers(&client, AI_CONSUMER_GROUP, &self.config),
+            );
+            let Some((topic, result)) = next.await? else { break };
 
-        while let Some(result) = self.consumer.next().await {
             let msg = match result {
-                Ok(m) => m,
+                Ok(m) => {
+                    errors.on_success();
+                    m
+                }
                 Err(e) => {
-                    error!("AI polling error: {e}");
+                    errors.on_error("AI", e)?;
+                    tokio::time::sleep(POLL_ERROR_BACKOFF).await;
                     continue;
                 }
             };
 
             let offset = msg.message.header.offset;
+            debug!("Polled message at offset {} from {}", offset, self.config.topics[topic]);
 
-            let sms: SMSMessage =
-                serde_json::from_slice(&msg.message.payload)?;
-
-            // Idempotency guard
-            if self.store.is_message_processed(&sms.id).await? {
-                info!("⏭️ Skipping duplicate {}", sms.id);
-
-                self.consumer
-                    .store_offset(offset + 1, None)
-                    .await?;
+            if self.taken_over(&msg.message).await? {
+                consumers.get_mut(topic).store_offset(offset + 1, None).await?;
                 continue;
             }
 
-            let history = self.store
-                .get_conversation_messages(&sms.conversation_id)
-                .await?
-                .into_iter()
-                .rev()
-                .take(10)
-                .rev()
-                .map(|m| AIMessage {
-                    role: m.role.as_str().to_string(),
-                    content: m.content,
-                })
-                .collect::<Vec<_>>();
-
-            let reply = self.ai
-                .generate_response(&sms.body, &history)
-                .await?;
+            // Dead-lettered by the Turso consumer; just skip it here
+            let sms = match validated_sms(&msg.message, self.store.clock().now()) {
+                Ok(sms) => sms,
+                Err(reason) => {
+                    warn!("Skipping message at offset {offset} from {}: {reason}", self.config.topics[topic]);
+                    consumers.get_mut(topic).store_offset(offset + 1, None).await?;
+                    continue;
+                }
+            };
 
-            info!(
-                "🤖 AI Reply | conv={} | to={} | reply={}",
-                sms.conversation_id,
-                sms.from,
-                reply
-            );
+            self.process_message(&sms).await?;
 
-            self.store
-                .store_message(
-                    sms.conversation_id.clone(),
-                    MessageRole::Assistant,
-                    reply.clone(),
-                )
+            // FINAL ACK (THIS IS THE COMMIT)
+            consumers
+                .get_mut(topic)
+                .store_offset(offset + 1, None)
                 .await?;
 
-            self.signalwire
-                .send_sms(&sms.from, &reply)
-                .await?;
+            info!("Committed offset for {}", sms.id);
+        }
 
-            self.store
-                .mark_message_processed(&sms.id)
-                .await?;
+        Ok(())
+    }
 
-            // FINAL ACK (THIS IS THE COMMIT)
-            self.consumer
-                .store_offset(offset + 1, None)
-                .await?;
+    /// Generate, store and send the AI reply for one inbound SMS
+    pub async fn process_message(&self, sms: &SMSMessage) -> Result<()> {
+        let lock = self
+            .conversation_locks
+            .entry(sms.conversation_id.clone())
+            .or_default()
+            .clone();
+
+        let result = {
+            let _guard = lock.lock().await;
+            self.reply(sms).await
+        };
+
+        // Drop the entry once nobody else is waiting on it
+        drop(lock);
+        self.conversation_locks
+            .remove_if(&sms.conversation_id, |_, l| Arc::strong_count(l) == 1);
 
-            info!("Reply sent & committed for {}", sms.id);
+        result
+    }
+
+    async fn reply(&self, sms: &SMSMessage) -> Result<()> {
+        // Idempotency guard
+        if self.store.is_message_processed(&sms.id).await? {
+            info!("⏭️ Skipping duplicate {}", sms.id);
+            return Ok(());
+        }
+
+        if self.store.is_opted_out(sms.from.as_str()).await? {
+            info!("⏭️ {} opted out, not replying to {}", sms.from, sms.id);
+            self.store.mark_message_processed(&sms.id).await?;
+            return Ok(());
+        }
+
+        self.store
+            .ensure_sms_conversation(
+                &sms.conversation_id,
+                &default_sms_title(sms.from.as_str()),
+                sms.from.as_str(),
+            )
+            .await?;
+
+        // A human agent has the thread; the Turso consumer still stores the message
+        if self
+            .store
+            .get_conversation(&sms.conversation_id)
+            .await?
+            .is_some_and(|c| !c.ai_enabled)
+        {
+            info!("⏭️ AI disabled for {}, not replying to {}", sms.conversation_id, sms.id);
+            self.store.mark_message_processed(&sms.id).await?;
+            return Ok(());
+        }
+
+        // A retry after a partial failure reuses the reply stored last time
+        let reply_id = reply_message_id(&sms.id);
+        let stored = match self.store.get_message(&reply_id).await? {
+            Some(existing) => {
+                info!("Reusing stored reply for {}", sms.id);
+                existing
+            }
+            None => {
+                if self.daily_cap_reached(sms).await?
+                    || !claim_usage(&*self.store, &self.guards.usage_caps, sms.from.as_str(), UsageKind::AiCompletion).await
+                {
+                    self.store.mark_message_processed(&sms.id).await?;
+                    return Ok(());
+                }
+                self.generate_reply(sms, reply_id).await?
+            }
+        };
+
+        if self
+            .store
+            .was_sms_sent(&sms.conversation_id, &stored.id)
+            .await?
+        {
+            info!("⏭️ Reply for {} already sent, not resending", sms.id);
+            self.store.mark_message_processed(&sms.id).await?;
+            return Ok(());
+        }
+
+        // Claimed before sending, so a send that fails still counts
+        if !claim_usage(&*self.store, &self.guards.usage_caps, sms.from.as_str(), UsageKind::OutboundSms).await {
+            warn!("🚫 {} hit the daily SMS cap, reply to {} stored but not sent", sms.from, sms.id);
+            self.store.mark_message_processed(&sms.id).await?;
+            return Ok(());
         }
 
+        let body = self
+            .branding
+            .outbound_text(&self.markdown.outbound_text(&stored.content));
+        debug!("Reply for {} is {} segment(s)", sms.id, segment_count(&body));
+
+        let provider_sid = match send_audited(&*self.store, &self.signalwire, Some(&sms.conversation_id), &sms.from, &body).await {
+            Ok(SendOutcome::Sent(sid)) => sid,
+            Ok(SendOutcome::NotAllowed) => {
+                // Reply is stored; nothing to track without a send
+                self.store.mark_message_processed(&sms.id).await?;
+                return Ok(());
+            }
+            Err(e) if e
+                .downcast_ref::<SignalWireError>()
+                .is_some_and(|e| e.code == ERROR_UNSUBSCRIBED) =>
+            {
+                warn!("{} is unsubscribed, opting out", sms.from);
+                self.store.set_opted_out(sms.from.as_str()).await?;
+                self.store.mark_message_processed(&sms.id).await?;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+
+        // Record the send first: everything after this may fail and retry
+        self.store
+            .mark_sms_sent(&sms.conversation_id, &stored.id)
+            .await?;
+
+        self.events.on_sms_sent(&stored, &sms.from, &provider_sid);
+
+        self.store
+            .record_outbound_sent(&stored.id, &provider_sid)
+            .await?;
+
+        self.store
+            .mark_message_processed(&sms.id)
+            .await?;
+
+        info!("Reply sent for {}", sms.id);
         Ok(())
     }
+
+    /// Ask the AI (or fall back to a canned apology) and store the reply
+    /// under `reply_id`
+    async fn generate_reply(&self, sms: &SMSMessage, reply_id: String) -> Result<Message> {
+        let conversation = self.store
+            .get_conversation(&sms.conversation_id)
+            .await?;
+
+        let system_prompt =
+            render_system_prompt(conversation.as_ref(), &self.default_system_prompt);
+
+        let messages = self.store
+            .get_recent_messages(&sms.conversation_id, HISTORY_WINDOW as u32, self.history_max_chars)
+            .await?;
+
+        // No assistant reply yet (ever, not just within the history
+        // window) means this is the conversation's first exchange
+        if self.auto_title && !self.store.has_assistant_reply(&sms.conversation_id).await? {
+            self.generate_title(sms).await;
+        }
+
+        let history = build_ai_history(&system_prompt, messages);
+
+        let generated = {
+            let _permit = self.guards.ai_permit().await?;
+            self.ai
+                .generate_response(&sms.body, &history, &generation_config(conversation.as_ref()))
+                .await
+        };
+
+        // Don't leave the sender hanging; apologise in their language. A
+        // transient failure is retried instead: the message stays
+        // unprocessed and is redelivered.
+        let reply = match generated {
+            Ok(reply) => reply,
+            Err(e) if is_transient_ai_error(&e) => return Err(e.context(format!("AI reply for {} failed", sms.id))),
+            Err(e) => {
+                error!("AI reply failed for {}, sending fallback: {e}", sms.id);
+                canned(Locale::detect(&sms.body), CannedKey::Error).to_string()
+            }
+        };
+
+        info!(
+            "🤖 AI Reply | conv={} | to={} | reply={}",
+            sms.conversation_id,
+            sms.from,
+            reply
+        );
+
+        if let Some(timeout) = self.sequential_delivery {
+            self.wait_for_prior_delivery(&sms.conversation_id, timeout).await?;
+        }
+
+        let mut content = self.branding.stored_text(self.markdown.stored_text(reply));
+        if self.normalize_bodies {
+            content = normalize_body(&content);
+        }
+        let mut message = Message::new(sms.conversation_id.clone(), MessageRole::Assistant, content)
+        .with_sender_label(resolve_sender_label(conversation.as_ref(), self.sender_label.as_deref()));
+        message.id = reply_id;
+
+        let stored = self.store.insert_message(message).await?;
+        self.events.on_ai_response(sms, &stored);
+        Ok(stored)
+    }
+
+    /// Header fast path: a message whose `conversation_id` header names a
+    /// conversation a human has taken over is skipped before its body is
+    /// decoded or validated. Messages without the header go the slow way,
+    /// through the same check in `reply`.
+    async fn taken_over(&self, msg: &IggyMessage) -> Result<bool> {
+        let Some(conversation_id) = header_value(msg, HEADER_CONVERSATION_ID) else {
+            return Ok(false);
+        };
+        if self.store.get_conversation(&conversation_id).await?.is_none_or(|c| c.ai_enabled) {
+            return Ok(false);
+        }
+
+        info!("⏭️ AI disabled for {conversation_id}, skipping message without decoding it");
+        Ok(true)
+    }
+
+    /// Whether the sender is over a daily cap. The first time that happens
+    /// each day the configured notice is sent; it is flagged before sending,
+    /// so a failed send is not retried.
+    async fn daily_cap_reached(&self, sms: &SMSMessage) -> Result<bool> {
+        if self.guards.usage_caps.is_unlimited() {
+            return Ok(false);
+        }
+
+        let today = usage_date(self.store.clock());
+        let usage = self.store.daily_usage(sms.from.as_str(), today).await?;
+        if !self.guards.usage_caps.is_exceeded(&usage) {
+            return Ok(false);
+        }
+
+        warn!("🚫 {} hit the daily cap, not replying to {}", sms.from, sms.id);
+
+        if let Some(notice) = &self.guards.usage_caps.notice {
+            if self.store.mark_cap_notice_sent(sms.from.as_str(), today).await? {
+                match send_audited(&*self.store, &self.signalwire, Some(&sms.conversation_id), &sms.from, notice).await {
+                    // Counted, but never held back by the cap it announces
+                    Ok(_) => {
+                        record_usage(&*self.store, &self.guards.usage_caps, sms.from.as_str(), UsageKind::OutboundSms)
+                            .await
+                    }
+                    Err(e) => warn!("Cap notice to {} failed: {e}", sms.from),
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn wait_for_prior_delivery(&self, conversation_id: &str, timeout: Duration) -> Result<()> {
+        let started = Instant::now();
+        let mut backoff = IdleBackoff::new(IDLE_BACKOFF_MIN, self.config.idle_backoff_max);
+
+        loop {
+            match self.store.last_reply_delivery_status(conversation_id).await? {
+                None => return Ok(()),
+                Some(status) if is_final_delivery_status(&status) => return Ok(()),
+                Some(status) if started.elapsed() >= timeout => {
+                    warn!(
+                        "Previous reply in {} still '{}' after {:?}, sending anyway",
+                        conversation_id, status, timeout
+                    );
+                    return Ok(());
+                }
+                Some(_) => tokio::time::sleep(backoff.on_empty()).await,
+            }
+        }
+    }
+
+    async fn generate_title_bounded(&self, first_message: &str) -> Result<String> {
+        let _permit = self.guards.ai_permit().await?;
+        self.ai.generate_title(first_message).await
+    }
+
+    /// Best-effort: a failed title never blocks the reply
+    async fn generate_title(&self, sms: &SMSMessage) {
+        let title = self.generate_title_bounded(&sms.body).await;
+        record_usage(&*self.store, &self.guards.usage_caps, sms.from.as_str(), UsageKind::AiCompletion).await;
+
+        let title = match title {
+            Ok(title) => title,
+            Err(e) => {
+                warn!("Title generation failed for {}: {e}", sms.conversation_id);
+                return;
+            }
+        };
+
+        if let Err(e) = self.store
+            .update_conversation_title(&sms.conversation_id, &title)
+            .await
+        {
+            warn!("Failed to update title for {}: {e}", sms.conversation_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_broker::build_message;
+    use crate::signalwire::ERROR_UNSUBSCRIBED;
+    use crate::test_support::{
+        fake_ai, fake_ai_failing, fake_ai_rejecting, fake_ai_slow, fake_groq, fake_signalwire, fake_signalwire_rejecting, fake_store,
+    };
+    use crate::InMemoryStore;
+
+    fn inbound(id: &str, conversation_id: &str, body: &str) -> SMSMessage {
+        SMSMessage {
+            id: id.into(),
+            from: crate::PhoneNumber::parse("+15551230000").unwrap(),
+            to: crate::PhoneNumber::parse("+15550000000").unwrap(),
+            body: body.into(),
+            timestamp: 0,
+            conversation_id: conversation_id.into(),
+            provider_sid: None,
+            in_reply_to: None,
+            received_at: None,
+        }
+    }
+
+    fn reply_context<'a, S>(store: &'a S, ai: &'a AIService, guards: &'a ReplyGuards) -> ReplyContext<'a, S> {
+        ReplyContext {
+            store,
+            ai,
+            guards,
+            default_system_prompt: "Persona",
+            default_sender_label: None,
+            history_max_chars: None,
+            normalize_bodies: true,
+        }
+    }
+
+    #[test]
+    fn test_only_legacy_formatted_conversations_are_merged() {
+        let mut legacy = Conversation::new(Some(default_sms_title("15551234567")));
+        legacy.created_at -= chrono::Duration::days(2);
+        let mut current = Conversation::new(Some("Renamed".into()));
+        current.from_number = Some("+15551234567".into());
+        current.created_at -= chrono::Duration::days(1);
+        // A later thread of the same number (e.g. after archiving) stays apart
+        let later = Conversation::new(Some(default_sms_title("+15551234567")));
+        let other = Conversation::new(Some(default_sms_title("+15559990000")));
+        let orphan = Conversation::new(Some(default_sms_title("15553334444")));
+
+        let pairs = duplicate_sms_conversations(&[later, current.clone(), other, legacy.clone(), orphan]);
+        assert_eq!(pairs, [(legacy.id, current.id)]);
+    }
+
+    #[tokio::test]
+    async fn test_split_conversations_are_merged_with_their_sent_replies() {
+        let store = InMemoryStore::new();
+        store.ensure_sms_conversation("legacy", "SMS: 15551234567", "15551234567").await.unwrap();
+        store.ensure_sms_conversation("current", "SMS: +15551234567", "+15551234567").await.unwrap();
+        let reply = store
+            .store_message("legacy".into(), MessageRole::Assistant, "Hi".into())
+            .await
+            .unwrap();
+        store.mark_sms_sent("legacy", &reply.id).await.unwrap();
+
+        assert_eq!(merge_split_sms_conversations(&store).await.unwrap(), 1);
+        assert!(store.get_conversation("legacy").await.unwrap().is_none());
+        assert_eq!(store.get_conversation_messages("current").await.unwrap()[0].id, reply.id);
+        // Replaying the reply under its new conversation doesn't send it again
+        assert!(store.was_sms_sent("current", &reply.id).await.unwrap());
+
+        assert_eq!(merge_split_sms_conversations(&store).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_turso_consumer_stores_user_messages_in_memory() {
+        let store = Arc::new(InMemoryStore::new());
+        let consumer = TursoConsumer::new(store.clone());
+
+        let mut first = inbound("m1", "conv-1", "Hello");
+        first.provider_sid = Some("SM123".into());
+        consumer.process_message(first).await.unwrap();
+        consumer.process_message(inbound("m2", "conv-1", "Still there?")).await.unwrap();
+
+        let conversation = store.get_conversation("conv-1").await.unwrap().unwrap();
+        assert_eq!(conversation.title, Some(default_sms_title("+15551230000")));
+
+        let messages = store.get_conversation_messages("conv-1").await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().all(|m| m.role == MessageRole::User));
+        assert_eq!(messages[0].content, "Hello");
+        assert_eq!(messages[0].provider_sid.as_deref(), Some("SM123"));
+        assert_eq!(messages[1].provider_sid, None);
+    }
+
+    #[tokio::test]
+    async fn test_user_message_keeps_carrier_timestamp() {
+        let (_turso, store) = fake_store().await;
+        let consumer = TursoConsumer::new(Arc::new(store));
+
+        let mut sms = inbound("m1", "conv-1", "Hello");
+        sms.timestamp = 1_700_000_000;
+        consumer.process_message(sms).await.unwrap();
+
+        let messages = consumer.store.get_conversation_messages("conv-1").await.unwrap();
+        assert_eq!(messages[0].created_at.timestamp(), 1_700_000_000);
+
+        // The millisecond receipt time wins over the whole seconds
+        let received_at = DateTime::from_timestamp_millis(1_700_000_000_250).unwrap();
+        let mut precise = inbound("m2", "conv-2", "Hello");
+        precise.timestamp = received_at.timestamp();
+        precise.received_at = Some(received_at);
+        consumer.process_message(precise).await.unwrap();
+        let stored = consumer.store.get_conversation_messages("conv-2").await.unwrap();
+        assert_eq!(stored[0].created_at, received_at);
+
+        // The conversation was created just now; the older message doesn't rewind it
+        let conversation = consumer.store.get_conversation("conv-1").await.unwrap().unwrap();
+        assert!(conversation.updated_at > messages[0].created_at);
+    }
+
+    #[tokio::test]
+    async fn test_greeting_sent_once_per_number() {
+        let store = Arc::new(InMemoryStore::new());
+        let (signalwire, sent) = fake_signalwire().await;
+        let consumer = TursoConsumer::new(store.clone())
+            .with_greeting(Arc::new(signalwire), "Welcome!".into());
+
+        consumer.process_message(inbound("m1", "conv-1", "Hi")).await.unwrap();
+        consumer.process_message(inbound("m2", "conv-1", "Anyone?")).await.unwrap();
+        // Each inbound SMS may start a new conversation
+        consumer.process_message(inbound("m3", "conv-2", "Hello again")).await.unwrap();
+
+        let mut other = inbound("m4", "conv-3", "Hi");
+        other.from = crate::PhoneNumber::parse("+15559990000").unwrap();
+        consumer.process_message(other).await.unwrap();
+
+        let sent = sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0]["Body"], "Welcome!");
+        assert_eq!(sent[0]["To"], "+15551230000");
+        assert_eq!(sent[1]["To"], "+15559990000");
+
+        // Not stored, so the AI still sees this as the first exchange
+        let messages = store.get_conversation_messages("conv-1").await.unwrap();
+        assert!(messages.iter().all(|m| m.role == MessageRole::User));
+    }
+
+    #[tokio::test]
+    async fn test_turso_consumer_keeps_existing_conversation_title() {
+        let store = Arc::new(InMemoryStore::new());
+        store.ensure_conversation("conv-1", "Support").await.unwrap();
+
+        TursoConsumer::new(store.clone())
+            .process_message(inbound("m1", "conv-1", "Hi"))
+            .await
+            .unwrap();
+
+        let conversation = store.get_conversation("conv-1").await.unwrap().unwrap();
+        assert_eq!(conversation.title.as_deref(), Some("Support"));
+        assert!(store.get_conversation("conv-2").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_auto_title_only_for_first_message() {
+        let (_turso, store) = fake_store().await;
+        let store = Arc::new(store);
+        let (ai, ai_requests) = fake_ai("Billing question").await;
+        let (signalwire, sent) = fake_signalwire().await;
+
+        let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire))
+            .with_auto_title(true);
+
+        consumer.process_message(&inbound("m1", "conv-1", "Why was I charged twice?")).await.unwrap();
+
+        let conversation = store.get_conversation("conv-1").await.unwrap().unwrap();
+        assert_eq!(conversation.title.as_deref(), Some("Billing question"));
+        // Title completion + reply completion
+        assert_eq!(ai_requests.lock().unwrap().len(), 2);
+
+        store.update_conversation_title("conv-1", "Renamed").await.unwrap();
+        consumer.process_message(&inbound("m2", "conv-1", "Any update?")).await.unwrap();
+
+        let conversation = store.get_conversation("conv-1").await.unwrap().unwrap();
+        assert_eq!(conversation.title.as_deref(), Some("Renamed"));
+        assert_eq!(ai_requests.lock().unwrap().len(), 3);
+        assert_eq!(sent.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_auto_title_sees_replies_older_than_the_history_window() {
+        let (_turso, store) = fake_store().await;
+        let store = Arc::new(store);
+        let (ai, ai_requests) = fake_ai("Billing question").await;
+        let (signalwire, _sent) = fake_signalwire().await;
+
+        store.ensure_conversation("conv-1", "Support").await.unwrap();
+        store.store_message("conv-1".into(), MessageRole::Assistant, "Hello!".into()).await.unwrap();
+        for i in 0..HISTORY_WINDOW + 2 {
+            store.store_message("conv-1".into(), MessageRole::User, format!("ping {i}")).await.unwrap();
+        }
+
+        let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire))
+            .with_auto_title(true);
+        consumer.process_message(&inbound("m1", "conv-1", "Any update?")).await.unwrap();
+
+        let conversation = store.get_conversation("conv-1").await.unwrap().unwrap();
+        assert_eq!(conversation.title.as_deref(), Some("Support"));
+        // Reply completion only
+        assert_eq!(ai_requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_custom_persona_is_sent_as_system_prompt() {
+        let (url, captured) = fake_groq("Hi!").await;
+        let ai = AIService::new("test-model".into(), "key".into()).with_api_url(url);
+
+        let pirate = Conversation::new(None)
+            .with_system_prompt(Some("You are a pirate.".into()));
+        let plain = Conversation::new(None);
+
+        for conversation in [&pirate, &plain] {
+            let prompt = resolve_system_prompt(Some(conversation), DEFAULT_SYSTEM_PROMPT);
+            let history = build_ai_history(prompt, vec![]);
+            ai.generate_response("hello", &history, &GenerationConfig::default())
+                .await
+                .unwrap();
+        }
+
+        let requests = captured.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+
+        assert_eq!(requests[0]["messages"][0]["role"], "system");
+        assert_eq!(requests[0]["messages"][0]["content"], "You are a pirate.");
+        assert_eq!(requests[1]["messages"][0]["content"], DEFAULT_SYSTEM_PROMPT);
+    }
+
+    #[tokio::test]
+    async fn test_pinned_model_is_used_only_for_its_conversation() {
+        let store = InMemoryStore::new();
+        let guards = ReplyGuards::default();
+        let (ai, requests) = fake_ai("Hi!").await;
+
+        let pinned = store.create_conversation(None, None).await.unwrap();
+        store
+            .set_conversation_ai_settings(&pinned.id, Some("small-fast-model"), Some(0.0))
+            .await
+            .unwrap();
+        let plain = store.create_conversation(None, None).await.unwrap();
+
+        for conversation in [&pinned, &plain] {
+            generate_assistant_reply(&reply_context(&store, &ai, &guards), &conversation.id, "Hello".into(), None)
+                .await
+                .unwrap();
+        }
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0]["model"], "small-fast-model");
+        assert_eq!(requests[0]["temperature"], 0.0);
+        assert_eq!(requests[1]["model"], "test-model");
+        assert_eq!(requests[1]["temperature"].as_f64().unwrap() as f32, 0.7);
+    }
+
+    #[tokio::test]
+    async fn test_conversation_context_goes_into_the_system_message() {
+        let store = InMemoryStore::new();
+        let guards = ReplyGuards::default();
+        let (ai, requests) = fake_ai("Hi Ada!").await;
+
+        let conversation = store.create_conversation(None, None).await.unwrap();
+        let context = serde_json::json!({ "name": "Ada", "plan": "pro", "open_tickets": [42] });
+        store.set_conversation_context(&conversation.id, Some(&context)).await.unwrap();
+
+        let (message, reply) =
+            generate_assistant_reply(&reply_context(&store, &ai, &guards), &conversation.id, "Hello".into(), None)
+                .await
+                .unwrap();
+
+        let sent = requests.lock().unwrap()[0]["messages"].clone();
+        assert_eq!(sent[0]["role"], "system");
+        assert_eq!(
+            sent[0]["content"],
+            r#"Persona
+
+Customer context (JSON): {"name":"Ada","open_tickets":[42],"plan":"pro"}"#
+        );
+        assert_eq!(sent.as_array().unwrap().len(), 2);
+
+        // Only the exchange itself is in the transcript
+        let stored = store.get_conversation_messages(&conversation.id).await.unwrap();
+        assert_eq!(stored.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(), [message.id, reply.id]);
+        assert!(stored.iter().all(|m| !m.content.contains("Ada\"")));
+    }
+
+    #[tokio::test]
+    async fn test_same_conversation_is_processed_serially() {
+        let (_turso, store) = fake_store().await;
+        let (ai, ai_requests) = fake_ai("On it").await;
+        let (signalwire, _sent) = fake_signalwire().await;
+
+        let consumer = AIConsumer::new(Arc::new(store), Arc::new(ai), Arc::new(signalwire));
+
+        let first = inbound("m1", "conv-1", "Hello");
+        let second = inbound("m2", "conv-1", "Are you there?");
+        let (a, b) = tokio::join!(
+            consumer.process_message(&first),
+            consumer.process_message(&second),
+        );
+        a.unwrap();
+        b.unwrap();
+
+        // Whichever ran second saw the first reply in its history
+        let requests = ai_requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1]["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|m| m["role"] == "assistant" && m["content"] == "On it"));
+
+        assert!(consumer.conversation_locks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sequential_delivery_waits_for_confirmation() {
+        let (_turso, store) = fake_store().await;
+        let store = Arc::new(store);
+        let (ai, _) = fake_ai("Reply").await;
+        let (signalwire, sent) = fake_signalwire().await;
+
+        let consumer = Arc::new(
+            AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire))
+                .with_sequential_delivery(Some(Duration::from_secs(10))),
+        );
+
+        consumer.process_message(&inbound("m1", "conv-1", "First")).await.unwrap();
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        let second = tokio::spawn({
+            let consumer = consumer.clone();
+            async move { consumer.process_message(&inbound("m2", "conv-1", "Second")).await }
+        });
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(sent.lock().unwrap().len(), 1, "second reply sent before delivery");
+
+        assert!(store.update_delivery_status("SM_fake_0", "delivered").await.unwrap());
+
+        tokio::time::timeout(Duration::from_secs(2), second)
+            .await
+            .expect("delivery confirmation should unblock the next send")
+            .unwrap()
+            .unwrap();
+        assert_eq!(sent.lock().unwrap().len(), 2);
+        assert_eq!(
+            store.last_reply_delivery_status("conv-1").await.unwrap().as_deref(),
+            Some("sent")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ai_disabled_conversation_stores_without_replying() {
+        let store = Arc::new(InMemoryStore::new());
+        let (ai, ai_requests) = fake_ai("Reply").await;
+        let (signalwire, sent) = fake_signalwire().await;
+
+        store.ensure_conversation("conv-1", "SMS: +15551230000").await.unwrap();
+        store.set_conversation_ai_enabled("conv-1", false).await.unwrap();
+
+        let turso = TursoConsumer::new(store.clone());
+        let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire));
+
+        let sms = inbound("m1", "conv-1", "I'd like to talk to a person");
+        turso.process_message(sms.clone()).await.unwrap();
+        consumer.process_message(&sms).await.unwrap();
+
+        let messages = store.get_conversation_messages("conv-1").await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].role, MessageRole::User);
+        assert!(ai_requests.lock().unwrap().is_empty());
+        assert!(sent.lock().unwrap().is_empty());
+        assert!(store.is_message_processed("m1").await.unwrap());
+
+        // Handed back to the AI
+        store.set_conversation_ai_enabled("conv-1", true).await.unwrap();
+        consumer.process_message(&inbound("m2", "conv-1", "Thanks!")).await.unwrap();
+        assert_eq!(sent.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_taken_over_messages_are_skipped_from_the_header() {
+        let store = Arc::new(InMemoryStore::new());
+        let (ai, _) = fake_ai("Reply").await;
+        let (signalwire, _) = fake_signalwire().await;
+        let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire));
+
+        store.ensure_conversation("conv-1", "SMS: +15551230000").await.unwrap();
+        let sms = inbound("m1", "conv-1", "Hi");
+        // A body the consumer couldn't decode: the header alone decides
+        let msg = build_message(&sms, "not json".into()).unwrap();
+
+        assert!(!consumer.taken_over(&msg).await.unwrap());
+        store.set_conversation_ai_enabled("conv-1", false).await.unwrap();
+        assert!(consumer.taken_over(&msg).await.unwrap());
+
+        // Without headers the slow path in `reply` takes over
+        let legacy = IggyMessage::from_str(&serde_json::to_string(&sms).unwrap()).unwrap();
+        assert!(!consumer.taken_over(&legacy).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribed_recipient_is_opted_out() {
+        let store = Arc::new(InMemoryStore::new());
+        let (ai, ai_requests) = fake_ai("Reply").await;
+        let (signalwire, sent) = fake_signalwire_rejecting(ERROR_UNSUBSCRIBED).await;
+
+        let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire));
+
+        consumer.process_message(&inbound("m1", "conv-1", "Hi")).await.unwrap();
+        assert!(store.is_opted_out("+15551230000").await.unwrap());
+        assert!(store.is_message_processed("m1").await.unwrap());
+
+        // No AI call or send for later messages
+        consumer.process_message(&inbound("m2", "conv-1", "Hello?")).await.unwrap();
+        assert_eq!(ai_requests.lock().unwrap().len(), 1);
+        assert_eq!(sent.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_ai_request_sends_fallback_in_sender_language() {
+        let store = Arc::new(InMemoryStore::new());
+        let (signalwire, sent) = fake_signalwire().await;
+
+        let consumer = AIConsumer::new(
+            store.clone(),
+            Arc::new(fake_ai_rejecting(axum::http::StatusCode::BAD_REQUEST).await),
+            Arc::new(signalwire),
+        );
+
+        let body = "Hola, ¿cómo estás? Quiero saber el estado de mi pedido, por favor.";
+        consumer.process_message(&inbound("m1", "conv-1", body)).await.unwrap();
+
+        assert_eq!(sent.lock().unwrap()[0]["Body"], canned(Locale::Es, CannedKey::Error));
+        assert!(store.is_message_processed("m1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_transient_ai_failure_leaves_the_message_for_redelivery() {
+        let store = Arc::new(InMemoryStore::new());
+        let (signalwire, sent) = fake_signalwire().await;
+
+        let consumer = AIConsumer::new(store.clone(), Arc::new(fake_ai_failing().await), Arc::new(signalwire));
+        let err = consumer.process_message(&inbound("m1", "conv-1", "Hello")).await.unwrap_err();
+
+        assert_eq!(err.root_cause().downcast_ref(), Some(&AIError::Status(500)));
+        assert!(sent.lock().unwrap().is_empty());
+        assert!(!store.is_message_processed("m1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_empty_ai_reply_sends_fallback() {
+        let store = Arc::new(InMemoryStore::new());
+        let (ai, _) = fake_ai("   ").await;
+        let (signalwire, sent) = fake_signalwire().await;
+
+        let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire));
+        consumer.process_message(&inbound("m1", "conv-1", "ok")).await.unwrap();
+
+        assert_eq!(sent.lock().unwrap()[0]["Body"], canned(Locale::En, CannedKey::Error));
+    }
+
+    #[tokio::test]
+    async fn test_reprocessing_sent_reply_does_not_resend() {
+        let store = Arc::new(InMemoryStore::new());
+        let (ai, ai_requests) = fake_ai("Reply").await;
+        let (signalwire, sent) = fake_signalwire().await;
+
+        // First attempt stored and sent the reply, then failed before
+        // marking the inbound message processed
+        store.ensure_conversation("conv-1", "SMS").await.unwrap();
+        let mut reply = Message::new("conv-1".into(), MessageRole::Assistant, "Reply".into());
+        reply.id = reply_message_id("m1");
+        store.insert_message(reply).await.unwrap();
+        store.mark_sms_sent("conv-1", &reply_message_id("m1")).await.unwrap();
+
+        let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire));
+        consumer.process_message(&inbound("m1", "conv-1", "Hello")).await.unwrap();
+
+        assert!(sent.lock().unwrap().is_empty());
+        assert!(ai_requests.lock().unwrap().is_empty());
+        assert!(store.is_message_processed("m1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stored_reply_is_the_normalized_text_that_was_sent() {
+        let store = Arc::new(InMemoryStore::new());
+        let (ai, _) = fake_ai("Your order\t\tships \r\n\r\n\r\ntoday.").await;
+        let (signalwire, sent) = fake_signalwire().await;
+
+        let ai = Arc::new(ai);
+        let consumer = AIConsumer::new(store.clone(), ai.clone(), Arc::new(signalwire));
+        consumer.process_message(&inbound("m1", "conv-1", "Where is it?")).await.unwrap();
+
+        let stored = store.get_message(&reply_message_id("m1")).await.unwrap().unwrap();
+        assert_eq!(stored.content, "Your order ships\n\ntoday.");
+        assert_eq!(sent.lock().unwrap()[0]["Body"], stored.content);
+
+        // API-generated replies are stored the same way
+        let guards = ReplyGuards::default();
+        let (_, reply) = generate_assistant_reply(&reply_context(&*store, &ai, &guards), "conv-1", "And?".into(), None)
+            .await
+            .unwrap();
+        assert_eq!(reply.content, "Your order ships\n\ntoday.");
+    }
+
+    #[tokio::test]
+    async fn test_branding_is_sent_and_stored_per_mode() {
+        for store_branded in [false, true] {
+            let store = Arc::new(InMemoryStore::new());
+            let (ai, _) = fake_ai("Your order ships today.").await;
+            let (signalwire, sent) = fake_signalwire().await;
+
+            let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire))
+                .with_reply_branding(ReplyBranding {
+                    prefix: Some("[Acme]".into()),
+                    suffix: Some("Reply STOP to opt out.".into()),
+                    store_branded,
+                });
+            consumer.process_message(&inbound("m1", "conv-1", "Where is it?")).await.unwrap();
+
+            let branded = "[Acme] Your order ships today.\nReply STOP to opt out.";
+            assert_eq!(sent.lock().unwrap()[0]["Body"], branded);
+
+            let reply = store.get_message(&reply_message_id("m1")).await.unwrap().unwrap();
+            let expected = if store_branded { branded } else { "Your order ships today." };
+            assert_eq!(reply.content, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_markdown_is_stripped_for_sending_per_policy() {
+        let generated = "**Good news:**\n- it shipped\n- arrives `Friday`";
+        let plain = "Good news:\nit shipped\narrives Friday";
+
+        for policy in [MarkdownPolicy::Keep, MarkdownPolicy::Send, MarkdownPolicy::Store] {
+            let store = Arc::new(InMemoryStore::new());
+            let (ai, _) = fake_ai(generated).await;
+            let (signalwire, sent) = fake_signalwire().await;
+
+            let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire))
+                .with_markdown_policy(policy);
+            consumer.process_message(&inbound("m1", "conv-1", "Where is it?")).await.unwrap();
+
+            let reply = store.get_message(&reply_message_id("m1")).await.unwrap().unwrap();
+            let (expected_sent, expected_stored) = match policy {
+                MarkdownPolicy::Keep => (generated, generated),
+                MarkdownPolicy::Send => (plain, generated),
+                MarkdownPolicy::Store => (plain, plain),
+            };
+            assert_eq!(sent.lock().unwrap()[0]["Body"], expected_sent, "{policy:?}");
+            assert_eq!(reply.content, expected_stored, "{policy:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sender_label_is_stored_and_shown_in_transcript() {
+        let (_turso, store) = fake_store().await;
+        let store = Arc::new(store);
+        store.ensure_conversation("conv-1", "Default").await.unwrap();
+        store.ensure_conversation("conv-2", "Escalated").await.unwrap();
+        store.set_conversation_sender_label("conv-2", Some("Agent Bot")).await.unwrap();
+
+        let (ai, _) = fake_ai("On its way.").await;
+        let (signalwire, _) = fake_signalwire().await;
+        let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire))
+            .with_sender_label(Some("AI".into()));
+        consumer.process_message(&inbound("m1", "conv-1", "Where is it?")).await.unwrap();
+        consumer.process_message(&inbound("m2", "conv-2", "Where is it?")).await.unwrap();
+
+        let reply = store.get_message(&reply_message_id("m1")).await.unwrap().unwrap();
+        assert_eq!(reply.sender_label.as_deref(), Some("AI"));
+
+        // The conversation's own label wins over the global one
+        let conversation = store.get_conversation("conv-2").await.unwrap().unwrap();
+        assert_eq!(conversation.sender_label.as_deref(), Some("Agent Bot"));
+        let messages = store.get_conversation_messages("conv-2").await.unwrap();
+        assert_eq!(messages[0].sender_label.as_deref(), Some("Agent Bot"));
+
+        let mut transcript = Vec::new();
+        crate::export::write_transcript(&mut transcript, &conversation, &messages).unwrap();
+        let transcript = String::from_utf8(transcript).unwrap();
+        assert!(transcript.contains("assistant (Agent Bot): On its way."), "{transcript}");
+    }
+
+    #[tokio::test]
+    async fn test_daily_cap_blocks_ai_and_sends_notice_once() {
+        let store = Arc::new(InMemoryStore::new());
+        let (ai, ai_requests) = fake_ai("Reply").await;
+        let (signalwire, sent) = fake_signalwire().await;
+
+        let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire))
+            .with_usage_caps(UsageCaps {
+                max_ai_calls: Some(2),
+                max_sms: None,
+                notice: Some("Daily limit reached".into()),
+            });
+
+        for i in 0..4 {
+            consumer
+                .process_message(&inbound(&format!("m{i}"), "conv-1", "Hi"))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(ai_requests.lock().unwrap().len(), 2);
+        let sent = sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[2]["Body"], "Daily limit reached");
+        assert!(store.is_message_processed("m3").await.unwrap());
+
+        // The notice is an SMS too
+        let usage = store.daily_usage("+15551230000", usage_date(store.clock())).await.unwrap();
+        assert_eq!((usage.ai_calls, usage.sms_sent), (2, 3));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_replies_cannot_pass_the_sms_cap() {
+        let store = Arc::new(InMemoryStore::new());
+        let (ai, _) = fake_ai("Reply").await;
+        let (signalwire, sent) = fake_signalwire().await;
+
+        let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire))
+            .with_usage_caps(UsageCaps {
+                max_ai_calls: None,
+                max_sms: Some(2),
+                notice: None,
+            });
+
+        // Different conversations, so nothing serializes them
+        let messages: Vec<SMSMessage> = (0..5)
+            .map(|i| inbound(&format!("m{i}"), &format!("conv-{i}"), "Hi"))
+            .collect();
+        futures_util::future::join_all(messages.iter().map(|sms| consumer.process_message(sms)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(sent.lock().unwrap().len(), 2);
+        let usage = store.daily_usage("+15551230000", usage_date(store.clock())).await.unwrap();
+        assert_eq!(usage.sms_sent, 2);
+    }
+
+    #[tokio::test]
+    async fn test_greeting_counts_toward_the_sms_cap() {
+        let store = Arc::new(InMemoryStore::new());
+        let (signalwire, _) = fake_signalwire().await;
+        let consumer = TursoConsumer::new(store.clone())
+            .with_greeting(Arc::new(signalwire), "Welcome!".into())
+            .with_usage_caps(UsageCaps {
+                max_ai_calls: None,
+                max_sms: Some(5),
+                notice: None,
+            });
+
+        consumer.process_message(inbound("m1", "conv-1", "Hi")).await.unwrap();
+
+        let usage = store.daily_usage("+15551230000", usage_date(store.clock())).await.unwrap();
+        assert_eq!(usage.sms_sent, 1);
+    }
+
+    #[derive(Default)]
+    struct CapturingSink(std::sync::Mutex<Vec<String>>);
+
+    impl EventSink for CapturingSink {
+        fn on_message_stored(&self, message: &Message) {
+            self.0.lock().unwrap().push(format!("stored:{}", message.content));
+        }
+
+        fn on_ai_response(&self, inbound: &SMSMessage, reply: &Message) {
+            self.0.lock().unwrap().push(format!("ai:{}->{}", inbound.id, reply.content));
+        }
+
+        fn on_sms_sent(&self, reply: &Message, to: &crate::PhoneNumber, provider_sid: &str) {
+            self.0.lock().unwrap().push(format!("sent:{}:{}:{}", reply.id, to, provider_sid));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events_fire_in_order_for_one_message() {
+        let store = Arc::new(InMemoryStore::new());
+        let (ai, _) = fake_ai("Reply").await;
+        let (signalwire, _) = fake_signalwire().await;
+        let sink = Arc::new(CapturingSink::default());
+
+        let turso = TursoConsumer::new(store.clone()).with_event_sink(sink.clone());
+        let consumer = AIConsumer::new(store, Arc::new(ai), Arc::new(signalwire))
+            .with_event_sink(sink.clone());
+
+        let sms = inbound("m1", "conv-1", "Hi");
+        turso.process_message(sms.clone()).await.unwrap();
+        consumer.process_message(&sms).await.unwrap();
+
+        // A replay neither regenerates nor resends
+        consumer.process_message(&sms).await.unwrap();
+
+        assert_eq!(
+            *sink.0.lock().unwrap(),
+            [
+                "stored:Hi".to_string(),
+                "ai:m1->Reply".into(),
+                "sent:reply_m1:+15551230000:SM_fake_0".into(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ai_calls_bounded_by_permits() {
+        let store = Arc::new(InMemoryStore::new());
+        let (ai, peak) = fake_ai_slow("Reply", Duration::from_millis(50)).await;
+        let (signalwire, sent) = fake_signalwire().await;
+
+        let consumer = AIConsumer::new(store, Arc::new(ai), Arc::new(signalwire))
+            .with_max_in_flight_ai(2);
+
+        // Different conversations, so only the semaphore limits concurrency
+        let messages: Vec<SMSMessage> = (0..6)
+            .map(|i| inbound(&format!("m{i}"), &format!("conv-{i}"), "Hi"))
+            .collect();
+        let results =
+            futures_util::future::join_all(messages.iter().map(|m| consumer.process_message(m)))
+                .await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(sent.lock().unwrap().len(), 6);
+        assert_eq!(peak.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_generate_assistant_reply_stores_both_messages() {
+        let store = InMemoryStore::new();
+        let guards = ReplyGuards::default();
+        let (ai, requests) = fake_ai("Hi! How can I help?").await;
+
+        for i in 0..(HISTORY_WINDOW + 5) {
+            store
+                .store_message("conv-1".into(), MessageRole::User, format!("old {i}"))
+                .await
+                .unwrap();
+        }
+
+        let metadata = Some(serde_json::json!({ "source": "widget" }));
+        let (message, reply) =
+            generate_assistant_reply(&reply_context(&store, &ai, &guards), "conv-1", "Hello".into(), metadata.clone())
+                .await
+                .unwrap();
+
+        assert_eq!(message.role, MessageRole::User);
+        assert_eq!(message.metadata, metadata);
+        assert_eq!(reply.role, MessageRole::Assistant);
+        assert_eq!(reply.content, "Hi! How can I help?");
+
+        let stored = store.get_conversation_messages("conv-1").await.unwrap();
+        let last_two: Vec<&str> = stored[stored.len() - 2..]
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect();
+        assert_eq!(last_two, ["Hello", "Hi! How can I help?"]);
+
+        // Persona + history window + the new message
+        let sent = &requests.lock().unwrap()[0]["messages"];
+        assert_eq!(sent.as_array().unwrap().len(), HISTORY_WINDOW + 2);
+        assert_eq!(sent[0]["content"], "Persona");
+        assert_eq!(sent[HISTORY_WINDOW + 1]["content"], "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_history_cap_applies_to_ai_context_only() {
+        let store = Arc::new(InMemoryStore::new());
+        let (ai, requests) = fake_ai("Noted").await;
+        let (signalwire, _) = fake_signalwire().await;
+
+        let long = "x".repeat(500);
+        store.store_message("conv-1".into(), MessageRole::User, long.clone()).await.unwrap();
+
+        let consumer = AIConsumer::new(store.clone(), Arc::new(ai), Arc::new(signalwire))
+            .with_history_max_chars(Some(20));
+        consumer.process_message(&inbound("m1", "conv-1", "And another thing")).await.unwrap();
+
+        let sent = requests.lock().unwrap()[0]["messages"].clone();
+        assert_eq!(sent[1]["content"], "x".repeat(20));
+        // The new message itself isn't history
+        assert_eq!(sent[2]["content"], "And another thing");
+
+        // What's stored (and exported) is untouched
+        let stored = store.get_conversation_messages("conv-1").await.unwrap();
+        assert_eq!(stored[0].content, long);
+    }
+
+    #[tokio::test]
+    async fn test_regenerated_reply_uses_history_before_the_message() {
+        let store = InMemoryStore::new();
+        let guards = ReplyGuards::default();
+        let (ai, requests) = fake_ai("Better answer").await;
+
+        let store_as = |role, content: &str| store.store_message("conv-1".into(), role, content.to_string());
+        store_as(MessageRole::User, "Hi").await.unwrap();
+        store_as(MessageRole::Assistant, "Hello!").await.unwrap();
+        let question = store_as(MessageRole::User, "Where is my order?").await.unwrap();
+        let old_reply = store_as(MessageRole::Assistant, "No idea").await.unwrap();
+
+        let reply = regenerate_assistant_reply(
+            &ReplyContext { default_sender_label: Some("AI"), ..reply_context(&store, &ai, &guards) },
+            &question.id,
+        )
+            .await
+            .unwrap();
+        assert_eq!(reply.role, MessageRole::Assistant);
+        assert_eq!(reply.content, "Better answer");
+        assert_eq!(reply.sender_label.as_deref(), Some("AI"));
+
+        // The old reply is kept and isn't part of the new prompt
+        let stored = store.get_conversation_messages("conv-1").await.unwrap();
+        assert_eq!(stored.len(), 5);
+        assert_eq!(stored[4].id, reply.id);
+
+        let sent = requests.lock().unwrap()[0]["messages"].clone();
+        let contents: Vec<&str> = sent.as_array().unwrap().iter().map(|m| m["content"].as_str().unwrap()).collect();
+        assert_eq!(contents, ["Persona", "Hi", "Hello!", "Where is my order?"]);
+
+        let err = regenerate_assistant_reply(&reply_context(&store, &ai, &guards), &old_reply.id)
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&NotAUserMessage(old_reply.id)));
+        let err = regenerate_assistant_reply(&reply_context(&store, &ai, &guards), "missing")
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<NotFound>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_regeneration_counts_toward_the_ai_cap() {
+        let store = InMemoryStore::new();
+        let (ai, requests) = fake_ai("Another answer").await;
+        let guards = ReplyGuards::new(1, UsageCaps { max_ai_calls: Some(1), max_sms: None, notice: None });
+
+        store.ensure_sms_conversation("conv-1", "SMS: +15551230000", "+15551230000").await.unwrap();
+        let question = store
+            .store_message("conv-1".into(), MessageRole::User, "Where is my order?".into())
+            .await
+            .unwrap();
+
+        regenerate_assistant_reply(&reply_context(&store, &ai, &guards), &question.id).await.unwrap();
+        let err = regenerate_assistant_reply(&reply_context(&store, &ai, &guards), &question.id)
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&UsageCapReached("+15551230000".into())));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        let usage = store.daily_usage("+15551230000", usage_date(store.clock())).await.unwrap();
+        assert_eq!(usage.ai_calls, 1);
+    }
+
+    /// Hangs (never waking the task), as a stalled server would, until
+    /// `recovered` is set; then yields `1` and ends
+    struct Stalled {
+        recovered: bool,
+        yielded: bool,
+    }
+
+    impl futures_util::Stream for Stalled {
+        type Item = u32;
+
+        fn poll_next(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<u32>> {
+            if !self.recovered {
+                return std::task::Poll::Pending;
+            }
+            if std::mem::replace(&mut self.yielded, true) {
+                std::task::Poll::Ready(None)
+            } else {
+                std::task::Poll::Ready(Some(1))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalled_poll_times_out_and_loop_recovers() {
+        let timeout = Duration::from_millis(20);
+        let mut stream = Stalled { recovered: false, yielded: false };
+
+        assert_eq!(next_with_timeout(&mut stream, timeout).await, PollOutcome::TimedOut);
+        assert_eq!(next_with_timeout(&mut stream, timeout).await, PollOutcome::TimedOut);
+
+        // The same stream is polled again once the server is back
+        stream.recovered = true;
+        assert_eq!(next_with_timeout(&mut stream, timeout).await, PollOutcome::Item(1));
+        assert_eq!(next_with_timeout(&mut stream, timeout).await, PollOutcome::Ended);
+
+        // Items that are ready are never cut off
+        let mut ready = futures_util::stream::iter([7]);
+        assert_eq!(next_with_timeout(&mut ready, timeout).await, PollOutcome::Item(7));
+    }
+
+    #[tokio::test]
+    async fn test_stalled_stream_is_replaced_only_when_ping_fails() {
+        let timeout = Duration::from_millis(20);
+        let reconnects = std::sync::atomic::AtomicUsize::new(0);
+        let pings = std::sync::atomic::AtomicUsize::new(0);
+        let recovered = || {
+            reconnects.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async { Ok(Stalled { recovered: true, yielded: false }) }
+        };
+
+        // Idle: the server answers twice, then goes away
+        let mut stream = Stalled { recovered: false, yielded: false };
+        let ping = || {
+            let n = pings.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move {
+                anyhow::ensure!(n < 2, "connection refused");
+                Ok(())
+            }
+        };
+        let mut errors = ErrorThreshold::new(3);
+        let mut idle = IdleBackoff::new(Duration::from_millis(1), Duration::from_millis(5));
+        let item = next_or_reconnect(&mut stream, timeout, &mut idle, &mut errors, "Turso", ping, recovered).await.unwrap();
+        assert_eq!(item, Some(1));
+        assert_eq!(pings.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert_eq!(reconnects.load(std::sync::atomic::Ordering::SeqCst), 1);
+        // Idle timeouts aren't errors; the stall is
+        assert_eq!(errors.consecutive(), 1);
+
+        // The fresh stream is kept
+        let ended = next_or_reconnect(&mut stream, timeout, &mut idle, &mut errors, "Turso", ping, recovered).await.unwrap();
+        assert_eq!(ended, None);
+    }
+
+    #[tokio::test]
+    async fn test_quiet_topic_backs_off_until_messages_arrive() {
+        let ms = Duration::from_millis;
+        let mut idle = IdleBackoff::new(ms(10), ms(80));
+        let mut errors = ErrorThreshold::new(3);
+        let reconnect = || async { anyhow::Ok(Stalled { recovered: true, yielded: false }) };
+
+        // Quiet but healthy: empty polls grow the backoff, nothing is an error
+        let mut quiet = Stalled { recovered: false, yielded: false };
+        let polled = tokio::time::timeout(
+            ms(400),
+            next_or_reconnect(&mut quiet, ms(300), &mut idle, &mut errors, "AI", || async { Ok(()) }, reconnect),
+        )
+        .await;
+        assert!(polled.is_err());
+        assert!(idle.on_empty() > ms(10));
+        assert_eq!(errors.consecutive(), 0);
+
+        // A message starts the backoff over
+        let mut busy = Stalled { recovered: true, yielded: false };
+        let item = next_or_reconnect(&mut busy, ms(300), &mut idle, &mut errors, "AI", || async { Ok(()) }, reconnect);
+        assert_eq!(item.await.unwrap(), Some(1));
+        assert_eq!(idle.on_empty(), ms(10));
+    }
+
+    #[tokio::test]
+    async fn test_consumer_loop_stops_when_iggy_stays_unreachable() {
+        let store = Arc::new(InMemoryStore::new());
+        let consumer = TursoConsumer::new(store.clone());
+        let reconnects = std::sync::atomic::AtomicUsize::new(0);
+
+        // Shaped like `TursoConsumer::start`, against a server that never answers
+        let mut stream = Stalled { recovered: false, yielded: false };
+        let mut errors = ErrorThreshold::new(3);
+        let mut idle = IdleBackoff::new(Duration::from_millis(1), Duration::from_millis(5));
+        let stopped = async {
+            loop {
+                let next = next_or_reconnect(
+                    &mut stream,
+                    Duration::from_millis(10),
+                    &mut idle,
+                    &mut errors,
+                    "Turso",
+                    std::future::pending::<Result<()>>,
+                    || {
+                        reconnects.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        async { anyhow::Ok(Stalled { recovered: false, yielded: false }) }
+                    },
+                );
+                let Some(_) = next.await? else { break };
+                consumer.process_message(inbound("m1", "conv-1", "Hello")).await?;
+            }
+            anyhow::Ok(())
+        };
+
+        let err = tokio::time::timeout(Duration::from_secs(5), stopped).await.unwrap().unwrap_err();
+        assert!(err.to_string().starts_with("Turso consumer stopping after 3 polling errors in a row: Iggy stalled"));
+        assert_eq!(reconnects.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert!(store.get_conversation_messages("conv-1").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_two_topics_are_polled_in_turn() {
+        let incoming = futures_util::stream::iter(vec![
+            inbound("a1", "conv-a", "one"),
+            inbound("a2", "conv-a", "two"),
+            inbound("a3", "conv-a", "three"),
+        ]);
+        let other = futures_util::stream::iter(vec![inbound("b1", "conv-b", "hello")]);
+        let mut topics = FairInterleave::new(vec![incoming, other]);
+
+        let store = Arc::new(InMemoryStore::new());
+        let consumer = TursoConsumer::new(store.clone());
+
+        let mut order = Vec::new();
+        while let PollOutcome::Item((topic, sms)) = next_with_timeout(&mut topics, Duration::from_secs(1)).await {
+            order.push((topic, sms.id.clone()));
+            consumer.process_message(sms).await.unwrap();
+        }
+
+        // The busy topic doesn't hold back the other one
+        let order: Vec<_> = order.iter().map(|(topic, id)| (*topic, id.as_str())).collect();
+        assert_eq!(order, [(0, "a1"), (1, "b1"), (0, "a2"), (0, "a3")]);
+        assert_eq!(store.get_conversation_messages("conv-a").await.unwrap().len(), 3);
+        assert_eq!(store.get_conversation_messages("conv-b").await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_poll_batch_scales_with_partitions() {
+        let config = ConsumerConfig {
+            poll_per_partition: 25,
+            ..Default::default()
+        };
+        assert_eq!(config.poll_batch_length(1), 25);
+        assert_eq!(config.poll_batch_length(8), 200);
+        // A topic reporting no partitions still polls
+        assert_eq!(config.poll_batch_length(0), 25);
+
+        let fixed = ConsumerConfig {
+            poll_batch_length: Some(500),
+            ..config
+        };
+        assert_eq!(fixed.poll_batch_length(8), 500);
+        assert_eq!(ConsumerConfig::default().poll_batch_length(4), 40);
+    }
+
+    #[test]
+    fn test_topic_target_parses_stream_and_topic() {
+        let target: TopicTarget = " sms_stream / sms_status ".parse().unwrap();
+        assert_eq!(target.to_string(), "sms_stream/sms_status");
+        assert!("sms_status".parse::<TopicTarget>().is_err());
+        assert!("sms_stream/".parse::<TopicTarget>().is_err());
+        assert_eq!(ConsumerConfig::default().topics, [TopicTarget::default()]);
+    }
+
+    #[test]
+    fn test_idle_backoff_grows_and_resets() {
+        let ms = Duration::from_millis;
+        let mut backoff = IdleBackoff::new(ms(25), ms(150));
+
+        let delays: Vec<Duration> = (0..5).map(|_| backoff.on_empty()).collect();
+        assert_eq!(delays, [ms(25), ms(50), ms(100), ms(150), ms(150)]);
+
+        backoff.on_messages();
+        assert_eq!(backoff.on_empty(), ms(25));
+        assert_eq!(backoff.on_empty(), ms(50));
+    }
+
+    #[test]
+    fn test_consumer_stops_after_too_many_errors_in_a_row() {
+        let mut errors = ErrorThreshold::new(3);
+
+        // A success in between starts the count over
+        errors.on_error("AI", "timeout").unwrap();
+        errors.on_error("AI", "timeout").unwrap();
+        errors.on_success();
+        assert_eq!(errors.consecutive(), 0);
+
+        errors.on_error("AI", "timeout").unwrap();
+        errors.on_error("AI", "timeout").unwrap();
+        let err = errors.on_error("AI", "connection refused").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "AI consumer stopping after 3 polling errors in a row: connection refused"
+        );
+
+        // 0 never gives up
+        let mut forever = ErrorThreshold::new(0);
+        for _ in 0..1_000 {
+            forever.on_error("Turso", "timeout").unwrap();
+        }
+        assert_eq!(forever.consecutive(), 1_000);
+    }
+
+    #[test]
+    fn test_start_strategy_maps_to_polling_strategy() {
+        let cases = [
+            ("next", StartStrategy::Next, PollingStrategy::next()),
+            ("first", StartStrategy::First, PollingStrategy::first()),
+            ("offset:42", StartStrategy::Offset(42), PollingStrategy::offset(42)),
+            (
+                "timestamp:1700000000",
+                StartStrategy::Timestamp(1_700_000_000),
+                PollingStrategy::timestamp(IggyTimestamp::from(1_700_000_000_000_000)),
+            ),
+        ];
+
+        for (raw, strategy, polling) in cases {
+            assert_eq!(raw.parse::<StartStrategy>().unwrap(), strategy);
+
+            let consumer = TursoConsumer::new(Arc::new(InMemoryStore::new()))
+                .with_config(ConsumerConfig { start_strategy: strategy, ..Default::default() });
+            assert_eq!(consumer.config.start_strategy.polling_strategy(), polling);
+        }
+
+        assert!("offset:abc".parse::<StartStrategy>().is_err());
+        assert!("last".parse::<StartStrategy>().is_err());
+    }
+
+    #[test]
+    fn test_history_window_keeps_persona_first() {
+        let messages = (0..15)
+            .map(|i| Message::new("c".into(), MessageRole::User, i.to_string()))
+            .collect();
+
+        let history = build_ai_history("persona", messages);
+
+        assert_eq!(history.len(), HISTORY_WINDOW + 1);
+        assert_eq!(history[0].role, "system");
+        assert_eq!(history[1].content, "5");
+        assert_eq!(history.last().unwrap().content, "14");
+    }
 }
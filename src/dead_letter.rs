@@ -0,0 +1,243 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use iggy::clients::client::IggyClient;
+use iggy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, warn};
+
+use crate::consumers::TopicTarget;
+use crate::message_broker::{decode_sms, SMSMessage};
+
+/// Topic (in the stream the payload came from) that rejected payloads are
+/// published to
+pub const DEAD_LETTER_TOPIC: &str = "sms_dead_letter";
+
+/// A polled message the consumers couldn't use, and why
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub reason: String,
+    /// `<stream>/<topic>` it was polled from
+    pub source: String,
+    pub offset: u64,
+    /// The original payload (lossy UTF-8)
+    pub payload: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Where rejected messages go (the Iggy topic, or a mock in tests)
+pub trait DeadLetterSink: Send + Sync {
+    /// Publish `letter` to the dead letter topic of `stream`
+    fn dead_letter(&self, stream: &str, letter: DeadLetter) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// -----------------------------
+/// Iggy Dead Letter Queue
+/// -----------------------------
+/// Publishes `DeadLetter`s as JSON to `DEAD_LETTER_TOPIC` of the stream
+/// each payload was polled from, creating the topics on connect. Nothing
+/// consumes them; inspect with the Iggy CLI and republish fixed payloads
+/// by hand.
+pub struct IggyDeadLetterQueue {
+    producers: HashMap<String, IggyProducer>,
+}
+
+impl IggyDeadLetterQueue {
+    /// One producer per stream among `topics`
+    pub async fn connect(client: Arc<IggyClient>, topics: &[TopicTarget]) -> Result<Self> {
+        let mut producers = HashMap::new();
+        for target in topics {
+            if !producers.contains_key(&target.stream) {
+                let producer = Self::producer(&client, &target.stream).await?;
+                producers.insert(target.stream.clone(), producer);
+            }
+        }
+        Ok(Self { producers })
+    }
+
+    async fn producer(client: &IggyClient, stream: &str) -> Result<IggyProducer> {
+        let producer = client
+            .producer(stream, DEAD_LETTER_TOPIC)
+            .context("Failed to create dead letter producer")?
+            .direct(
+                DirectConfig::builder()
+                    .linger_time(IggyDuration::new(Duration::from_millis(1)))
+                    .build(),
+            )
+            .partitioning(Partitioning::balanced())
+            .create_stream_if_not_exists()
+            .create_topic_if_not_exists(1, None, IggyExpiry::ServerDefault, MaxTopicSize::ServerDefault)
+            .build();
+
+        producer.init().await?;
+        Ok(producer)
+    }
+}
+
+impl DeadLetterSink for IggyDeadLetterQueue {
+    async fn dead_letter(&self, stream: &str, letter: DeadLetter) -> Result<()> {
+        let producer = self
+            .producers
+            .get(stream)
+            .with_context(|| format!("No dead letter producer for stream {stream}"))?;
+        let payload = serde_json::to_string(&letter)?;
+        let msg = IggyMessage::builder().payload(payload.into()).build()?;
+        producer.send(vec![msg]).await?;
+        Ok(())
+    }
+}
+
+/// -----------------------------
+/// Validated decode
+/// -----------------------------
+/// The SMS in a polled message, or why it can't be used: a payload that
+/// doesn't decode or fails `SMSMessage::validate` (e.g. from another
+/// producer version)
+pub fn validated_sms(msg: &IggyMessage, now: DateTime<Utc>) -> Result<SMSMessage, String> {
+    decode_sms(msg)
+        .and_then(|sms| sms.validate(now).map(|_| sms))
+        .map_err(|e| format!("{e:#}"))
+}
+
+/// `validated_sms`, handing a rejected payload to `sink` and returning
+/// `None`. The caller then skips it like a processed message rather than
+/// stopping the consumer. Only one consumer group should pass a sink,
+/// or each rejected payload is dead-lettered once per group; the others
+/// use `validated_sms` and skip. A failed publish is logged and the
+/// payload skipped all the same: it would fail validation again on
+/// redelivery.
+pub async fn decode_or_dead_letter<Q: DeadLetterSink>(
+    msg: &IggyMessage,
+    source: &TopicTarget,
+    sink: &Q,
+    now: DateTime<Utc>,
+) -> Option<SMSMessage> {
+    let reason = match validated_sms(msg, now) {
+        Ok(sms) => return Some(sms),
+        Err(reason) => reason,
+    };
+
+    warn!("Dead-lettering message at offset {} from {source}: {reason}", msg.header.offset);
+    let letter = DeadLetter {
+        reason,
+        source: source.to_string(),
+        offset: msg.header.offset,
+        payload: String::from_utf8_lossy(&msg.payload).into_owned(),
+        failed_at: now,
+    };
+    if let Err(e) = sink.dead_letter(&source.stream, letter).await {
+        error!(
+            "Failed to dead-letter message at offset {} from {source}, skipping it: {e:#}",
+            msg.header.offset
+        );
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingSink(Mutex<Vec<(String, DeadLetter)>>);
+
+    impl DeadLetterSink for RecordingSink {
+        async fn dead_letter(&self, stream: &str, letter: DeadLetter) -> Result<()> {
+            self.0.lock().unwrap().push((stream.to_string(), letter));
+            Ok(())
+        }
+    }
+
+    struct FailingSink;
+
+    impl DeadLetterSink for FailingSink {
+        async fn dead_letter(&self, _stream: &str, _letter: DeadLetter) -> Result<()> {
+            anyhow::bail!("Iggy unreachable")
+        }
+    }
+
+    fn target(s: &str) -> TopicTarget {
+        s.parse().unwrap()
+    }
+
+    fn polled(payload: serde_json::Value) -> IggyMessage {
+        IggyMessage::builder()
+            .id(7)
+            .payload(payload.to_string().into())
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_payload_missing_conversation_id_goes_to_the_dlq() {
+        let sink = RecordingSink::default();
+        let payload = serde_json::json!({
+            "id": "m1",
+            "from": "+15550001111",
+            "to": "+15552223333",
+            "body": "Hello",
+            "timestamp": 1_700_000_000,
+        });
+
+        let sms = decode_or_dead_letter(&polled(payload.clone()), &target("partner_stream/sms_incoming"), &sink, Utc::now()).await;
+        assert!(sms.is_none());
+
+        let letters = sink.0.lock().unwrap();
+        assert_eq!(letters.len(), 1);
+        let (stream, letter) = &letters[0];
+        // Published next to the topic it came from
+        assert_eq!(stream, "partner_stream");
+        assert!(letter.reason.contains("conversation_id"), "{}", letter.reason);
+        assert_eq!(letter.source, "partner_stream/sms_incoming");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&letter.payload).unwrap(), payload);
+    }
+
+    #[tokio::test]
+    async fn test_failed_dead_letter_publish_still_skips_the_payload() {
+        let msg = polled(serde_json::json!({ "id": "m1" }));
+
+        assert!(decode_or_dead_letter(&msg, &target("sms_stream/sms_incoming"), &FailingSink, Utc::now()).await.is_none());
+        assert!(validated_sms(&msg, Utc::now()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_out_of_range_values_are_rejected() {
+        let sink = RecordingSink::default();
+        let t = TopicTarget::default();
+        let sms = |conversation_id: &str, timestamp: i64| {
+            polled(serde_json::json!({
+                "id": "m1",
+                "from": "+15550001111",
+                "to": "+15552223333",
+                "body": "Hello",
+                "timestamp": timestamp,
+                "conversation_id": conversation_id,
+            }))
+        };
+
+        let valid = decode_or_dead_letter(&sms("conv-1", 1_700_000_000), &t, &sink, Utc::now()).await;
+        assert_eq!(valid.unwrap().conversation_id, "conv-1");
+        // Unknown receive time
+        assert!(decode_or_dead_letter(&sms("conv-1", 0), &t, &sink, Utc::now()).await.is_some());
+
+        // Milliseconds instead of seconds
+        assert!(decode_or_dead_letter(&sms("conv-1", 1_700_000_000_000), &t, &sink, Utc::now()).await.is_none());
+        assert!(decode_or_dead_letter(&sms("conv-1", -1), &t, &sink, Utc::now()).await.is_none());
+        assert!(decode_or_dead_letter(&sms(" ", 1_700_000_000), &t, &sink, Utc::now()).await.is_none());
+
+        let reasons: Vec<String> = sink.0.lock().unwrap().iter().map(|(_, l)| l.reason.clone()).collect();
+        assert_eq!(
+            reasons,
+            [
+                "timestamp 1700000000000 is in the future",
+                "negative timestamp -1",
+                "empty conversation_id",
+            ]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use std::fmt::Write;
+use std::future::Future;
+use std::time::Duration;
+
+/// A check that doesn't answer within this counts as failed
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// -----------------------------
+/// Dependency Checks
+/// -----------------------------
+/// Outcome of one `doctor` check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: String,
+    /// `None` when the check passed
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Run one check, bounded by `timeout`
+pub async fn run_check<F>(name: &str, timeout: Duration, check: F) -> CheckResult
+where
+    F: Future<Output = Result<()>>,
+{
+    let error = match tokio::time::timeout(timeout, check).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("{e:#}")),
+        Err(_) => Some(format!("no answer within {:?}", timeout)),
+    };
+
+    CheckResult {
+        name: name.to_string(),
+        error,
+    }
+}
+
+/// Pass/fail table, one row per check
+pub fn render_table(results: &[CheckResult]) -> String {
+    let width = results.iter().map(|r| r.name.len()).max().unwrap_or(0);
+    let mut table = String::new();
+
+    for result in results {
+        let status = if result.passed() { "PASS" } else { "FAIL" };
+        let _ = write!(table, "{:<width$}  {}", result.name, status);
+        if let Some(error) = &result.error {
+            let _ = write!(table, "  {}", error);
+        }
+        table.push('\n');
+    }
+
+    table
+}
+
+/// Process exit code: non-zero as soon as one check failed
+pub fn exit_code(results: &[CheckResult]) -> i32 {
+    if results.iter().all(CheckResult::passed) {
+        0
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_any_failed_check_fails_the_run() {
+        let results = vec![
+            run_check("turso", CHECK_TIMEOUT, async { Ok(()) }).await,
+            run_check("groq", CHECK_TIMEOUT, async { anyhow::bail!("HTTP 401") }).await,
+            run_check("iggy", Duration::from_millis(10), std::future::pending()).await,
+        ];
+
+        assert!(results[0].passed());
+        assert_eq!(results[1].error.as_deref(), Some("HTTP 401"));
+        assert!(results[2].error.as_deref().unwrap().contains("no answer"));
+        assert_eq!(exit_code(&results), 1);
+        assert_eq!(exit_code(&results[..1]), 0);
+
+        let table = render_table(&results);
+        assert!(table.contains("turso  PASS\n"));
+        assert!(table.contains("groq   FAIL  HTTP 401\n"));
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use tracing::info;
+
+use crate::message_broker::SMSMessage;
+use crate::models::Message;
+use crate::phone_number::PhoneNumber;
+
+/// -----------------------------
+/// Event Sink
+/// -----------------------------
+/// Hook for integrations (Slack webhooks, analytics) that react to what
+/// the consumers do. Every method defaults to a no-op, so a sink only
+/// implements what it cares about.
+///
+/// Called inline on the consumer task after the step succeeded: keep it
+/// quick and spawn anything slow. A sink can't fail processing.
+pub trait EventSink: Send + Sync {
+    /// An inbound SMS was stored as a user message
+    fn on_message_stored(&self, _message: &Message) {}
+
+    /// A reply to `inbound` (AI-generated, or the fallback when the AI
+    /// failed) was generated and stored
+    fn on_ai_response(&self, _inbound: &SMSMessage, _reply: &Message) {}
+
+    /// A reply was accepted by the carrier
+    fn on_sms_sent(&self, _reply: &Message, _to: &PhoneNumber, _provider_sid: &str) {}
+}
+
+/// Ignores every event; what consumers use unless given a sink
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopSink;
+
+impl EventSink for NoopSink {}
+
+/// Default sink for consumers
+pub fn noop_sink() -> Arc<dyn EventSink> {
+    Arc::new(NoopSink)
+}
+
+/// Logs each event at info level (ids only, no message text)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoggingSink;
+
+impl EventSink for LoggingSink {
+    fn on_message_stored(&self, message: &Message) {
+        info!("event=message_stored conv={} message={}", message.conversation_id, message.id);
+    }
+
+    fn on_ai_response(&self, inbound: &SMSMessage, reply: &Message) {
+        info!(
+            "event=ai_response conv={} inbound={} reply={}",
+            reply.conversation_id, inbound.id, reply.id
+        );
+    }
+
+    fn on_sms_sent(&self, reply: &Message, to: &PhoneNumber, provider_sid: &str) {
+        info!(
+            "event=sms_sent conv={} reply={} to={} sid={}",
+            reply.conversation_id, reply.id, to, provider_sid
+        );
+    }
+}
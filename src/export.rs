@@ -0,0 +1,413 @@
+use anyhow::Result;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use serde::ser::{Error as _, SerializeSeq};
+use serde::{Serialize, Serializer};
+use std::cell::RefCell;
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::models::{Conversation, Message, MessageCursor};
+use crate::storage::ConversationStorage;
+
+/// Bump when the layout of the bundle changes
+const EXPORT_FORMAT_VERSION: u32 = 1;
+/// Size of the chunks handed to the HTTP body
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Messages fetched per query by the paged export
+pub const EXPORT_PAGE_SIZE: u32 = 500;
+
+/// -----------------------------
+/// JSON / Text Exports
+/// -----------------------------
+#[derive(Serialize)]
+struct ConversationExport<'a, M> {
+    conversation: &'a Conversation,
+    messages: M,
+}
+
+/// Conversation plus all of its messages as pretty-printed JSON
+pub fn write_conversation_json<W: Write>(
+    writer: W,
+    conversation: &Conversation,
+    messages: &[Message],
+) -> Result<()> {
+    serde_json::to_writer_pretty(
+        writer,
+        &ConversationExport {
+            conversation,
+            messages,
+        },
+    )?;
+    Ok(())
+}
+
+/// Human-readable transcript, one line per message
+pub fn write_transcript<W: Write>(
+    mut writer: W,
+    conversation: &Conversation,
+    messages: &[Message],
+) -> Result<()> {
+    write_transcript_header(&mut writer, conversation)?;
+    for message in messages {
+        write_transcript_line(&mut writer, message)?;
+    }
+
+    Ok(())
+}
+
+fn write_transcript_header<W: Write>(writer: &mut W, conversation: &Conversation) -> Result<()> {
+    writeln!(
+        writer,
+        "{}",
+        conversation.title.as_deref().unwrap_or("Untitled conversation")
+    )?;
+    writeln!(writer, "Conversation {}", conversation.id)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// `[time] role: content`, or `[time] role (label): content` when labelled
+fn write_transcript_line<W: Write>(writer: &mut W, message: &Message) -> Result<()> {
+    write!(
+        writer,
+        "[{}] {}",
+        message.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        message.role.as_str()
+    )?;
+    if let Some(label) = &message.sender_label {
+        write!(writer, " ({label})")?;
+    }
+    writeln!(writer, ": {}", message.content)?;
+    Ok(())
+}
+
+/// -----------------------------
+/// Paged Messages
+/// -----------------------------
+/// Walk a conversation through `fetch_page` (messages after a cursor, or
+/// from the start for `None`, oldest first; empty once exhausted), one
+/// page in memory at a time. Returns how many messages were visited.
+fn for_each_page<F, V>(fetch_page: &mut F, mut visit: V) -> Result<usize>
+where
+    F: FnMut(Option<&MessageCursor>) -> Result<Vec<Message>>,
+    V: FnMut(&Message) -> Result<()>,
+{
+    let mut cursor = None;
+    let mut count = 0;
+
+    loop {
+        let page = fetch_page(cursor.as_ref())?;
+        let Some(last) = page.last() else {
+            return Ok(count);
+        };
+        cursor = Some(MessageCursor::after(last));
+
+        for message in &page {
+            visit(message)?;
+        }
+        count += page.len();
+    }
+}
+
+/// Serializes as a JSON array, pulling pages while it is written
+struct PagedMessages<'f, F>(RefCell<&'f mut F>);
+
+impl<F> Serialize for PagedMessages<'_, F>
+where
+    F: FnMut(Option<&MessageCursor>) -> Result<Vec<Message>>,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut fetch_page = self.0.borrow_mut();
+        let mut seq = serializer.serialize_seq(None)?;
+        let mut cursor = None;
+
+        loop {
+            let page = fetch_page(cursor.as_ref()).map_err(S::Error::custom)?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            cursor = Some(MessageCursor::after(last));
+
+            for message in &page {
+                seq.serialize_element(message)?;
+            }
+        }
+
+        seq.end()
+    }
+}
+
+#[derive(Serialize)]
+struct ExportMetadata<'a> {
+    format_version: u32,
+    conversation_id: &'a str,
+    message_count: usize,
+    exported_at: DateTime<Utc>,
+}
+
+/// -----------------------------
+/// Zip Bundle
+/// -----------------------------
+/// Write `conversation.json`, `transcript.txt` and `metadata.json` as a zip.
+/// The writer only needs `Write`: entries are emitted in order with data
+/// descriptors, so the output can go straight to a socket.
+pub fn write_export_zip<W: Write>(
+    writer: W,
+    conversation: &Conversation,
+    messages: &[Message],
+) -> Result<()> {
+    write_export_zip_paged(writer, conversation, |cursor| {
+        Ok(if cursor.is_none() { messages.to_vec() } else { Vec::new() })
+    })
+}
+
+/// `conversation-<id>.zip`, keeping only `[A-Za-z0-9._-]` of the id so
+/// it is safe inside a quoted `Content-Disposition` filename
+pub fn export_filename(conversation_id: &str) -> String {
+    let id: String = conversation_id
+        .chars()
+        .map(|c| match c {
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '.' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+
+    format!("conversation-{id}.zip")
+}
+
+/// `write_export_zip` for conversations too long to load at once: the
+/// messages are read through `fetch_page` (see `for_each_page`), twice,
+/// since the JSON and the transcript are separate entries.
+pub fn write_export_zip_paged<W, F>(writer: W, conversation: &Conversation, mut fetch_page: F) -> Result<()>
+where
+    W: Write,
+    F: FnMut(Option<&MessageCursor>) -> Result<Vec<Message>>,
+{
+    let mut zip = ZipWriter::new_stream(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("conversation.json", options)?;
+    serde_json::to_writer_pretty(
+        &mut zip,
+        &ConversationExport {
+            conversation,
+            messages: PagedMessages(RefCell::new(&mut fetch_page)),
+        },
+    )?;
+
+    zip.start_file("transcript.txt", options)?;
+    write_transcript_header(&mut zip, conversation)?;
+    let message_count = for_each_page(&mut fetch_page, |message| write_transcript_line(&mut zip, message))?;
+
+    zip.start_file("metadata.json", options)?;
+    serde_json::to_writer_pretty(
+        &mut zip,
+        &ExportMetadata {
+            format_version: EXPORT_FORMAT_VERSION,
+            conversation_id: &conversation.id,
+            message_count,
+            exported_at: Utc::now(),
+        },
+    )?;
+
+    zip.finish()?.flush()?;
+    Ok(())
+}
+
+/// Build the zip on a blocking thread and yield it in chunks, so the
+/// archive is never held in memory as a whole.
+///
+/// Use with `axum::body::Body::from_stream`. If the client goes away the
+/// channel closes and the writer stops with a broken pipe.
+pub fn stream_export_zip(
+    conversation: Conversation,
+    messages: Vec<Message>,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    stream_zip(move |writer| write_export_zip(writer, &conversation, &messages))
+}
+
+/// `stream_export_zip` reading the messages from `store` a page at a time,
+/// so neither the messages nor the archive are ever held as a whole
+pub fn stream_export_zip_paged<S: ConversationStorage + 'static>(
+    store: Arc<S>,
+    conversation: Conversation,
+    page_size: u32,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let runtime = tokio::runtime::Handle::current();
+
+    stream_zip(move |writer| {
+        write_export_zip_paged(writer, &conversation, |cursor| {
+            runtime.block_on(store.get_conversation_messages_after(&conversation.id, cursor, page_size))
+        })
+    })
+}
+
+/// Run `write` on a blocking thread, yielding what it writes in chunks
+fn stream_zip<F>(write: F) -> impl Stream<Item = io::Result<Bytes>>
+where
+    F: FnOnce(BufWriter<ChannelWriter>) -> Result<()> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(4);
+
+    tokio::task::spawn_blocking(move || {
+        let writer = BufWriter::with_capacity(CHUNK_SIZE, ChannelWriter(tx.clone()));
+
+        if let Err(e) = write(writer) {
+            let _ = tx.blocking_send(Err(io::Error::other(e)));
+        }
+    });
+
+    futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    })
+}
+
+/// `Write` adapter that forwards every write as a body chunk
+struct ChannelWriter(mpsc::Sender<io::Result<Bytes>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MessageRole;
+    use crate::storage::ConversationStorage;
+    use futures_util::StreamExt;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn test_export_filename_is_header_safe() {
+        assert_eq!(export_filename("sms_1f2e-3d"), "conversation-sms_1f2e-3d.zip");
+        assert_eq!(
+            export_filename("x\"\r\nSet-Cookie: a=b"),
+            "conversation-x___Set-Cookie__a_b.zip"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_zip_contains_expected_entries() {
+        let conversation = Conversation::new(Some("Support".into()));
+        let messages = vec![
+            Message::new(conversation.id.clone(), MessageRole::User, "Hi".into()),
+            Message::new(conversation.id.clone(), MessageRole::Assistant, "Hello!".into()),
+        ];
+
+        let mut bytes = Vec::new();
+        let mut stream = Box::pin(stream_export_zip(conversation.clone(), messages));
+        while let Some(chunk) = stream.next().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(names, ["conversation.json", "metadata.json", "transcript.txt"]);
+
+        let mut transcript = String::new();
+        archive
+            .by_name("transcript.txt")
+            .unwrap()
+            .read_to_string(&mut transcript)
+            .unwrap();
+        assert!(transcript.contains("user: Hi"), "{transcript}");
+        assert!(transcript.contains("assistant: Hello!"), "{transcript}");
+
+        let metadata: serde_json::Value =
+            serde_json::from_reader(archive.by_name("metadata.json").unwrap()).unwrap();
+        assert_eq!(metadata["conversation_id"], conversation.id.as_str());
+        assert_eq!(metadata["message_count"], 2);
+    }
+
+    async fn seeded_store(count: usize) -> (Arc<crate::InMemoryStore>, Conversation) {
+        let store = crate::InMemoryStore::new();
+        let conversation = store.create_conversation(Some("Long".into()), None).await.unwrap();
+        let start = conversation.created_at;
+
+        let messages = (0..count)
+            .map(|i| Message {
+                created_at: start + chrono::Duration::seconds(i as i64),
+                ..Message::new(conversation.id.clone(), MessageRole::User, format!("message {i}"))
+            })
+            .collect();
+        store.store_messages_batch(messages).await.unwrap();
+
+        (Arc::new(store), conversation)
+    }
+
+    #[tokio::test]
+    async fn test_paged_export_holds_one_page_at_a_time() {
+        let (store, conversation) = seeded_store(1050).await;
+        let mut pages = Vec::new();
+
+        write_export_zip_paged(io::sink(), &conversation, |cursor| {
+            let page = futures_util::FutureExt::now_or_never(
+                store.get_conversation_messages_after(&conversation.id, cursor, 100),
+            )
+            .unwrap()?;
+            pages.push(page.len());
+            Ok(page)
+        })
+        .unwrap();
+
+        // Eleven pages plus the empty one ending each of the two passes
+        assert_eq!(pages.len(), 24);
+        assert!(pages.iter().all(|len| *len <= 100), "{pages:?}");
+        assert_eq!(pages.iter().sum::<usize>(), 2 * 1050);
+    }
+
+    #[tokio::test]
+    async fn test_paged_export_streams_every_message_in_order() {
+        let (store, conversation) = seeded_store(2500).await;
+
+        let mut bytes = Vec::new();
+        let mut chunks = 0;
+        let mut stream = Box::pin(stream_export_zip_paged(store, conversation.clone(), 100));
+        while let Some(chunk) = stream.next().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+            chunks += 1;
+        }
+        assert!(chunks > 1);
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let export: serde_json::Value =
+            serde_json::from_reader(archive.by_name("conversation.json").unwrap()).unwrap();
+        assert_eq!(export["conversation"]["id"], conversation.id.as_str());
+        let contents: Vec<&str> = export["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["content"].as_str().unwrap())
+            .collect();
+        let expected: Vec<String> = (0..2500).map(|i| format!("message {i}")).collect();
+        assert_eq!(contents, expected);
+
+        let mut transcript = String::new();
+        archive
+            .by_name("transcript.txt")
+            .unwrap()
+            .read_to_string(&mut transcript)
+            .unwrap();
+        assert_eq!(transcript.lines().count(), 3 + 2500);
+        assert!(transcript.ends_with("user: message 2499\n"));
+
+        let metadata: serde_json::Value =
+            serde_json::from_reader(archive.by_name("metadata.json").unwrap()).unwrap();
+        assert_eq!(metadata["message_count"], 2500);
+    }
+}
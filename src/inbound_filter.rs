@@ -0,0 +1,51 @@
+/// -----------------------------
+/// Inbound Filter
+/// -----------------------------
+/// Drops inbound SMS whose body contains a blocked phrase (known spam,
+/// prohibited content) before any AI or database work is done.
+/// Matching is a case-insensitive substring check.
+#[derive(Debug, Clone, Default)]
+pub struct InboundFilter {
+    /// Lowercased, non-empty patterns
+    patterns: Vec<String>,
+}
+
+impl InboundFilter {
+    pub fn new(patterns: Vec<String>) -> Self {
+        Self {
+            patterns: patterns
+                .into_iter()
+                .map(|p| p.trim().to_lowercase())
+                .filter(|p| !p.is_empty())
+                .collect(),
+        }
+    }
+
+    /// The first pattern `body` matches, if any
+    pub fn blocked_by(&self, body: &str) -> Option<&str> {
+        let body = body.to_lowercase();
+
+        self.patterns
+            .iter()
+            .find(|p| body.contains(p.as_str()))
+            .map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_case_insensitively() {
+        let filter = InboundFilter::new(vec!["Free Bitcoin".into(), "  ".into()]);
+
+        assert_eq!(filter.blocked_by("Claim your FREE bitcoin now"), Some("free bitcoin"));
+        assert_eq!(filter.blocked_by("Where is my order?"), None);
+        assert!(InboundFilter::default().blocked_by("anything").is_none());
+    }
+}
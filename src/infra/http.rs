@@ -0,0 +1,64 @@
+use anyhow::{Context, Result};
+use reqwest::Certificate;
+use std::fs;
+use std::path::Path;
+
+/// Load an extra root CA (PEM) to trust on top of the system roots,
+/// e.g. for a TLS-inspecting corporate proxy
+pub fn load_root_certificate(path: impl AsRef<Path>) -> Result<Certificate> {
+    let path = path.as_ref();
+    let pem = fs::read(path)
+        .with_context(|| format!("Failed to read CA certificate {}", path.display()))?;
+
+    parse_root_certificate(&pem)
+        .with_context(|| format!("Invalid CA certificate {}", path.display()))
+}
+
+fn parse_root_certificate(pem: &[u8]) -> Result<Certificate> {
+    let cert = Certificate::from_pem(pem).context("Not a PEM certificate")?;
+
+    // from_pem is lenient; a client build is what actually validates it
+    reqwest::Client::builder()
+        .add_root_certificate(cert.clone())
+        .build()
+        .context("Certificate rejected by TLS backend")?;
+
+    Ok(cert)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_CA: &str = "-----BEGIN CERTIFICATE-----
+MIIBkzCCATmgAwIBAgIULbZsuPD75OK2KFvB8X5cCbkSyh0wCgYIKoZIzj0EAwIw
+HjEcMBoGA1UEAwwTc21zLXNlcnZpY2UgdGVzdCBDQTAgFw0yNjEwMTYxMzMwMjda
+GA8yMTI2MDkyMjEzMzAyN1owHjEcMBoGA1UEAwwTc21zLXNlcnZpY2UgdGVzdCBD
+QTBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABIrU4uOclI3QxJHnYZULATid+qyX
+TLAPbqUDE9D60XVQoWk2hffe4+vb/pAL10A1jGrCuqIJB0mtUxwpRVcL9PKjUzBR
+MB0GA1UdDgQWBBTy0DUD51nZw0spQH07I46ePciwIzAfBgNVHSMEGDAWgBTy0DUD
+51nZw0spQH07I46ePciwIzAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0gA
+MEUCIQD34hoElgxQfpSsaIkTj/SJ7qOFQK8C7JEQza2y+Gr4qgIgOaoOCu7F37nP
+SsNLkT58nTCDcUZPhXGBl4E1lGDoZP0=
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn test_root_certificate_validation() {
+        let cert = parse_root_certificate(TEST_CA.as_bytes()).unwrap();
+
+        crate::ConversationStore::new("https://db.example".into(), "token".into())
+            .with_root_certificate(cert.clone())
+            .unwrap();
+        crate::AIService::new("model".into(), "key".into())
+            .with_root_certificate(cert)
+            .unwrap();
+
+        let garbage = "-----BEGIN CERTIFICATE-----\nbm90IGEgY2VydA==\n-----END CERTIFICATE-----\n";
+        let err = parse_root_certificate(garbage.as_bytes()).unwrap_err();
+        assert!(format!("{err:#}").contains("Certificate rejected"), "{err:#}");
+
+        let err = load_root_certificate("/nonexistent/ca.pem").unwrap_err();
+        assert!(err.to_string().contains("Failed to read CA certificate"));
+    }
+}
@@ -1 +1,2 @@
 pub mod iggy;
+pub mod http;
@@ -1,18 +1,48 @@
 pub mod models;
 pub mod store;
+pub mod storage;
 pub mod ai_service;
 pub mod signalwire;
+pub mod messages;
 pub mod zero_copy;
 pub mod message_broker;
+pub mod batcher;
 pub mod consumers;
 pub mod infra;
 pub mod app_config;
 pub mod broker_config;
+pub mod api_logging;
+pub mod concurrency_limit;
+pub mod rate_limit;
+pub mod api_error;
+pub mod export;
+pub mod inbound_filter;
+pub mod replay_guard;
+pub mod doctor;
+pub mod phone_number;
+pub mod usage_caps;
+pub mod normalize;
+pub mod events;
+pub mod branding;
+pub mod markdown;
+pub mod prompt_template;
+pub mod segments;
+pub mod scheduler;
+pub mod clock;
+pub mod dead_letter;
+pub mod outbound_audit;
+pub mod webhook_signature;
+pub mod util;
+
+#[cfg(test)]
+mod test_support;
 
 pub use models::{Conversation, Message, MessageRole};
 pub use store::ConversationStore;
+pub use storage::{ConversationStorage, InMemoryStore};
 pub use ai_service::{AIMessage, AIService};
 pub use signalwire::SignalWireClient;
+pub use phone_number::PhoneNumber;
 use anyhow::Result;
 /// Connect to a Turso database using HTTP API
 pub async fn connect_turso(database_url: &str, auth_token: &str) -> Result<ConversationStore> {
@@ -1,9 +1,99 @@
 use anyhow::Result;
-use conversation_store::{ConversationStore, MessageRole};
+use clap::{Parser, Subcommand};
+use conversation_store::app_config::AppConfig;
+use conversation_store::doctor::{exit_code, render_table, run_check, CHECK_TIMEOUT};
+use conversation_store::infra::http::load_root_certificate;
+use conversation_store::infra::iggy::connect_iggy;
+use conversation_store::{AIService, ConversationStorage, ConversationStore, MessageRole, SignalWireClient};
+use iggy::prelude::SystemClient;
 use std::env;
 
+/// =============================
+/// CLI
+/// =============================
+#[derive(Parser)]
+#[command(name = "api-server")]
+#[command(about = "Conversation store demo and deployment checks")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Store and print a demo conversation (the default)
+    Demo,
+    /// Check that every configured dependency is reachable and accepts
+    /// its credentials; exits non-zero if any check fails
+    Doctor,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    match Cli::parse().command.unwrap_or(Command::Demo) {
+        Command::Demo => demo().await,
+        Command::Doctor => std::process::exit(doctor().await),
+    }
+}
+
+async fn doctor() -> i32 {
+    let config = match AppConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("config  FAIL  {e:#}");
+            return 1;
+        }
+    };
+
+    let cert = match config.ca_cert_path.as_ref().map(load_root_certificate).transpose() {
+        Ok(cert) => cert,
+        Err(e) => {
+            eprintln!("ca_cert  FAIL  {e:#}");
+            return 1;
+        }
+    };
+
+    let turso = async {
+        let mut store = ConversationStore::new(config.turso_db_url.clone(), config.turso_auth_token.clone());
+        if let Some(cert) = &cert {
+            store = store.with_root_certificate(cert.clone())?;
+        }
+        store.health_check().await
+    };
+
+    let groq = async {
+        let mut ai = AIService::new(config.groq_model.clone(), config.groq_api_key.clone());
+        if let Some(cert) = &cert {
+            ai = ai.with_root_certificate(cert.clone())?;
+        }
+        ai.health_check().await
+    };
+
+    let signalwire = SignalWireClient::new(
+        config.signalwire_project_id.clone(),
+        config.signalwire_auth_token.clone(),
+        config.signalwire_space_url.clone(),
+        config.signalwire_from_number.clone(),
+    );
+
+    let iggy = async {
+        let client = connect_iggy().await?;
+        client.ping().await?;
+        Ok(())
+    };
+
+    let results = vec![
+        run_check("turso", CHECK_TIMEOUT, turso).await,
+        run_check("groq", CHECK_TIMEOUT, groq).await,
+        run_check("signalwire", CHECK_TIMEOUT, signalwire.health_check()).await,
+        run_check("iggy", CHECK_TIMEOUT, iggy).await,
+    ];
+
+    print!("{}", render_table(&results));
+    exit_code(&results)
+}
+
+async fn demo() -> Result<()> {
     dotenvy::dotenv().ok();
 
     let database_url = env::var("TURSO_DATABASE_URL")?;
@@ -0,0 +1,216 @@
+use std::borrow::Cow;
+
+/// Paired inline markers, longest first so `**` isn't read as two `*`
+const EMPHASIS_MARKERS: [&str; 5] = ["**", "__", "~~", "*", "_"];
+
+/// -----------------------------
+/// Markdown Policy
+/// -----------------------------
+/// What the AI consumer does with markdown in AI replies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MarkdownPolicy {
+    /// Send and store replies as generated
+    #[default]
+    Keep,
+    /// Strip when sending; store the reply as generated
+    Send,
+    /// Strip before storing, so the stored reply is what was sent
+    Store,
+}
+
+impl std::str::FromStr for MarkdownPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "keep" => Ok(MarkdownPolicy::Keep),
+            "send" => Ok(MarkdownPolicy::Send),
+            "store" => Ok(MarkdownPolicy::Store),
+            other => anyhow::bail!("Unknown markdown policy: {other}"),
+        }
+    }
+}
+
+impl MarkdownPolicy {
+    /// What to store for a freshly generated reply
+    pub fn stored_text(self, reply: String) -> String {
+        match self {
+            MarkdownPolicy::Store => strip_markdown_for_sms(&reply),
+            _ => reply,
+        }
+    }
+
+    /// What to send for a stored reply
+    pub fn outbound_text(self, stored: &str) -> Cow<'_, str> {
+        match self {
+            MarkdownPolicy::Send => Cow::Owned(strip_markdown_for_sms(stored)),
+            _ => Cow::Borrowed(stored),
+        }
+    }
+}
+
+/// -----------------------------
+/// Markdown Stripping
+/// -----------------------------
+/// Plain-text version of a markdown reply, for SMS where the markup shows
+/// up literally: emphasis and inline-code markers are dropped, headers,
+/// quotes and bullets lose their prefix (numbered items keep theirs),
+/// code fences are removed around their contents and `[text](url)`
+/// becomes `text (url)`. Markers inside words (`snake_case`, `2*3`) stay.
+pub fn strip_markdown_for_sms(text: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_fence = false;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        // Code is kept verbatim
+        if in_fence {
+            lines.push(line.to_string());
+            continue;
+        }
+        if is_rule(trimmed) {
+            continue;
+        }
+
+        let mut plain = strip_links(strip_line_prefix(trimmed)).replace('`', "");
+        for marker in EMPHASIS_MARKERS {
+            plain = strip_paired(&plain, marker);
+        }
+        lines.push(plain);
+    }
+
+    lines.join("\n").trim().to_string()
+}
+
+/// `# Header`, `> quote`, `- item`, `* item` and `+ item` without the prefix
+fn strip_line_prefix(line: &str) -> &str {
+    let hashes = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) && line[hashes..].starts_with(' ') {
+        return line[hashes..].trim_start();
+    }
+
+    for prefix in ["> ", "- ", "* ", "+ "] {
+        if let Some(rest) = line.strip_prefix(prefix) {
+            return rest.trim_start();
+        }
+    }
+
+    line
+}
+
+/// `---`, `***` or `___` on a line of its own
+fn is_rule(line: &str) -> bool {
+    let line = line.trim_end();
+    line.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|c| line.chars().all(|x| x == *c))
+}
+
+/// `[text](url)` as `text (url)`, or just the url when they're the same
+fn strip_links(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(open) = rest.find('[') {
+        let Some(mid) = rest[open..].find("](").map(|i| open + i) else {
+            break;
+        };
+        let Some(close) = rest[mid..].find(')').map(|i| mid + i) else {
+            break;
+        };
+
+        let (label, url) = (&rest[open + 1..mid], &rest[mid + 2..close]);
+        out.push_str(&rest[..open]);
+        if label.is_empty() || label == url {
+            out.push_str(url);
+        } else {
+            out.push_str(&format!("{label} ({url})"));
+        }
+        rest = &rest[close + 1..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Drop `marker` pairs that wrap text, e.g. `*this*`, leaving the text.
+/// An opening marker can't follow a letter or digit, a closing one can't
+/// precede one, and neither may touch whitespace on the inside.
+fn strip_paired(line: &str, marker: &str) -> String {
+    let before = |i: usize| line[..i].chars().next_back();
+    let after = |i: usize| line[i + marker.len()..].chars().next();
+    let opens = |i: usize| {
+        after(i).is_some_and(|c| !c.is_whitespace()) && !before(i).is_some_and(char::is_alphanumeric)
+    };
+    let closes = |i: usize| {
+        before(i).is_some_and(|c| !c.is_whitespace()) && !after(i).is_some_and(char::is_alphanumeric)
+    };
+
+    let mut out = String::with_capacity(line.len());
+    let mut copied = 0;
+    let mut open = None;
+
+    for (i, _) in line.match_indices(marker) {
+        match open {
+            Some(start) if i > start + marker.len() && closes(i) => {
+                out.push_str(&line[copied..start]);
+                out.push_str(&line[start + marker.len()..i]);
+                copied = i + marker.len();
+                open = None;
+            }
+            None if opens(i) => open = Some(i),
+            _ => {}
+        }
+    }
+
+    out.push_str(&line[copied..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emphasis_and_inline_code_are_unwrapped() {
+        assert_eq!(
+            strip_markdown_for_sms("Your order is **confirmed** and *ships* ~~today~~ __soon__."),
+            "Your order is confirmed and ships today soon."
+        );
+        assert_eq!(strip_markdown_for_sms("Run `reset` then ***retry***"), "Run reset then retry");
+        assert_eq!(strip_markdown_for_sms("## Next steps"), "Next steps");
+
+        // Not emphasis
+        assert_eq!(strip_markdown_for_sms("use order_id and 2*3*4"), "use order_id and 2*3*4");
+        assert_eq!(strip_markdown_for_sms("a * b"), "a * b");
+    }
+
+    #[test]
+    fn test_lists_become_plain_lines() {
+        let reply = "You can:\n- track it online\n* call us\n+ **reply** here\n\n1. First\n2. Second";
+        assert_eq!(
+            strip_markdown_for_sms(reply),
+            "You can:\ntrack it online\ncall us\nreply here\n\n1. First\n2. Second"
+        );
+    }
+
+    #[test]
+    fn test_code_fences_are_removed_around_their_code() {
+        let reply = "Use this:\n```bash\ncurl -X *POST* /api\n```\n---\nDone.";
+        assert_eq!(strip_markdown_for_sms(reply), "Use this:\ncurl -X *POST* /api\nDone.");
+    }
+
+    #[test]
+    fn test_links_keep_text_and_url() {
+        assert_eq!(
+            strip_markdown_for_sms("See [the FAQ](https://x.co/faq) or [https://x.co](https://x.co)"),
+            "See the FAQ (https://x.co/faq) or https://x.co"
+        );
+    }
+}
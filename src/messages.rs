@@ -0,0 +1,82 @@
+use whatlang::{Detector, Lang};
+
+/// Below this, whatlang is mostly guessing
+const MIN_CONFIDENCE: f64 = 0.5;
+/// Only languages with canned text are candidates, so a short body can't
+/// be won by a language we would map to English anyway
+const CANDIDATES: [Lang; 5] = [Lang::Eng, Lang::Spa, Lang::Fra, Lang::Por, Lang::Deu];
+
+/// -----------------------------
+/// Locale
+/// -----------------------------
+/// Languages we have canned replies for. Anything else falls back to English.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+    Fr,
+    Pt,
+    De,
+}
+
+impl Locale {
+    /// Best guess at the language of an inbound body. Short or ambiguous
+    /// texts (e.g. "ok", "STOP") are not reliable enough and give English.
+    pub fn detect(text: &str) -> Self {
+        let detector = Detector::with_allowlist(CANDIDATES.to_vec());
+        let Some(info) = detector.detect(text) else {
+            return Locale::En;
+        };
+        // `is_reliable()` is tuned for longer documents and rejects most SMS
+        if info.confidence() < MIN_CONFIDENCE {
+            return Locale::En;
+        }
+
+        match info.lang() {
+            Lang::Spa => Locale::Es,
+            Lang::Fra => Locale::Fr,
+            Lang::Por => Locale::Pt,
+            Lang::Deu => Locale::De,
+            _ => Locale::En,
+        }
+    }
+}
+
+/// -----------------------------
+/// Canned Messages
+/// -----------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CannedKey {
+    /// Sent when no AI reply could be generated
+    Error,
+}
+
+/// Fixed reply text for `key` in `locale`
+pub fn canned(locale: Locale, key: CannedKey) -> &'static str {
+    use CannedKey::*;
+    use Locale::*;
+
+    match (locale, key) {
+        (En, Error) => "Sorry, something went wrong on our end. Please try again in a few minutes.",
+        (Es, Error) => "Lo sentimos, algo salió mal. Por favor, inténtalo de nuevo en unos minutos.",
+        (Fr, Error) => "Désolé, une erreur s'est produite. Veuillez réessayer dans quelques minutes.",
+        (Pt, Error) => "Desculpe, algo deu errado. Por favor, tente novamente em alguns minutos.",
+        (De, Error) => "Entschuldigung, etwas ist schiefgelaufen. Bitte versuche es in ein paar Minuten erneut.",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_spanish_and_defaults_to_english() {
+        assert_eq!(
+            Locale::detect("Hola, ¿cómo estás? Quiero saber el estado de mi pedido, por favor."),
+            Locale::Es
+        );
+        assert_eq!(Locale::detect("ok"), Locale::En);
+        assert_eq!(Locale::detect(""), Locale::En);
+    }
+}
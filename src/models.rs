@@ -1,23 +1,41 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::clock::{Clock, SystemClock};
+
 /// Represents the role of a message sender
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
+///
+/// Roles this version doesn't know (e.g. a future `system`, or corrupted
+/// data) are kept as `Unknown` with the raw value, so one odd row never
+/// fails a whole read. `Unknown` round-trips verbatim: it is written back
+/// to JSON and the DB exactly as it was read.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MessageRole {
     User,
     Assistant,
+    Unknown(String),
 }
 
 impl MessageRole {
+    /// Canonical form, used both as the DB value and (via the manual
+    /// `Serialize` below) in JSON, so the two can never drift apart
     pub fn as_str(&self) -> &str {
         match self {
             MessageRole::User => "user",
             MessageRole::Assistant => "assistant",
+            MessageRole::Unknown(raw) => raw,
         }
     }
 
+    /// Lenient parse: never fails, unrecognised values become `Unknown`
+    pub fn parse(s: &str) -> Self {
+        Self::from_str(s).unwrap_or_else(|| MessageRole::Unknown(s.to_string()))
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s.to_lowercase().as_str() {
             "user" => Some(MessageRole::User),
@@ -25,47 +43,483 @@ impl MessageRole {
             _ => None,
         }
     }
+
+    /// Strict `deserialize_with` for API input: only `user` or `assistant`,
+    /// so a bad role is a 400 instead of a stored `Unknown` row
+    pub fn deserialize_known<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        Self::from_str(&raw).ok_or_else(|| {
+            serde::de::Error::custom(format!("unknown role `{raw}`, expected `user` or `assistant`"))
+        })
+    }
+
+    /// `deserialize_known` for optional fields (pair with `#[serde(default)]`)
+    pub fn deserialize_known_opt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Self>, D::Error> {
+        #[derive(Deserialize)]
+        struct Known(#[serde(deserialize_with = "MessageRole::deserialize_known")] MessageRole);
+
+        Ok(Option::<Known>::deserialize(deserializer)?.map(|Known(role)| role))
+    }
+
+    pub fn is_user(&self) -> bool {
+        matches!(self, MessageRole::User)
+    }
+
+    pub fn is_assistant(&self) -> bool {
+        matches!(self, MessageRole::Assistant)
+    }
+}
+
+impl Serialize for MessageRole {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Documented as the two known roles; `Unknown` only exists for odd data
+impl utoipa::PartialSchema for MessageRole {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        utoipa::openapi::ObjectBuilder::new()
+            .schema_type(utoipa::openapi::schema::Type::String)
+            .enum_values(Some(["user", "assistant"]))
+            .into()
+    }
+}
+
+impl utoipa::ToSchema for MessageRole {}
+
+impl<'de> Deserialize<'de> for MessageRole {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        Ok(MessageRole::parse(&raw))
+    }
 }
 
 /// Represents a single message in a conversation
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Message {
     pub id: String,
     pub conversation_id: String,
     pub role: MessageRole,
     pub content: String,
+    /// Carrier message id (e.g. SignalWire `MessageSid`) for correlation
+    pub provider_sid: Option<String>,
+    /// Arbitrary integrator data (channel, campaign id, ...), stored as JSON
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<serde_json::Value>,
+    /// Who sent an assistant message (e.g. "AI", "Agent Bot"), when labelled
+    #[serde(default)]
+    pub sender_label: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
 impl Message {
     pub fn new(conversation_id: String, role: MessageRole, content: String) -> Self {
+        Self::new_with_clock(conversation_id, role, content, &SystemClock)
+    }
+
+    /// Like `new`, timestamped by `clock`
+    pub fn new_with_clock(conversation_id: String, role: MessageRole, content: String, clock: &dyn Clock) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
             conversation_id,
             role,
             content,
-            created_at: Utc::now(),
+            provider_sid: None,
+            metadata: None,
+            sender_label: None,
+            created_at: clock.now(),
         }
     }
+
+    pub fn is_from_user(&self) -> bool {
+        self.role.is_user()
+    }
+
+    pub fn is_from_assistant(&self) -> bool {
+        self.role.is_assistant()
+    }
+
+    pub fn with_provider_sid(mut self, provider_sid: Option<String>) -> Self {
+        self.provider_sid = provider_sid;
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: Option<serde_json::Value>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn with_sender_label(mut self, sender_label: Option<String>) -> Self {
+        self.sender_label = sender_label;
+        self
+    }
+
+    /// Backdate to when the message actually happened (e.g. carrier receive time)
+    pub fn with_created_at(mut self, created_at: DateTime<Utc>) -> Self {
+        self.created_at = created_at;
+        self
+    }
+}
+
+fn ai_enabled_by_default() -> bool {
+    true
 }
 
 /// Represents a conversation thread
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Conversation {
     pub id: String,
     pub title: Option<String>,
+    /// Custom AI persona; `None` falls back to the global default
+    pub system_prompt: Option<String>,
+    /// Left out of listings unless archived ones are asked for
+    #[serde(default)]
+    pub archived: bool,
+    /// `false` once a human agent has taken the thread over: inbound
+    /// messages are still stored, but the AI doesn't reply
+    #[serde(default = "ai_enabled_by_default")]
+    pub ai_enabled: bool,
+    /// AI model for replies; `None` uses the global default
+    #[serde(default)]
+    pub ai_model: Option<String>,
+    /// AI sampling temperature for replies; `None` uses the global default
+    #[serde(default)]
+    pub ai_temperature: Option<f32>,
+    /// Label for this conversation's assistant replies; `None` uses the global default
+    #[serde(default)]
+    pub sender_label: Option<String>,
+    /// Customer profile (name, plan, open tickets, ...) given to the AI
+    /// with the system prompt; never stored as a message
+    #[serde(default)]
+    #[schema(value_type = Option<Object>)]
+    pub context: Option<serde_json::Value>,
+    /// Customer number of an SMS conversation; `None` for ones created via the API
+    #[serde(default)]
+    pub from_number: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
 impl Conversation {
     pub fn new(title: Option<String>) -> Self {
-        let now = Utc::now();
+        Self::new_with_clock(title, &SystemClock)
+    }
+
+    /// Like `new`, timestamped by `clock`
+    pub fn new_with_clock(title: Option<String>, clock: &dyn Clock) -> Self {
+        let now = clock.now();
         Self {
             id: Uuid::new_v4().to_string(),
             title,
+            system_prompt: None,
+            archived: false,
+            ai_enabled: true,
+            ai_model: None,
+            ai_temperature: None,
+            sender_label: None,
+            context: None,
+            from_number: None,
             created_at: now,
             updated_at: now,
         }
     }
+
+    pub fn with_system_prompt(mut self, system_prompt: Option<String>) -> Self {
+        self.system_prompt = system_prompt;
+        self
+    }
+
+    /// Record activity at `at` (normally the new message's `created_at`).
+    /// Never moves `updated_at` back, so a backdated message can't make a
+    /// conversation look older than its newest message.
+    pub fn touch(&mut self, at: DateTime<Utc>) {
+        self.updated_at = self.updated_at.max(at);
+    }
+}
+
+/// One page of a listing plus what a client needs to render page controls
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Matching rows across all pages
+    pub total: i64,
+    pub limit: u32,
+    /// Offset-paged listings only; keyset-only listings leave it out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+    /// Pass back as `?cursor=` for the next page; absent on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// -----------------------------
+/// Keyset Cursor
+/// -----------------------------
+/// Position in the conversation listing (`updated_at DESC, id DESC`).
+/// The id breaks ties between conversations updated at the same instant,
+/// so paging never skips or repeats a row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversationCursor {
+    pub updated_at: DateTime<Utc>,
+    pub id: String,
+}
+
+impl ConversationCursor {
+    /// Cursor pointing just past `conversation`
+    pub fn after(conversation: &Conversation) -> Self {
+        Self {
+            updated_at: conversation.updated_at,
+            id: conversation.id.clone(),
+        }
+    }
+
+    /// Opaque, URL-safe form (hex) for API clients
+    pub fn encode(&self) -> String {
+        encode_keyset(self.updated_at, &self.id)
+    }
+
+    /// `None` for anything `encode` didn't produce
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let (updated_at, id) = decode_keyset(cursor)?;
+        Some(Self { updated_at, id })
+    }
+}
+
+/// Position in a conversation's messages (`created_at ASC, id ASC`), for
+/// walking very long conversations a page at a time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageCursor {
+    pub created_at: DateTime<Utc>,
+    pub id: String,
+}
+
+impl MessageCursor {
+    /// Cursor pointing just past `message`
+    pub fn after(message: &Message) -> Self {
+        Self {
+            created_at: message.created_at,
+            id: message.id.clone(),
+        }
+    }
+
+    /// Whether `message` comes after this position
+    pub fn precedes(&self, message: &Message) -> bool {
+        (message.created_at, message.id.as_str()) > (self.created_at, self.id.as_str())
+    }
+
+    /// Opaque, URL-safe form (hex) for API clients
+    pub fn encode(&self) -> String {
+        encode_keyset(self.created_at, &self.id)
+    }
+
+    /// `None` for anything `encode` didn't produce
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let (created_at, id) = decode_keyset(cursor)?;
+        Some(Self { created_at, id })
+    }
+}
+
+/// Position in the outbound audit (`created_at ASC, id ASC`); the id
+/// keeps records sharing a timestamp from repeating across pages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditCursor {
+    pub created_at: DateTime<Utc>,
+    pub id: String,
+}
+
+impl AuditCursor {
+    /// Cursor pointing just past `audit`
+    pub fn after(audit: &OutboundAudit) -> Self {
+        Self {
+            created_at: audit.created_at,
+            id: audit.id.clone(),
+        }
+    }
+
+    /// Whether `audit` comes after this position
+    pub fn precedes(&self, audit: &OutboundAudit) -> bool {
+        (audit.created_at, audit.id.as_str()) > (self.created_at, self.id.as_str())
+    }
+
+    /// Opaque, URL-safe form (hex) for API clients
+    pub fn encode(&self) -> String {
+        encode_keyset(self.created_at, &self.id)
+    }
+
+    /// `None` for anything `encode` didn't produce
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let (created_at, id) = decode_keyset(cursor)?;
+        Some(Self { created_at, id })
+    }
+}
+
+/// `<rfc3339>\n<id>` as hex
+fn encode_keyset(at: DateTime<Utc>, id: &str) -> String {
+    format!("{}\n{}", at.to_rfc3339(), id)
+        .bytes()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn decode_keyset(cursor: &str) -> Option<(DateTime<Utc>, String)> {
+    if !cursor.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    let raw = String::from_utf8(bytes).ok()?;
+    let (at, id) = raw.split_once('\n')?;
+
+    Some((DateTime::parse_from_rfc3339(at).ok()?.with_timezone(&Utc), id.to_string()))
+}
+
+/// -----------------------------
+/// Aggregate Stats
+/// -----------------------------
+/// Totals across the whole store, for `GET /api/stats`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct AggregateStats {
+    pub total_conversations: i64,
+    pub total_messages: i64,
+    /// Message count per role (`user`, `assistant`, ...)
+    pub messages_by_role: BTreeMap<String, i64>,
+    /// One bucket per UTC day, oldest first; days without messages count 0
+    pub daily_messages: Vec<DailyCount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DailyCount {
+    pub date: NaiveDate,
+    pub messages: i64,
+}
+
+/// -----------------------------
+/// Scheduled Message
+/// -----------------------------
+/// An outbound SMS held until `send_at` (e.g. a reminder)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ScheduledMessage {
+    pub id: String,
+    /// E.164 recipient
+    pub to: String,
+    pub body: String,
+    pub send_at: DateTime<Utc>,
+    /// When it was sent (or given up on); `None` while pending
+    pub sent_at: Option<DateTime<Utc>>,
+    /// Carrier message id, when it was sent
+    pub provider_sid: Option<String>,
+    /// Failed sends so far
+    #[serde(default)]
+    pub attempts: u32,
+    /// Held back until then after a failed (or capped) send
+    #[serde(default)]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ScheduledMessage {
+    pub fn new(to: String, body: String, send_at: DateTime<Utc>) -> Self {
+        Self::new_with_clock(to, body, send_at, &SystemClock)
+    }
+
+    /// Like `new`, timestamped by `clock`
+    pub fn new_with_clock(to: String, body: String, send_at: DateTime<Utc>, clock: &dyn Clock) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            to,
+            body,
+            send_at,
+            sent_at: None,
+            provider_sid: None,
+            attempts: 0,
+            next_attempt_at: None,
+            created_at: clock.now(),
+        }
+    }
+}
+
+/// -----------------------------
+/// Outbound Audit
+/// -----------------------------
+/// Compliance record of one outbound SMS attempt. Keeps the body's
+/// length only, never its text.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct OutboundAudit {
+    pub id: String,
+    /// Unset for sends outside a conversation (scheduled messages)
+    pub conversation_id: Option<String>,
+    /// E.164 recipient
+    pub to: String,
+    /// Characters in the body
+    pub body_length: i64,
+    /// `sent`, `not_allowed` or `failed`
+    pub status: String,
+    /// Carrier message id, when it was sent
+    pub provider_sid: Option<String>,
+    /// Why the send failed
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_role_round_trips_verbatim() {
+        let role: MessageRole = serde_json::from_str("\"system\"").unwrap();
+        assert_eq!(role, MessageRole::Unknown("system".into()));
+        assert_eq!(serde_json::to_string(&role).unwrap(), "\"system\"");
+
+        let role: MessageRole = serde_json::from_str("\"assistant\"").unwrap();
+        assert_eq!(role, MessageRole::Assistant);
+    }
+
+    #[test]
+    fn test_api_input_only_accepts_known_roles() {
+        #[derive(Debug, Deserialize)]
+        struct Req {
+            #[serde(deserialize_with = "MessageRole::deserialize_known")]
+            role: MessageRole,
+        }
+
+        let req: Req = serde_json::from_str(r#"{"role":"assistant"}"#).unwrap();
+        assert_eq!(req.role, MessageRole::Assistant);
+        let err = serde_json::from_str::<Req>(r#"{"role":"foo"}"#).unwrap_err();
+        assert!(err.to_string().contains("unknown role `foo`"), "{err}");
+    }
+
+    #[test]
+    fn test_cursor_round_trips_and_rejects_garbage() {
+        let cursor = ConversationCursor {
+            updated_at: Utc::now(),
+            id: "conv-1".into(),
+        };
+
+        assert_eq!(ConversationCursor::decode(&cursor.encode()), Some(cursor));
+        assert_eq!(ConversationCursor::decode("zz"), None);
+        assert_eq!(ConversationCursor::decode("abc"), None);
+    }
+
+    #[test]
+    fn test_db_and_json_representations_agree() {
+        for role in [MessageRole::User, MessageRole::Assistant] {
+            assert_eq!(MessageRole::from_str(role.as_str()), Some(role.clone()));
+            assert_eq!(
+                serde_json::to_value(&role).unwrap(),
+                serde_json::Value::String(role.as_str().to_string())
+            );
+        }
+
+        let unknown = MessageRole::Unknown("system".into());
+        assert_eq!(MessageRole::parse(unknown.as_str()), unknown);
+        assert_eq!(serde_json::to_value(&unknown).unwrap(), "system");
+
+        assert!(MessageRole::User.is_user() && !MessageRole::User.is_assistant());
+        assert!(MessageRole::Assistant.is_assistant());
+    }
 }
@@ -0,0 +1,87 @@
+use sha2::{Digest, Sha256};
+
+/// Blank lines kept between paragraphs; longer runs are collapsed
+const MAX_CONSECUTIVE_NEWLINES: usize = 2;
+
+/// -----------------------------
+/// Body Normalization
+/// -----------------------------
+/// Tidy an SMS body before it is stored or sent: CRLF / CR become LF,
+/// runs of spaces and tabs become one space, more than one blank line
+/// becomes one, control characters are dropped and the ends are trimmed.
+/// Every removed character is SMS segment space saved.
+///
+/// Returns an empty string for a body that was only whitespace; callers
+/// treat that as nothing to store or send.
+pub fn normalize_body(body: &str) -> String {
+    let body = body.replace("\r\n", "\n").replace('\r', "\n");
+
+    let mut out = String::with_capacity(body.len());
+    let mut pending_space = false;
+    let mut pending_newlines = 0;
+
+    for c in body.chars() {
+        match c {
+            '\n' => {
+                pending_newlines += 1;
+                pending_space = false;
+            }
+            c if c.is_whitespace() => pending_space = true,
+            c if c.is_control() => {}
+            c => {
+                // Separators are only written between visible characters,
+                // which trims both ends and trailing spaces on each line
+                if !out.is_empty() {
+                    if pending_newlines > 0 {
+                        out.extend(std::iter::repeat_n('\n', pending_newlines.min(MAX_CONSECUTIVE_NEWLINES)));
+                    } else if pending_space {
+                        out.push(' ');
+                    }
+                }
+                pending_space = false;
+                pending_newlines = 0;
+                out.push(c);
+            }
+        }
+    }
+
+    out
+}
+
+/// SHA-256 (hex) of the normalized body: messages that differ only in
+/// whitespace or control characters hash the same
+pub fn content_hash(content: &str) -> String {
+    Sha256::digest(normalize_body(content).as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_endings_and_blank_lines() {
+        assert_eq!(normalize_body("\r\nHello  \r\n\r\n\r\n\r\nworld\r\n"), "Hello\n\nworld");
+        assert_eq!(normalize_body("one\rtwo"), "one\ntwo");
+    }
+
+    #[test]
+    fn test_whitespace_runs_and_control_chars() {
+        assert_eq!(normalize_body("  where\t\t is   my\u{a0}order?\t"), "where is my order?");
+        assert_eq!(normalize_body("bell\u{7}ed\u{0}"), "belled");
+    }
+
+    #[test]
+    fn test_content_hash_ignores_formatting_only() {
+        assert_eq!(content_hash("Where is  my order?\r\n"), content_hash("Where is my order?"));
+        assert_ne!(content_hash("Where is my order?"), content_hash("Where is my refund?"));
+        assert_eq!(content_hash("").len(), 64);
+    }
+
+    #[test]
+    fn test_all_whitespace_body_is_empty() {
+        assert_eq!(normalize_body(" \r\n\t \u{a0}\n"), "");
+    }
+}
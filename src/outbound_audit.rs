@@ -0,0 +1,126 @@
+use anyhow::Result;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::models::OutboundAudit;
+use crate::phone_number::PhoneNumber;
+use crate::signalwire::{SendOutcome, SignalWireClient};
+use crate::storage::ConversationStorage;
+
+/// `OutboundAudit::status` values
+pub const STATUS_SENT: &str = "sent";
+pub const STATUS_NOT_ALLOWED: &str = "not_allowed";
+pub const STATUS_FAILED: &str = "failed";
+
+/// -----------------------------
+/// Audited Send
+/// -----------------------------
+/// `SignalWireClient::send_sms`, plus an `OutboundAudit` row (tagged with
+/// `conversation_id` when the send belongs to one) for the attempt
+/// whatever its outcome. Every outbound SMS goes through here. Failing to write the audit row is logged and doesn't
+/// change the send's result: the SMS may already be out.
+pub async fn send_audited<S: ConversationStorage>(
+    store: &S,
+    signalwire: &SignalWireClient,
+    conversation_id: Option<&str>,
+    to: &PhoneNumber,
+    body: &str,
+) -> Result<SendOutcome> {
+    let result = signalwire.send_sms(to, body).await;
+
+    let (status, provider_sid, error) = match &result {
+        Ok(SendOutcome::Sent(sid)) => (STATUS_SENT, Some(sid.clone()), None),
+        Ok(SendOutcome::NotAllowed) => (STATUS_NOT_ALLOWED, None, None),
+        Err(e) => (STATUS_FAILED, None, Some(format!("{e:#}"))),
+    };
+    let audit = OutboundAudit {
+        id: Uuid::new_v4().to_string(),
+        conversation_id: conversation_id.map(str::to_string),
+        to: to.to_string(),
+        body_length: body.chars().count() as i64,
+        status: status.to_string(),
+        provider_sid,
+        error,
+        created_at: store.clock().now(),
+    };
+
+    if let Err(e) = store.record_outbound(&audit).await {
+        warn!("Failed to audit outbound SMS to {to}: {e}");
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AuditCursor;
+    use crate::test_support::{fake_signalwire, fake_signalwire_rejecting, fake_store};
+    use chrono::{Duration, Utc};
+
+    #[tokio::test]
+    async fn test_every_send_is_audited_without_its_body() {
+        let (_turso, store) = fake_store().await;
+        let to = PhoneNumber::parse("+15551230000").unwrap();
+        let before = Utc::now() - Duration::seconds(1);
+
+        let (signalwire, _sent) = fake_signalwire().await;
+        let outcome = send_audited(&store, &signalwire, Some("conv-1"), &to, "Your code is 1234").await.unwrap();
+        let SendOutcome::Sent(sid) = outcome else {
+            panic!("expected a send, got {outcome:?}");
+        };
+
+        let (rejecting, _) = fake_signalwire_rejecting(21614).await;
+        assert!(send_audited(&store, &rejecting, None, &to, "Hi").await.is_err());
+
+        let audit = store.outbound_audit_since(before, None, 10).await.unwrap();
+        assert_eq!(audit.len(), 2);
+        assert_eq!(audit[0].status, STATUS_SENT);
+        assert_eq!(audit[0].to, "+15551230000");
+        assert_eq!(audit[0].conversation_id.as_deref(), Some("conv-1"));
+        assert_eq!(audit[0].body_length, 17);
+        assert_eq!(audit[0].provider_sid.as_deref(), Some(sid.as_str()));
+        assert_eq!(audit[0].error, None);
+        assert_eq!(audit[1].status, STATUS_FAILED);
+        assert!(audit[1].error.is_some());
+        assert_eq!(audit[1].conversation_id, None);
+
+        assert!(!serde_json::to_string(&audit).unwrap().contains("1234"));
+        assert!(store
+            .outbound_audit_since(Utc::now() + Duration::seconds(1), None, 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_audit_pages_through_records_sharing_a_timestamp() {
+        let (_turso, store) = fake_store().await;
+        let at = Utc::now();
+        for i in 0..5 {
+            let audit = OutboundAudit {
+                id: format!("a{i}"),
+                conversation_id: None,
+                to: "+15551230000".into(),
+                body_length: 2,
+                status: STATUS_SENT.into(),
+                provider_sid: None,
+                error: None,
+                created_at: at,
+            };
+            store.record_outbound(&audit).await.unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = store.outbound_audit_since(at, cursor.as_ref(), 2).await.unwrap();
+            let Some(last) = page.last() else { break };
+            cursor = Some(AuditCursor::after(last));
+            seen.extend(page.into_iter().map(|a| a.id));
+        }
+
+        assert_eq!(seen, ["a0", "a1", "a2", "a3", "a4"]);
+        assert_eq!(store.count_outbound_audit_since(at).await.unwrap(), 5);
+    }
+}
@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// E.164 allows at most 15 digits after the `+`
+const MAX_DIGITS: usize = 15;
+
+/// Carrier short codes (`12345`) are 3 to 8 digits, written without a `+`
+const SHORT_CODE_DIGITS: std::ops::RangeInclusive<usize> = 3..=8;
+
+/// Alphanumeric sender IDs (`Acme`) are at most 11 characters
+const MAX_SENDER_ID_LEN: usize = 11;
+
+/// Separators people and carriers put in numbers; dropped when parsing
+const FORMATTING: [char; 5] = [' ', '-', '.', '(', ')'];
+
+/// `raw` split into whether it had a leading `+` and its digits, with
+/// formatting dropped; `None` if anything else is in it
+fn split_number(raw: &str) -> Option<(bool, String)> {
+    let trimmed = raw.trim();
+    let (plus, rest) = match trimmed.strip_prefix('+') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed),
+    };
+
+    let mut digits = String::with_capacity(MAX_DIGITS);
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            c if FORMATTING.contains(&c) => {}
+            _ => return None,
+        }
+    }
+    Some((plus, digits))
+}
+
+/// Comparable form of a number as it may have been written over time:
+/// formatting dropped, and a long number without its `+` (a legacy
+/// `15551234567`) read as if it had one. For matching only; `parse`
+/// decides what may be stored or sent. Short codes and sender IDs compare
+/// as written.
+pub fn normalize_number(raw: &str) -> String {
+    match split_number(raw) {
+        Some((plus, digits)) if plus || digits.len() > *SHORT_CODE_DIGITS.end() => format!("+{digits}"),
+        Some((_, digits)) => digits,
+        None => raw.trim().to_string(),
+    }
+}
+
+/// -----------------------------
+/// Phone Number
+/// -----------------------------
+/// An SMS address: an E.164 number (`+` and up to 15 digits, no leading
+/// zero), a short code (3-8 digits) or an alphanumeric sender ID (up to
+/// 11 letters, digits or spaces, at least one letter). Only `parse` builds
+/// one from outside input; values read back from storage or the stream go
+/// through `from_stored`, which keeps ones stored under older rules.
+/// Serialized as the plain string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct PhoneNumber(String);
+
+/// Why a string isn't a usable phone number
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPhoneNumber(pub String);
+
+impl fmt::Display for InvalidPhoneNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not a phone number, short code or sender ID: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPhoneNumber {}
+
+impl PhoneNumber {
+    /// Accepts `+15551234567` as well as formatted input such as
+    /// `+1 (555) 123-4567`, short codes and sender IDs. A long number
+    /// without its `+` is ambiguous (national or international?) and
+    /// fails, as do a misplaced `+`, a leading zero or too many digits.
+    pub fn parse(raw: &str) -> Result<Self, InvalidPhoneNumber> {
+        let invalid = || InvalidPhoneNumber(raw.to_string());
+        let trimmed = raw.trim();
+
+        let Some((plus, digits)) = split_number(trimmed) else {
+            return match is_sender_id(trimmed) {
+                true => Ok(PhoneNumber(trimmed.to_string())),
+                false => Err(invalid()),
+            };
+        };
+
+        if !plus {
+            // Short codes are written as-is, never formatted
+            return match SHORT_CODE_DIGITS.contains(&digits.len()) && digits == trimmed {
+                true => Ok(PhoneNumber(digits)),
+                false => Err(invalid()),
+            };
+        }
+
+        if digits.len() < 2 || digits.len() > MAX_DIGITS || digits.starts_with('0') {
+            return Err(invalid());
+        }
+
+        Ok(PhoneNumber(format!("+{digits}")))
+    }
+
+    /// A number read back from storage or the stream: parsed when it still
+    /// parses, else kept verbatim, so rows written under older (looser)
+    /// rules stay readable
+    pub fn from_stored(raw: &str) -> Self {
+        Self::parse(raw).unwrap_or_else(|_| PhoneNumber(raw.trim().to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+fn is_sender_id(raw: &str) -> bool {
+    (1..=MAX_SENDER_ID_LEN).contains(&raw.len())
+        && raw.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ')
+        && raw.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+impl fmt::Display for PhoneNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for PhoneNumber {
+    type Err = InvalidPhoneNumber;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PhoneNumber::parse(s)
+    }
+}
+
+impl From<String> for PhoneNumber {
+    fn from(value: String) -> Self {
+        PhoneNumber::from_stored(&value)
+    }
+}
+
+impl From<PhoneNumber> for String {
+    fn from(number: PhoneNumber) -> Self {
+        number.0
+    }
+}
+
+impl AsRef<str> for PhoneNumber {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_normalizes_valid_numbers() {
+        for raw in ["+15551234567", " +1 (555) 123-4567 ", "+1.555.123.4567"] {
+            assert_eq!(PhoneNumber::parse(raw).unwrap().as_str(), "+15551234567");
+        }
+    }
+
+    #[test]
+    fn test_short_codes_and_sender_ids_are_kept_as_written() {
+        for raw in ["12345", "898211", "Acme", "ACME Bank", "Shop24"] {
+            assert_eq!(PhoneNumber::parse(raw).unwrap().as_str(), raw);
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_numbers() {
+        for raw in [
+            "",
+            "+",
+            "15551234567",
+            "12-345",
+            "+1555CALLNOW",
+            "1+5551234567",
+            "+0123456789",
+            "+1234567890123456",
+            "ACME Bank Inc",
+            "hi!",
+        ] {
+            assert_eq!(PhoneNumber::parse(raw), Err(InvalidPhoneNumber(raw.to_string())));
+        }
+    }
+
+    #[test]
+    fn test_normalized_numbers_match_across_formats() {
+        assert_eq!(normalize_number("15551234567"), "+15551234567");
+        assert_eq!(normalize_number("+1 (555) 123-4567"), "+15551234567");
+        assert_eq!(normalize_number("12345"), "12345");
+        assert_eq!(normalize_number(" Acme "), "Acme");
+
+        // Whatever parses is already in its normalized form
+        for raw in ["+15551234567", "12345", "Acme"] {
+            let number = PhoneNumber::parse(raw).unwrap();
+            assert_eq!(normalize_number(number.as_str()), number.as_str());
+        }
+    }
+
+    #[test]
+    fn test_display_and_serde_round_trip() {
+        let number = PhoneNumber::parse("+44 20 7946 0958").unwrap();
+
+        assert_eq!(number.to_string(), "+442079460958");
+        assert_eq!(number.to_string().parse::<PhoneNumber>().unwrap(), number);
+
+        let json = serde_json::to_string(&number).unwrap();
+        assert_eq!(json, "\"+442079460958\"");
+        assert_eq!(serde_json::from_str::<PhoneNumber>(&json).unwrap(), number);
+    }
+
+    #[test]
+    fn test_stored_numbers_that_no_longer_parse_are_kept() {
+        let legacy: PhoneNumber = serde_json::from_str("\"15551234567\"").unwrap();
+        assert_eq!(legacy.as_str(), "15551234567");
+        assert_eq!(PhoneNumber::from_stored(" +1 555 123 4567 ").as_str(), "+15551234567");
+    }
+}
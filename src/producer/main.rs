@@ -2,13 +2,29 @@ use std::error::Error;
 use std::sync::Arc;
 use std::time::Duration;
 
+use anyhow::Result;
+use clap::Parser;
 use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
 use tracing::info;
 
 use conversation_store::app_config::AppConfig;
 use conversation_store::infra::iggy::connect_iggy;
-use conversation_store::message_broker::{MessageBroker, SMSMessage};
+use conversation_store::message_broker::{MessageBroker, SMSMessage, SmsPublisher};
 use conversation_store::broker_config::BrokerConfig;
+use conversation_store::PhoneNumber;
+
+/// =============================
+/// CLI
+/// =============================
+#[derive(Parser)]
+#[command(name = "producer")]
+#[command(about = "Publishes demo SMS messages to Iggy")]
+struct Cli {
+    /// Stop after this many messages (overrides PRODUCER_MAX_MESSAGES)
+    #[arg(long)]
+    max_messages: Option<u64>,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -16,8 +32,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .with_env_filter("info")
         .init();
 
+    let cli = Cli::parse();
+
     // Load + validate env ONCE (no dotenv here)
-    let _config = AppConfig::load()?;
+    let config = AppConfig::load()?;
+    let max_messages = cli.max_messages.or(config.producer_max_messages);
 
     info!("Starting SMS producer");
 
@@ -46,33 +65,111 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // =====================================================
     // PRODUCER LOOP
     // =====================================================
-    produce_sms_loop(broker).await?;
+    let cancel = CancellationToken::new();
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                info!("Shutdown requested");
+                cancel.cancel();
+            }
+        }
+    });
+
+    let published = produce_sms_loop(
+        broker.as_ref(),
+        Duration::from_millis(500),
+        max_messages,
+        cancel,
+    )
+    .await?;
+    info!("Producer stopped after {} messages", published);
+
+    // Don't exit with sends still in flight
+    broker.close().await?;
 
     Ok(())
 }
 
-async fn produce_sms_loop(
-    broker: Arc<MessageBroker>,
-) -> Result<(), Box<dyn Error>> {
-    let interval = Duration::from_millis(500);
+/// Publish a demo SMS every `interval` until `max_messages` have been sent
+/// or `cancel` fires. Returns how many were published.
+async fn produce_sms_loop<P: SmsPublisher>(
+    broker: &P,
+    interval: Duration,
+    max_messages: Option<u64>,
+    cancel: CancellationToken,
+) -> Result<u64> {
     let mut current_id: u64 = 0;
 
-    loop {
+    while max_messages.is_none_or(|max| current_id < max) {
         current_id += 1;
 
         let sms = SMSMessage {
             id: uuid::Uuid::new_v4().to_string(),
-            from: "+1234567890".to_string(),
-            to: "+1098765432".to_string(),
+            from: PhoneNumber::parse("+1234567890")?,
+            to: PhoneNumber::parse("+1098765432")?,
             body: format!("Hello, this is message #{}", current_id),
             timestamp: chrono::Utc::now().timestamp(),
             conversation_id: format!("conv-{}", current_id % 4),
+            provider_sid: None,
+            in_reply_to: None,
+            received_at: None,
         };
 
+        // Finish the publish in progress; only the wait is interruptible
         broker.publish_sms(sms).await?;
 
         info!("Published SMS #{}", current_id);
 
-        sleep(interval).await;
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = sleep(interval) => {}
+        }
+    }
+
+    Ok(current_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingPublisher {
+        published: Mutex<Vec<SMSMessage>>,
+    }
+
+    impl SmsPublisher for RecordingPublisher {
+        async fn publish_sms_batch(&self, messages: Vec<SMSMessage>) -> Result<()> {
+            self.published.lock().unwrap().extend(messages);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loop_stops_after_max_messages() {
+        let publisher = RecordingPublisher::default();
+
+        let published =
+            produce_sms_loop(&publisher, Duration::ZERO, Some(3), CancellationToken::new())
+                .await
+                .unwrap();
+
+        assert_eq!(published, 3);
+        assert_eq!(publisher.published.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_loop_stops_on_cancel() {
+        let publisher = RecordingPublisher::default();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let published = produce_sms_loop(&publisher, Duration::from_secs(60), None, cancel)
+            .await
+            .unwrap();
+
+        assert_eq!(published, 1);
     }
 }
@@ -0,0 +1,122 @@
+use anyhow::Result;
+
+use crate::ai_service::AIMessage;
+
+const MESSAGE: &str = "{{message}}";
+const HISTORY: &str = "{{history}}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Message,
+    History,
+}
+
+/// -----------------------------
+/// Prompt Template
+/// -----------------------------
+/// Wraps the user's message before it goes to the AI provider, e.g.
+/// `Customer said: {{message}}. Respond politely.` `{{history}}` renders
+/// the earlier conversation as `role: content` lines; a template that
+/// uses it replaces the usual message history instead of repeating it.
+///
+/// Parsed once, so placeholders typed into a message are left alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate {
+    parts: Vec<Part>,
+}
+
+impl PromptTemplate {
+    /// Fails unless the template has `{{message}}` and no other `{{...}}`
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find("{{") {
+            let Some(len) = rest[start..].find("}}").map(|end| end + 2) else {
+                anyhow::bail!("Prompt template has an unclosed {{{{ at {:?}", &rest[start..]);
+            };
+
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            parts.push(match &rest[start..start + len] {
+                MESSAGE => Part::Message,
+                HISTORY => Part::History,
+                other => anyhow::bail!("Unknown prompt template placeholder {other}"),
+            });
+            rest = &rest[start + len..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        if !parts.contains(&Part::Message) {
+            anyhow::bail!("Prompt template must contain {MESSAGE}");
+        }
+        Ok(Self { parts })
+    }
+
+    pub fn uses_history(&self) -> bool {
+        self.parts.contains(&Part::History)
+    }
+
+    /// The prompt for `message`; system entries of `history` are left out
+    pub fn render(&self, message: &str, history: &[AIMessage]) -> String {
+        let mut prompt = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => prompt.push_str(text),
+                Part::Message => prompt.push_str(message),
+                Part::History => {
+                    let lines: Vec<String> = history
+                        .iter()
+                        .filter(|m| m.role != "system")
+                        .map(|m| format!("{}: {}", m.role, m.content))
+                        .collect();
+                    prompt.push_str(&lines.join("\n"));
+                }
+            }
+        }
+
+        prompt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholders_are_substituted_once() {
+        let template = PromptTemplate::parse("Customer said: {{message}}. Respond politely.").unwrap();
+        assert!(!template.uses_history());
+        assert_eq!(
+            template.render("Where is {{history}}?", &[]),
+            "Customer said: Where is {{history}}?. Respond politely."
+        );
+
+        let template = PromptTemplate::parse("Earlier:\n{{history}}\nNow: {{message}}").unwrap();
+        let history = [
+            AIMessage::system("Be brief"),
+            AIMessage {
+                role: "user".into(),
+                content: "Hi".into(),
+            },
+            AIMessage {
+                role: "assistant".into(),
+                content: "Hello!".into(),
+            },
+        ];
+        assert!(template.uses_history());
+        assert_eq!(template.render("Thanks", &history), "Earlier:\nuser: Hi\nassistant: Hello!\nNow: Thanks");
+    }
+
+    #[test]
+    fn test_invalid_templates_are_rejected() {
+        assert!(PromptTemplate::parse("Respond politely.").is_err());
+        assert!(PromptTemplate::parse("{{message}} {{name}}").is_err());
+        assert!(PromptTemplate::parse("{{message}} {{history").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::api_error::ApiError;
+
+/// -----------------------------
+/// Rate Limit
+/// -----------------------------
+/// Caps the requests accepted through the routes it wraps per fixed
+/// window. Past the cap a request is answered `429` with `Retry-After`
+/// (seconds until the window rolls over) and never reaches the handler,
+/// so bulk readers of sensitive listings can't scrape them flat out.
+///
+/// Use with `axum::middleware::from_fn_with_state(limit, limit_rate)`.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    max: u32,
+    window: Duration,
+    /// Start of the current window and requests accepted in it
+    state: Arc<Mutex<(Instant, u32)>>,
+}
+
+impl RateLimit {
+    /// At most `max` requests (at least 1) per `window`
+    pub fn new(max: u32, window: Duration) -> Self {
+        Self {
+            max: max.max(1),
+            window,
+            state: Arc::new(Mutex::new((Instant::now(), 0))),
+        }
+    }
+
+    pub fn per_minute(max: u32) -> Self {
+        Self::new(max, Duration::from_secs(60))
+    }
+
+    /// Counts one request; `Err` holds how long until the next is accepted
+    fn acquire(&self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let (started, accepted) = &mut *state;
+
+        if now.duration_since(*started) >= self.window {
+            *started = now;
+            *accepted = 0;
+        }
+        if *accepted >= self.max {
+            return Err(self.window.saturating_sub(now.duration_since(*started)));
+        }
+        *accepted += 1;
+        Ok(())
+    }
+}
+
+pub async fn limit_rate(
+    State(limit): State<RateLimit>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Err(wait) = limit.acquire() {
+        warn!("Rejecting {} {}: rate limit reached", request.method(), request.uri().path());
+        let retry_after = wait.as_secs_f64().ceil().max(1.0).to_string();
+        return (
+            [(header::RETRY_AFTER, retry_after)],
+            ApiError::too_many_requests("Too many requests, retry later"),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, middleware, routing::get, Router};
+
+    #[tokio::test]
+    async fn test_requests_over_the_rate_get_429_until_the_window_rolls_over() {
+        let limit = RateLimit::new(2, Duration::from_millis(300));
+        let router = Router::new()
+            .route("/api/audit/outbound", get(|| async { StatusCode::OK }))
+            .route_layer(middleware::from_fn_with_state(limit, limit_rate));
+        let url = crate::test_support::serve(router).await;
+        let client = reqwest::Client::new();
+        let get = || client.get(format!("{url}/api/audit/outbound")).send();
+
+        for _ in 0..2 {
+            assert_eq!(get().await.unwrap().status(), reqwest::StatusCode::OK);
+        }
+
+        let rejected = get().await.unwrap();
+        assert_eq!(rejected.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(rejected.headers()["retry-after"], "1");
+        let body: serde_json::Value = rejected.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "rate_limited");
+
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        assert_eq!(get().await.unwrap().status(), reqwest::StatusCode::OK);
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// -----------------------------
+/// Replay Guard
+/// -----------------------------
+/// Remembers the message SIDs of recent webhooks so a captured request
+/// can't be replayed. LaML webhooks carry no timestamp, so the window
+/// runs from when we first received the SID. At most `capacity` SIDs are
+/// kept; the oldest are forgotten first.
+#[derive(Debug)]
+pub struct ReplayGuard {
+    window: Duration,
+    capacity: usize,
+    seen: Mutex<Seen>,
+}
+
+#[derive(Debug, Default)]
+struct Seen {
+    at: HashMap<String, Instant>,
+    /// Receive order, for expiry and eviction
+    order: VecDeque<(String, Instant)>,
+}
+
+impl ReplayGuard {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity: capacity.max(1),
+            seen: Mutex::new(Seen::default()),
+        }
+    }
+
+    /// Record `sid` as received at `now`. False when it was already
+    /// received within the window, i.e. this is a replay.
+    pub fn check(&self, sid: &str, now: Instant) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+
+        // Drop what fell out of the window (or was forgotten and re-added)
+        while let Some((oldest, at)) = seen.order.front() {
+            let expired = now.saturating_duration_since(*at) >= self.window;
+            let stale = seen.at.get(oldest) != Some(at);
+            if !expired && !stale {
+                break;
+            }
+            let (oldest, at) = seen.order.pop_front().unwrap();
+            if seen.at.get(&oldest) == Some(&at) {
+                seen.at.remove(&oldest);
+            }
+        }
+
+        if seen.at.contains_key(sid) {
+            return false;
+        }
+
+        while seen.at.len() >= self.capacity {
+            let Some((oldest, at)) = seen.order.pop_front() else {
+                break;
+            };
+            if seen.at.get(&oldest) == Some(&at) {
+                seen.at.remove(&oldest);
+            }
+        }
+
+        seen.at.insert(sid.to_string(), now);
+        seen.order.push_back((sid.to_string(), now));
+        true
+    }
+
+    /// Forget `sid`, e.g. when its webhook failed and the carrier will
+    /// legitimately retry it
+    pub fn forget(&self, sid: &str) {
+        self.seen.lock().unwrap().at.remove(sid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_within_window_is_rejected() {
+        let guard = ReplayGuard::new(Duration::from_secs(60), 100);
+        let start = Instant::now();
+
+        assert!(guard.check("SM1", start));
+        assert!(!guard.check("SM1", start + Duration::from_secs(30)));
+        assert!(guard.check("SM2", start + Duration::from_secs(30)));
+
+        // Outside the window the SID is accepted again
+        assert!(guard.check("SM1", start + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn test_forgotten_and_evicted_sids_pass() {
+        let guard = ReplayGuard::new(Duration::from_secs(60), 2);
+        let now = Instant::now();
+
+        assert!(guard.check("SM1", now));
+        guard.forget("SM1");
+        assert!(guard.check("SM1", now));
+
+        assert!(guard.check("SM2", now));
+        assert!(guard.check("SM3", now));
+        // SM1 was the oldest of three with room for two
+        assert!(guard.check("SM1", now));
+        assert!(!guard.check("SM3", now));
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use tracing::{error, info, warn};
+
+use crate::outbound_audit::send_audited;
+use crate::phone_number::PhoneNumber;
+use crate::signalwire::{SendOutcome, SignalWireClient};
+use crate::storage::ConversationStorage;
+use crate::usage_caps::{claim_usage, UsageCaps, UsageKind};
+
+/// Most scheduled messages sent per poll
+pub const SCHEDULER_BATCH_SIZE: u32 = 50;
+
+/// Failed sends before a scheduled message is given up on
+pub const SCHEDULER_MAX_ATTEMPTS: u32 = 5;
+
+/// Wait after the `attempts`-th failed send: 1 minute, doubling up to an hour
+fn retry_delay(attempts: u32) -> Duration {
+    Duration::minutes(1 << attempts.saturating_sub(1).min(6)).min(Duration::hours(1))
+}
+
+/// -----------------------------
+/// Scheduled SMS
+/// -----------------------------
+/// Send every scheduled message due by `now` and take it off the queue.
+/// Rows live in the store, so messages that fell due while the server
+/// was down go out on the first poll after a restart. A failed send is
+/// retried with backoff and given up after `SCHEDULER_MAX_ATTEMPTS`; a
+/// recipient over the daily SMS cap is held until the next day. An
+/// opted-out or not allowed recipient is dropped. Returns how
+/// many were sent.
+///
+/// Not coordinated across instances: run the poller on one server only.
+pub async fn send_due_scheduled<S: ConversationStorage>(
+    store: &S,
+    signalwire: &SignalWireClient,
+    caps: &UsageCaps,
+    now: DateTime<Utc>,
+) -> Result<usize> {
+    let mut sent = 0;
+
+    for scheduled in store.due_scheduled_messages(now, SCHEDULER_BATCH_SIZE).await? {
+        // Validated when scheduled; a row from before today's rules is
+        // still sent, and the carrier rejects it if it must
+        let to = PhoneNumber::from_stored(&scheduled.to);
+
+        if store.is_opted_out(to.as_str()).await? {
+            info!("{to} opted out, dropping scheduled message {}", scheduled.id);
+            finish(store, &scheduled.id, None).await;
+            continue;
+        }
+
+        if !claim_usage(store, caps, to.as_str(), UsageKind::OutboundSms).await {
+            let tomorrow = (now.date_naive() + Duration::days(1)).and_time(Default::default()).and_utc();
+            info!("{to} hit the daily SMS cap, holding scheduled message {} until {tomorrow}", scheduled.id);
+            defer(store, &scheduled.id, scheduled.attempts, tomorrow).await;
+            continue;
+        }
+
+        match send_audited(store, signalwire, None, &to, &scheduled.body).await {
+            Ok(SendOutcome::Sent(sid)) => {
+                finish(store, &scheduled.id, Some(&sid)).await;
+                sent += 1;
+            }
+            Ok(SendOutcome::NotAllowed) => finish(store, &scheduled.id, None).await,
+            Err(e) => {
+                let attempts = scheduled.attempts + 1;
+                if attempts >= SCHEDULER_MAX_ATTEMPTS {
+                    error!("Giving up on scheduled message {} after {attempts} attempts: {e}", scheduled.id);
+                    finish(store, &scheduled.id, None).await;
+                } else {
+                    let retry_at = now + retry_delay(attempts);
+                    warn!("Scheduled message {} not sent, retrying at {retry_at}: {e}", scheduled.id);
+                    defer(store, &scheduled.id, attempts, retry_at).await;
+                }
+            }
+        }
+    }
+
+    Ok(sent)
+}
+
+/// Take a message off the queue. Logged rather than returned: failing the
+/// poll after a send would send it again next time.
+async fn finish<S: ConversationStorage>(store: &S, id: &str, provider_sid: Option<&str>) {
+    if let Err(e) = store.mark_scheduled_sent(id, provider_sid).await {
+        error!("Failed to take scheduled message {id} off the queue: {e}");
+    }
+}
+
+async fn defer<S: ConversationStorage>(store: &S, id: &str, attempts: u32, until: DateTime<Utc>) {
+    if let Err(e) = store.defer_scheduled(id, attempts, until).await {
+        error!("Failed to defer scheduled message {id}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fake_signalwire, fake_signalwire_rejecting, fake_store};
+
+    #[tokio::test]
+    async fn test_message_due_before_a_restart_is_sent_once() {
+        let (turso, store) = fake_store().await;
+        let to = PhoneNumber::parse("+15551230000").unwrap();
+        let now = Utc::now();
+
+        let due = store
+            .schedule_sms(&to, "Reminder: your appointment is today", now - Duration::minutes(5))
+            .await
+            .unwrap();
+        store.schedule_sms(&to, "Tomorrow's reminder", now + Duration::days(1)).await.unwrap();
+
+        // A fresh store over the same database, as after a restart
+        drop(store);
+        let store = turso.store();
+        store.initialize().await.unwrap();
+
+        let (signalwire, sent) = fake_signalwire().await;
+        assert_eq!(send_due_scheduled(&store, &signalwire, &UsageCaps::default(), now).await.unwrap(), 1);
+        {
+            let sent = sent.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0]["To"], "+15551230000");
+            assert_eq!(sent[0]["Body"], "Reminder: your appointment is today");
+        }
+
+        // Marked sent, so the next poll leaves it alone
+        assert_eq!(send_due_scheduled(&store, &signalwire, &UsageCaps::default(), now).await.unwrap(), 0);
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        let later = store.due_scheduled_messages(now + Duration::days(2), 10).await.unwrap();
+        assert_eq!(later.len(), 1);
+        assert_eq!(later[0].body, "Tomorrow's reminder");
+        assert_ne!(later[0].id, due.id);
+    }
+
+    #[tokio::test]
+    async fn test_failed_sends_back_off_and_are_given_up() {
+        let (_turso, store) = fake_store().await;
+        let to = PhoneNumber::parse("+15551230000").unwrap();
+        let caps = UsageCaps::default();
+        let mut now = Utc::now();
+
+        store.schedule_sms(&to, "Reminder", now).await.unwrap();
+        let (signalwire, attempts) = fake_signalwire_rejecting(30001).await;
+        let signalwire = signalwire.with_circuit_breaker(100, std::time::Duration::ZERO);
+
+        assert_eq!(send_due_scheduled(&store, &signalwire, &caps, now).await.unwrap(), 0);
+        // Not due again until the backoff passes, so it can't block the queue
+        assert!(store.due_scheduled_messages(now, 10).await.unwrap().is_empty());
+        let due = store.due_scheduled_messages(now + retry_delay(1), 10).await.unwrap();
+        assert_eq!(due[0].attempts, 1);
+
+        for _ in 1..SCHEDULER_MAX_ATTEMPTS {
+            now += Duration::hours(1);
+            send_due_scheduled(&store, &signalwire, &caps, now).await.unwrap();
+        }
+
+        assert_eq!(attempts.lock().unwrap().len(), SCHEDULER_MAX_ATTEMPTS as usize);
+        assert!(store.due_scheduled_messages(now + Duration::days(1), 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_capped_recipient_is_held_until_the_next_day() {
+        let (_turso, store) = fake_store().await;
+        let to = PhoneNumber::parse("+15551230000").unwrap();
+        let caps = UsageCaps {
+            max_ai_calls: None,
+            max_sms: Some(1),
+            notice: None,
+        };
+        let now = Utc::now();
+
+        store.schedule_sms(&to, "First", now).await.unwrap();
+        store.schedule_sms(&to, "Second", now).await.unwrap();
+
+        let (signalwire, sent) = fake_signalwire().await;
+        assert_eq!(send_due_scheduled(&store, &signalwire, &caps, now).await.unwrap(), 1);
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        let held = store.due_scheduled_messages(now + Duration::days(1), 10).await.unwrap();
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].attempts, 0);
+        assert!(held[0].next_attempt_at.unwrap() > now);
+    }
+
+    #[test]
+    fn test_retry_delay_doubles_up_to_an_hour() {
+        assert_eq!(retry_delay(1), Duration::minutes(1));
+        assert_eq!(retry_delay(2), Duration::minutes(2));
+        assert_eq!(retry_delay(4), Duration::minutes(8));
+        assert_eq!(retry_delay(20), Duration::hours(1));
+    }
+}
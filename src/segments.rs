@@ -0,0 +1,60 @@
+/// GSM 03.38 basic character set (one septet each)
+const GSM7_BASIC: &str = "@£$¥èéùìòÇ\nØø\rÅåΔ_ΦΓΛΩΠΨΣΘΞÆæßÉ !\"#¤%&'()*+,-./0123456789:;<=>?\
+    ¡ABCDEFGHIJKLMNOPQRSTUVWXYZÄÖÑÜ§¿abcdefghijklmnopqrstuvwxyzäöñüà";
+
+/// GSM 03.38 extension table (escape + char: two septets each)
+const GSM7_EXTENDED: &str = "^{}\\[~]|€\u{c}";
+
+/// -----------------------------
+/// SMS Segments
+/// -----------------------------
+/// How many SMS segments the carrier bills for `body`: 160 GSM-7 septets
+/// (153 per part once split), or 70 UTF-16 units (67 per part) as soon as
+/// one character is outside the GSM alphabet. Empty bodies are 0.
+pub fn segment_count(body: &str) -> usize {
+    if body.is_empty() {
+        return 0;
+    }
+
+    let septets = body
+        .chars()
+        .map(|c| {
+            if GSM7_BASIC.contains(c) {
+                Some(1)
+            } else if GSM7_EXTENDED.contains(c) {
+                Some(2)
+            } else {
+                None
+            }
+        })
+        .sum::<Option<usize>>();
+
+    let (units, single, multi) = match septets {
+        Some(septets) => (septets, 160, 153),
+        None => (body.encode_utf16().count(), 70, 67),
+    };
+
+    if units <= single {
+        1
+    } else {
+        units.div_ceil(multi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gsm_and_unicode_limits() {
+        assert_eq!(segment_count(""), 0);
+        assert_eq!(segment_count(&"a".repeat(160)), 1);
+        assert_eq!(segment_count(&"a".repeat(161)), 2);
+        assert_eq!(segment_count(&"€".repeat(80)), 1);
+        assert_eq!(segment_count(&"€".repeat(81)), 2);
+
+        // One emoji switches the whole message to UCS-2
+        assert_eq!(segment_count(&format!("{}🙂", "a".repeat(68))), 1);
+        assert_eq!(segment_count(&format!("{}🙂", "a".repeat(69))), 2);
+    }
+}
@@ -1,7 +1,18 @@
 use anyhow::{Context, Result};
 use reqwest::Client;
-use serde::Serialize;
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::util::stable_hash;
+use crate::normalize::normalize_body;
+use crate::phone_number::PhoneNumber;
+pub use crate::phone_number::normalize_number;
 
 #[derive(Serialize)]
 struct Message {
@@ -11,6 +22,213 @@ struct Message {
     to: String,
     #[serde(rename = "Body")]
     body: String,
+    /// Where SignalWire posts delivery updates for this message
+    #[serde(rename = "StatusCallback", skip_serializing_if = "Option::is_none")]
+    status_callback: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SendResponse {
+    sid: String,
+}
+
+/// Result of a send that didn't fail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendOutcome {
+    /// Accepted by SignalWire, with the provider message SID
+    Sent(String),
+    /// Skipped: recipient isn't on the allow-list
+    NotAllowed,
+}
+
+/// Recipient unsubscribed (replied STOP)
+pub const ERROR_UNSUBSCRIBED: u32 = 21610;
+/// Recipient is not a valid phone number
+pub const ERROR_INVALID_NUMBER: u32 = 21614;
+
+/// -----------------------------
+/// SignalWire Error
+/// -----------------------------
+/// Rejection returned by the Messages API, e.g.
+/// `{"code": 21610, "message": "...", "status": 400}`.
+/// Returned inside `anyhow::Error`; use `downcast_ref` to inspect the code.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SignalWireError {
+    pub code: u32,
+    pub message: String,
+}
+
+impl SignalWireError {
+    /// Parse an error body; `None` if it isn't SignalWire's error JSON
+    pub fn parse(body: &str) -> Option<Self> {
+        serde_json::from_str(body).ok()
+    }
+
+    /// Rejections caused by the recipient rather than a SignalWire outage
+    pub fn is_recipient_error(&self) -> bool {
+        matches!(self.code, ERROR_UNSUBSCRIBED | ERROR_INVALID_NUMBER)
+    }
+}
+
+impl fmt::Display for SignalWireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SignalWire error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for SignalWireError {}
+
+/// -----------------------------
+/// From-Number Pool
+/// -----------------------------
+/// How a sender number is picked when several are configured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FromNumberStrategy {
+    /// A customer (recipient number) always sees the same number
+    #[default]
+    Sticky,
+    /// Spread sends evenly across the pool
+    RoundRobin,
+}
+
+impl FromStr for FromNumberStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "sticky" => Ok(FromNumberStrategy::Sticky),
+            "round_robin" | "round-robin" => Ok(FromNumberStrategy::RoundRobin),
+            other => anyhow::bail!("Unknown from-number strategy: {other}"),
+        }
+    }
+}
+
+/// -----------------------------
+/// Circuit Breaker
+/// -----------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Calls flow normally
+    Closed,
+    /// Calls are short-circuited until the cooldown elapses
+    Open,
+    /// Cooldown elapsed; a single probe call is allowed through
+    HalfOpen,
+}
+
+/// Breaker state and failure count, for metrics
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStats {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub failure_threshold: u32,
+    pub cooldown_secs: u64,
+}
+
+#[derive(Debug)]
+struct BreakerInner {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_in_flight: bool,
+}
+
+/// Opens after `failure_threshold` consecutive failures, rejects calls
+/// for `cooldown`, then half-opens to let one probe through.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    inner: Arc<Mutex<BreakerInner>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            inner: Arc::new(Mutex::new(BreakerInner {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                probe_in_flight: false,
+            })),
+        }
+    }
+
+    /// Current state (moves Open -> HalfOpen once the cooldown has elapsed)
+    pub fn state(&self) -> CircuitState {
+        let mut inner = self.inner.lock().unwrap();
+        self.refresh(&mut inner);
+        inner.state
+    }
+
+    pub fn stats(&self) -> CircuitStats {
+        let mut inner = self.inner.lock().unwrap();
+        self.refresh(&mut inner);
+
+        CircuitStats {
+            state: inner.state,
+            consecutive_failures: inner.consecutive_failures,
+            failure_threshold: self.failure_threshold,
+            cooldown_secs: self.cooldown.as_secs(),
+        }
+    }
+
+    /// Returns true if a call may proceed
+    pub fn allow(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        self.refresh(&mut inner);
+
+        match inner.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                if inner.probe_in_flight {
+                    false
+                } else {
+                    inner.probe_in_flight = true;
+                    true
+                }
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = CircuitState::Closed;
+        inner.consecutive_failures = 0;
+        inner.opened_at = None;
+        inner.probe_in_flight = false;
+    }
+
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures += 1;
+        inner.probe_in_flight = false;
+
+        if inner.state == CircuitState::HalfOpen
+            || inner.consecutive_failures >= self.failure_threshold
+        {
+            inner.state = CircuitState::Open;
+            inner.opened_at = Some(Instant::now());
+        }
+    }
+
+    fn refresh(&self, inner: &mut BreakerInner) {
+        if inner.state == CircuitState::Open {
+            let elapsed = inner
+                .opened_at
+                .map(|t| t.elapsed() >= self.cooldown)
+                .unwrap_or(true);
+
+            if elapsed {
+                inner.state = CircuitState::HalfOpen;
+                inner.probe_in_flight = false;
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -19,7 +237,17 @@ pub struct SignalWireClient {
     project_id: String,
     auth_token: String,
     space_url: String,
-    from_number: String,
+    /// Never empty
+    from_numbers: Arc<Vec<String>>,
+    from_strategy: FromNumberStrategy,
+    next_from: Arc<AtomicUsize>,
+    breaker: CircuitBreaker,
+    /// When set, only these numbers are texted (staging safety net)
+    allowed_recipients: Option<Arc<HashSet<String>>>,
+    /// Run bodies through `normalize_body` before sending
+    normalize_bodies: bool,
+    /// Sent as `StatusCallback` with every message
+    status_callback: Option<String>,
 }
 
 impl SignalWireClient {
@@ -42,21 +270,177 @@ impl SignalWireClient {
             project_id,
             auth_token,
             space_url,
-            from_number,
+            from_numbers: Arc::new(vec![from_number]),
+            from_strategy: FromNumberStrategy::default(),
+            next_from: Arc::new(AtomicUsize::new(0)),
+            breaker: CircuitBreaker::new(5, Duration::from_secs(30)),
+            allowed_recipients: None,
+            normalize_bodies: true,
+            status_callback: None,
+        }
+    }
+
+    /// Send from a pool of numbers (to stay under per-number throughput
+    /// caps). An empty pool keeps the number given to `new`.
+    pub fn with_from_numbers(mut self, numbers: Vec<String>, strategy: FromNumberStrategy) -> Self {
+        if !numbers.is_empty() {
+            self.from_numbers = Arc::new(numbers);
+        }
+        self.from_strategy = strategy;
+        self
+    }
+
+    /// Sender number for the next message to `to`. Sticky picks by the
+    /// recipient, not the conversation: every inbound SMS may open a new
+    /// conversation, and the customer should still see one number.
+    pub fn pick_from_number(&self, to: &PhoneNumber) -> &str {
+        let index = match self.from_strategy {
+            FromNumberStrategy::Sticky => stable_hash(to.as_str()) as usize,
+            FromNumberStrategy::RoundRobin => self.next_from.fetch_add(1, Ordering::Relaxed),
+        };
+
+        &self.from_numbers[index % self.from_numbers.len()]
+    }
+
+    /// Only send to these numbers; everything else is logged and skipped
+    pub fn with_allow_list(mut self, recipients: Vec<String>) -> Self {
+        // Compared against parsed recipients, so keep the same (E.164) form
+        let recipients = recipients
+            .iter()
+            .filter_map(|raw| match PhoneNumber::parse(raw) {
+                Ok(number) => Some(number.into()),
+                Err(e) => {
+                    warn!("Ignoring allow-list entry: {e}");
+                    None
+                }
+            })
+            .collect();
+        self.allowed_recipients = Some(Arc::new(recipients));
+        self
+    }
+
+    /// Send bodies exactly as given instead of normalizing them (on by default)
+    pub fn with_body_normalization(mut self, enabled: bool) -> Self {
+        self.normalize_bodies = enabled;
+        self
+    }
+
+    /// Ask SignalWire to post delivery updates for every send to `url`
+    /// (the server's `/sms/status`); without it only the account-wide
+    /// callback, if any, reports deliveries
+    pub fn with_status_callback(mut self, url: String) -> Self {
+        self.status_callback = Some(url);
+        self
+    }
+
+    /// Override the default circuit breaker settings
+    pub fn with_circuit_breaker(mut self, failure_threshold: u32, cooldown: Duration) -> Self {
+        self.breaker = CircuitBreaker::new(failure_threshold, cooldown);
+        self
+    }
+
+    /// Breaker state, for metrics/health reporting
+    pub fn circuit_state(&self) -> CircuitState {
+        self.breaker.state()
+    }
+
+    /// Breaker state with its failure count and settings
+    pub fn circuit_stats(&self) -> CircuitStats {
+        self.breaker.stats()
+    }
+
+    /// Send SMS via SignalWire, from the pool number picked for `to`
+    pub async fn send_sms(&self, to: &PhoneNumber, body: &str) -> Result<SendOutcome> {
+        if let Some(allowed) = &self.allowed_recipients {
+            if !allowed.contains(to.as_str()) {
+                warn!("Recipient {to} not on allow-list, skipping send");
+                return Ok(SendOutcome::NotAllowed);
+            }
         }
+
+        let body = if self.normalize_bodies {
+            normalize_body(body)
+        } else {
+            body.to_string()
+        };
+        if body.trim().is_empty() {
+            anyhow::bail!("Refusing to send an empty SMS to {to}");
+        }
+
+        if !self.breaker.allow() {
+            anyhow::bail!("SignalWire circuit open, skipping send");
+        }
+
+        let from = self.pick_from_number(to);
+        let result = self.send_sms_inner(from, to.as_str(), &body).await;
+
+        match &result {
+            Ok(_) => self.breaker.record_success(),
+            // SignalWire answered; a bad recipient says nothing about its health
+            Err(e) if e
+                .downcast_ref::<SignalWireError>()
+                .is_some_and(SignalWireError::is_recipient_error) =>
+            {
+                self.breaker.record_success()
+            }
+            Err(e) => {
+                self.breaker.record_failure();
+                if self.breaker.state() == CircuitState::Open {
+                    warn!("SignalWire circuit opened after failure: {e}");
+                }
+            }
+        }
+
+        result.map(SendOutcome::Sent)
+    }
+
+    /// `space_url` is normally a bare host; a full URL is used as-is
+    fn base_url(&self) -> String {
+        if self.space_url.starts_with("http://") || self.space_url.starts_with("https://") {
+            self.space_url.trim_end_matches('/').to_string()
+        } else {
+            format!("https://{}", self.space_url)
+        }
+    }
+
+    /// Fetch the account resource (no message sent) to confirm the space is
+    /// reachable and the project id / token are accepted
+    pub async fn health_check(&self) -> Result<()> {
+        let url = format!(
+            "{}/api/laml/2010-04-01/Accounts/{}.json",
+            self.base_url(), self.project_id
+        );
+
+        let response = self
+            .client
+            .get(&url)
+            .basic_auth(&self.project_id, Some(&self.auth_token))
+            .send()
+            .await
+            .context("Failed to reach SignalWire")?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            anyhow::bail!("SignalWire rejected the credentials (HTTP {})", status.as_u16());
+        }
+        if !status.is_success() {
+            anyhow::bail!("SignalWire returned HTTP {}", status.as_u16());
+        }
+
+        Ok(())
     }
 
-    /// Send SMS via SignalWire
-    pub async fn send_sms(&self, to: &str, body: &str) -> Result<()> {
+    async fn send_sms_inner(&self, from: &str, to: &str, body: &str) -> Result<String> {
         let url = format!(
-            "https://{}/api/laml/2010-04-01/Accounts/{}/Messages.json",
-            self.space_url, self.project_id
+            "{}/api/laml/2010-04-01/Accounts/{}/Messages.json",
+            self.base_url(), self.project_id
         );
 
         let message = Message {
-            from: self.from_number.clone(),
+            from: from.to_string(),
             to: to.to_string(),
             body: body.to_string(),
+            status_callback: self.status_callback.clone(),
         };
 
         let response = self
@@ -71,9 +455,186 @@ impl SignalWireClient {
         if !response.status().is_success() {
             let status = response.status();
             let text = response.text().await.unwrap_or_default();
+
+            if let Some(err) = SignalWireError::parse(&text) {
+                return Err(err.into());
+            }
             anyhow::bail!("SignalWire error {}: {}", status, text);
         }
 
-        Ok(())
+        let sent: SendResponse = response
+            .json()
+            .await
+            .context("Invalid SignalWire send response")?;
+
+        Ok(sent.sid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_opens_and_recovers_after_probe() {
+        let breaker = CircuitBreaker::new(3, Duration::from_millis(20));
+
+        for _ in 0..3 {
+            assert!(breaker.allow());
+            breaker.record_failure();
+        }
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
+        assert_eq!(breaker.stats().consecutive_failures, 3);
+
+        std::thread::sleep(Duration::from_millis(30));
+
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.allow());
+        // Only one probe at a time
+        assert!(!breaker.allow());
+
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allow());
+
+        let stats = serde_json::to_value(breaker.stats()).unwrap();
+        assert_eq!(
+            stats,
+            serde_json::json!({
+                "state": "closed",
+                "consecutive_failures": 0,
+                "failure_threshold": 3,
+                "cooldown_secs": 0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_error_response() {
+        let body = r#"{
+            "code": 21610,
+            "message": "Attempt to send to unsubscribed recipient",
+            "more_info": "https://www.twilio.com/docs/errors/21610",
+            "status": 400
+        }"#;
+
+        let err = SignalWireError::parse(body).unwrap();
+        assert_eq!(err.code, ERROR_UNSUBSCRIBED);
+        assert!(err.is_recipient_error());
+        assert_eq!(SignalWireError::parse("Bad Gateway"), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_returns_typed_error() {
+        let (client, _) = crate::test_support::fake_signalwire_rejecting(ERROR_INVALID_NUMBER).await;
+        let client = client.with_circuit_breaker(1, Duration::from_secs(60));
+
+        let err = client.send_sms(&PhoneNumber::parse("+1000").unwrap(), "hi").await.unwrap_err();
+
+        let err = err.downcast_ref::<SignalWireError>().unwrap();
+        assert_eq!(err.code, ERROR_INVALID_NUMBER);
+        // Recipient errors don't trip the breaker
+        assert_eq!(client.circuit_state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_health_check_reports_rejected_credentials() {
+        use axum::{http::StatusCode, routing::get, Router};
+
+        let router = Router::new().route(
+            "/api/laml/2010-04-01/Accounts/project.json",
+            get(|| async { StatusCode::UNAUTHORIZED }),
+        );
+        let url = crate::test_support::serve(router).await;
+        let client = SignalWireClient::new("project".into(), "bad".into(), url, "+15550000000".into());
+
+        let err = client.health_check().await.unwrap_err();
+        assert!(err.to_string().contains("rejected the credentials (HTTP 401)"));
+    }
+
+    #[tokio::test]
+    async fn test_allow_list_skips_other_recipients() {
+        let (client, sent) = crate::test_support::fake_signalwire().await;
+        let client = client.with_allow_list(vec!["+15551112222".into()]);
+
+        let skipped = client.send_sms(&PhoneNumber::parse("+15559998888").unwrap(), "hi").await.unwrap();
+        assert_eq!(skipped, SendOutcome::NotAllowed);
+        assert!(sent.lock().unwrap().is_empty());
+
+        let outcome = client.send_sms(&PhoneNumber::parse("+15551112222").unwrap(), "hi").await.unwrap();
+        assert_eq!(outcome, SendOutcome::Sent("SM_fake_0".into()));
+        assert_eq!(sent.lock().unwrap()[0]["To"], "+15551112222");
+    }
+
+    #[tokio::test]
+    async fn test_bodies_are_normalized_unless_disabled() {
+        let (client, sent) = crate::test_support::fake_signalwire().await;
+        let to = PhoneNumber::parse("+15551112222").unwrap();
+
+        client.send_sms(&to, "  Hello\r\n\r\n\r\nthere\t\t!\n").await.unwrap();
+        assert_eq!(sent.lock().unwrap()[0]["Body"], "Hello\n\nthere !");
+
+        assert!(client.send_sms(&to, " \r\n\t").await.is_err());
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        let raw = client.with_body_normalization(false);
+        raw.send_sms(&to, "a\t\tb").await.unwrap();
+        assert_eq!(sent.lock().unwrap()[1]["Body"], "a\t\tb");
+    }
+
+    #[tokio::test]
+    async fn test_status_callback_is_sent_with_every_message() {
+        let (client, sent) = crate::test_support::fake_signalwire().await;
+        let to = PhoneNumber::parse("+15551112222").unwrap();
+
+        client.send_sms(&to, "hi").await.unwrap();
+        assert!(!sent.lock().unwrap()[0].contains_key("StatusCallback"));
+
+        let client = client.with_status_callback("https://sms.example.com/sms/status".into());
+        client.send_sms(&to, "hi").await.unwrap();
+        client.send_sms(&to, "again").await.unwrap();
+        for form in &sent.lock().unwrap()[1..] {
+            assert_eq!(form["StatusCallback"], "https://sms.example.com/sms/status");
+        }
+    }
+
+    #[test]
+    fn test_from_number_pool_strategies() {
+        let pool = vec!["+15550000001".to_string(), "+15550000002".into(), "+15550000003".into()];
+        let client = SignalWireClient::new("p".into(), "t".into(), "space".into(), "+15550000000".into());
+
+        let sticky = client
+            .clone()
+            .with_from_numbers(pool.clone(), FromNumberStrategy::Sticky);
+        let to = PhoneNumber::parse("+15551230000").unwrap();
+        let first = sticky.pick_from_number(&to).to_string();
+        assert!(pool.contains(&first));
+        for _ in 0..5 {
+            assert_eq!(sticky.pick_from_number(&to), first);
+        }
+        let others: std::collections::HashSet<&str> = (0..20)
+            .map(|i| sticky.pick_from_number(&PhoneNumber::parse(&format!("+1555123{i:04}")).unwrap()))
+            .collect();
+        assert!(others.len() > 1, "recipients should spread across the pool");
+
+        let round_robin = client.with_from_numbers(pool.clone(), FromNumberStrategy::RoundRobin);
+        let picked: Vec<&str> = (0..4).map(|_| round_robin.pick_from_number(&to)).collect();
+        assert_eq!(picked, [&pool[0], &pool[1], &pool[2], &pool[0]]);
+    }
+
+    #[test]
+    fn test_failed_probe_reopens_breaker() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(10));
+
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(15));
+
+        assert!(breaker.allow());
+        breaker.record_failure();
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
     }
 }
@@ -0,0 +1,1302 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use crate::clock::{Clock, SystemClock};
+use crate::models::{
+    AuditCursor, Conversation, ConversationCursor, Message, MessageCursor, MessageRole, OutboundAudit, ScheduledMessage,
+};
+use crate::phone_number::PhoneNumber;
+use crate::store::{ContentLimit, ContentOverflowPolicy};
+use crate::usage_caps::{DailyUsage, UsageKind};
+
+/// A referenced record doesn't exist. Returned inside `anyhow::Error`;
+/// use `downcast_ref` to tell it from a storage failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotFound {
+    pub kind: &'static str,
+    pub id: String,
+}
+
+impl NotFound {
+    pub fn conversation(id: impl Into<String>) -> Self {
+        Self {
+            kind: "Conversation",
+            id: id.into(),
+        }
+    }
+
+    pub fn message(id: impl Into<String>) -> Self {
+        Self {
+            kind: "Message",
+            id: id.into(),
+        }
+    }
+
+    pub fn raw_webhook(id: i64) -> Self {
+        Self {
+            kind: "Raw webhook",
+            id: id.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} not found", self.kind, self.id)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// =============================
+/// Storage Trait
+/// =============================
+/// Persistence used by the consumers. `ConversationStore` (Turso) is the
+/// production implementation; `InMemoryStore` runs without any network.
+/// In both, the conversation setters fail with `NotFound` for an unknown id.
+pub trait ConversationStorage: Send + Sync {
+    /// Create tables / run migrations
+    fn initialize(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Source of the timestamps this store generates
+    fn clock(&self) -> &dyn Clock {
+        &SystemClock
+    }
+
+    fn is_message_processed(&self, message_id: &str) -> impl Future<Output = Result<bool>> + Send;
+
+    fn mark_message_processed(&self, message_id: &str) -> impl Future<Output = Result<()>> + Send;
+
+    fn create_conversation(
+        &self,
+        title: Option<String>,
+        system_prompt: Option<String>,
+    ) -> impl Future<Output = Result<Conversation>> + Send;
+
+    /// Create the conversation if it doesn't exist yet
+    fn ensure_conversation(
+        &self,
+        conversation_id: &str,
+        title: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Create an SMS conversation with `from_number` (the customer) if it
+    /// doesn't exist yet
+    fn ensure_sms_conversation(
+        &self,
+        conversation_id: &str,
+        title: &str,
+        from_number: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn update_conversation_title(
+        &self,
+        conversation_id: &str,
+        title: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Pin the AI model / temperature used for this conversation's replies
+    /// (`None` clears the override)
+    fn set_conversation_ai_settings(
+        &self,
+        conversation_id: &str,
+        model: Option<&str>,
+        temperature: Option<f32>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Label this conversation's assistant replies (`None` clears the override)
+    fn set_conversation_sender_label(
+        &self,
+        conversation_id: &str,
+        label: Option<&str>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Turn AI replies for this conversation on or off (human takeover)
+    fn set_conversation_ai_enabled(
+        &self,
+        conversation_id: &str,
+        enabled: bool,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Set the customer context given to the AI (`None` clears it)
+    fn set_conversation_context(
+        &self,
+        conversation_id: &str,
+        context: Option<&serde_json::Value>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn get_conversation(
+        &self,
+        conversation_id: &str,
+    ) -> impl Future<Output = Result<Option<Conversation>>> + Send;
+
+    /// Conversations, most recently updated first
+    fn list_conversations(
+        &self,
+        include_archived: bool,
+        limit: u32,
+        offset: u32,
+    ) -> impl Future<Output = Result<Vec<Conversation>>> + Send;
+
+    /// Keyset page: up to `limit` conversations after `cursor` (from the
+    /// start when `None`), plus the cursor for the next page if there is one
+    fn list_conversations_after(
+        &self,
+        include_archived: bool,
+        limit: u32,
+        cursor: Option<&ConversationCursor>,
+    ) -> impl Future<Output = Result<(Vec<Conversation>, Option<ConversationCursor>)>> + Send;
+
+    /// Total for `list_conversations` with the same filter
+    fn count_conversations(&self, include_archived: bool) -> impl Future<Output = Result<i64>> + Send;
+
+    /// Ids of the non-archived SMS conversations with `from_number`, most
+    /// recently updated first
+    fn active_conversations_from(&self, from_number: &str) -> impl Future<Output = Result<Vec<String>>> + Send;
+
+    /// The most recently updated active conversation of `from_number` that
+    /// a human has taken over (AI replies off), if any
+    fn taken_over_conversation_from(
+        &self,
+        from_number: &str,
+    ) -> impl Future<Output = Result<Option<String>>> + Send;
+
+    /// Existing conversation an inbound SMS from `from_number` joins: the
+    /// one holding the `in_reply_to` message, else the number's taken-over
+    /// conversation, else its latest once it has `max_per_number` active
+    /// ones. One round trip, so the webhook can put a deadline on it.
+    fn resolve_inbound_conversation(
+        &self,
+        from_number: &str,
+        in_reply_to: Option<&str>,
+        max_per_number: Option<usize>,
+    ) -> impl Future<Output = Result<Option<String>>> + Send;
+
+    fn set_conversation_archived(
+        &self,
+        conversation_id: &str,
+        archived: bool,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Mark everything in the conversation up to `at` as read
+    fn mark_read(
+        &self,
+        conversation_id: &str,
+        at: DateTime<Utc>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Messages created after the conversation was last read
+    /// (all of them if it never was)
+    fn unread_count(&self, conversation_id: &str) -> impl Future<Output = Result<i64>> + Send;
+
+    /// Persist a message and bump the conversation's `updated_at`
+    fn insert_message(&self, message: Message) -> impl Future<Output = Result<Message>> + Send;
+
+    /// Persist several messages at once. Each conversation's `updated_at`
+    /// is bumped once, to its newest message, rather than per message.
+    fn store_messages_batch(
+        &self,
+        messages: Vec<Message>,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send;
+
+    fn get_message(&self, message_id: &str) -> impl Future<Output = Result<Option<Message>>> + Send;
+
+    /// Conversation holding the message with this carrier SID, if any
+    fn find_conversation_by_provider_sid(
+        &self,
+        provider_sid: &str,
+    ) -> impl Future<Output = Result<Option<String>>> + Send;
+
+    /// Messages of a conversation, oldest first
+    fn get_conversation_messages(
+        &self,
+        conversation_id: &str,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send;
+
+    /// The last `limit` messages of a conversation, oldest first, for AI
+    /// context. With `max_chars` each message's content is cut to that
+    /// many characters by the store, so long history isn't transferred.
+    fn get_recent_messages(
+        &self,
+        conversation_id: &str,
+        limit: u32,
+        max_chars: Option<usize>,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send;
+
+    /// Keyset page: up to `limit` messages of a conversation after `cursor`
+    /// (from the start when `None`), oldest first
+    fn get_conversation_messages_after(
+        &self,
+        conversation_id: &str,
+        cursor: Option<&MessageCursor>,
+        limit: u32,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send;
+
+    /// Total for `get_conversation_messages_after` across all pages
+    fn count_conversation_messages(&self, conversation_id: &str) -> impl Future<Output = Result<i64>> + Send;
+
+    /// Whether the assistant has ever replied in the conversation
+    fn has_assistant_reply(&self, conversation_id: &str) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Keyset page like `get_conversation_messages_after`, optionally
+    /// limited to one role and/or to messages created at or after `since`
+    fn get_conversation_messages_filtered(
+        &self,
+        conversation_id: &str,
+        role: Option<&MessageRole>,
+        since: Option<DateTime<Utc>>,
+        cursor: Option<&MessageCursor>,
+        limit: u32,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send;
+
+    /// Total for `get_conversation_messages_filtered` across all pages
+    fn count_conversation_messages_filtered(
+        &self,
+        conversation_id: &str,
+        role: Option<&MessageRole>,
+        since: Option<DateTime<Utc>>,
+    ) -> impl Future<Output = Result<i64>> + Send;
+
+    /// Attach the carrier SID to a sent reply and mark it `sent`
+    fn record_outbound_sent(
+        &self,
+        message_id: &str,
+        provider_sid: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Apply a carrier status callback; returns false for an unknown SID
+    fn update_delivery_status(
+        &self,
+        provider_sid: &str,
+        status: &str,
+    ) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Delivery status of the latest tracked reply in a conversation
+    fn last_reply_delivery_status(
+        &self,
+        conversation_id: &str,
+    ) -> impl Future<Output = Result<Option<String>>> + Send;
+
+    /// Delete conversations (and their messages) last updated before `cutoff`.
+    /// Returns the number of conversations removed.
+    fn purge_conversations_older_than(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> impl Future<Output = Result<u64>> + Send;
+
+    /// Move every message of `from_id` into `into_id` (keeping the later
+    /// `updated_at`), carry over its sent-reply records so replays stay
+    /// deduplicated, and delete the then-empty `from_id`
+    fn merge_conversations(
+        &self,
+        from_id: &str,
+        into_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Record that a number must not receive further messages
+    fn set_opted_out(&self, phone_number: &str) -> impl Future<Output = Result<()>> + Send;
+
+    fn is_opted_out(&self, phone_number: &str) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Count one AI completion or outbound SMS for `phone_number` on `date`
+    fn record_usage(
+        &self,
+        phone_number: &str,
+        date: NaiveDate,
+        kind: UsageKind,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Like `record_usage`, but only while `phone_number` has used fewer
+    /// than `max` of `kind` on `date` (`None`: no limit), as one atomic
+    /// update. `false` if the cap was already reached and nothing counted.
+    fn claim_usage(
+        &self,
+        phone_number: &str,
+        date: NaiveDate,
+        kind: UsageKind,
+        max: Option<u32>,
+    ) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Usage so far; all zero for a number/day with nothing recorded
+    fn daily_usage(
+        &self,
+        phone_number: &str,
+        date: NaiveDate,
+    ) -> impl Future<Output = Result<DailyUsage>> + Send;
+
+    /// Flag the cap notice as sent. Returns `false` if it already was, so
+    /// concurrent callers agree on who sends it.
+    fn mark_cap_notice_sent(
+        &self,
+        phone_number: &str,
+        date: NaiveDate,
+    ) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Record that the reply `message_id` went out to the carrier, so a
+    /// retry never texts the customer the same reply twice
+    fn mark_sms_sent(
+        &self,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn was_sms_sent(
+        &self,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Save (or replace) the embedding of a message for semantic search
+    fn store_embedding(
+        &self,
+        message_id: &str,
+        model: &str,
+        embedding: &[f32],
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn get_embedding(
+        &self,
+        message_id: &str,
+    ) -> impl Future<Output = Result<Option<Vec<f32>>>> + Send;
+
+    /// Keep the exact inbound webhook body for auditing carrier quirks.
+    /// Returns its id, to attach the conversation once one is resolved.
+    fn store_raw_webhook(&self, payload: &str) -> impl Future<Output = Result<i64>> + Send;
+
+    /// Record the conversation a kept webhook ended up in
+    fn set_raw_webhook_conversation(
+        &self,
+        id: i64,
+        conversation_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Delete raw webhooks received before `cutoff`; returns how many
+    fn purge_raw_webhooks_older_than(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> impl Future<Output = Result<u64>> + Send;
+
+    /// Queue `body` to be texted to `to` once `send_at` has passed
+    fn schedule_sms(
+        &self,
+        to: &PhoneNumber,
+        body: &str,
+        send_at: DateTime<Utc>,
+    ) -> impl Future<Output = Result<ScheduledMessage>> + Send;
+
+    /// Pending scheduled messages due by `now`, earliest first
+    fn due_scheduled_messages(
+        &self,
+        now: DateTime<Utc>,
+        limit: u32,
+    ) -> impl Future<Output = Result<Vec<ScheduledMessage>>> + Send;
+
+    /// Take a scheduled message off the queue, with the carrier's id when
+    /// it was actually sent
+    fn mark_scheduled_sent(
+        &self,
+        id: &str,
+        provider_sid: Option<&str>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Hold a pending scheduled message back until `until`, with `attempts`
+    /// failed sends so far
+    fn defer_scheduled(
+        &self,
+        id: &str,
+        attempts: u32,
+        until: DateTime<Utc>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Keep the audit record of an outbound SMS attempt
+    fn record_outbound(&self, audit: &OutboundAudit) -> impl Future<Output = Result<()>> + Send;
+
+    /// Keyset page of the outbound audit records created at or after
+    /// `since`: up to `limit` after `cursor`, oldest first
+    fn outbound_audit_since(
+        &self,
+        since: DateTime<Utc>,
+        cursor: Option<&AuditCursor>,
+        limit: u32,
+    ) -> impl Future<Output = Result<Vec<OutboundAudit>>> + Send;
+
+    /// Total for `outbound_audit_since` across all pages
+    fn count_outbound_audit_since(&self, since: DateTime<Utc>) -> impl Future<Output = Result<i64>> + Send;
+
+    fn store_message(
+        &self,
+        conversation_id: String,
+        role: MessageRole,
+        content: String,
+    ) -> impl Future<Output = Result<Message>> + Send {
+        self.insert_message(Message::new_with_clock(conversation_id, role, content, self.clock()))
+    }
+
+    /// Store a message along with the carrier's message id
+    fn store_message_with_provider_sid(
+        &self,
+        conversation_id: String,
+        role: MessageRole,
+        content: String,
+        provider_sid: Option<String>,
+    ) -> impl Future<Output = Result<Message>> + Send {
+        self.insert_message(
+            Message::new_with_clock(conversation_id, role, content, self.clock()).with_provider_sid(provider_sid),
+        )
+    }
+
+    /// Store a message stamped `created_at` instead of now, e.g. the carrier
+    /// receive time of an inbound SMS
+    fn store_message_at(
+        &self,
+        conversation_id: String,
+        role: MessageRole,
+        content: String,
+        provider_sid: Option<String>,
+        created_at: DateTime<Utc>,
+    ) -> impl Future<Output = Result<Message>> + Send {
+        self.insert_message(
+            Message::new_with_clock(conversation_id, role, content, self.clock())
+                .with_provider_sid(provider_sid)
+                .with_created_at(created_at),
+        )
+    }
+
+    /// Store a message with integrator-supplied JSON metadata
+    fn store_message_with_metadata(
+        &self,
+        conversation_id: String,
+        role: MessageRole,
+        content: String,
+        metadata: Option<serde_json::Value>,
+    ) -> impl Future<Output = Result<Message>> + Send {
+        self.insert_message(Message::new_with_clock(conversation_id, role, content, self.clock()).with_metadata(metadata))
+    }
+
+    /// Insert a message and return it with the conversation as it is after
+    /// the insert (fresh `updated_at`), e.g. to publish a consistent snapshot.
+    /// Fails with `NotFound`, storing nothing, for an unknown conversation.
+    fn store_message_returning_conversation(
+        &self,
+        message: Message,
+    ) -> impl Future<Output = Result<(Message, Conversation)>> + Send {
+        async move {
+            if self.get_conversation(&message.conversation_id).await?.is_none() {
+                anyhow::bail!(NotFound::conversation(&message.conversation_id));
+            }
+            let message = self.insert_message(message).await?;
+            let conversation = self
+                .get_conversation(&message.conversation_id)
+                .await?
+                .ok_or_else(|| NotFound::conversation(&message.conversation_id))?;
+
+            Ok((message, conversation))
+        }
+    }
+}
+
+/// Idempotency key for an outbound reply
+pub fn sent_sms_key(conversation_id: &str, message_id: &str) -> String {
+    format!("{conversation_id}:{message_id}")
+}
+
+/// =============================
+/// In-Memory Store
+/// =============================
+/// `HashMap`-backed storage for tests and local runs. Nothing is persisted.
+pub struct InMemoryStore {
+    inner: Mutex<InMemoryState>,
+    clock: Arc<dyn Clock>,
+    content_limit: ContentLimit,
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::default(),
+            clock: Arc::new(SystemClock),
+            content_limit: ContentLimit::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct InMemoryState {
+    conversations: HashMap<String, Conversation>,
+    messages: HashMap<String, Vec<Message>>,
+    processed: HashSet<String>,
+    opted_out: HashSet<String>,
+    usage: HashMap<(String, NaiveDate), DailyUsage>,
+    /// `sent_sms_key`s of replies handed to the carrier
+    sent_sms: HashSet<String>,
+    /// Delivery status by message id
+    deliveries: HashMap<String, String>,
+    /// `last_read_at` by conversation id
+    last_read: HashMap<String, DateTime<Utc>>,
+    /// Embedding vector by message id
+    embeddings: HashMap<String, Vec<f32>>,
+    /// `(id, payload, conversation_id, received_at)`, oldest first
+    raw_webhooks: Vec<(i64, String, Option<String>, DateTime<Utc>)>,
+    scheduled: Vec<ScheduledMessage>,
+    /// Oldest first
+    outbound_audit: Vec<OutboundAudit>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Timestamp with `clock` instead of the system time
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Same as `ConversationStore::with_content_limit`
+    pub fn with_content_limit(mut self, max_bytes: usize, policy: ContentOverflowPolicy) -> Self {
+        self.content_limit = ContentLimit::new(max_bytes, policy);
+        self
+    }
+
+    /// Raw webhook payloads kept so far with their conversation, oldest first
+    pub fn raw_webhooks(&self) -> Vec<(String, Option<String>)> {
+        self.inner
+            .lock()
+            .unwrap()
+            .raw_webhooks
+            .iter()
+            .map(|(_, payload, conversation_id, _)| (payload.clone(), conversation_id.clone()))
+            .collect()
+    }
+
+    /// Apply `update` to a conversation; `NotFound` for an unknown id
+    fn update_conversation(&self, conversation_id: &str, update: impl FnOnce(&mut Conversation)) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let conversation = inner
+            .conversations
+            .get_mut(conversation_id)
+            .ok_or_else(|| NotFound::conversation(conversation_id))?;
+
+        update(conversation);
+        Ok(())
+    }
+}
+
+impl ConversationStorage for InMemoryStore {
+    async fn initialize(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    async fn is_message_processed(&self, message_id: &str) -> Result<bool> {
+        Ok(self.inner.lock().unwrap().processed.contains(message_id))
+    }
+
+    async fn mark_message_processed(&self, message_id: &str) -> Result<()> {
+        self.inner.lock().unwrap().processed.insert(message_id.to_string());
+        Ok(())
+    }
+
+    async fn create_conversation(
+        &self,
+        title: Option<String>,
+        system_prompt: Option<String>,
+    ) -> Result<Conversation> {
+        let conversation = Conversation::new_with_clock(title, self.clock()).with_system_prompt(system_prompt);
+
+        self.inner
+            .lock()
+            .unwrap()
+            .conversations
+            .insert(conversation.id.clone(), conversation.clone());
+
+        Ok(conversation)
+    }
+
+    async fn ensure_conversation(&self, conversation_id: &str, title: &str) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap()
+            .conversations
+            .entry(conversation_id.to_string())
+            .or_insert_with(|| Conversation {
+                id: conversation_id.to_string(),
+                ..Conversation::new_with_clock(Some(title.to_string()), self.clock())
+            });
+
+        Ok(())
+    }
+
+    async fn ensure_sms_conversation(&self, conversation_id: &str, title: &str, from_number: &str) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap()
+            .conversations
+            .entry(conversation_id.to_string())
+            .or_insert_with(|| Conversation {
+                id: conversation_id.to_string(),
+                from_number: Some(from_number.to_string()),
+                ..Conversation::new_with_clock(Some(title.to_string()), self.clock())
+            });
+
+        Ok(())
+    }
+
+    async fn update_conversation_title(&self, conversation_id: &str, title: &str) -> Result<()> {
+        self.update_conversation(conversation_id, |conversation| {
+            conversation.title = Some(title.to_string());
+        })
+    }
+
+    async fn set_conversation_ai_settings(
+        &self,
+        conversation_id: &str,
+        model: Option<&str>,
+        temperature: Option<f32>,
+    ) -> Result<()> {
+        self.update_conversation(conversation_id, |conversation| {
+            conversation.ai_model = model.map(str::to_string);
+            conversation.ai_temperature = temperature;
+        })
+    }
+
+    async fn set_conversation_sender_label(&self, conversation_id: &str, label: Option<&str>) -> Result<()> {
+        self.update_conversation(conversation_id, |conversation| {
+            conversation.sender_label = label.map(str::to_string);
+        })
+    }
+
+    async fn set_conversation_ai_enabled(&self, conversation_id: &str, enabled: bool) -> Result<()> {
+        self.update_conversation(conversation_id, |conversation| {
+            conversation.ai_enabled = enabled;
+        })
+    }
+
+    async fn set_conversation_context(&self, conversation_id: &str, context: Option<&serde_json::Value>) -> Result<()> {
+        self.update_conversation(conversation_id, |conversation| {
+            conversation.context = context.cloned();
+        })
+    }
+
+    async fn get_conversation(&self, conversation_id: &str) -> Result<Option<Conversation>> {
+        Ok(self.inner.lock().unwrap().conversations.get(conversation_id).cloned())
+    }
+
+    async fn list_conversations(
+        &self,
+        include_archived: bool,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<Conversation>> {
+        let mut conversations: Vec<Conversation> = self
+            .inner
+            .lock()
+            .unwrap()
+            .conversations
+            .values()
+            .filter(|c| include_archived || !c.archived)
+            .cloned()
+            .collect();
+
+        conversations.sort_by_key(|c| std::cmp::Reverse(c.updated_at));
+
+        Ok(conversations
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn list_conversations_after(
+        &self,
+        include_archived: bool,
+        limit: u32,
+        cursor: Option<&ConversationCursor>,
+    ) -> Result<(Vec<Conversation>, Option<ConversationCursor>)> {
+        let key = |c: &Conversation| (c.updated_at, c.id.clone());
+
+        let mut conversations: Vec<Conversation> = self
+            .inner
+            .lock()
+            .unwrap()
+            .conversations
+            .values()
+            .filter(|c| include_archived || !c.archived)
+            .filter(|c| cursor.is_none_or(|cur| key(c) < (cur.updated_at, cur.id.clone())))
+            .cloned()
+            .collect();
+
+        conversations.sort_by_key(|c| std::cmp::Reverse(key(c)));
+
+        let has_more = conversations.len() > limit as usize;
+        conversations.truncate(limit as usize);
+        let next = has_more
+            .then(|| conversations.last().map(ConversationCursor::after))
+            .flatten();
+
+        Ok((conversations, next))
+    }
+
+    async fn count_conversations(&self, include_archived: bool) -> Result<i64> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .conversations
+            .values()
+            .filter(|c| include_archived || !c.archived)
+            .count() as i64)
+    }
+
+    async fn active_conversations_from(&self, from_number: &str) -> Result<Vec<String>> {
+        let mut conversations: Vec<Conversation> = self
+            .inner
+            .lock()
+            .unwrap()
+            .conversations
+            .values()
+            .filter(|c| !c.archived && c.from_number.as_deref() == Some(from_number))
+            .cloned()
+            .collect();
+
+        conversations.sort_by_key(|c| std::cmp::Reverse(c.updated_at));
+        Ok(conversations.into_iter().map(|c| c.id).collect())
+    }
+
+    async fn taken_over_conversation_from(&self, from_number: &str) -> Result<Option<String>> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .conversations
+            .values()
+            .filter(|c| !c.archived && !c.ai_enabled && c.from_number.as_deref() == Some(from_number))
+            .max_by(|a, b| (a.updated_at, &a.id).cmp(&(b.updated_at, &b.id)))
+            .map(|c| c.id.clone()))
+    }
+
+    async fn resolve_inbound_conversation(
+        &self,
+        from_number: &str,
+        in_reply_to: Option<&str>,
+        max_per_number: Option<usize>,
+    ) -> Result<Option<String>> {
+        if let Some(sid) = in_reply_to {
+            if let Some(conversation_id) = self.find_conversation_by_provider_sid(sid).await? {
+                return Ok(Some(conversation_id));
+            }
+        }
+        if let Some(conversation_id) = self.taken_over_conversation_from(from_number).await? {
+            return Ok(Some(conversation_id));
+        }
+        let Some(max) = max_per_number else {
+            return Ok(None);
+        };
+        let active = self.active_conversations_from(from_number).await?;
+        Ok(if active.len() >= max { active.into_iter().next() } else { None })
+    }
+
+    async fn set_conversation_archived(&self, conversation_id: &str, archived: bool) -> Result<()> {
+        self.update_conversation(conversation_id, |conversation| {
+            conversation.archived = archived;
+        })
+    }
+
+    async fn mark_read(&self, conversation_id: &str, at: DateTime<Utc>) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.conversations.contains_key(conversation_id) {
+            anyhow::bail!(NotFound::conversation(conversation_id));
+        }
+
+        inner.last_read.insert(conversation_id.to_string(), at);
+        Ok(())
+    }
+
+    async fn unread_count(&self, conversation_id: &str) -> Result<i64> {
+        let inner = self.inner.lock().unwrap();
+        let last_read = inner.last_read.get(conversation_id);
+
+        Ok(inner
+            .messages
+            .get(conversation_id)
+            .map(|messages| {
+                messages
+                    .iter()
+                    .filter(|m| last_read.is_none_or(|at| m.created_at > *at))
+                    .count()
+            })
+            .unwrap_or_default() as i64)
+    }
+
+    async fn store_messages_batch(&self, messages: Vec<Message>) -> Result<Vec<Message>> {
+        // All-or-nothing, like the Turso transaction
+        let messages = messages
+            .into_iter()
+            .map(|m| self.content_limit.apply(m))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut stored = Vec::with_capacity(messages.len());
+        for message in messages {
+            stored.push(self.insert_message(message).await?);
+        }
+        Ok(stored)
+    }
+
+    async fn insert_message(&self, message: Message) -> Result<Message> {
+        let message = self.content_limit.apply(message)?;
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(conversation) = inner.conversations.get_mut(&message.conversation_id) {
+            conversation.touch(message.created_at);
+        }
+
+        inner
+            .messages
+            .entry(message.conversation_id.clone())
+            .or_default()
+            .push(message.clone());
+
+        Ok(message)
+    }
+
+    async fn get_message(&self, message_id: &str) -> Result<Option<Message>> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .messages
+            .values()
+            .flatten()
+            .find(|m| m.id == message_id)
+            .cloned())
+    }
+
+    async fn find_conversation_by_provider_sid(&self, provider_sid: &str) -> Result<Option<String>> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .messages
+            .values()
+            .flatten()
+            .find(|m| m.provider_sid.as_deref() == Some(provider_sid))
+            .map(|m| m.conversation_id.clone()))
+    }
+
+    async fn get_conversation_messages(&self, conversation_id: &str) -> Result<Vec<Message>> {
+        let mut messages = self
+            .inner
+            .lock()
+            .unwrap()
+            .messages
+            .get(conversation_id)
+            .cloned()
+            .unwrap_or_default();
+
+        messages.sort_by_key(|m| m.created_at);
+        Ok(messages)
+    }
+
+    async fn get_recent_messages(
+        &self,
+        conversation_id: &str,
+        limit: u32,
+        max_chars: Option<usize>,
+    ) -> Result<Vec<Message>> {
+        let mut messages = self.get_conversation_messages(conversation_id).await?;
+        messages.drain(..messages.len().saturating_sub(limit as usize));
+
+        if let Some(max_chars) = max_chars {
+            for message in &mut messages {
+                if let Some((cut, _)) = message.content.char_indices().nth(max_chars) {
+                    message.content.truncate(cut);
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    async fn get_conversation_messages_after(
+        &self,
+        conversation_id: &str,
+        cursor: Option<&MessageCursor>,
+        limit: u32,
+    ) -> Result<Vec<Message>> {
+        let key = |m: &Message| (m.created_at, m.id.clone());
+
+        let mut messages: Vec<Message> = self
+            .inner
+            .lock()
+            .unwrap()
+            .messages
+            .get(conversation_id)
+            .into_iter()
+            .flatten()
+            .filter(|m| cursor.is_none_or(|cur| key(m) > (cur.created_at, cur.id.clone())))
+            .cloned()
+            .collect();
+
+        messages.sort_by_key(key);
+        messages.truncate(limit as usize);
+        Ok(messages)
+    }
+
+    async fn count_conversation_messages(&self, conversation_id: &str) -> Result<i64> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .messages
+            .get(conversation_id)
+            .map_or(0, |messages| messages.len() as i64))
+    }
+
+    async fn has_assistant_reply(&self, conversation_id: &str) -> Result<bool> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .messages
+            .get(conversation_id)
+            .is_some_and(|messages| messages.iter().any(|m| m.is_from_assistant())))
+    }
+
+    async fn get_conversation_messages_filtered(
+        &self,
+        conversation_id: &str,
+        role: Option<&MessageRole>,
+        since: Option<DateTime<Utc>>,
+        cursor: Option<&MessageCursor>,
+        limit: u32,
+    ) -> Result<Vec<Message>> {
+        let mut messages = self.get_conversation_messages(conversation_id).await?;
+
+        messages.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        messages.retain(|m| {
+            role.is_none_or(|role| &m.role == role)
+                && since.is_none_or(|since| m.created_at >= since)
+                && cursor.is_none_or(|cur| cur.precedes(m))
+        });
+        messages.truncate(limit as usize);
+        Ok(messages)
+    }
+
+    async fn count_conversation_messages_filtered(
+        &self,
+        conversation_id: &str,
+        role: Option<&MessageRole>,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<i64> {
+        let messages = self.get_conversation_messages(conversation_id).await?;
+
+        Ok(messages
+            .iter()
+            .filter(|m| role.is_none_or(|role| &m.role == role) && since.is_none_or(|since| m.created_at >= since))
+            .count() as i64)
+    }
+
+    async fn record_outbound_sent(&self, message_id: &str, provider_sid: &str) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let found = inner
+            .messages
+            .values_mut()
+            .flatten()
+            .find(|m| m.id == message_id);
+
+        if let Some(message) = found {
+            message.provider_sid = Some(provider_sid.to_string());
+            inner.deliveries.insert(message_id.to_string(), "sent".into());
+        }
+
+        Ok(())
+    }
+
+    async fn update_delivery_status(&self, provider_sid: &str, status: &str) -> Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let ids: Vec<String> = inner
+            .messages
+            .values()
+            .flatten()
+            .filter(|m| m.is_from_assistant())
+            .filter(|m| m.provider_sid.as_deref() == Some(provider_sid))
+            .map(|m| m.id.clone())
+            .collect();
+
+        for id in &ids {
+            inner.deliveries.insert(id.clone(), status.to_string());
+        }
+
+        Ok(!ids.is_empty())
+    }
+
+    async fn purge_conversations_older_than(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let stale: Vec<String> = inner
+            .conversations
+            .values()
+            .filter(|c| c.updated_at < cutoff)
+            .map(|c| c.id.clone())
+            .collect();
+
+        for id in &stale {
+            if let Some(messages) = inner.messages.remove(id) {
+                for message in messages {
+                    inner.deliveries.remove(&message.id);
+                    inner.embeddings.remove(&message.id);
+                }
+            }
+            inner.conversations.remove(id);
+            inner.last_read.remove(id);
+        }
+
+        Ok(stale.len() as u64)
+    }
+
+    async fn merge_conversations(&self, from_id: &str, into_id: &str) -> Result<()> {
+        if from_id == into_id {
+            anyhow::bail!("Cannot merge conversation {from_id} into itself");
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+
+        if !inner.conversations.contains_key(into_id) {
+            return Err(NotFound::conversation(into_id).into());
+        }
+        let Some(source) = inner.conversations.remove(from_id) else {
+            return Err(NotFound::conversation(from_id).into());
+        };
+        inner.last_read.remove(from_id);
+
+        let mut moved = inner.messages.remove(from_id).unwrap_or_default();
+        for message in &mut moved {
+            message.conversation_id = into_id.to_string();
+        }
+
+        let from_prefix = format!("{from_id}:");
+        let rekeyed: Vec<String> = inner
+            .sent_sms
+            .iter()
+            .filter(|key| key.starts_with(&from_prefix))
+            .cloned()
+            .collect();
+        for key in rekeyed {
+            inner.sent_sms.remove(&key);
+            inner.sent_sms.insert(sent_sms_key(into_id, &key[from_prefix.len()..]));
+        }
+
+        let messages = inner.messages.entry(into_id.to_string()).or_default();
+        messages.extend(moved);
+        messages.sort_by_key(|m| m.created_at);
+
+        if let Some(target) = inner.conversations.get_mut(into_id) {
+            target.touch(target.updated_at.max(source.updated_at));
+        }
+
+        Ok(())
+    }
+
+    async fn set_opted_out(&self, phone_number: &str) -> Result<()> {
+        self.inner.lock().unwrap().opted_out.insert(phone_number.to_string());
+        Ok(())
+    }
+
+    async fn is_opted_out(&self, phone_number: &str) -> Result<bool> {
+        Ok(self.inner.lock().unwrap().opted_out.contains(phone_number))
+    }
+
+    async fn record_usage(&self, phone_number: &str, date: NaiveDate, kind: UsageKind) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let usage = inner.usage.entry((phone_number.to_string(), date)).or_default();
+        match kind {
+            UsageKind::AiCompletion => usage.ai_calls += 1,
+            UsageKind::OutboundSms => usage.sms_sent += 1,
+        }
+        Ok(())
+    }
+
+    async fn claim_usage(&self, phone_number: &str, date: NaiveDate, kind: UsageKind, max: Option<u32>) -> Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        let usage = inner.usage.entry((phone_number.to_string(), date)).or_default();
+        let count = match kind {
+            UsageKind::AiCompletion => &mut usage.ai_calls,
+            UsageKind::OutboundSms => &mut usage.sms_sent,
+        };
+        if max.is_some_and(|max| *count >= max) {
+            return Ok(false);
+        }
+        *count += 1;
+        Ok(true)
+    }
+
+    async fn daily_usage(&self, phone_number: &str, date: NaiveDate) -> Result<DailyUsage> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .usage
+            .get(&(phone_number.to_string(), date))
+            .copied()
+            .unwrap_or_default())
+    }
+
+    async fn mark_cap_notice_sent(&self, phone_number: &str, date: NaiveDate) -> Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        let usage = inner.usage.entry((phone_number.to_string(), date)).or_default();
+        Ok(!std::mem::replace(&mut usage.notice_sent, true))
+    }
+
+    async fn store_embedding(&self, message_id: &str, _model: &str, embedding: &[f32]) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap()
+            .embeddings
+            .insert(message_id.to_string(), embedding.to_vec());
+        Ok(())
+    }
+
+    async fn get_embedding(&self, message_id: &str) -> Result<Option<Vec<f32>>> {
+        Ok(self.inner.lock().unwrap().embeddings.get(message_id).cloned())
+    }
+
+    async fn store_raw_webhook(&self, payload: &str) -> Result<i64> {
+        let raw_webhooks = &mut self.inner.lock().unwrap().raw_webhooks;
+        let id = raw_webhooks.last().map_or(1, |(id, ..)| id + 1);
+        raw_webhooks.push((id, payload.to_string(), None, self.clock.now()));
+        Ok(id)
+    }
+
+    async fn set_raw_webhook_conversation(&self, id: i64, conversation_id: &str) -> Result<()> {
+        let raw_webhooks = &mut self.inner.lock().unwrap().raw_webhooks;
+        match raw_webhooks.iter_mut().find(|(raw_id, ..)| *raw_id == id) {
+            Some((_, _, conversation, _)) => *conversation = Some(conversation_id.to_string()),
+            None => anyhow::bail!(NotFound::raw_webhook(id)),
+        }
+        Ok(())
+    }
+
+    async fn purge_raw_webhooks_older_than(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        let raw_webhooks = &mut self.inner.lock().unwrap().raw_webhooks;
+        let before = raw_webhooks.len();
+        raw_webhooks.retain(|(_, _, _, received_at)| *received_at >= cutoff);
+        Ok((before - raw_webhooks.len()) as u64)
+    }
+
+    async fn schedule_sms(&self, to: &PhoneNumber, body: &str, send_at: DateTime<Utc>) -> Result<ScheduledMessage> {
+        let scheduled = ScheduledMessage::new_with_clock(to.to_string(), body.to_string(), send_at, self.clock());
+        self.inner.lock().unwrap().scheduled.push(scheduled.clone());
+        Ok(scheduled)
+    }
+
+    async fn due_scheduled_messages(&self, now: DateTime<Utc>, limit: u32) -> Result<Vec<ScheduledMessage>> {
+        let mut due: Vec<ScheduledMessage> = self
+            .inner
+            .lock()
+            .unwrap()
+            .scheduled
+            .iter()
+            .filter(|s| s.sent_at.is_none() && s.send_at <= now)
+            .filter(|s| s.next_attempt_at.is_none_or(|at| at <= now))
+            .cloned()
+            .collect();
+
+        due.sort_by(|a, b| (a.send_at, &a.id).cmp(&(b.send_at, &b.id)));
+        due.truncate(limit as usize);
+        Ok(due)
+    }
+
+    async fn mark_scheduled_sent(&self, id: &str, provider_sid: Option<&str>) -> Result<()> {
+        if let Some(scheduled) = self.inner.lock().unwrap().scheduled.iter_mut().find(|s| s.id == id) {
+            scheduled.sent_at = Some(self.clock.now());
+            scheduled.provider_sid = provider_sid.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    async fn defer_scheduled(&self, id: &str, attempts: u32, until: DateTime<Utc>) -> Result<()> {
+        if let Some(scheduled) = self.inner.lock().unwrap().scheduled.iter_mut().find(|s| s.id == id) {
+            scheduled.attempts = attempts;
+            scheduled.next_attempt_at = Some(until);
+        }
+        Ok(())
+    }
+
+    async fn record_outbound(&self, audit: &OutboundAudit) -> Result<()> {
+        self.inner.lock().unwrap().outbound_audit.push(audit.clone());
+        Ok(())
+    }
+
+    async fn outbound_audit_since(
+        &self,
+        since: DateTime<Utc>,
+        cursor: Option<&AuditCursor>,
+        limit: u32,
+    ) -> Result<Vec<OutboundAudit>> {
+        let mut audit: Vec<OutboundAudit> = self
+            .inner
+            .lock()
+            .unwrap()
+            .outbound_audit
+            .iter()
+            .filter(|a| a.created_at >= since && cursor.is_none_or(|cur| cur.precedes(a)))
+            .cloned()
+            .collect();
+
+        audit.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        audit.truncate(limit as usize);
+        Ok(audit)
+    }
+
+    async fn count_outbound_audit_since(&self, since: DateTime<Utc>) -> Result<i64> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .outbound_audit
+            .iter()
+            .filter(|a| a.created_at >= since)
+            .count() as i64)
+    }
+
+    async fn mark_sms_sent(&self, conversation_id: &str, message_id: &str) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap()
+            .sent_sms
+            .insert(sent_sms_key(conversation_id, message_id));
+        Ok(())
+    }
+
+    async fn was_sms_sent(&self, conversation_id: &str, message_id: &str) -> Result<bool> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .sent_sms
+            .contains(&sent_sms_key(conversation_id, message_id)))
+    }
+
+    async fn last_reply_delivery_status(&self, conversation_id: &str) -> Result<Option<String>> {
+        let inner = self.inner.lock().unwrap();
+
+        Ok(inner
+            .messages
+            .get(conversation_id)
+            .into_iter()
+            .flatten()
+            .filter(|m| m.is_from_assistant())
+            .filter_map(|m| inner.deliveries.get(&m.id).map(|s| (m.created_at, s)))
+            .max_by_key(|(created_at, _)| *created_at)
+            .map(|(_, status)| status.clone()))
+    }
+}
@@ -1,29 +1,154 @@
 use anyhow::{Context, Result};
-use chrono::{DateTime, Utc};
-use reqwest::Client;
+use chrono::{DateTime, NaiveDate, Utc};
+use reqwest::{Certificate, Client};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::{field, info, instrument, warn, Span};
 
-use crate::models::{Conversation, Message, MessageRole};
+use crate::clock::{Clock, SystemClock};
+use crate::normalize::content_hash;
+use crate::models::{
+    AggregateStats, AuditCursor, Conversation, ConversationCursor, DailyCount, Message, MessageCursor, MessageRole,
+    OutboundAudit, ScheduledMessage,
+};
+use crate::phone_number::PhoneNumber;
+use crate::storage::{sent_sms_key, ConversationStorage, NotFound};
+use crate::usage_caps::{DailyUsage, UsageKind};
 
 /// =============================
 /// Turso HTTP Types
 /// =============================
 #[derive(Debug, Serialize)]
 struct TursoRequest {
-    requests: Vec<TursoExecute>,
+    requests: Vec<TursoStreamRequest>,
+    /// The caller vouched that running it twice is harmless
+    #[serde(skip)]
+    idempotent: bool,
 }
 
+impl TursoRequest {
+    /// Safe to send again after a 5xx, which may arrive after the
+    /// statements already ran: reads, `IF [NOT] EXISTS` schema changes,
+    /// or anything the caller marked with `PipelineBuilder::idempotent`
+    fn is_idempotent(&self) -> bool {
+        self.idempotent
+            || self
+                .requests
+                .iter()
+                .flat_map(|r| match r {
+                    TursoStreamRequest::Execute { stmt } => vec![stmt],
+                    TursoStreamRequest::Batch { batch } => batch.steps.iter().map(|s| &s.stmt).collect(),
+                })
+                .all(|stmt| is_idempotent_sql(&stmt.sql))
+    }
+
+    /// `SELECT`, `INSERT`, ... for a single statement, `BATCH` for a
+    /// single batch, else `PIPELINE`
+    fn kind(&self) -> String {
+        match self.requests.as_slice() {
+            [TursoStreamRequest::Execute { stmt }] => stmt
+                .sql
+                .split_whitespace()
+                .next()
+                .unwrap_or("")
+                .to_uppercase(),
+            [TursoStreamRequest::Batch { .. }] => "BATCH".to_string(),
+            _ => "PIPELINE".to_string(),
+        }
+    }
+
+    /// Redacted statements, safe to log
+    fn summary(&self) -> String {
+        self.requests
+            .iter()
+            .flat_map(|r| match r {
+                TursoStreamRequest::Execute { stmt } => vec![stmt],
+                TursoStreamRequest::Batch { batch } => batch.steps.iter().map(|s| &s.stmt).collect(),
+            })
+            .map(|stmt| redact_sql(&stmt.sql))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TursoStreamRequest {
+    Execute { stmt: TursoStatement },
+    Batch { batch: TursoBatch },
+}
+
+/// Statements run server-side in one go, each step only when its
+/// `condition` holds
 #[derive(Debug, Serialize)]
-struct TursoExecute {
-    #[serde(rename = "type")]
-    kind: &'static str,
+struct TursoBatch {
+    steps: Vec<TursoBatchStep>,
+}
+
+#[derive(Debug, Serialize)]
+struct TursoBatchStep {
     stmt: TursoStatement,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    condition: Option<TursoCondition>,
 }
 
 #[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TursoCondition {
+    /// Step `step` ran and succeeded
+    Ok { step: usize },
+    Not { cond: Box<TursoCondition> },
+}
+
+#[derive(Debug, Clone, Serialize)]
 struct TursoStatement {
     sql: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    args: Vec<SqlArg>,
+}
+
+/// Positional statement argument, encoded as a Turso typed value
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum SqlArg {
+    Null,
+    /// Turso expects integers as strings
+    Integer(String),
+    Float(f64),
+    Text(String),
+}
+
+impl From<&str> for SqlArg {
+    fn from(value: &str) -> Self {
+        SqlArg::Text(value.to_string())
+    }
+}
+
+impl From<String> for SqlArg {
+    fn from(value: String) -> Self {
+        SqlArg::Text(value)
+    }
+}
+
+impl From<i64> for SqlArg {
+    fn from(value: i64) -> Self {
+        SqlArg::Integer(value.to_string())
+    }
+}
+
+impl From<f64> for SqlArg {
+    fn from(value: f64) -> Self {
+        SqlArg::Float(value)
+    }
+}
+
+impl<T: Into<SqlArg>> From<Option<T>> for SqlArg {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(SqlArg::Null)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -33,222 +158,3782 @@ struct TursoResponse {
 
 #[derive(Debug, Deserialize)]
 struct TursoResult {
+    #[serde(rename = "type", default)]
+    kind: String,
     response: Option<TursoInnerResponse>,
+    error: Option<TursoError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TursoError {
+    message: String,
 }
 
 #[derive(Debug, Deserialize)]
 struct TursoInnerResponse {
-    result: Option<TursoQueryResult>,
+    result: Option<TursoResultBody>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TursoResultBody {
+    Batch(TursoBatchResult),
+    Execute(TursoQueryResult),
+}
+
+impl TursoResultBody {
+    fn query(&self) -> Option<&TursoQueryResult> {
+        match self {
+            TursoResultBody::Execute(result) => Some(result),
+            TursoResultBody::Batch(_) => None,
+        }
+    }
+}
+
+/// One entry per step; `None` where the step was skipped or failed
+#[derive(Debug, Deserialize)]
+struct TursoBatchResult {
+    step_results: Vec<Option<TursoQueryResult>>,
+    step_errors: Vec<Option<TursoError>>,
 }
 
 #[derive(Debug, Deserialize)]
 struct TursoQueryResult {
+    #[serde(default)]
+    cols: Vec<TursoColumn>,
     rows: Option<Vec<Vec<TursoValue>>>,
+    #[serde(default)]
+    affected_row_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TursoColumn {
+    name: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct TursoValue {
+    /// Absent for NULL (`{"type": "null"}`)
+    #[serde(default)]
     value: serde_json::Value,
 }
 
+impl TursoResponse {
+    /// Rows of the first statement result (empty if none)
+    fn rows(&self) -> &[Vec<TursoValue>] {
+        self.results
+            .first()
+            .and_then(|r| r.response.as_ref())
+            .and_then(|r| r.result.as_ref())
+            .and_then(TursoResultBody::query)
+            .and_then(|r| r.rows.as_deref())
+            .unwrap_or(&[])
+    }
+}
+
+impl TursoValue {
+    fn as_str(&self) -> Option<&str> {
+        self.value.as_str()
+    }
+}
+
+/// Statements that change nothing, or nothing more when repeated
+fn is_idempotent_sql(sql: &str) -> bool {
+    let sql = sql.to_uppercase();
+    match sql.split_whitespace().next().unwrap_or("") {
+        "SELECT" | "PRAGMA" | "EXPLAIN" | "BEGIN" | "COMMIT" | "ROLLBACK" => true,
+        "CREATE" => sql.contains(" IF NOT EXISTS "),
+        "DROP" => sql.contains(" IF EXISTS "),
+        _ => false,
+    }
+}
+
+/// -----------------------------
+/// Pipeline Builder
+/// -----------------------------
+/// Collects several statements so they run in a single `/v2/pipeline`
+/// round-trip. Statements run in order; use `?` placeholders for args.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineBuilder {
+    statements: Vec<TursoStatement>,
+    idempotent: bool,
+}
+
+impl PipelineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a statement with positional args
+    pub fn statement(mut self, sql: impl Into<String>, args: Vec<SqlArg>) -> Self {
+        self.statements.push(TursoStatement {
+            sql: sql.into(),
+            args,
+        });
+        self
+    }
+
+    /// Retry on server errors even though the pipeline writes: the caller
+    /// vouches that applying it twice is harmless (upserts, `INSERT OR
+    /// IGNORE`, `UPDATE`s that set absolute values). Reads are always
+    /// retried.
+    pub fn idempotent(mut self) -> Self {
+        self.idempotent = true;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.statements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+
+    fn into_request(self) -> TursoRequest {
+        TursoRequest {
+            requests: self
+                .statements
+                .into_iter()
+                .map(|stmt| TursoStreamRequest::Execute { stmt })
+                .collect(),
+            idempotent: self.idempotent,
+        }
+    }
+
+    /// One batch: `BEGIN`, the statements, `COMMIT`, each step running
+    /// only if the one before it succeeded, and `ROLLBACK` unless the
+    /// commit happened
+    fn into_transaction(self) -> TursoRequest {
+        let plain = |sql: &str| TursoStatement {
+            sql: sql.to_string(),
+            args: Vec::new(),
+        };
+        let after = |step: usize| Some(TursoCondition::Ok { step });
+
+        let mut steps = vec![TursoBatchStep {
+            stmt: plain("BEGIN"),
+            condition: None,
+        }];
+        steps.extend(self.statements.into_iter().enumerate().map(|(i, stmt)| TursoBatchStep {
+            stmt,
+            condition: after(i),
+        }));
+
+        let commit = steps.len();
+        steps.push(TursoBatchStep {
+            stmt: plain("COMMIT"),
+            condition: after(commit - 1),
+        });
+        steps.push(TursoBatchStep {
+            stmt: plain("ROLLBACK"),
+            condition: Some(TursoCondition::Not {
+                cond: Box::new(TursoCondition::Ok { step: commit }),
+            }),
+        });
+
+        TursoRequest {
+            requests: vec![TursoStreamRequest::Batch {
+                batch: TursoBatch { steps },
+            }],
+            idempotent: self.idempotent,
+        }
+    }
+}
+
+/// Result of one pipeline statement
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    /// Raw cell values; NULL is `Value::Null`, integers arrive as strings
+    pub rows: Vec<Vec<serde_json::Value>>,
+    pub affected_row_count: u64,
+}
+
+impl From<TursoQueryResult> for QueryResult {
+    fn from(result: TursoQueryResult) -> Self {
+        Self {
+            columns: result
+                .cols
+                .into_iter()
+                .map(|c| c.name.unwrap_or_default())
+                .collect(),
+            rows: result
+                .rows
+                .unwrap_or_default()
+                .into_iter()
+                .map(|row| row.into_iter().map(|v| v.value).collect())
+                .collect(),
+            affected_row_count: result.affected_row_count,
+        }
+    }
+}
+
+/// Tables `initialize` must leave behind
+const SCHEMA_TABLES: [&str; 10] = [
+    "conversations",
+    "messages",
+    "processed_messages",
+    "opt_outs",
+    "sent_sms",
+    "raw_webhooks",
+    "message_embeddings",
+    "usage_daily",
+    "scheduled_messages",
+    "outbound_audit",
+];
+
+/// Column lists matching `decode_conversation` / `decode_message`
+const CONVERSATION_COLUMNS: &str =
+    "id, title, system_prompt, created_at, updated_at, archived, ai_model, ai_temperature, sender_label, context, ai_enabled, from_number";
+const MESSAGE_COLUMNS: &str =
+    "id, conversation_id, role, content, provider_sid, metadata, created_at, sender_label";
+/// Column list matching `decode_scheduled_message`
+const SCHEDULED_MESSAGE_COLUMNS: &str =
+    "id, recipient, body, send_at, sent_at, provider_sid, created_at, attempts, next_attempt_at";
+/// Column list matching `decode_outbound_audit`
+const OUTBOUND_AUDIT_COLUMNS: &str =
+    "id, conversation_id, recipient, body_length, status, provider_sid, error, created_at";
+
+fn parse_timestamp(value: &TursoValue) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(value.as_str().unwrap_or(""))?.with_timezone(&Utc))
+}
+
+fn decode_conversation(row: &[TursoValue]) -> Result<Conversation> {
+    let id = row[0].as_str().unwrap_or("").to_string();
+
+    // Bad context JSON shouldn't hide the conversation itself
+    let context = row[9].as_str().and_then(|raw| {
+        serde_json::from_str(raw)
+            .map_err(|e| warn!("Conversation {} has invalid context: {e}", id))
+            .ok()
+    });
+
+    Ok(Conversation {
+        title: row[1].as_str().map(str::to_string),
+        system_prompt: row[2].as_str().map(str::to_string),
+        created_at: parse_timestamp(&row[3])?,
+        updated_at: parse_timestamp(&row[4])?,
+        archived: row[5].as_str().is_some_and(|v| v != "0"),
+        ai_enabled: row[10].as_str().is_none_or(|v| v != "0"),
+        ai_model: row[6].as_str().map(str::to_string),
+        ai_temperature: row[7].value.as_f64().map(|t| t as f32),
+        sender_label: row[8].as_str().map(str::to_string),
+        context,
+        from_number: row[11].as_str().map(str::to_string),
+        id,
+    })
+}
+
+/// Pipeline rows carry plain JSON values; wrap them for the decoders
+fn typed_row(row: &[serde_json::Value]) -> Vec<TursoValue> {
+    row.iter()
+        .map(|value| TursoValue { value: value.clone() })
+        .collect()
+}
+
+fn decode_message(row: &[TursoValue]) -> Result<Message> {
+    let id = row[0].as_str().unwrap_or("").to_string();
+
+    let role = MessageRole::parse(row[2].as_str().unwrap_or(""));
+    if let MessageRole::Unknown(raw) = &role {
+        warn!("Message {} has unknown role {:?}", id, raw);
+    }
+
+    // Bad metadata JSON shouldn't hide the message itself
+    let metadata = row[5].as_str().and_then(|raw| {
+        serde_json::from_str(raw)
+            .map_err(|e| warn!("Message {} has invalid metadata: {e}", id))
+            .ok()
+    });
+
+    Ok(Message {
+        conversation_id: row[1].as_str().unwrap_or("").to_string(),
+        role,
+        content: row[3].as_str().unwrap_or("").to_string(),
+        provider_sid: row[4].as_str().map(str::to_string),
+        metadata,
+        sender_label: row[7].as_str().map(str::to_string),
+        created_at: parse_timestamp(&row[6])?,
+        id,
+    })
+}
+
+fn decode_scheduled_message(row: &[TursoValue]) -> Result<ScheduledMessage> {
+    Ok(ScheduledMessage {
+        id: row[0].as_str().unwrap_or("").to_string(),
+        to: row[1].as_str().unwrap_or("").to_string(),
+        body: row[2].as_str().unwrap_or("").to_string(),
+        send_at: parse_timestamp(&row[3])?,
+        sent_at: row[4].as_str().is_some().then(|| parse_timestamp(&row[4])).transpose()?,
+        provider_sid: row[5].as_str().map(str::to_string),
+        created_at: parse_timestamp(&row[6])?,
+        attempts: row[7].as_str().and_then(|n| n.parse().ok()).unwrap_or(0),
+        next_attempt_at: row[8].as_str().is_some().then(|| parse_timestamp(&row[8])).transpose()?,
+    })
+}
+
+fn decode_outbound_audit(row: &[TursoValue]) -> Result<OutboundAudit> {
+    Ok(OutboundAudit {
+        id: row[0].as_str().unwrap_or("").to_string(),
+        conversation_id: row[1].as_str().map(str::to_string),
+        to: row[2].as_str().unwrap_or("").to_string(),
+        body_length: row[3].as_str().and_then(|n| n.parse().ok()).unwrap_or(0),
+        status: row[4].as_str().unwrap_or("").to_string(),
+        provider_sid: row[5].as_str().map(str::to_string),
+        error: row[6].as_str().map(str::to_string),
+        created_at: parse_timestamp(&row[7])?,
+    })
+}
+
+/// Longest SQL prefix that ends up in logs
+const LOGGED_SQL_LEN: usize = 80;
+
+/// Blank out string literals (message bodies, phone numbers) and
+/// truncate, so statements can be logged
+fn redact_sql(sql: &str) -> String {
+    let mut out = String::new();
+    let mut chars = sql.chars().peekable();
+    let mut in_literal = false;
+
+    while let Some(c) = chars.next() {
+        match (c, in_literal) {
+            ('\'', false) => {
+                in_literal = true;
+                out.push_str("'?'");
+            }
+            // '' is an escaped quote inside a literal
+            ('\'', true) if chars.peek() == Some(&'\'') => {
+                chars.next();
+            }
+            ('\'', true) => in_literal = false,
+            (_, true) => {}
+            (c, false) if c.is_whitespace() => {
+                if !out.ends_with(' ') {
+                    out.push(' ');
+                }
+            }
+            (c, false) => out.push(c),
+        }
+    }
+
+    let out = out.trim();
+    match out.char_indices().nth(LOGGED_SQL_LEN) {
+        Some((i, _)) => format!("{}…", &out[..i]),
+        None => out.to_string(),
+    }
+}
+
+/// `Conversation::touch` in SQL
+const TOUCH_CONVERSATION_SQL: &str =
+    "UPDATE conversations SET updated_at = MAX(updated_at, ?) WHERE id = ?";
+
+/// Newest `created_at` per conversation, ordered by conversation id: the
+/// one `updated_at` write each conversation needs after a batch insert
+fn coalesce_touches(messages: &[Message]) -> BTreeMap<&str, DateTime<Utc>> {
+    let mut touches: BTreeMap<&str, DateTime<Utc>> = BTreeMap::new();
+
+    for message in messages {
+        touches
+            .entry(message.conversation_id.as_str())
+            .and_modify(|at| *at = (*at).max(message.created_at))
+            .or_insert(message.created_at);
+    }
+
+    touches
+}
+
+/// All inserts of a batch followed by the coalesced touches, so no
+/// conversation is touched before its messages exist
+fn batch_insert_pipeline(messages: &[Message]) -> Result<PipelineBuilder> {
+    let mut pipeline = PipelineBuilder::new();
+
+    for message in messages {
+        let metadata = message
+            .metadata
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+
+        pipeline = pipeline.statement(
+            "INSERT INTO messages
+                 (id, conversation_id, role, content, provider_sid, metadata, created_at, content_hash, sender_label)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            vec![
+                message.id.as_str().into(),
+                message.conversation_id.as_str().into(),
+                message.role.as_str().into(),
+                message.content.as_str().into(),
+                message.provider_sid.as_deref().into(),
+                metadata.into(),
+                message.created_at.to_rfc3339().into(),
+                content_hash(&message.content).into(),
+                message.sender_label.as_deref().into(),
+            ],
+        );
+    }
+
+    for (conversation_id, at) in coalesce_touches(messages) {
+        pipeline = pipeline.statement(
+            TOUCH_CONVERSATION_SQL,
+            vec![at.to_rfc3339().into(), conversation_id.into()],
+        );
+    }
+
+    Ok(pipeline)
+}
+
+/// Which endpoint a statement goes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    /// May be served by the read replica, which can lag the primary
+    Read,
+    /// Primary only: writes, and reads that must see the latest writes
+    Write,
+}
+
 /// =============================
-/// Conversation Store
+/// Transport
 /// =============================
-pub struct ConversationStore {
+/// Sends one `/v2/pipeline` body and returns the raw JSON response.
+/// `HttpTransport` talks to Turso; tests can swap in canned responses.
+pub trait TursoTransport: Send + Sync {
+    fn post_pipeline(
+        &self,
+        body: serde_json::Value,
+    ) -> impl Future<Output = Result<serde_json::Value>> + Send;
+}
+
+/// Turso over HTTPS
+pub struct HttpTransport {
     client: Client,
-    database_url: String,
+    url: String,
     auth_token: String,
 }
 
-impl ConversationStore {
-    /// Create store (HTTP API)
-    pub fn new(database_url: String, auth_token: String) -> Self {
+impl HttpTransport {
+    pub fn new(database_url: &str, auth_token: &str) -> Self {
         Self {
             client: Client::new(),
-            database_url: database_url.replace("libsql://", "https://"),
+            url: database_url.replace("libsql://", "https://"),
             auth_token: auth_token.trim().to_string(),
         }
     }
+}
 
-    /// -----------------------------
-    /// Low-level SQL executor
-    /// -----------------------------
-    async fn execute_sql(&self, sql: &str) -> Result<TursoResponse> {
-        let url = format!("{}/v2/pipeline", self.database_url);
+impl TursoTransport for HttpTransport {
+    async fn post_pipeline(&self, body: serde_json::Value) -> Result<serde_json::Value> {
+        let url = format!("{}/v2/pipeline", self.url);
 
         let response = self
             .client
             .post(&url)
             .header("Authorization", format!("Bearer {}", self.auth_token))
-            .json(&TursoRequest {
-                requests: vec![TursoExecute {
-                    kind: "execute",
-                    stmt: TursoStatement {
-                        sql: sql.to_string(),
-                    },
-                }],
-            })
+            .json(&body)
             .send()
             .await
             .context("Failed to send request to Turso")?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            anyhow::bail!("Turso error {}: {}", status, text);
+            let status = response.status().as_u16();
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after);
+            let body = response.text().await.unwrap_or_default();
+            return Err(TursoHttpError { status, retry_after, body }.into());
         }
 
-        Ok(response.json::<TursoResponse>().await?)
+        Ok(response.json().await?)
     }
+}
 
-    /// -----------------------------
-    /// Initialize schema
-    /// -----------------------------
-    pub async fn initialize(&self) -> Result<()> {
-        self.execute_sql(
-            "CREATE TABLE IF NOT EXISTS conversations (
-                id TEXT PRIMARY KEY,
-                title TEXT,
-                created_at TEXT NOT NULL,
-                updated_at TEXT NOT NULL
-            )",
-        )
-        .await?;
+/// Longest `Retry-After` we are willing to wait; anything above is capped
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
 
-        self.execute_sql(
-            "CREATE TABLE IF NOT EXISTS messages (
-                id TEXT PRIMARY KEY,
-                conversation_id TEXT NOT NULL,
-                role TEXT NOT NULL,
-                content TEXT NOT NULL,
-                created_at TEXT NOT NULL
-            )",
-        )
-        .await?;
+/// Non-2xx answer from Turso's HTTP endpoint
+#[derive(Debug)]
+pub struct TursoHttpError {
+    pub status: u16,
+    /// Server-advised wait, from the `Retry-After` header
+    pub retry_after: Option<Duration>,
+    pub body: String,
+}
 
-        self.execute_sql(
-            "CREATE TABLE IF NOT EXISTS processed_messages (
-                message_id TEXT PRIMARY KEY
-            )",
-        )
-        .await?;
+impl TursoHttpError {
+    /// Rate limiting and server errors are worth another attempt;
+    /// other 4xx (bad SQL, auth) will fail the same way again
+    pub fn is_retryable(&self) -> bool {
+        self.status == 429 || self.status >= 500
+    }
+}
 
-        Ok(())
+impl std::fmt::Display for TursoHttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Turso error {}: {}", self.status, self.body)
     }
+}
 
-    /// =============================
-    /// IDEMPOTENCY (CRITICAL)
-    /// =============================
-    pub async fn is_message_processed(&self, message_id: &str) -> Result<bool> {
-        let sql = format!(
-            "SELECT 1 FROM processed_messages WHERE message_id = '{}' LIMIT 1",
-            message_id.replace("'", "''")
-        );
+impl std::error::Error for TursoHttpError {}
 
-        let response = self.execute_sql(&sql).await?;
+/// Context on a retryable `TursoHttpError` that `send` gave back without
+/// retrying, because the request writes and may have been applied
+#[derive(Debug)]
+struct WriteNotRetried;
 
-        Ok(response
-            .results
-            .get(0)
-            .and_then(|r| r.response.as_ref())
-            .and_then(|r| r.result.as_ref())
-            .and_then(|r| r.rows.as_ref())
-            .map(|rows| !rows.is_empty())
-            .unwrap_or(false))
+impl std::fmt::Display for WriteNotRetried {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Turso write not retried: it may have been applied")
     }
+}
 
-    pub async fn mark_message_processed(&self, message_id: &str) -> Result<()> {
-        let sql = format!(
-            "INSERT OR IGNORE INTO processed_messages (message_id)
-             VALUES ('{}')",
-            message_id.replace("'", "''")
-        );
+/// `Retry-After` in delta-seconds form. Turso doesn't send HTTP dates,
+/// so those are ignored and the regular backoff applies.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_secs)
+}
 
-        self.execute_sql(&sql).await?;
-        Ok(())
-    }
+async fn post_pipeline<T: TursoTransport>(transport: &T, request: &TursoRequest) -> Result<TursoResponse> {
+    let response = transport.post_pipeline(serde_json::to_value(request)?).await?;
+    serde_json::from_value(response).context("Failed to parse Turso response")
+}
 
-    /// -----------------------------
-    /// Store message
-    /// -----------------------------
-    pub async fn store_message(
-        &self,
-        conversation_id: String,
-        role: MessageRole,
-        content: String,
-    ) -> Result<Message> {
-        let message = Message::new(conversation_id.clone(), role, content);
+/// -----------------------------
+/// Content Size Limit
+/// -----------------------------
+/// What `insert_message` does with content over the configured limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContentOverflowPolicy {
+    /// Cut the content and append `TRUNCATION_MARKER`
+    #[default]
+    Truncate,
+    /// Fail the insert
+    Reject,
+}
 
-        let sql = format!(
-            "INSERT INTO messages (id, conversation_id, role, content, created_at)
-             VALUES ('{}', '{}', '{}', '{}', '{}')",
-            message.id,
-            message.conversation_id,
-            message.role.as_str(),
-            message.content.replace("'", "''"),
-            message.created_at.to_rfc3339()
-        );
+impl std::str::FromStr for ContentOverflowPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "truncate" => Ok(ContentOverflowPolicy::Truncate),
+            "reject" => Ok(ContentOverflowPolicy::Reject),
+            other => anyhow::bail!("Unknown content overflow policy: {other}"),
+        }
+    }
+}
 
-        self.execute_sql(&sql).await?;
+/// Appended to truncated content; counts towards the limit
+pub const TRUNCATION_MARKER: &str = "… [truncated]";
 
-        let update_sql = format!(
-            "UPDATE conversations
-             SET updated_at = '{}'
-             WHERE id = '{}'",
-            Utc::now().to_rfc3339(),
-            conversation_id
-        );
+/// Default cap on a message's `content`, in bytes
+pub const DEFAULT_MAX_CONTENT_BYTES: usize = 64 * 1024;
 
-        self.execute_sql(&update_sql).await?;
-        Ok(message)
+/// Content size cap and what happens past it. Shared by both stores, so
+/// what tests store over `InMemoryStore` matches production.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentLimit {
+    max_bytes: usize,
+    policy: ContentOverflowPolicy,
+}
+
+impl Default for ContentLimit {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CONTENT_BYTES, ContentOverflowPolicy::default())
     }
+}
 
-    /// -----------------------------
-    /// Get conversation history
-    /// -----------------------------
-    pub async fn get_conversation_messages(
-        &self,
-        conversation_id: &str,
-    ) -> Result<Vec<Message>> {
-        let sql = format!(
-            "SELECT id, conversation_id, role, content, created_at
-             FROM messages
-             WHERE conversation_id = '{}'
-             ORDER BY created_at ASC",
-            conversation_id
-        );
+impl ContentLimit {
+    pub fn new(max_bytes: usize, policy: ContentOverflowPolicy) -> Self {
+        Self {
+            max_bytes: max_bytes.max(TRUNCATION_MARKER.len()),
+            policy,
+        }
+    }
 
-        let response = self.execute_sql(&sql).await?;
-        let mut messages = Vec::new();
+    /// Apply the limit before an insert
+    pub fn apply(&self, mut message: Message) -> Result<Message> {
+        let len = message.content.len();
+        if len <= self.max_bytes {
+            return Ok(message);
+        }
 
-        if let Some(result) = response.results.get(0)
-            .and_then(|r| r.response.as_ref())
-            .and_then(|r| r.result.as_ref())
-            .and_then(|r| r.rows.as_ref())
-        {
-            for row in result {
-                let id = row[0].value.as_str().unwrap_or("").to_string();
-                let conv_id = row[1].value.as_str().unwrap_or("").to_string();
-                let role_str = row[2].value.as_str().unwrap_or("");
-                let content = row[3].value.as_str().unwrap_or("").to_string();
-                let created_at_str = row[4].value.as_str().unwrap_or("");
-
-                let role = MessageRole::from_str(role_str)
-                    .context("Invalid role")?;
-
-                let created_at = DateTime::parse_from_rfc3339(created_at_str)?
-                    .with_timezone(&Utc);
-
-                messages.push(Message {
-                    id,
-                    conversation_id: conv_id,
-                    role,
-                    content,
-                    created_at,
-                });
+        match self.policy {
+            ContentOverflowPolicy::Reject => anyhow::bail!(
+                "Message content is {} bytes, over the {} byte limit",
+                len,
+                self.max_bytes
+            ),
+            ContentOverflowPolicy::Truncate => {
+                warn!(
+                    "Truncating message {} in {} from {} to {} bytes",
+                    message.id, message.conversation_id, len, self.max_bytes
+                );
+                message.content = truncate_content(&message.content, self.max_bytes);
+                Ok(message)
             }
         }
+    }
+}
 
-        Ok(messages)
+/// Days of per-day message counts in `aggregate_stats` by default
+pub const DEFAULT_STATS_DAYS: u32 = 30;
+
+/// Longest prefix of `content` that fits in `max_bytes` with the marker
+fn truncate_content(content: &str, max_bytes: usize) -> String {
+    let mut end = max_bytes.saturating_sub(TRUNCATION_MARKER.len()).min(content.len());
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}{}", &content[..end], TRUNCATION_MARKER)
+}
+
+/// =============================
+/// Conversation Store
+/// =============================
+pub struct ConversationStore<T: TursoTransport = HttpTransport> {
+    transport: T,
+    /// Replica for read-only API queries; `None` means the primary
+    read_transport: Option<T>,
+    max_attempts: u32,
+    retry_backoff: Duration,
+    slow_query_threshold: Duration,
+    content_limit: ContentLimit,
+    clock: Arc<dyn Clock>,
+}
+
+impl ConversationStore {
+    /// Create store (HTTP API)
+    pub fn new(database_url: String, auth_token: String) -> Self {
+        Self::with_transport(HttpTransport::new(&database_url, &auth_token))
+    }
+
+    /// Serve listings, searches, counts and stats from a replica. Reads
+    /// that may follow a write stay on the primary, so callers see their
+    /// own writes: lookups by id (`get_conversation`, `get_message`), AI
+    /// history, the per-number cap, idempotency and opt-out checks. That
+    /// is a deliberate trade: those are cheap point reads, and a replica
+    /// that lags would answer 404 for a conversation just created.
+    pub fn with_read_url(mut self, read_url: String) -> Self {
+        self.read_transport = Some(HttpTransport {
+            client: self.transport.client.clone(),
+            url: read_url.replace("libsql://", "https://"),
+            auth_token: self.transport.auth_token.clone(),
+        });
+        self
+    }
+
+    /// Trust an extra root CA for the Turso connection
+    pub fn with_root_certificate(mut self, cert: Certificate) -> Result<Self> {
+        let client = Client::builder()
+            .add_root_certificate(cert)
+            .build()
+            .context("Failed to build Turso HTTP client")?;
+
+        if let Some(read) = &mut self.read_transport {
+            read.client = client.clone();
+        }
+        self.transport.client = client;
+        Ok(self)
+    }
+}
+
+impl<T: TursoTransport> ConversationStore<T> {
+    /// Store on top of any transport (e.g. canned responses in tests)
+    pub fn with_transport(transport: T) -> Self {
+        Self {
+            transport,
+            read_transport: None,
+            max_attempts: 3,
+            retry_backoff: Duration::from_millis(200),
+            slow_query_threshold: Duration::from_millis(500),
+            content_limit: ContentLimit::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Advance the conversation's `updated_at` to `at`. Pass the new
+    /// message's `created_at` so both timestamps agree exactly.
+    pub async fn touch_conversation(&self, conversation_id: &str, at: DateTime<Utc>) -> Result<()> {
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            TOUCH_CONVERSATION_SQL,
+            vec![at.to_rfc3339().into(), conversation_id.into()],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// Messages whose normalized content hashes to `hash` (see
+    /// `normalize::content_hash`), oldest first, across all conversations
+    pub async fn find_messages_by_hash(&self, hash: &str) -> Result<Vec<Message>> {
+        let sql = format!(
+            "SELECT {}
+             FROM messages
+             WHERE content_hash = ?
+             ORDER BY created_at ASC",
+            MESSAGE_COLUMNS
+        );
+
+        let results = self
+            .run_pipeline(PipelineBuilder::new().statement(sql, vec![hash.into()]), Access::Read)
+            .await?;
+
+        results
+            .first()
+            .map(|r| r.rows.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|row| decode_message(&typed_row(row)))
+            .collect()
+    }
+
+    /// -----------------------------
+    /// Stats
+    /// -----------------------------
+    /// Conversation and message totals, messages per role, and messages
+    /// per UTC day for the `days` days ending today. Three aggregate
+    /// queries in one pipeline; the day buckets scan `created_at` by index.
+    pub async fn aggregate_stats(&self, days: u32) -> Result<AggregateStats> {
+        let today = self.clock.now().date_naive();
+        let first_day = today - chrono::Duration::days(days.saturating_sub(1) as i64);
+
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new()
+                    .statement("SELECT COUNT(*) FROM conversations", vec![])
+                    .statement("SELECT role, COUNT(*) FROM messages GROUP BY role", vec![])
+                    .statement(
+                        "SELECT substr(created_at, 1, 10) AS day, COUNT(*) FROM messages
+                         WHERE created_at >= ? GROUP BY day",
+                        vec![first_day.to_string().into()],
+                    ),
+                Access::Read,
+            )
+            .await?;
+
+        let int = |v: Option<&serde_json::Value>| -> Result<i64> {
+            v.and_then(serde_json::Value::as_str)
+                .context("stats row is missing a column")?
+                .parse()
+                .context("stats count is not an integer")
+        };
+        let rows = |i: usize| results.get(i).map(|r| r.rows.as_slice()).unwrap_or_default();
+
+        let total_conversations = int(rows(0).first().and_then(|row| row.first()))?;
+
+        let mut messages_by_role = BTreeMap::new();
+        for row in rows(1) {
+            let role = row.first().and_then(serde_json::Value::as_str).unwrap_or_default();
+            messages_by_role.insert(role.to_string(), int(row.get(1))?);
+        }
+
+        let mut per_day = BTreeMap::new();
+        for row in rows(2) {
+            let day: NaiveDate = row
+                .first()
+                .and_then(serde_json::Value::as_str)
+                .context("stats row is missing a column")?
+                .parse()?;
+            per_day.insert(day, int(row.get(1))?);
+        }
+
+        Ok(AggregateStats {
+            total_conversations,
+            total_messages: messages_by_role.values().sum(),
+            messages_by_role,
+            daily_messages: first_day
+                .iter_days()
+                .take(days as usize)
+                .map(|date| DailyCount {
+                    date,
+                    messages: per_day.get(&date).copied().unwrap_or(0),
+                })
+                .collect(),
+        })
+    }
+
+    /// -----------------------------
+    /// Maintenance
+    /// -----------------------------
+    /// `PRAGMA optimize` (cheap: refreshes query planner statistics) and,
+    /// only when `vacuum` is set, `VACUUM`. VACUUM rewrites the whole
+    /// database file to reclaim space freed by deletes; it takes time and
+    /// I/O proportional to the database size and blocks writes while it
+    /// runs, so schedule it off-peak. Some hosted Turso plans reject it.
+    pub async fn maintenance(&self, vacuum: bool) -> Result<()> {
+        self.execute_sql("PRAGMA optimize", Access::Write).await?;
+
+        if vacuum {
+            let started = Instant::now();
+            self.execute_sql("VACUUM", Access::Write).await?;
+            info!("VACUUM finished in {:?}", started.elapsed());
+        }
+
+        Ok(())
+    }
+
+    /// Round-trip a trivial query to the primary
+    pub async fn health_check(&self) -> Result<()> {
+        self.execute_sql("SELECT 1", Access::Write).await?;
+        Ok(())
+    }
+
+    /// Cap message content at `max_bytes`; longer content is truncated or
+    /// rejected according to `policy`
+    pub fn with_content_limit(mut self, max_bytes: usize, policy: ContentOverflowPolicy) -> Self {
+        self.content_limit = ContentLimit::new(max_bytes, policy);
+        self
+    }
+
+    /// Requests slower than this are logged as warnings
+    pub fn with_slow_query_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_query_threshold = threshold;
+        self
+    }
+
+    /// Override retry settings; the backoff doubles after each failed attempt
+    pub fn with_retry(mut self, max_attempts: u32, backoff: Duration) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Timestamp with `clock` instead of the system time
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Run `op` until it succeeds or `max_attempts` is reached.
+    /// Only use for idempotent operations. HTTP status errors are returned
+    /// as-is, since `send` has already retried the retryable ones, except
+    /// `WriteNotRetried`: `send` can't know a write is safe to repeat, but
+    /// rerunning the whole `op` is.
+    async fn with_retry_backoff<R, F, Fut>(&self, what: &str, mut op: F) -> Result<R>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<R>>,
+    {
+        let mut backoff = self.retry_backoff;
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+
+            match op().await {
+                Ok(value) => return Ok(value),
+                Err(e)
+                    if e.downcast_ref::<TursoHttpError>().is_some()
+                        && e.downcast_ref::<WriteNotRetried>().is_none() =>
+                {
+                    return Err(e)
+                }
+                Err(e) if attempt < self.max_attempts => {
+                    warn!("Turso {} failed (attempt {}): {e:#}", what, attempt);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("Turso {} failed after {} attempts", what, attempt)
+                    })
+                }
+            }
+        }
+    }
+
+    /// -----------------------------
+    /// Low-level SQL executor
+    /// -----------------------------
+    async fn execute_sql(&self, sql: &str, access: Access) -> Result<TursoResponse> {
+        let response = self
+            .send(
+                TursoRequest {
+                requests: vec![TursoStreamRequest::Execute {
+                    stmt: TursoStatement {
+                        sql: sql.to_string(),
+                        args: Vec::new(),
+                    },
+                }],
+                idempotent: false,
+                },
+                access,
+            )
+            .await?;
+
+        // Turso reports statement errors inside a 200 response
+        if let Some(result) = response.results.iter().find(|r| r.kind == "error") {
+            let message = result.error.as_ref().map(|e| e.message.as_str()).unwrap_or("");
+            anyhow::bail!("Turso statement failed: {}", message);
+        }
+
+        Ok(response)
+    }
+
+    /// Run every statement in `pipeline` in one request.
+    /// Fails on the first statement Turso reports as an error.
+    pub async fn execute_sql_pipeline(&self, pipeline: PipelineBuilder) -> Result<Vec<QueryResult>> {
+        self.run_pipeline(pipeline, Access::Write).await
+    }
+
+    /// Run one `UPDATE conversations ... WHERE id = ?`; `NotFound` when
+    /// no conversation has that id
+    async fn update_conversation(&self, conversation_id: &str, sql: &str, args: Vec<SqlArg>) -> Result<()> {
+        let results = self.execute_sql_pipeline(PipelineBuilder::new().statement(sql, args)).await?;
+
+        if results.first().map_or(0, |r| r.affected_row_count) == 0 {
+            anyhow::bail!(NotFound::conversation(conversation_id));
+        }
+        Ok(())
+    }
+
+    async fn run_pipeline(&self, pipeline: PipelineBuilder, access: Access) -> Result<Vec<QueryResult>> {
+        let response = self.send(pipeline.into_request(), access).await?;
+
+        response
+            .results
+            .into_iter()
+            .enumerate()
+            .map(|(i, result)| {
+                if result.kind == "error" {
+                    let message = result.error.map(|e| e.message).unwrap_or_default();
+                    anyhow::bail!("Turso statement {} failed: {}", i, message);
+                }
+
+                Ok(result
+                    .response
+                    .and_then(|r| r.result)
+                    .and_then(|r| match r {
+                        TursoResultBody::Execute(result) => Some(QueryResult::from(result)),
+                        TursoResultBody::Batch(_) => None,
+                    })
+                    .unwrap_or_default())
+            })
+            .collect()
+    }
+
+    /// Run every statement in `pipeline` atomically, in one request: if
+    /// any fails, the transaction is rolled back and nothing is changed.
+    /// Fails naming the first statement that failed.
+    pub async fn transaction(&self, pipeline: PipelineBuilder) -> Result<Vec<QueryResult>> {
+        let count = pipeline.len();
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let response = self.send(pipeline.into_transaction(), Access::Write).await?;
+        let result = response.results.into_iter().next().context("Turso returned no result")?;
+        if result.kind == "error" {
+            let message = result.error.map(|e| e.message).unwrap_or_default();
+            anyhow::bail!("Turso transaction failed: {}", message);
+        }
+
+        let Some(TursoResultBody::Batch(batch)) = result.response.and_then(|r| r.result) else {
+            anyhow::bail!("Turso transaction returned no batch result");
+        };
+
+        // Step 0 is BEGIN, the statements follow, then COMMIT
+        if let Some((step, error)) = batch
+            .step_errors
+            .iter()
+            .enumerate()
+            .find_map(|(step, e)| e.as_ref().map(|e| (step, e)))
+        {
+            match step {
+                0 => anyhow::bail!("Turso transaction failed to begin: {}", error.message),
+                step if step <= count => {
+                    anyhow::bail!("Turso statement {} failed, rolled back: {}", step - 1, error.message)
+                }
+                _ => anyhow::bail!("Turso transaction failed to commit: {}", error.message),
+            }
+        }
+
+        Ok(batch
+            .step_results
+            .into_iter()
+            .skip(1)
+            .take(count)
+            .map(|r| r.map(QueryResult::from).unwrap_or_default())
+            .collect())
+    }
+
+    #[instrument(name = "turso", skip_all, fields(kind = %request.kind(), elapsed_ms = field::Empty))]
+    async fn send(&self, request: TursoRequest, access: Access) -> Result<TursoResponse> {
+        let transport = match (access, &self.read_transport) {
+            (Access::Read, Some(read)) => read,
+            _ => &self.transport,
+        };
+
+        let started = Instant::now();
+        let result = self.post_with_retry(transport, &request).await;
+        let elapsed = started.elapsed();
+
+        Span::current().record("elapsed_ms", elapsed.as_millis() as u64);
+
+        if elapsed >= self.slow_query_threshold {
+            warn!(
+                elapsed_ms = elapsed.as_millis() as u64,
+                sql = %request.summary(),
+                "Slow Turso query"
+            );
+        }
+
+        result
+    }
+
+    /// Retry 429s after the advised `Retry-After` (capped) and, for
+    /// idempotent requests only, 5xx with exponential backoff, up to
+    /// `max_attempts`. A 429 is refused before anything runs; a 5xx may
+    /// come after a write was applied, so writes fail at once rather than
+    /// risk applying twice. Other errors fail at once.
+    async fn post_with_retry(&self, transport: &T, request: &TursoRequest) -> Result<TursoResponse> {
+        let mut backoff = self.retry_backoff;
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+
+            let err = match post_pipeline(transport, request).await {
+                Ok(response) => return Ok(response),
+                Err(e) => e,
+            };
+            let Some(http) = err.downcast_ref::<TursoHttpError>() else {
+                return Err(err);
+            };
+            if !http.is_retryable() {
+                return Err(err);
+            }
+            if http.status >= 500 && !request.is_idempotent() {
+                return Err(err).context(WriteNotRetried);
+            }
+            if attempt >= self.max_attempts {
+                return Err(err).with_context(|| format!("Turso request failed after {} attempts", attempt));
+            }
+
+            let delay = match http.retry_after {
+                Some(advised) => advised.min(MAX_RETRY_AFTER),
+                None => {
+                    let delay = backoff;
+                    backoff *= 2;
+                    delay
+                }
+            };
+            warn!("Turso returned {} (attempt {}), retrying in {:?}", http.status, attempt, delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn create_schema(&self) -> Result<()> {
+        self.execute_sql(
+            "CREATE TABLE IF NOT EXISTS conversations (
+                id TEXT PRIMARY KEY,
+                title TEXT,
+                system_prompt TEXT,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                archived INTEGER NOT NULL DEFAULT 0
+            )",
+            Access::Write,
+        )
+        .await?;
+
+        self.ensure_column("conversations", "system_prompt", "TEXT")
+            .await?;
+        self.ensure_column("conversations", "archived", "INTEGER NOT NULL DEFAULT 0")
+            .await?;
+        self.ensure_column("conversations", "last_read_at", "TEXT")
+            .await?;
+        self.ensure_column("conversations", "ai_model", "TEXT")
+            .await?;
+        self.ensure_column("conversations", "ai_temperature", "REAL")
+            .await?;
+        self.ensure_column("conversations", "sender_label", "TEXT")
+            .await?;
+        self.ensure_column("conversations", "context", "TEXT")
+            .await?;
+        self.ensure_column("conversations", "ai_enabled", "INTEGER NOT NULL DEFAULT 1")
+            .await?;
+        self.ensure_column("conversations", "from_number", "TEXT")
+            .await?;
+        // SMS conversations created before the column: the number is in the default title
+        self.execute_sql(
+            "UPDATE conversations SET from_number = substr(title, 6)
+             WHERE from_number IS NULL AND title LIKE 'SMS: %'",
+            Access::Write,
+        )
+        .await?;
+        // Per-number conversation cap looks SMS conversations up by number
+        self.execute_sql("DROP INDEX IF EXISTS idx_conversations_title", Access::Write)
+            .await?;
+        self.execute_sql(
+            "CREATE INDEX IF NOT EXISTS idx_conversations_from_number ON conversations (from_number)",
+            Access::Write,
+        )
+        .await?;
+
+        self.execute_sql(
+            "CREATE TABLE IF NOT EXISTS messages (
+                id TEXT PRIMARY KEY,
+                conversation_id TEXT NOT NULL,
+                role TEXT NOT NULL,
+                content TEXT NOT NULL,
+                provider_sid TEXT,
+                metadata TEXT,
+                delivery_status TEXT,
+                created_at TEXT NOT NULL
+            )",
+            Access::Write,
+        )
+        .await?;
+
+        self.ensure_column("messages", "provider_sid", "TEXT")
+            .await?;
+        self.ensure_column("messages", "metadata", "TEXT")
+            .await?;
+        self.ensure_column("messages", "delivery_status", "TEXT")
+            .await?;
+        // SHA-256 of the normalized content; NULL for rows stored before it existed
+        self.ensure_column("messages", "content_hash", "TEXT")
+            .await?;
+        self.ensure_column("messages", "sender_label", "TEXT")
+            .await?;
+
+        // Range scans for the per-day stats
+        self.execute_sql(
+            "CREATE INDEX IF NOT EXISTS idx_messages_created_at ON messages (created_at)",
+            Access::Write,
+        )
+        .await?;
+        self.execute_sql(
+            "CREATE INDEX IF NOT EXISTS idx_messages_content_hash ON messages (content_hash)",
+            Access::Write,
+        )
+        .await?;
+
+        self.execute_sql(
+            "CREATE TABLE IF NOT EXISTS processed_messages (
+                message_id TEXT PRIMARY KEY
+            )",
+            Access::Write,
+        )
+        .await?;
+
+        self.execute_sql(
+            "CREATE TABLE IF NOT EXISTS opt_outs (
+                phone_number TEXT PRIMARY KEY,
+                created_at TEXT NOT NULL
+            )",
+            Access::Write,
+        )
+        .await?;
+
+        self.execute_sql(
+            "CREATE TABLE IF NOT EXISTS sent_sms (
+                idempotency_key TEXT PRIMARY KEY,
+                created_at TEXT NOT NULL
+            )",
+            Access::Write,
+        )
+        .await?;
+
+        self.execute_sql(
+            "CREATE TABLE IF NOT EXISTS raw_webhooks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                conversation_id TEXT,
+                payload TEXT NOT NULL,
+                received_at TEXT NOT NULL
+            )",
+            Access::Write,
+        )
+        .await?;
+
+        // Vectors are JSON arrays until a vector extension is available
+        self.execute_sql(
+            "CREATE TABLE IF NOT EXISTS message_embeddings (
+                message_id TEXT PRIMARY KEY,
+                model TEXT NOT NULL,
+                dimensions INTEGER NOT NULL,
+                embedding TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            Access::Write,
+        )
+        .await?;
+
+        self.execute_sql(
+            "CREATE TABLE IF NOT EXISTS usage_daily (
+                phone_number TEXT NOT NULL,
+                date TEXT NOT NULL,
+                ai_calls INTEGER NOT NULL DEFAULT 0,
+                sms_sent INTEGER NOT NULL DEFAULT 0,
+                notice_sent INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (phone_number, date)
+            )",
+            Access::Write,
+        )
+        .await?;
+
+        self.execute_sql(
+            "CREATE TABLE IF NOT EXISTS scheduled_messages (
+                id TEXT PRIMARY KEY,
+                recipient TEXT NOT NULL,
+                body TEXT NOT NULL,
+                send_at TEXT NOT NULL,
+                sent_at TEXT,
+                provider_sid TEXT,
+                created_at TEXT NOT NULL
+            )",
+            Access::Write,
+        )
+        .await?;
+        self.ensure_column("scheduled_messages", "attempts", "INTEGER NOT NULL DEFAULT 0")
+            .await?;
+        self.ensure_column("scheduled_messages", "next_attempt_at", "TEXT")
+            .await?;
+        // The scheduler polls pending rows by due time
+        self.execute_sql(
+            "CREATE INDEX IF NOT EXISTS idx_scheduled_messages_pending
+             ON scheduled_messages (send_at) WHERE sent_at IS NULL",
+            Access::Write,
+        )
+        .await?;
+
+        // Compliance record of outbound SMS; deliberately no body column
+        self.execute_sql(
+            "CREATE TABLE IF NOT EXISTS outbound_audit (
+                id TEXT PRIMARY KEY,
+                conversation_id TEXT,
+                recipient TEXT NOT NULL,
+                body_length INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                provider_sid TEXT,
+                error TEXT,
+                created_at TEXT NOT NULL
+            )",
+            Access::Write,
+        )
+        .await?;
+        self.execute_sql(
+            "CREATE INDEX IF NOT EXISTS idx_outbound_audit_created_at ON outbound_audit (created_at)",
+            Access::Write,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn verify_schema(&self) -> Result<()> {
+        let response = self
+            .execute_sql("SELECT name FROM sqlite_master WHERE type = 'table'", Access::Write)
+            .await?;
+
+        let tables: Vec<&str> = response
+            .rows()
+            .iter()
+            .filter_map(|row| row.first().and_then(TursoValue::as_str))
+            .collect();
+
+        let missing: Vec<&str> = SCHEMA_TABLES
+            .into_iter()
+            .filter(|t| !tables.contains(t))
+            .collect();
+
+        if !missing.is_empty() {
+            anyhow::bail!("Schema incomplete, missing tables: {}", missing.join(", "));
+        }
+
+        Ok(())
+    }
+
+    /// Add a column to an existing table (schema migration for older databases)
+    async fn ensure_column(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        let response = self
+            .execute_sql(&format!("PRAGMA table_info({})", table), Access::Write)
+            .await?;
+
+        let exists = response
+            .rows()
+            .iter()
+            .any(|row| row.get(1).and_then(TursoValue::as_str) == Some(column));
+
+        if !exists {
+            self.execute_sql(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                Access::Write,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: TursoTransport> ConversationStorage for ConversationStore<T> {
+    /// -----------------------------
+    /// Initialize schema
+    /// -----------------------------
+    /// Every step is idempotent, so a partial failure is retried from the top.
+    /// Succeeds only once all tables are confirmed to exist.
+    async fn initialize(&self) -> Result<()> {
+        self.with_retry_backoff("schema setup", || async {
+            self.create_schema().await?;
+            self.verify_schema().await
+        })
+        .await
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// =============================
+    /// IDEMPOTENCY (CRITICAL)
+    /// =============================
+    async fn is_message_processed(&self, message_id: &str) -> Result<bool> {
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "SELECT 1 FROM processed_messages WHERE message_id = ? LIMIT 1",
+                vec![message_id.into()],
+            ))
+            .await?;
+
+        Ok(results.first().is_some_and(|r| !r.rows.is_empty()))
+    }
+
+    async fn mark_message_processed(&self, message_id: &str) -> Result<()> {
+        self.execute_sql_pipeline(
+            PipelineBuilder::new()
+                .statement(
+                    "INSERT OR IGNORE INTO processed_messages (message_id) VALUES (?)",
+                    vec![message_id.into()],
+                )
+                .idempotent(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// -----------------------------
+    /// Create conversation
+    /// -----------------------------
+    async fn create_conversation(
+        &self,
+        title: Option<String>,
+        system_prompt: Option<String>,
+    ) -> Result<Conversation> {
+        let conversation = Conversation::new_with_clock(title, self.clock()).with_system_prompt(system_prompt);
+
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            "INSERT INTO conversations (id, title, system_prompt, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?)",
+            vec![
+                conversation.id.as_str().into(),
+                conversation.title.as_deref().into(),
+                conversation.system_prompt.as_deref().into(),
+                conversation.created_at.to_rfc3339().into(),
+                conversation.updated_at.to_rfc3339().into(),
+            ],
+        ))
+        .await?;
+        Ok(conversation)
+    }
+
+    /// Create the conversation row if it doesn't exist yet
+    async fn ensure_conversation(&self, conversation_id: &str, title: &str) -> Result<()> {
+        let now = self.clock.now().to_rfc3339();
+
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            "INSERT OR IGNORE INTO conversations (id, title, created_at, updated_at)
+             VALUES (?, ?, ?, ?)",
+            vec![
+                conversation_id.into(),
+                title.into(),
+                now.as_str().into(),
+                now.as_str().into(),
+            ],
+        ).idempotent())
+        .await?;
+        Ok(())
+    }
+
+    async fn ensure_sms_conversation(&self, conversation_id: &str, title: &str, from_number: &str) -> Result<()> {
+        let now = self.clock.now().to_rfc3339();
+
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            "INSERT OR IGNORE INTO conversations (id, title, from_number, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?)",
+            vec![
+                conversation_id.into(),
+                title.into(),
+                from_number.into(),
+                now.as_str().into(),
+                now.as_str().into(),
+            ],
+        ).idempotent())
+        .await?;
+        Ok(())
+    }
+
+    async fn update_conversation_title(&self, conversation_id: &str, title: &str) -> Result<()> {
+        self.update_conversation(
+            conversation_id,
+            "UPDATE conversations SET title = ? WHERE id = ?",
+            vec![title.into(), conversation_id.into()],
+        )
+        .await
+    }
+
+    async fn set_conversation_ai_settings(
+        &self,
+        conversation_id: &str,
+        model: Option<&str>,
+        temperature: Option<f32>,
+    ) -> Result<()> {
+        self.update_conversation(
+            conversation_id,
+            "UPDATE conversations SET ai_model = ?, ai_temperature = ? WHERE id = ?",
+            vec![model.into(), temperature.map(f64::from).into(), conversation_id.into()],
+        )
+        .await
+    }
+
+    async fn set_conversation_sender_label(&self, conversation_id: &str, label: Option<&str>) -> Result<()> {
+        self.update_conversation(
+            conversation_id,
+            "UPDATE conversations SET sender_label = ? WHERE id = ?",
+            vec![label.into(), conversation_id.into()],
+        )
+        .await
+    }
+
+    async fn set_conversation_ai_enabled(&self, conversation_id: &str, enabled: bool) -> Result<()> {
+        self.update_conversation(
+            conversation_id,
+            "UPDATE conversations SET ai_enabled = ? WHERE id = ?",
+            vec![(enabled as i64).into(), conversation_id.into()],
+        )
+        .await
+    }
+
+    async fn set_conversation_context(&self, conversation_id: &str, context: Option<&serde_json::Value>) -> Result<()> {
+        let context = context.map(serde_json::to_string).transpose()?;
+
+        self.update_conversation(
+            conversation_id,
+            "UPDATE conversations SET context = ? WHERE id = ?",
+            vec![context.as_deref().into(), conversation_id.into()],
+        )
+        .await
+    }
+
+    /// -----------------------------
+    /// Get conversation
+    /// -----------------------------
+    async fn get_conversation(&self, conversation_id: &str) -> Result<Option<Conversation>> {
+        let sql = format!(
+            "SELECT {}
+             FROM conversations
+             WHERE id = ?
+             LIMIT 1",
+            CONVERSATION_COLUMNS
+        );
+
+        // Point lookups follow writes (create then get, update then get),
+        // so they read the primary rather than a lagging replica
+        let results = self
+            .run_pipeline(PipelineBuilder::new().statement(sql, vec![conversation_id.into()]), Access::Write)
+            .await?;
+
+        results
+            .first()
+            .and_then(|r| r.rows.first())
+            .map(|row| decode_conversation(&typed_row(row)))
+            .transpose()
+    }
+
+    async fn list_conversations(
+        &self,
+        include_archived: bool,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<Conversation>> {
+        let sql = format!(
+            "SELECT {}
+             FROM conversations
+             WHERE ? OR archived = 0
+             ORDER BY updated_at DESC, id DESC
+             LIMIT ? OFFSET ?",
+            CONVERSATION_COLUMNS
+        );
+
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    sql,
+                    vec![
+                        (include_archived as i64).into(),
+                        (limit as i64).into(),
+                        (offset as i64).into(),
+                    ],
+                ),
+                Access::Read,
+            )
+            .await?;
+
+        results
+            .first()
+            .map(|r| r.rows.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|row| decode_conversation(&typed_row(row)))
+            .collect()
+    }
+
+    /// Fetches one row past `limit` to know whether another page exists
+    async fn list_conversations_after(
+        &self,
+        include_archived: bool,
+        limit: u32,
+        cursor: Option<&ConversationCursor>,
+    ) -> Result<(Vec<Conversation>, Option<ConversationCursor>)> {
+        let (after, mut args): (&str, Vec<SqlArg>) = match cursor {
+            Some(cursor) => (
+                "AND (updated_at, id) < (?, ?)",
+                vec![cursor.updated_at.to_rfc3339().into(), cursor.id.as_str().into()],
+            ),
+            None => ("", Vec::new()),
+        };
+        args.insert(0, (include_archived as i64).into());
+        args.push((limit as i64 + 1).into());
+
+        let sql = format!(
+            "SELECT {}
+             FROM conversations
+             WHERE (? OR archived = 0) {}
+             ORDER BY updated_at DESC, id DESC
+             LIMIT ?",
+            CONVERSATION_COLUMNS, after
+        );
+
+        let results = self
+            .run_pipeline(PipelineBuilder::new().statement(sql, args), Access::Read)
+            .await?;
+
+        let mut conversations = results
+            .first()
+            .map(|r| r.rows.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|row| decode_conversation(&typed_row(row)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let has_more = conversations.len() > limit as usize;
+        conversations.truncate(limit as usize);
+        let next = has_more
+            .then(|| conversations.last().map(ConversationCursor::after))
+            .flatten();
+
+        Ok((conversations, next))
+    }
+
+    async fn count_conversations(&self, include_archived: bool) -> Result<i64> {
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    "SELECT COUNT(*) FROM conversations WHERE ? OR archived = 0",
+                    vec![(include_archived as i64).into()],
+                ),
+                Access::Read,
+            )
+            .await?;
+
+        let count = results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .context("COUNT(*) returned no rows")?;
+
+        Ok(count.parse()?)
+    }
+
+    async fn active_conversations_from(&self, from_number: &str) -> Result<Vec<String>> {
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    "SELECT id FROM conversations
+                     WHERE from_number = ? AND archived = 0
+                     ORDER BY updated_at DESC, id DESC",
+                    vec![from_number.into()],
+                ),
+                Access::Write,
+            )
+            .await?;
+
+        Ok(results
+            .first()
+            .map(|r| r.rows.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|row| row.first().and_then(|v| v.as_str()).map(str::to_string))
+            .collect())
+    }
+
+    async fn taken_over_conversation_from(&self, from_number: &str) -> Result<Option<String>> {
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    "SELECT id FROM conversations
+                     WHERE from_number = ? AND archived = 0 AND ai_enabled = 0
+                     ORDER BY updated_at DESC, id DESC
+                     LIMIT 1",
+                    vec![from_number.into()],
+                ),
+                Access::Write,
+            )
+            .await?;
+
+        Ok(results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .map(str::to_string))
+    }
+
+    async fn resolve_inbound_conversation(
+        &self,
+        from_number: &str,
+        in_reply_to: Option<&str>,
+        max_per_number: Option<usize>,
+    ) -> Result<Option<String>> {
+        // The three lookups of the in-memory store as one statement; a
+        // NULL cap never compares true
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    "SELECT COALESCE(
+                        (SELECT conversation_id FROM messages WHERE provider_sid = ? LIMIT 1),
+                        (SELECT id FROM conversations
+                         WHERE from_number = ? AND archived = 0 AND ai_enabled = 0
+                         ORDER BY updated_at DESC, id DESC
+                         LIMIT 1),
+                        (SELECT id FROM conversations
+                         WHERE from_number = ? AND archived = 0
+                           AND (SELECT COUNT(*) FROM conversations
+                                WHERE from_number = ? AND archived = 0) >= ?
+                         ORDER BY updated_at DESC, id DESC
+                         LIMIT 1)
+                    )",
+                    vec![
+                        in_reply_to.into(),
+                        from_number.into(),
+                        from_number.into(),
+                        from_number.into(),
+                        max_per_number.map(|max| max as i64).into(),
+                    ],
+                ),
+                Access::Write,
+            )
+            .await?;
+
+        Ok(results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .map(str::to_string))
+    }
+
+    async fn set_conversation_archived(&self, conversation_id: &str, archived: bool) -> Result<()> {
+        self.update_conversation(
+            conversation_id,
+            "UPDATE conversations SET archived = ? WHERE id = ?",
+            vec![(archived as i64).into(), conversation_id.into()],
+        )
+        .await
+    }
+
+    /// -----------------------------
+    /// Read tracking
+    /// -----------------------------
+    async fn mark_read(&self, conversation_id: &str, at: DateTime<Utc>) -> Result<()> {
+        self.update_conversation(
+            conversation_id,
+            "UPDATE conversations SET last_read_at = ? WHERE id = ?",
+            vec![at.to_rfc3339().into(), conversation_id.into()],
+        )
+        .await
+    }
+
+    /// Counted on the primary so it reflects a `mark_read` made just before
+    async fn unread_count(&self, conversation_id: &str) -> Result<i64> {
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "SELECT COUNT(*) FROM messages m
+                 JOIN conversations c ON c.id = m.conversation_id
+                 WHERE m.conversation_id = ?
+                   AND (c.last_read_at IS NULL OR m.created_at > c.last_read_at)",
+                vec![conversation_id.into()],
+            ))
+            .await?;
+
+        let count = results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .context("COUNT(*) returned no rows")?;
+
+        Ok(count.parse()?)
+    }
+
+    /// -----------------------------
+    /// Store message
+    /// -----------------------------
+    async fn insert_message(&self, message: Message) -> Result<Message> {
+        let message = self.content_limit.apply(message)?;
+
+        // Insert and touch in one round-trip, every value bound
+        self.execute_sql_pipeline(batch_insert_pipeline(std::slice::from_ref(&message))?)
+            .await?;
+
+        Ok(message)
+    }
+
+    /// One round-trip for the whole batch, stored all-or-nothing
+    async fn store_messages_batch(&self, messages: Vec<Message>) -> Result<Vec<Message>> {
+        if messages.is_empty() {
+            return Ok(messages);
+        }
+
+        let messages = messages
+            .into_iter()
+            .map(|m| self.content_limit.apply(m))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.transaction(batch_insert_pipeline(&messages)?).await?;
+
+        Ok(messages)
+    }
+
+    /// Insert, bump `updated_at` and read the conversation back in a
+    /// single pipeline round-trip
+    async fn store_message_returning_conversation(&self, message: Message) -> Result<(Message, Conversation)> {
+        let message = self.content_limit.apply(message)?;
+
+        // No orphan messages; the primary, as the conversation may be brand new
+        let exists = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    "SELECT 1 FROM conversations WHERE id = ? LIMIT 1",
+                    vec![message.conversation_id.as_str().into()],
+                ),
+                Access::Write,
+            )
+            .await?;
+        if exists.first().is_none_or(|r| r.rows.is_empty()) {
+            anyhow::bail!(NotFound::conversation(&message.conversation_id));
+        }
+
+        // Same columns as `insert_message`, metadata and sender label included
+        let pipeline = batch_insert_pipeline(std::slice::from_ref(&message))?.statement(
+            format!("SELECT {} FROM conversations WHERE id = ? LIMIT 1", CONVERSATION_COLUMNS),
+            vec![message.conversation_id.as_str().into()],
+        );
+
+        let results = self.execute_sql_pipeline(pipeline).await?;
+
+        let row: Vec<TursoValue> = results
+            .last()
+            .and_then(|r| r.rows.first())
+            .ok_or_else(|| NotFound::conversation(&message.conversation_id))?
+            .iter()
+            .map(|value| TursoValue { value: value.clone() })
+            .collect();
+
+        Ok((message, decode_conversation(&row)?))
+    }
+
+    /// -----------------------------
+    /// Delivery tracking
+    /// -----------------------------
+    /// Attach the carrier SID to a sent reply and mark it `sent`
+    async fn record_outbound_sent(&self, message_id: &str, provider_sid: &str) -> Result<()> {
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            "UPDATE messages SET provider_sid = ?, delivery_status = 'sent' WHERE id = ?",
+            vec![provider_sid.into(), message_id.into()],
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    /// Apply a carrier status callback; returns false for an unknown SID
+    async fn update_delivery_status(&self, provider_sid: &str, status: &str) -> Result<bool> {
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "UPDATE messages SET delivery_status = ?
+                 WHERE provider_sid = ? AND role = 'assistant'",
+                vec![status.into(), provider_sid.into()],
+            ))
+            .await?;
+
+        Ok(results.first().is_some_and(|r| r.affected_row_count > 0))
+    }
+
+    /// Delivery status of the latest tracked reply in a conversation
+    async fn last_reply_delivery_status(&self, conversation_id: &str) -> Result<Option<String>> {
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "SELECT delivery_status FROM messages
+                 WHERE conversation_id = ? AND role = 'assistant'
+                   AND delivery_status IS NOT NULL
+                 ORDER BY created_at DESC
+                 LIMIT 1",
+                vec![conversation_id.into()],
+            ))
+            .await?;
+
+        Ok(results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .map(str::to_string))
+    }
+
+    /// -----------------------------
+    /// Retention
+    /// -----------------------------
+    /// Messages go first so no message is left without its conversation
+    async fn purge_conversations_older_than(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        let cutoff = cutoff.to_rfc3339();
+
+        let results = self
+            .transaction(
+                PipelineBuilder::new()
+                    .statement(
+                        "DELETE FROM message_embeddings WHERE message_id IN
+                         (SELECT m.id FROM messages m
+                          JOIN conversations c ON c.id = m.conversation_id
+                          WHERE c.updated_at < ?)",
+                        vec![cutoff.as_str().into()],
+                    )
+                    .statement(
+                        "DELETE FROM messages WHERE conversation_id IN
+                         (SELECT id FROM conversations WHERE updated_at < ?)",
+                        vec![cutoff.as_str().into()],
+                    )
+                    .statement(
+                        "DELETE FROM conversations WHERE updated_at < ?",
+                        vec![cutoff.as_str().into()],
+                    ),
+            )
+            .await?;
+
+        Ok(results.last().map(|r| r.affected_row_count).unwrap_or(0))
+    }
+
+    async fn merge_conversations(&self, from_id: &str, into_id: &str) -> Result<()> {
+        if from_id == into_id {
+            anyhow::bail!("Cannot merge conversation {from_id} into itself");
+        }
+
+        // Both must exist, or the moved messages would be orphaned
+        let found = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "SELECT COUNT(*) FROM conversations WHERE id IN (?, ?)",
+                vec![from_id.into(), into_id.into()],
+            ))
+            .await?;
+        let count = found
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .unwrap_or("0");
+        if count != "2" {
+            anyhow::bail!("Cannot merge {from_id} into {into_id}: conversation not found");
+        }
+
+        self.transaction(
+            PipelineBuilder::new()
+                .statement(
+                    "UPDATE messages SET conversation_id = ? WHERE conversation_id = ?",
+                    vec![into_id.into(), from_id.into()],
+                )
+                // `sent_sms_key` is `{conversation_id}:{message_id}`
+                .statement(
+                    "UPDATE sent_sms SET idempotency_key = ? || substr(idempotency_key, length(?) + 1)
+                     WHERE substr(idempotency_key, 1, length(?)) = ?",
+                    vec![
+                        format!("{into_id}:").into(),
+                        format!("{from_id}:").into(),
+                        format!("{from_id}:").into(),
+                        format!("{from_id}:").into(),
+                    ],
+                )
+                .statement(
+                    "UPDATE conversations
+                     SET updated_at = MAX(updated_at, (SELECT updated_at FROM conversations WHERE id = ?))
+                     WHERE id = ?",
+                    vec![from_id.into(), into_id.into()],
+                )
+                .statement("DELETE FROM conversations WHERE id = ?", vec![from_id.into()]),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// -----------------------------
+    /// Opt-outs
+    /// -----------------------------
+    async fn set_opted_out(&self, phone_number: &str) -> Result<()> {
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            "INSERT OR IGNORE INTO opt_outs (phone_number, created_at) VALUES (?, ?)",
+            vec![phone_number.into(), self.clock.now().to_rfc3339().into()],
+        ).idempotent())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn is_opted_out(&self, phone_number: &str) -> Result<bool> {
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "SELECT 1 FROM opt_outs WHERE phone_number = ? LIMIT 1",
+                vec![phone_number.into()],
+            ))
+            .await?;
+
+        Ok(results.first().is_some_and(|r| !r.rows.is_empty()))
+    }
+
+    /// -----------------------------
+    /// Daily usage caps
+    /// -----------------------------
+    async fn record_usage(&self, phone_number: &str, date: NaiveDate, kind: UsageKind) -> Result<()> {
+        let (ai_calls, sms_sent) = match kind {
+            UsageKind::AiCompletion => (1, 0),
+            UsageKind::OutboundSms => (0, 1),
+        };
+
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            "INSERT INTO usage_daily (phone_number, date, ai_calls, sms_sent) VALUES (?, ?, ?, ?)
+             ON CONFLICT (phone_number, date) DO UPDATE SET
+                 ai_calls = ai_calls + excluded.ai_calls,
+                 sms_sent = sms_sent + excluded.sms_sent",
+            vec![
+                phone_number.into(),
+                date.to_string().into(),
+                (ai_calls as i64).into(),
+                (sms_sent as i64).into(),
+            ],
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn claim_usage(&self, phone_number: &str, date: NaiveDate, kind: UsageKind, max: Option<u32>) -> Result<bool> {
+        let Some(max) = max else {
+            self.record_usage(phone_number, date, kind).await?;
+            return Ok(true);
+        };
+        if max == 0 {
+            return Ok(false);
+        }
+
+        let (column, ai_calls, sms_sent) = match kind {
+            UsageKind::AiCompletion => ("ai_calls", 1, 0),
+            UsageKind::OutboundSms => ("sms_sent", 0, 1),
+        };
+
+        // A row that doesn't exist yet is under any cap of 1 or more
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                format!(
+                    "INSERT INTO usage_daily (phone_number, date, ai_calls, sms_sent) VALUES (?, ?, ?, ?)
+                     ON CONFLICT (phone_number, date) DO UPDATE SET
+                         ai_calls = ai_calls + excluded.ai_calls,
+                         sms_sent = sms_sent + excluded.sms_sent
+                     WHERE {column} < ?"
+                ),
+                vec![
+                    phone_number.into(),
+                    date.to_string().into(),
+                    (ai_calls as i64).into(),
+                    (sms_sent as i64).into(),
+                    (max as i64).into(),
+                ],
+            ))
+            .await?;
+
+        Ok(results.first().is_some_and(|r| r.affected_row_count > 0))
+    }
+
+    async fn daily_usage(&self, phone_number: &str, date: NaiveDate) -> Result<DailyUsage> {
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "SELECT ai_calls, sms_sent, notice_sent FROM usage_daily
+                 WHERE phone_number = ? AND date = ?",
+                vec![phone_number.into(), date.to_string().into()],
+            ))
+            .await?;
+
+        let Some(row) = results.first().and_then(|r| r.rows.first()) else {
+            return Ok(DailyUsage::default());
+        };
+        let int = |i: usize| -> Result<u32> {
+            row.get(i)
+                .and_then(|v| v.as_str())
+                .context("usage_daily row is missing a column")?
+                .parse()
+                .context("usage_daily column is not an integer")
+        };
+
+        Ok(DailyUsage {
+            ai_calls: int(0)?,
+            sms_sent: int(1)?,
+            notice_sent: int(2)? != 0,
+        })
+    }
+
+    async fn mark_cap_notice_sent(&self, phone_number: &str, date: NaiveDate) -> Result<bool> {
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "INSERT INTO usage_daily (phone_number, date, notice_sent) VALUES (?, ?, 1)
+                 ON CONFLICT (phone_number, date) DO UPDATE SET notice_sent = 1
+                 WHERE notice_sent = 0",
+                vec![phone_number.into(), date.to_string().into()],
+            ))
+            .await?;
+
+        Ok(results.first().is_some_and(|r| r.affected_row_count > 0))
+    }
+
+    /// -----------------------------
+    /// Outbound idempotency
+    /// -----------------------------
+    async fn schedule_sms(&self, to: &PhoneNumber, body: &str, send_at: DateTime<Utc>) -> Result<ScheduledMessage> {
+        let scheduled = ScheduledMessage::new_with_clock(to.to_string(), body.to_string(), send_at, self.clock());
+
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            "INSERT INTO scheduled_messages (id, recipient, body, send_at, created_at)
+             VALUES (?, ?, ?, ?, ?)",
+            vec![
+                scheduled.id.as_str().into(),
+                scheduled.to.as_str().into(),
+                scheduled.body.as_str().into(),
+                scheduled.send_at.to_rfc3339().into(),
+                scheduled.created_at.to_rfc3339().into(),
+            ],
+        ))
+        .await?;
+
+        Ok(scheduled)
+    }
+
+    async fn due_scheduled_messages(&self, now: DateTime<Utc>, limit: u32) -> Result<Vec<ScheduledMessage>> {
+        let sql = format!(
+            "SELECT {}
+             FROM scheduled_messages
+             WHERE sent_at IS NULL AND send_at <= ?
+               AND (next_attempt_at IS NULL OR next_attempt_at <= ?)
+             ORDER BY send_at ASC, id ASC
+             LIMIT ?",
+            SCHEDULED_MESSAGE_COLUMNS
+        );
+        let now = now.to_rfc3339();
+
+        // Primary: a stale replica would hand out rows already sent
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    sql,
+                    vec![now.as_str().into(), now.as_str().into(), (limit as i64).into()],
+                ),
+                Access::Write,
+            )
+            .await?;
+
+        results
+            .first()
+            .map(|r| r.rows.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|row| decode_scheduled_message(&typed_row(row)))
+            .collect()
+    }
+
+    async fn mark_scheduled_sent(&self, id: &str, provider_sid: Option<&str>) -> Result<()> {
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            "UPDATE scheduled_messages SET sent_at = ?, provider_sid = ? WHERE id = ?",
+            vec![self.clock.now().to_rfc3339().into(), provider_sid.into(), id.into()],
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn defer_scheduled(&self, id: &str, attempts: u32, until: DateTime<Utc>) -> Result<()> {
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            "UPDATE scheduled_messages SET attempts = ?, next_attempt_at = ? WHERE id = ?",
+            vec![(attempts as i64).into(), until.to_rfc3339().into(), id.into()],
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    /// -----------------------------
+    /// Outbound audit
+    /// -----------------------------
+    async fn record_outbound(&self, audit: &OutboundAudit) -> Result<()> {
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            format!("INSERT INTO outbound_audit ({}) VALUES (?, ?, ?, ?, ?, ?, ?, ?)", OUTBOUND_AUDIT_COLUMNS),
+            vec![
+                audit.id.as_str().into(),
+                audit.conversation_id.as_deref().into(),
+                audit.to.as_str().into(),
+                audit.body_length.into(),
+                audit.status.as_str().into(),
+                audit.provider_sid.as_deref().into(),
+                audit.error.as_deref().into(),
+                audit.created_at.to_rfc3339().into(),
+            ],
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn outbound_audit_since(
+        &self,
+        since: DateTime<Utc>,
+        cursor: Option<&AuditCursor>,
+        limit: u32,
+    ) -> Result<Vec<OutboundAudit>> {
+        let (after, mut args): (&str, Vec<SqlArg>) = match cursor {
+            Some(cursor) => (
+                "AND (created_at, id) > (?, ?)",
+                vec![cursor.created_at.to_rfc3339().into(), cursor.id.as_str().into()],
+            ),
+            None => ("", Vec::new()),
+        };
+        args.insert(0, since.to_rfc3339().into());
+        args.push((limit as i64).into());
+
+        let sql = format!(
+            "SELECT {}
+             FROM outbound_audit
+             WHERE created_at >= ? {}
+             ORDER BY created_at ASC, id ASC
+             LIMIT ?",
+            OUTBOUND_AUDIT_COLUMNS, after
+        );
+
+        let results = self
+            .run_pipeline(PipelineBuilder::new().statement(sql, args), Access::Read)
+            .await?;
+
+        results
+            .first()
+            .map(|r| r.rows.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|row| decode_outbound_audit(&typed_row(row)))
+            .collect()
+    }
+
+    async fn count_outbound_audit_since(&self, since: DateTime<Utc>) -> Result<i64> {
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    "SELECT COUNT(*) FROM outbound_audit WHERE created_at >= ?",
+                    vec![since.to_rfc3339().into()],
+                ),
+                Access::Read,
+            )
+            .await?;
+
+        let count = results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .context("COUNT(*) returned no rows")?;
+
+        Ok(count.parse()?)
+    }
+
+    async fn mark_sms_sent(&self, conversation_id: &str, message_id: &str) -> Result<()> {
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            "INSERT OR IGNORE INTO sent_sms (idempotency_key, created_at) VALUES (?, ?)",
+            vec![
+                sent_sms_key(conversation_id, message_id).into(),
+                self.clock.now().to_rfc3339().into(),
+            ],
+        ).idempotent())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn was_sms_sent(&self, conversation_id: &str, message_id: &str) -> Result<bool> {
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "SELECT 1 FROM sent_sms WHERE idempotency_key = ? LIMIT 1",
+                vec![sent_sms_key(conversation_id, message_id).into()],
+            ))
+            .await?;
+
+        Ok(results.first().is_some_and(|r| !r.rows.is_empty()))
+    }
+
+    /// -----------------------------
+    /// Embeddings
+    /// -----------------------------
+    async fn store_embedding(&self, message_id: &str, model: &str, embedding: &[f32]) -> Result<()> {
+        let json = serde_json::to_string(embedding).context("Failed to encode embedding")?;
+
+        self.execute_sql_pipeline(PipelineBuilder::new().statement(
+            "INSERT OR REPLACE INTO message_embeddings
+             (message_id, model, dimensions, embedding, created_at) VALUES (?, ?, ?, ?, ?)",
+            vec![
+                message_id.into(),
+                model.into(),
+                (embedding.len() as i64).into(),
+                json.into(),
+                self.clock.now().to_rfc3339().into(),
+            ],
+        ).idempotent())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_embedding(&self, message_id: &str) -> Result<Option<Vec<f32>>> {
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    "SELECT embedding FROM message_embeddings WHERE message_id = ?",
+                    vec![message_id.into()],
+                ),
+                Access::Read,
+            )
+            .await?;
+
+        results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .map(|json| serde_json::from_str(json).context("Corrupt embedding"))
+            .transpose()
+    }
+
+    /// -----------------------------
+    /// Raw webhook audit
+    /// -----------------------------
+    async fn store_raw_webhook(&self, payload: &str) -> Result<i64> {
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "INSERT INTO raw_webhooks (payload, received_at) VALUES (?, ?) RETURNING id",
+                vec![payload.into(), self.clock.now().to_rfc3339().into()],
+            ))
+            .await?;
+
+        let id = results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .context("INSERT returned no id")?;
+
+        Ok(id.parse()?)
+    }
+
+    async fn set_raw_webhook_conversation(&self, id: i64, conversation_id: &str) -> Result<()> {
+        let results = self
+            .execute_sql_pipeline(
+                PipelineBuilder::new()
+                    .statement(
+                        "UPDATE raw_webhooks SET conversation_id = ? WHERE id = ?",
+                        vec![conversation_id.into(), id.into()],
+                    )
+                    .idempotent(),
+            )
+            .await?;
+
+        if results.first().map_or(0, |r| r.affected_row_count) == 0 {
+            anyhow::bail!(NotFound::raw_webhook(id));
+        }
+        Ok(())
+    }
+
+    async fn purge_raw_webhooks_older_than(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "DELETE FROM raw_webhooks WHERE received_at < ?",
+                vec![cutoff.to_rfc3339().into()],
+            ))
+            .await?;
+
+        Ok(results.first().map(|r| r.affected_row_count).unwrap_or(0))
+    }
+
+    /// -----------------------------
+    /// Get message
+    /// -----------------------------
+    async fn get_message(&self, message_id: &str) -> Result<Option<Message>> {
+        let sql = format!(
+            "SELECT {}
+             FROM messages
+             WHERE id = ?
+             LIMIT 1",
+            MESSAGE_COLUMNS
+        );
+
+        let results = self
+            .run_pipeline(PipelineBuilder::new().statement(sql, vec![message_id.into()]), Access::Write)
+            .await?;
+
+        results
+            .first()
+            .and_then(|r| r.rows.first())
+            .map(|row| decode_message(&typed_row(row)))
+            .transpose()
+    }
+
+    async fn find_conversation_by_provider_sid(&self, provider_sid: &str) -> Result<Option<String>> {
+        let results = self
+            .execute_sql_pipeline(PipelineBuilder::new().statement(
+                "SELECT conversation_id FROM messages WHERE provider_sid = ? LIMIT 1",
+                vec![provider_sid.into()],
+            ))
+            .await?;
+
+        Ok(results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .map(str::to_string))
+    }
+
+    /// -----------------------------
+    /// Get conversation history
+    /// -----------------------------
+    async fn get_conversation_messages(
+        &self,
+        conversation_id: &str,
+    ) -> Result<Vec<Message>> {
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    format!(
+                        "SELECT {}
+                         FROM messages
+                         WHERE conversation_id = ?
+                         ORDER BY created_at ASC",
+                        MESSAGE_COLUMNS
+                    ),
+                    vec![conversation_id.into()],
+                ),
+                Access::Read,
+            )
+            .await?;
+
+        results
+            .first()
+            .map(|r| r.rows.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|row| decode_message(&typed_row(row)))
+            .collect()
+    }
+
+    async fn get_recent_messages(
+        &self,
+        conversation_id: &str,
+        limit: u32,
+        max_chars: Option<usize>,
+    ) -> Result<Vec<Message>> {
+        // Cut in SQL so the full content never leaves the database
+        let columns = match max_chars {
+            Some(max_chars) => MESSAGE_COLUMNS.replacen(
+                ", content,",
+                &format!(", substr(content, 1, {max_chars}) AS content,"),
+                1,
+            ),
+            None => MESSAGE_COLUMNS.to_string(),
+        };
+
+        let sql = format!(
+            "SELECT * FROM (
+                 SELECT {}
+                 FROM messages
+                 WHERE conversation_id = ?
+                 ORDER BY created_at DESC, id DESC
+                 LIMIT ?
+             )
+             ORDER BY created_at ASC, id ASC",
+            columns
+        );
+
+        let results = self
+            .run_pipeline(
+                // AI history, read right after the message being answered is stored
+                PipelineBuilder::new().statement(sql, vec![conversation_id.into(), (limit as i64).into()]),
+                Access::Write,
+            )
+            .await?;
+
+        results
+            .first()
+            .map(|r| r.rows.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|row| decode_message(&typed_row(row)))
+            .collect()
+    }
+
+    async fn get_conversation_messages_after(
+        &self,
+        conversation_id: &str,
+        cursor: Option<&MessageCursor>,
+        limit: u32,
+    ) -> Result<Vec<Message>> {
+        let (after, mut args): (&str, Vec<SqlArg>) = match cursor {
+            Some(cursor) => (
+                "AND (created_at, id) > (?, ?)",
+                vec![cursor.created_at.to_rfc3339().into(), cursor.id.as_str().into()],
+            ),
+            None => ("", Vec::new()),
+        };
+        args.insert(0, conversation_id.into());
+        args.push((limit as i64).into());
+
+        let sql = format!(
+            "SELECT {}
+             FROM messages
+             WHERE conversation_id = ? {}
+             ORDER BY created_at ASC, id ASC
+             LIMIT ?",
+            MESSAGE_COLUMNS, after
+        );
+
+        let results = self
+            .run_pipeline(PipelineBuilder::new().statement(sql, args), Access::Read)
+            .await?;
+
+        results
+            .first()
+            .map(|r| r.rows.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|row| decode_message(&typed_row(row)))
+            .collect()
+    }
+
+    async fn count_conversation_messages(&self, conversation_id: &str) -> Result<i64> {
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    "SELECT COUNT(*) FROM messages WHERE conversation_id = ?",
+                    vec![conversation_id.into()],
+                ),
+                Access::Read,
+            )
+            .await?;
+
+        let count = results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .context("COUNT(*) returned no rows")?;
+
+        Ok(count.parse()?)
+    }
+
+    async fn has_assistant_reply(&self, conversation_id: &str) -> Result<bool> {
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(
+                    "SELECT EXISTS (SELECT 1 FROM messages WHERE conversation_id = ? AND role = 'assistant')",
+                    vec![conversation_id.into()],
+                ),
+                Access::Write,
+            )
+            .await?;
+
+        let exists = results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .context("EXISTS returned no rows")?;
+
+        Ok(exists == "1")
+    }
+
+    async fn get_conversation_messages_filtered(
+        &self,
+        conversation_id: &str,
+        role: Option<&MessageRole>,
+        since: Option<DateTime<Utc>>,
+        cursor: Option<&MessageCursor>,
+        limit: u32,
+    ) -> Result<Vec<Message>> {
+        let (filter, mut args) = message_filter(conversation_id, role, since);
+        let mut sql = format!("SELECT {} FROM messages WHERE {}", MESSAGE_COLUMNS, filter);
+
+        if let Some(cursor) = cursor {
+            sql.push_str(" AND (created_at, id) > (?, ?)");
+            args.push(cursor.created_at.to_rfc3339().into());
+            args.push(cursor.id.as_str().into());
+        }
+        sql.push_str(" ORDER BY created_at ASC, id ASC LIMIT ?");
+        args.push((limit as i64).into());
+
+        let results = self
+            .run_pipeline(PipelineBuilder::new().statement(sql, args), Access::Read)
+            .await?;
+
+        results
+            .first()
+            .map(|r| r.rows.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|row| decode_message(&typed_row(row)))
+            .collect()
+    }
+
+    async fn count_conversation_messages_filtered(
+        &self,
+        conversation_id: &str,
+        role: Option<&MessageRole>,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<i64> {
+        let (filter, args) = message_filter(conversation_id, role, since);
+        let results = self
+            .run_pipeline(
+                PipelineBuilder::new().statement(format!("SELECT COUNT(*) FROM messages WHERE {}", filter), args),
+                Access::Read,
+            )
+            .await?;
+
+        let count = results
+            .first()
+            .and_then(|r| r.rows.first())
+            .and_then(|row| row.first())
+            .and_then(|v| v.as_str())
+            .context("COUNT(*) returned no rows")?;
+
+        Ok(count.parse()?)
+    }
+}
+
+/// `WHERE` clause (and its args) shared by the filtered message page and
+/// its count
+fn message_filter(
+    conversation_id: &str,
+    role: Option<&MessageRole>,
+    since: Option<DateTime<Utc>>,
+) -> (String, Vec<SqlArg>) {
+    let mut filter = String::from("conversation_id = ?");
+    let mut args: Vec<SqlArg> = vec![conversation_id.into()];
+
+    if let Some(role) = role {
+        filter.push_str(" AND role = ?");
+        args.push(role.as_str().into());
+    }
+    if let Some(since) = since {
+        // Timestamps are stored as UTC RFC 3339, so text order is time order
+        filter.push_str(" AND created_at >= ?");
+        args.push(since.to_rfc3339().into());
+    }
+    (filter, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        capture_logs, fake_store, fake_turso_delayed, fake_turso_failing,
+        fake_turso_rejecting,
+    };
+    use axum::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_unknown_role_does_not_fail_fetch() {
+        let (_turso, store) = fake_store().await;
+
+        store
+            .store_message("conv".into(), MessageRole::User, "hi".into())
+            .await
+            .unwrap();
+        store
+            .execute_sql(
+                "INSERT INTO messages (id, conversation_id, role, content, created_at)
+                 VALUES ('sys-1', 'conv', 'system', 'be nice', '2099-01-01T00:00:00+00:00')",
+                Access::Write,
+            )
+            .await
+            .unwrap();
+
+        let messages = store.get_conversation_messages("conv").await.unwrap();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, MessageRole::User);
+        assert_eq!(messages[1].role, MessageRole::Unknown("system".into()));
+        assert_eq!(messages[1].role.as_str(), "system");
+    }
+
+    #[test]
+    fn test_pipeline_request_shape() {
+        let pipeline = PipelineBuilder::new()
+            .statement(
+                "INSERT INTO processed_messages (message_id) VALUES (?)",
+                vec!["m1".into()],
+            )
+            .statement(
+                "SELECT ?, ?, ?",
+                vec![SqlArg::from(42), SqlArg::from(None::<String>), 1.5.into()],
+            );
+
+        let body = serde_json::to_value(pipeline.into_request()).unwrap();
+
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "requests": [
+                    {
+                        "type": "execute",
+                        "stmt": {
+                            "sql": "INSERT INTO processed_messages (message_id) VALUES (?)",
+                            "args": [{ "type": "text", "value": "m1" }]
+                        }
+                    },
+                    {
+                        "type": "execute",
+                        "stmt": {
+                            "sql": "SELECT ?, ?, ?",
+                            "args": [
+                                { "type": "integer", "value": "42" },
+                                { "type": "null" },
+                                { "type": "float", "value": 1.5 }
+                            ]
+                        }
+                    }
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_runs_statements_in_one_request() {
+        let (_turso, store) = fake_store().await;
+
+        let results = store
+            .execute_sql_pipeline(
+                PipelineBuilder::new()
+                    .statement(
+                        "INSERT INTO processed_messages (message_id) VALUES (?)",
+                        vec!["it's".into()],
+                    )
+                    .statement("SELECT message_id FROM processed_messages", vec![]),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].affected_row_count, 1);
+        assert_eq!(results[1].columns, vec!["message_id"]);
+        assert_eq!(results[1].rows, vec![vec![serde_json::json!("it's")]]);
+
+        let err = store
+            .execute_sql_pipeline(PipelineBuilder::new().statement("SELECT * FROM nope", vec![]))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("statement 0"));
+    }
+
+    #[tokio::test]
+    async fn test_initialize_retries_after_transient_failure() {
+        // Third schema request fails once and is retried
+        let turso = fake_turso_failing(vec![2]).await;
+        let store = turso.store().with_retry(3, Duration::from_millis(1));
+
+        store.initialize().await.unwrap();
+        store.verify_schema().await.unwrap();
+
+        store
+            .store_message("conv".into(), MessageRole::User, "hi".into())
+            .await
+            .unwrap();
+        store.mark_message_processed("m1").await.unwrap();
+        assert!(store.is_message_processed("m1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_initialize_retries_a_schema_write_that_got_a_server_error() {
+        // Writes aren't retried by `send`...
+        let turso = crate::test_support::fake_turso_failing_sql("ALTER TABLE").await;
+        let err = turso.store().with_retry(1, Duration::from_millis(1)).initialize().await.unwrap_err();
+        assert!(format!("{err:#}").contains("write not retried"), "{err:#}");
+
+        // ...but schema setup reruns from the top
+        let turso = crate::test_support::fake_turso_failing_sql("ALTER TABLE").await;
+        let store = turso.store().with_retry(3, Duration::from_millis(1));
+
+        store.initialize().await.unwrap();
+        store.verify_schema().await.unwrap();
+        // The columns added by ALTER are there
+        let conversation = store.create_conversation(None, None).await.unwrap();
+        store.set_conversation_ai_settings(&conversation.id, Some("m"), None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_initialize_gives_up_after_max_attempts() {
+        let turso = fake_turso_failing(vec![0, 1]).await;
+        let store = turso.store().with_retry(2, Duration::from_millis(1));
+
+        let err = store.initialize().await.unwrap_err();
+        assert!(err.to_string().contains("failed after 2 attempts"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_waits_for_retry_after() {
+        let turso = fake_turso_rejecting(vec![0], StatusCode::TOO_MANY_REQUESTS, Some(1)).await;
+        let store = turso.store().with_retry(2, Duration::from_millis(1));
+
+        let started = Instant::now();
+        store.initialize().await.unwrap();
+
+        // Waited for the advised second, not the 1ms backoff
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_retried_only_for_idempotent_requests() {
+        let turso = fake_turso_failing(vec![0, 2, 4]).await;
+        let store = turso.store().with_retry(3, Duration::from_millis(1));
+
+        let create = || PipelineBuilder::new().statement("CREATE TABLE t (x TEXT)", vec![]);
+        let err = store.execute_sql_pipeline(create()).await.unwrap_err();
+        assert!(err.to_string().contains("not retried"));
+        assert_eq!(err.downcast_ref::<TursoHttpError>().unwrap().status, 503);
+        store.execute_sql_pipeline(create()).await.unwrap();
+
+        // Request 2 fails, 3 is the retry
+        store.execute_sql("SELECT x FROM t", Access::Read).await.unwrap();
+
+        let insert = PipelineBuilder::new().statement("INSERT OR IGNORE INTO t (x) VALUES (?)", vec!["a".into()]);
+        store.execute_sql_pipeline(insert.idempotent()).await.unwrap();
+        let rows = store.execute_sql("SELECT x FROM t", Access::Read).await.unwrap();
+        assert_eq!(rows.rows().len(), 1);
+
+        assert!(is_idempotent_sql("create index if not exists idx ON t (x)"));
+        assert!(!is_idempotent_sql("UPDATE t SET x = x || 'a'"));
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let turso = fake_turso_rejecting(vec![0], StatusCode::BAD_REQUEST, None).await;
+        let store = turso.store().with_retry(3, Duration::from_millis(1));
+
+        let err = store.initialize().await.unwrap_err();
+        assert_eq!(err.downcast_ref::<TursoHttpError>().unwrap().status, 400);
+
+        // Only the rejected request was sent; the next one goes through
+        store.initialize().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_raw_webhooks_are_kept_until_purged() {
+        let (_turso, store) = fake_store().await;
+
+        let id = store.store_raw_webhook("From=%2B15551234567&Body=hi").await.unwrap();
+        store.set_raw_webhook_conversation(id, "conv").await.unwrap();
+        let err = store.set_raw_webhook_conversation(id + 1, "conv").await.unwrap_err();
+        assert!(err.downcast_ref::<NotFound>().is_some());
+
+        let kept = store
+            .purge_raw_webhooks_older_than(Utc::now() - chrono::Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(kept, 0);
+
+        let rows = store
+            .execute_sql("SELECT payload, conversation_id FROM raw_webhooks", Access::Read)
+            .await
+            .unwrap();
+        assert_eq!(rows.rows()[0][0].as_str(), Some("From=%2B15551234567&Body=hi"));
+        assert_eq!(rows.rows()[0][1].as_str(), Some("conv"));
+
+        let purged = store
+            .purge_raw_webhooks_older_than(Utc::now() + chrono::Duration::seconds(1))
+            .await
+            .unwrap();
+        assert_eq!(purged, 1);
+    }
+
+    #[tokio::test]
+    async fn test_usage_is_counted_per_number_and_day() {
+        let (_turso, store) = fake_store().await;
+        let today = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let tomorrow = today.succ_opt().unwrap();
+
+        for kind in [UsageKind::AiCompletion, UsageKind::AiCompletion, UsageKind::OutboundSms] {
+            store.record_usage("+15551230000", today, kind).await.unwrap();
+        }
+
+        let usage = store.daily_usage("+15551230000", today).await.unwrap();
+        assert_eq!((usage.ai_calls, usage.sms_sent, usage.notice_sent), (2, 1, false));
+        assert_eq!(store.daily_usage("+15551230000", tomorrow).await.unwrap(), DailyUsage::default());
+        assert_eq!(store.daily_usage("+15559999999", today).await.unwrap(), DailyUsage::default());
+
+        assert!(store.mark_cap_notice_sent("+15551230000", today).await.unwrap());
+        assert!(!store.mark_cap_notice_sent("+15551230000", today).await.unwrap());
+        assert!(store.daily_usage("+15551230000", today).await.unwrap().notice_sent);
+    }
+
+    #[tokio::test]
+    async fn test_claims_stop_at_the_cap() {
+        let (_turso, store) = fake_store().await;
+        let today = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let claim = |kind, max| store.claim_usage("+15551230000", today, kind, max);
+
+        assert!(claim(UsageKind::OutboundSms, Some(2)).await.unwrap());
+        assert!(claim(UsageKind::OutboundSms, Some(2)).await.unwrap());
+        assert!(!claim(UsageKind::OutboundSms, Some(2)).await.unwrap());
+        // Each kind has its own cap
+        assert!(claim(UsageKind::AiCompletion, Some(1)).await.unwrap());
+        assert!(!claim(UsageKind::AiCompletion, Some(0)).await.unwrap());
+        assert!(claim(UsageKind::OutboundSms, None).await.unwrap());
+
+        let usage = store.daily_usage("+15551230000", today).await.unwrap();
+        assert_eq!((usage.ai_calls, usage.sms_sent), (1, 3));
+    }
+
+    #[tokio::test]
+    async fn test_batch_touches_each_conversation_once() {
+        let (_turso, store) = fake_store().await;
+        store.ensure_conversation("a", "A").await.unwrap();
+        store.ensure_conversation("b", "B").await.unwrap();
+
+        let messages: Vec<Message> = ["a", "b", "a", "a", "b"]
+            .iter()
+            .map(|conv| Message::new(conv.to_string(), MessageRole::User, "hi".into()))
+            .collect();
+        let newest = |conv: &str| {
+            messages
+                .iter()
+                .filter(|m| m.conversation_id == conv)
+                .map(|m| m.created_at)
+                .max()
+                .unwrap()
+        };
+
+        let pipeline = batch_insert_pipeline(&messages).unwrap();
+        let touches: Vec<&TursoStatement> = pipeline
+            .statements
+            .iter()
+            .filter(|s| s.sql == TOUCH_CONVERSATION_SQL)
+            .collect();
+        assert_eq!(pipeline.len(), 7);
+        assert_eq!(touches.len(), 2);
+
+        store.store_messages_batch(messages.clone()).await.unwrap();
+
+        assert_eq!(store.get_conversation_messages("a").await.unwrap().len(), 3);
+        assert_eq!(store.get_conversation_messages("b").await.unwrap().len(), 2);
+        for conv in ["a", "b"] {
+            let conversation = store.get_conversation(conv).await.unwrap().unwrap();
+            assert_eq!(conversation.updated_at, newest(conv));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_embeddings_round_trip_and_are_replaced() {
+        let (_turso, store) = fake_store().await;
+
+        assert_eq!(store.get_embedding("m1").await.unwrap(), None);
+
+        store.store_embedding("m1", "old", &[1.0, 2.0]).await.unwrap();
+        store.store_embedding("m1", "new", &[0.5, -0.25, 3.0]).await.unwrap();
+
+        assert_eq!(store.get_embedding("m1").await.unwrap(), Some(vec![0.5, -0.25, 3.0]));
+    }
+
+    #[tokio::test]
+    async fn test_purge_removes_only_stale_conversations() {
+        let (_turso, store) = fake_store().await;
+
+        for id in ["stale", "fresh"] {
+            store.ensure_conversation(id, "t").await.unwrap();
+            store
+                .store_message(id.into(), MessageRole::User, "hi".into())
+                .await
+                .unwrap();
+        }
+        store
+            .execute_sql(
+                "UPDATE conversations SET updated_at = '2020-01-01T00:00:00+00:00'
+                 WHERE id = 'stale'",
+                Access::Write,
+            )
+            .await
+            .unwrap();
+
+        let removed = store
+            .purge_conversations_older_than(Utc::now() - chrono::Duration::days(1))
+            .await
+            .unwrap();
+
+        assert_eq!(removed, 1);
+        assert!(store.get_conversation("stale").await.unwrap().is_none());
+        assert!(store.get_conversation_messages("stale").await.unwrap().is_empty());
+        assert!(store.get_conversation("fresh").await.unwrap().is_some());
+        assert_eq!(store.get_conversation_messages("fresh").await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_redact_sql_hides_literals() {
+        let sql = "INSERT INTO messages (id, content)
+                   VALUES ('m1', 'my PIN is 1234, don''t share')";
+
+        assert_eq!(
+            redact_sql(sql),
+            "INSERT INTO messages (id, content) VALUES ('?', '?')"
+        );
+        assert!(redact_sql(&"x".repeat(200)).ends_with('…'));
+    }
+
+    #[tokio::test]
+    async fn test_slow_query_logs_warning() {
+        let turso = fake_turso_delayed(Duration::from_millis(60)).await;
+        let store = turso
+            .store()
+            .with_slow_query_threshold(Duration::from_millis(50));
+        store.initialize().await.unwrap();
+        let (_guard, logs) = capture_logs();
+
+        store.is_message_processed("secret-id").await.unwrap();
+
+        let logs = logs();
+        assert!(logs.contains("Slow Turso query"), "{logs}");
+        assert!(logs.contains("kind=SELECT"), "{logs}");
+        assert!(!logs.contains("secret-id"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn test_get_message_by_id() {
+        let (_turso, store) = fake_store().await;
+
+        let stored = store
+            .store_message_with_provider_sid(
+                "conv".into(),
+                MessageRole::Assistant,
+                "Hello".into(),
+                Some("SM1".into()),
+            )
+            .await
+            .unwrap();
+
+        let fetched = store.get_message(&stored.id).await.unwrap().unwrap();
+        assert_eq!(fetched.id, stored.id);
+        assert_eq!(fetched.role, MessageRole::Assistant);
+        assert_eq!(fetched.content, "Hello");
+        assert_eq!(fetched.provider_sid.as_deref(), Some("SM1"));
+        assert_eq!(fetched.created_at, stored.created_at);
+
+        assert!(store.get_message("missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_store_message_returning_conversation() {
+        let (_turso, store) = fake_store().await;
+        let conversation = store.create_conversation(Some("Chat".into()), None).await.unwrap();
+
+        let message = Message::new(conversation.id.clone(), MessageRole::Assistant, "Hi".into())
+            .with_metadata(Some(serde_json::json!({ "source": "api" })))
+            .with_sender_label(Some("AI".into()));
+        let (message, updated) = store.store_message_returning_conversation(message).await.unwrap();
+
+        assert_eq!(updated.id, conversation.id);
+        assert_eq!(updated.title.as_deref(), Some("Chat"));
+        assert_eq!(updated.updated_at, message.created_at);
+        assert!(updated.updated_at >= conversation.updated_at);
+
+        let stored = store.get_message(&message.id).await.unwrap().unwrap();
+        assert_eq!(stored.content, "Hi");
+        assert_eq!(stored.metadata, Some(serde_json::json!({ "source": "api" })));
+        assert_eq!(stored.sender_label.as_deref(), Some("AI"));
+
+        // Nothing is stored for a conversation that doesn't exist
+        let orphan = Message::new("missing".into(), MessageRole::User, "Hi".into());
+        let err = store.store_message_returning_conversation(orphan.clone()).await.unwrap_err();
+        assert!(err.downcast_ref::<NotFound>().is_some());
+        assert!(store.get_message(&orphan.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_count_conversations_respects_archived_filter() {
+        let (_turso, store) = fake_store().await;
+        assert_eq!(store.count_conversations(false).await.unwrap(), 0);
+
+        let mut ids = Vec::new();
+        for title in ["a", "b", "c"] {
+            ids.push(store.create_conversation(Some(title.into()), None).await.unwrap().id);
+        }
+        store.set_conversation_archived(&ids[0], true).await.unwrap();
+
+        assert_eq!(store.count_conversations(false).await.unwrap(), 2);
+        assert_eq!(store.count_conversations(true).await.unwrap(), 3);
+
+        let page = store.list_conversations(false, 1, 1).await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert!(!page[0].archived);
+
+        let all = store.list_conversations(true, 10, 0).await.unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(all.iter().any(|c| c.id == ids[0] && c.archived));
+    }
+
+    #[tokio::test]
+    async fn test_cursor_pages_have_no_gaps_or_repeats_on_ties() {
+        let (_turso, store) = fake_store().await;
+
+        for id in ["a", "b", "c", "d", "e"] {
+            store.ensure_conversation(id, "t").await.unwrap();
+        }
+        store
+            .execute_sql(
+                "UPDATE conversations SET updated_at = '2024-01-01T00:00:00+00:00'
+                 WHERE id IN ('b', 'c', 'd')",
+                Access::Write,
+            )
+            .await
+            .unwrap();
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (page, next) = store
+                .list_conversations_after(false, 2, cursor.as_ref())
+                .await
+                .unwrap();
+            seen.extend(page.into_iter().map(|c| c.id));
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(seen.len(), 5);
+        assert_eq!(&seen[2..], ["d", "c", "b"]);
+        let mut unique = seen.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_failed_transaction_changes_nothing() {
+        let (_turso, store) = fake_store().await;
+        let at = "2024-01-01T00:00:00+00:00";
+
+        let err = store
+            .transaction(
+                PipelineBuilder::new()
+                    .statement(
+                        "INSERT INTO conversations (id, title, created_at, updated_at) VALUES (?, ?, ?, ?)",
+                        vec!["c1".into(), "first".into(), at.into(), at.into()],
+                    )
+                    .statement("INSERT INTO no_such_table VALUES (1)", vec![]),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("statement 1 failed"), "{err}");
+        assert!(store.get_conversation("c1").await.unwrap().is_none());
+
+        // The connection isn't left inside the aborted transaction
+        let results = store
+            .transaction(PipelineBuilder::new().statement(
+                "INSERT INTO conversations (id, title, created_at, updated_at) VALUES (?, ?, ?, ?)",
+                vec!["c2".into(), "second".into(), at.into(), at.into()],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(results[0].affected_row_count, 1);
+        assert!(store.get_conversation("c2").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_message_pages_walk_in_order_across_ties() {
+        let (_turso, store) = fake_store().await;
+        store.ensure_conversation("a", "t").await.unwrap();
+
+        let at = Utc::now();
+        let messages: Vec<Message> = ["m3", "m1", "m2", "m4", "m5"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, id)| Message {
+                id: id.into(),
+                // m1..m3 share a timestamp, so only the id orders them
+                created_at: at + chrono::Duration::seconds(i.saturating_sub(2) as i64),
+                ..Message::new("a".into(), MessageRole::User, id.into())
+            })
+            .collect();
+        store.store_messages_batch(messages).await.unwrap();
+        store.ensure_conversation("b", "t").await.unwrap();
+        store
+            .store_messages_batch(vec![Message::new("b".into(), MessageRole::User, "other".into())])
+            .await
+            .unwrap();
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = store
+                .get_conversation_messages_after("a", cursor.as_ref(), 2)
+                .await
+                .unwrap();
+            let Some(last) = page.last() else { break };
+            cursor = Some(MessageCursor::after(last));
+            seen.extend(page.into_iter().map(|m| m.id));
+        }
+
+        assert_eq!(seen, ["m1", "m2", "m3", "m4", "m5"]);
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_come_from_the_injected_clock() {
+        let at = DateTime::parse_from_rfc3339("2024-03-01T09:30:00Z").unwrap().with_timezone(&Utc);
+        let clock = Arc::new(crate::clock::FixedClock::new(at));
+        let (_turso, store) = fake_store().await;
+        let store = store.with_clock(clock.clone());
+
+        let conv = store.create_conversation(None, None).await.unwrap();
+        assert_eq!(conv.created_at, at);
+
+        clock.advance(chrono::Duration::seconds(30));
+        let message = store.store_message(conv.id.clone(), MessageRole::User, "hi".into()).await.unwrap();
+        assert_eq!(message.created_at, at + chrono::Duration::seconds(30));
+
+        let stored = store.get_recent_messages(&conv.id, 10, None).await.unwrap();
+        assert_eq!(stored[0].created_at, message.created_at);
+        let conv = store.get_conversation(&conv.id).await.unwrap().unwrap();
+        assert_eq!(conv.updated_at, message.created_at);
+    }
+
+    #[tokio::test]
+    async fn test_recent_messages_are_cut_in_the_query() {
+        let (_turso, store) = fake_store().await;
+        store.ensure_conversation("a", "t").await.unwrap();
+
+        let at = Utc::now();
+        let messages: Vec<Message> = (0..4)
+            .map(|i| Message {
+                created_at: at + chrono::Duration::seconds(i),
+                ..Message::new("a".into(), MessageRole::User, format!("{i}: ünïcode history"))
+            })
+            .collect();
+        store.store_messages_batch(messages).await.unwrap();
+
+        let recent = store.get_recent_messages("a", 3, Some(6)).await.unwrap();
+        let contents: Vec<&str> = recent.iter().map(|m| m.content.as_str()).collect();
+        // SQLite did the cutting; the store decodes rows as returned
+        assert_eq!(contents, ["1: ünï", "2: ünï", "3: ünï"]);
+
+        // Without a cap, and everywhere else, content is whole
+        let recent = store.get_recent_messages("a", 1, None).await.unwrap();
+        assert_eq!(recent[0].content, "3: ünïcode history");
+        let all = store.get_conversation_messages("a").await.unwrap();
+        assert!(all.iter().all(|m| m.content.ends_with("ünïcode history")));
+    }
+
+    #[tokio::test]
+    async fn test_reads_go_to_replica_and_writes_to_primary() {
+        let (replica, replica_store) = fake_store().await;
+        let conversation = replica_store
+            .create_conversation(Some("on replica".into()), None)
+            .await
+            .unwrap();
+
+        let message = replica_store
+            .store_message(conversation.id.clone(), MessageRole::User, "hello".into())
+            .await
+            .unwrap();
+
+        // The primary starts empty, so only the replica can answer these
+        let (primary, primary_store) = fake_store().await;
+        let store = primary.store().with_read_url(replica.url.clone());
+
+        let listed = store.list_conversations(false, 10, 0).await.unwrap();
+        assert_eq!(listed[0].id, conversation.id);
+        let (page, _) = store.list_conversations_after(false, 10, None).await.unwrap();
+        assert_eq!(page[0].id, conversation.id);
+        assert_eq!(store.count_conversations(false).await.unwrap(), 1);
+        let filtered = store
+            .get_conversation_messages_filtered(&conversation.id, Some(&MessageRole::User), None, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(filtered[0].id, message.id);
+        let found = store.find_messages_by_hash(&crate::normalize::content_hash("hello")).await.unwrap();
+        assert_eq!(found[0].id, message.id);
+
+        let created = store.create_conversation(None, None).await.unwrap();
+        assert!(primary_store.get_conversation(&created.id).await.unwrap().is_some());
+        assert!(replica_store.get_conversation(&created.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lookups_after_a_write_see_it_despite_a_lagging_replica() {
+        // The replica has the schema but never receives the writes
+        let (replica, _replica_store) = fake_store().await;
+        let (primary, _primary_store) = fake_store().await;
+        let store = primary.store().with_read_url(replica.url.clone());
+
+        let conversation = store.create_conversation(Some("new".into()), None).await.unwrap();
+        let message = store
+            .store_message(conversation.id.clone(), MessageRole::User, "hello".into())
+            .await
+            .unwrap();
+
+        assert!(store.get_conversation(&conversation.id).await.unwrap().is_some());
+        assert!(store.get_message(&message.id).await.unwrap().is_some());
+        assert_eq!(store.get_recent_messages(&conversation.id, 10, None).await.unwrap().len(), 1);
+        assert!(!store.has_assistant_reply(&conversation.id).await.unwrap());
+
+        // Listings may lag
+        assert!(store.list_conversations(false, 10, 0).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_filtered_messages_by_role_and_since() {
+        let (_turso, store) = fake_store().await;
+
+        store
+            .execute_sql(
+                "INSERT INTO messages (id, conversation_id, role, content, created_at) VALUES
+                 ('m1', 'conv', 'user', 'old question', '2024-01-01T00:00:00+00:00'),
+                 ('m2', 'conv', 'assistant', 'old answer', '2024-01-01T00:00:01+00:00'),
+                 ('m3', 'conv', 'user', 'new question', '2024-06-01T00:00:00+00:00'),
+                 ('m4', 'conv', 'assistant', 'new answer', '2024-06-01T00:00:01+00:00'),
+                 ('m5', 'other', 'assistant', 'elsewhere', '2024-06-01T00:00:01+00:00')",
+                Access::Write,
+            )
+            .await
+            .unwrap();
+
+        let ids = |messages: Vec<Message>| messages.into_iter().map(|m| m.id).collect::<Vec<_>>();
+
+        let assistant = store
+            .get_conversation_messages_filtered("conv", Some(&MessageRole::Assistant), None, None, 50)
+            .await
+            .unwrap();
+        assert_eq!(ids(assistant), ["m2", "m4"]);
+
+        let since = "2024-03-01T00:00:00Z".parse().unwrap();
+        let recent = store
+            .get_conversation_messages_filtered("conv", None, Some(since), None, 50)
+            .await
+            .unwrap();
+        assert_eq!(ids(recent), ["m3", "m4"]);
+
+        let both = store
+            .get_conversation_messages_filtered("conv", Some(&MessageRole::User), Some(since), None, 50)
+            .await
+            .unwrap();
+        assert_eq!(ids(both), ["m3"]);
+
+        let first = store
+            .get_conversation_messages_filtered("conv", Some(&MessageRole::Assistant), None, None, 1)
+            .await
+            .unwrap();
+        assert_eq!(ids(first.clone()), ["m2"]);
+        let cursor = MessageCursor::after(&first[0]);
+        let next = store
+            .get_conversation_messages_filtered("conv", Some(&MessageRole::Assistant), None, Some(&cursor), 1)
+            .await
+            .unwrap();
+        assert_eq!(ids(next), ["m4"]);
+
+        let total = store
+            .count_conversation_messages_filtered("conv", Some(&MessageRole::Assistant), None)
+            .await
+            .unwrap();
+        assert_eq!(total, 2);
+    }
+
+    #[tokio::test]
+    async fn test_store_message_touches_conversation_with_message_time() {
+        let (_turso, store) = fake_store().await;
+        let conversation = store.create_conversation(None, None).await.unwrap();
+
+        let message = store
+            .store_message(conversation.id.clone(), MessageRole::User, "hi".into())
+            .await
+            .unwrap();
+
+        let conversation = store.get_conversation(&conversation.id).await.unwrap().unwrap();
+        assert_eq!(conversation.updated_at, message.created_at);
+    }
+
+    /// Replies to every pipeline with the same JSON and records the bodies
+    struct CannedTransport {
+        response: serde_json::Value,
+        requests: std::sync::Mutex<Vec<serde_json::Value>>,
+    }
+
+    impl TursoTransport for CannedTransport {
+        async fn post_pipeline(&self, body: serde_json::Value) -> Result<serde_json::Value> {
+            self.requests.lock().unwrap().push(body);
+            Ok(self.response.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_messages_decoded_from_canned_response() {
+        let text = |v: &str| serde_json::json!({ "type": "text", "value": v });
+        let sql_null = || serde_json::json!({ "type": "null" });
+
+        let store = ConversationStore::with_transport(CannedTransport {
+            response: serde_json::json!({
+                "results": [{
+                    "type": "ok",
+                    "response": { "type": "execute", "result": {
+                        "cols": [],
+                        "rows": [
+                            [text("m1"), text("conv"), text("user"), text("Hi"),
+                             text("SM1"), sql_null(), text("2024-01-01T00:00:00+00:00"), sql_null()],
+                            [text("m2"), text("conv"), text("assistant"), text("Hello!"),
+                             sql_null(), text("{\"channel\":\"sms\"}"),
+                             text("2024-01-01T00:00:05+00:00"), text("AI")],
+                        ],
+                    }},
+                }],
+            }),
+            requests: Default::default(),
+        });
+
+        let messages = store.get_conversation_messages("conv").await.unwrap();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].id, "m1");
+        assert_eq!(messages[0].role, MessageRole::User);
+        assert_eq!(messages[0].provider_sid.as_deref(), Some("SM1"));
+        assert_eq!(messages[1].role, MessageRole::Assistant);
+        assert_eq!(messages[1].content, "Hello!");
+        assert_eq!(messages[1].metadata, Some(serde_json::json!({ "channel": "sms" })));
+        assert_eq!(messages[1].sender_label.as_deref(), Some("AI"));
+        assert_eq!(
+            messages[1].created_at,
+            "2024-01-01T00:00:05Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        let requests = store.transport.requests.lock().unwrap();
+        let sql = requests[0]["requests"][0]["stmt"]["sql"].as_str().unwrap();
+        assert!(sql.contains("FROM messages"), "{sql}");
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_vacuums_only_when_asked() {
+        let store = ConversationStore::with_transport(CannedTransport {
+            response: serde_json::json!({
+                "results": [{
+                    "type": "ok",
+                    "response": { "type": "execute", "result": { "cols": [], "rows": [] } },
+                }],
+            }),
+            requests: Default::default(),
+        });
+        let sql = |store: &ConversationStore<CannedTransport>| -> Vec<String> {
+            store
+                .transport
+                .requests
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|r| r["requests"][0]["stmt"]["sql"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        store.maintenance(false).await.unwrap();
+        assert_eq!(sql(&store), ["PRAGMA optimize"]);
+
+        store.maintenance(true).await.unwrap();
+        assert_eq!(sql(&store), ["PRAGMA optimize", "PRAGMA optimize", "VACUUM"]);
+    }
+
+    #[tokio::test]
+    async fn test_identical_content_shares_a_hash() {
+        let (_turso, store) = fake_store().await;
+        store.ensure_conversation("a", "A").await.unwrap();
+        store.ensure_conversation("b", "B").await.unwrap();
+
+        let first = store
+            .store_message("a".into(), MessageRole::User, "STOP  sending\r\n".into())
+            .await
+            .unwrap();
+        let same = store
+            .store_message("b".into(), MessageRole::User, "STOP sending".into())
+            .await
+            .unwrap();
+        store
+            .store_message("b".into(), MessageRole::User, "Keep sending".into())
+            .await
+            .unwrap();
+
+        let matches = store.find_messages_by_hash(&content_hash("STOP sending")).await.unwrap();
+        let ids: Vec<&str> = matches.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, [first.id.as_str(), same.id.as_str()]);
+
+        // Batch inserts are hashed too
+        store
+            .store_messages_batch(vec![Message::new("a".into(), MessageRole::User, "stop sending".into())])
+            .await
+            .unwrap();
+        assert_eq!(store.find_messages_by_hash(&content_hash("stop sending")).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_stats_counts_roles_and_days() {
+        let (_turso, store) = fake_store().await;
+        store.ensure_conversation("a", "A").await.unwrap();
+        store.ensure_conversation("b", "B").await.unwrap();
+
+        let now = Utc::now();
+        let seed = [
+            ("a", MessageRole::User, now),
+            ("a", MessageRole::Assistant, now),
+            ("b", MessageRole::User, now - chrono::Duration::days(1)),
+            // Outside the window: counted in the totals only
+            ("b", MessageRole::User, now - chrono::Duration::days(40)),
+        ];
+        for (conversation, role, at) in seed {
+            store
+                .store_message_at(conversation.into(), role, "hi".into(), None, at)
+                .await
+                .unwrap();
+        }
+
+        let stats = store.aggregate_stats(DEFAULT_STATS_DAYS).await.unwrap();
+        assert_eq!(stats.total_conversations, 2);
+        assert_eq!(stats.total_messages, 4);
+        assert_eq!(stats.messages_by_role["user"], 3);
+        assert_eq!(stats.messages_by_role["assistant"], 1);
+
+        let days = &stats.daily_messages;
+        assert_eq!(days.len(), 30);
+        assert_eq!(days[29].date, now.date_naive());
+        assert_eq!(days[29].messages, 2);
+        assert_eq!(days[28].messages, 1);
+        assert_eq!(days.iter().map(|d| d.messages).sum::<i64>(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_merge_conversations_moves_messages_in_time_order() {
+        let (_turso, store) = fake_store().await;
+
+        store
+            .execute_sql(
+                "INSERT INTO conversations (id, title, created_at, updated_at) VALUES
+                 ('legacy', 'SMS: 15551234567', '2024-01-01T00:00:00+00:00', '2024-01-03T00:00:00+00:00'),
+                 ('current', 'SMS: +15551234567', '2024-01-02T00:00:00+00:00', '2024-01-04T00:00:00+00:00')",
+                Access::Write,
+            )
+            .await
+            .unwrap();
+        store
+            .execute_sql(
+                "INSERT INTO messages (id, conversation_id, role, content, created_at) VALUES
+                 ('m1', 'legacy', 'user', 'first', '2024-01-01T00:00:00+00:00'),
+                 ('m2', 'current', 'user', 'second', '2024-01-02T00:00:00+00:00'),
+                 ('m3', 'legacy', 'user', 'third', '2024-01-03T00:00:00+00:00'),
+                 ('m4', 'current', 'user', 'fourth', '2024-01-04T00:00:00+00:00')",
+                Access::Write,
+            )
+            .await
+            .unwrap();
+
+        store.mark_sms_sent("legacy", "m3").await.unwrap();
+        store.mark_sms_sent("legacy-other", "m9").await.unwrap();
+
+        store.merge_conversations("legacy", "current").await.unwrap();
+
+        assert!(store.was_sms_sent("current", "m3").await.unwrap());
+        assert!(!store.was_sms_sent("legacy", "m3").await.unwrap());
+        assert!(store.was_sms_sent("legacy-other", "m9").await.unwrap());
+
+        let messages = store.get_conversation_messages("current").await.unwrap();
+        let ids: Vec<&str> = messages.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["m1", "m2", "m3", "m4"]);
+        assert!(messages.iter().all(|m| m.conversation_id == "current"));
+
+        assert!(store.get_conversation("legacy").await.unwrap().is_none());
+        assert!(store.merge_conversations("legacy", "current").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_content_follows_policy() {
+        let (turso, store) = fake_store().await;
+        let store = store.with_content_limit(32, ContentOverflowPolicy::Truncate);
+        let long = "é".repeat(40);
+
+        let stored = store
+            .store_message("conv".into(), MessageRole::Assistant, long.clone())
+            .await
+            .unwrap();
+        assert!(stored.content.len() <= 32);
+        assert!(stored.content.ends_with(TRUNCATION_MARKER));
+        assert!(long.starts_with(stored.content.trim_end_matches(TRUNCATION_MARKER)));
+
+        let loaded = store.get_message(&stored.id).await.unwrap().unwrap();
+        assert_eq!(loaded.content, stored.content);
+
+        let strict = turso.store().with_content_limit(32, ContentOverflowPolicy::Reject);
+        let err = strict
+            .store_message("conv".into(), MessageRole::User, long.clone())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("over the 32 byte limit"));
+        assert_eq!(strict.get_conversation_messages("conv").await.unwrap().len(), 1);
+
+        // The in-memory store used by handler and consumer tests agrees
+        let memory = crate::storage::InMemoryStore::new().with_content_limit(32, ContentOverflowPolicy::Truncate);
+        memory.ensure_conversation("conv", "t").await.unwrap();
+        let truncated = memory
+            .store_message("conv".into(), MessageRole::Assistant, long.clone())
+            .await
+            .unwrap();
+        assert_eq!(truncated.content, stored.content);
+
+        let memory = crate::storage::InMemoryStore::new().with_content_limit(32, ContentOverflowPolicy::Reject);
+        let batch = vec![
+            Message::new("conv".into(), MessageRole::User, "short".into()),
+            Message::new("conv".into(), MessageRole::User, long),
+        ];
+        let err = memory.store_messages_batch(batch).await.unwrap_err();
+        assert!(err.to_string().contains("over the 32 byte limit"));
+        assert!(memory.get_conversation_messages("conv").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ai_settings_round_trip() {
+        let (_turso, store) = fake_store().await;
+        let conversation = store.create_conversation(None, None).await.unwrap();
+
+        store
+            .set_conversation_ai_settings(&conversation.id, Some("small-model"), Some(0.25))
+            .await
+            .unwrap();
+
+        let loaded = store.get_conversation(&conversation.id).await.unwrap().unwrap();
+        assert_eq!(loaded.ai_model.as_deref(), Some("small-model"));
+        assert_eq!(loaded.ai_temperature, Some(0.25));
+        assert_eq!(loaded.context, None);
+        assert!(loaded.ai_enabled);
+
+        store.set_conversation_ai_enabled(&conversation.id, false).await.unwrap();
+        assert!(!store.get_conversation(&conversation.id).await.unwrap().unwrap().ai_enabled);
+
+        let context = serde_json::json!({ "name": "Ada", "open_tickets": [42] });
+        store.set_conversation_context(&conversation.id, Some(&context)).await.unwrap();
+        let loaded = store.get_conversation(&conversation.id).await.unwrap().unwrap();
+        assert_eq!(loaded.context, Some(context));
+    }
+
+    #[tokio::test]
+    async fn test_quotes_in_values_are_bound_not_spliced() {
+        let (_turso, store) = fake_store().await;
+
+        store.ensure_conversation("o'brien", "It's me").await.unwrap();
+        let loaded = store.get_conversation("o'brien").await.unwrap().unwrap();
+        assert_eq!(loaded.title.as_deref(), Some("It's me"));
+
+        assert!(!store.is_message_processed("m'1").await.unwrap());
+        store.mark_message_processed("m'1").await.unwrap();
+        assert!(store.is_message_processed("m'1").await.unwrap());
+    }
+
+    /// Every conversation setter fails with `NotFound` for an unknown id
+    async fn assert_setters_reject_unknown_conversation<S: ConversationStorage>(store: &S) {
+        let not_found = |result: Result<()>| {
+            let err = result.unwrap_err();
+            assert_eq!(err.downcast_ref::<NotFound>(), Some(&NotFound::conversation("missing")), "{err}");
+        };
+
+        not_found(store.update_conversation_title("missing", "t").await);
+        not_found(store.set_conversation_ai_settings("missing", Some("m"), Some(0.5)).await);
+        not_found(store.set_conversation_sender_label("missing", Some("Bot")).await);
+        not_found(store.set_conversation_ai_enabled("missing", false).await);
+        not_found(store.set_conversation_context("missing", None).await);
+        not_found(store.set_conversation_archived("missing", true).await);
+        not_found(store.mark_read("missing", Utc::now()).await);
+    }
+
+    #[tokio::test]
+    async fn test_setters_report_unknown_conversations_alike() {
+        let (_turso, store) = fake_store().await;
+        assert_setters_reject_unknown_conversation(&store).await;
+        assert_setters_reject_unknown_conversation(&crate::storage::InMemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_unread_count_after_mark_read() {
+        let (_turso, store) = fake_store().await;
+        store.ensure_conversation("conv", "t").await.unwrap();
+
+        let first = store
+            .store_message("conv".into(), MessageRole::User, "one".into())
+            .await
+            .unwrap();
+        assert_eq!(store.unread_count("conv").await.unwrap(), 1);
+
+        store.mark_read("conv", first.created_at).await.unwrap();
+        assert_eq!(store.unread_count("conv").await.unwrap(), 0);
+
+        store
+            .store_message("conv".into(), MessageRole::User, "two".into())
+            .await
+            .unwrap();
+        assert_eq!(store.unread_count("conv").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_round_trip() {
+        let (_turso, store) = fake_store().await;
+
+        let metadata = serde_json::json!({
+            "channel": "web",
+            "campaign": { "id": 42, "tags": ["spring", "promo"] },
+            "sentiment": null
+        });
+
+        store
+            .store_message_with_metadata(
+                "conv".into(),
+                MessageRole::User,
+                "It's great".into(),
+                Some(metadata.clone()),
+            )
+            .await
+            .unwrap();
+        store
+            .store_message("conv".into(), MessageRole::Assistant, "Thanks!".into())
+            .await
+            .unwrap();
+
+        let messages = store.get_conversation_messages("conv").await.unwrap();
+
+        assert_eq!(messages[0].metadata, Some(metadata));
+        assert_eq!(messages[0].content, "It's great");
+        assert_eq!(messages[1].metadata, None);
+    }
+
+    #[tokio::test]
+    async fn test_insert_binds_quotes_in_every_column() {
+        let (_turso, store) = fake_store().await;
+
+        let message = Message::new("it's-conv".into(), MessageRole::User, "it's".into())
+            .with_provider_sid(Some("SM'1".into()));
+        let id = message.id.clone();
+        store.insert_message(message).await.unwrap();
+
+        let stored = store.get_message(&id).await.unwrap().unwrap();
+        assert_eq!(stored.conversation_id, "it's-conv");
+        assert_eq!(stored.provider_sid.as_deref(), Some("SM'1"));
+    }
+
+    #[tokio::test]
+    async fn test_conversation_id_is_bound_when_listing_messages() {
+        let (_turso, store) = fake_store().await;
+
+        store.store_message("a".into(), MessageRole::User, "mine".into()).await.unwrap();
+        store.store_message("b".into(), MessageRole::User, "theirs".into()).await.unwrap();
+
+        let injected = store.get_conversation_messages("x' OR '1'='1").await.unwrap();
+        assert!(injected.is_empty());
+
+        let messages = store.get_conversation_messages("a").await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "mine");
+    }
+
+    #[tokio::test]
+    async fn test_sms_conversations_are_found_by_number_after_rename() {
+        let (_turso, store) = fake_store().await;
+
+        store
+            .ensure_sms_conversation("sms_1", "SMS: +15551234567", "+15551234567")
+            .await
+            .unwrap();
+        store.update_conversation_title("sms_1", "Billing").await.unwrap();
+        // Stored before the column existed
+        store
+            .execute_sql(
+                "INSERT INTO conversations (id, title, created_at, updated_at)
+                 VALUES ('sms_legacy', 'SMS: +15551234567', '2024-01-01T00:00:00+00:00', '2024-01-01T00:00:00+00:00')",
+                Access::Write,
+            )
+            .await
+            .unwrap();
+        store.initialize().await.unwrap();
+
+        let active = store.active_conversations_from("+15551234567").await.unwrap();
+        assert_eq!(active, ["sms_1", "sms_legacy"]);
+        assert!(store.active_conversations_from("+15559990000").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_taken_over_conversation_is_found_by_number() {
+        let (_turso, store) = fake_store().await;
+
+        store.ensure_sms_conversation("sms_1", "SMS: +1555", "+1555").await.unwrap();
+        assert_eq!(store.taken_over_conversation_from("+1555").await.unwrap(), None);
+
+        store.set_conversation_ai_enabled("sms_1", false).await.unwrap();
+        assert_eq!(
+            store.taken_over_conversation_from("+1555").await.unwrap().as_deref(),
+            Some("sms_1")
+        );
+        assert_eq!(store.taken_over_conversation_from("+1666").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_inbound_conversation_checks_reply_then_takeover_then_cap() {
+        let (_turso, store) = fake_store().await;
+
+        store.ensure_sms_conversation("sms_1", "SMS: +1555", "+1555").await.unwrap();
+        store.ensure_sms_conversation("sms_2", "SMS: +1555", "+1555").await.unwrap();
+        store
+            .insert_message(
+                Message::new("sms_1".into(), MessageRole::User, "hi".into())
+                    .with_provider_sid(Some("SM_1".into())),
+            )
+            .await
+            .unwrap();
+        let resolve = |reply: Option<&'static str>, max: Option<usize>| {
+            let store = &store;
+            async move { store.resolve_inbound_conversation("+1555", reply, max).await.unwrap() }
+        };
+
+        assert_eq!(resolve(None, None).await, None);
+        assert_eq!(resolve(Some("SM_unknown"), Some(3)).await, None);
+        assert!(resolve(None, Some(2)).await.is_some());
+
+        store.set_conversation_ai_enabled("sms_2", false).await.unwrap();
+        assert_eq!(resolve(None, None).await.as_deref(), Some("sms_2"));
+        assert_eq!(resolve(Some("SM_1"), None).await.as_deref(), Some("sms_1"));
     }
 }
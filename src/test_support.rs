@@ -0,0 +1,425 @@
+//! Local fakes for the external HTTP APIs, used by unit tests.
+
+use axum::response::{IntoResponse, Response};
+use axum::{extract::State, http::StatusCode, routing::post, Form, Json, Router};
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::ai_service::AIService;
+use crate::signalwire::SignalWireClient;
+use crate::storage::ConversationStorage;
+use crate::store::ConversationStore;
+
+/// Serve `router` on an ephemeral localhost port and return its base URL
+pub async fn serve(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+
+    format!("http://{}", addr)
+}
+
+/// Capture `tracing` output on the current thread until the guard drops.
+/// Call the returned closure to read what was logged so far.
+pub fn capture_logs() -> (tracing::subscriber::DefaultGuard, impl Fn() -> String) {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+
+    let writer = {
+        let buffer = buffer.clone();
+        move || LogWriter(buffer.clone())
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_ansi(false)
+        .finish();
+
+    let guard = tracing::subscriber::set_default(subscriber);
+    (guard, move || String::from_utf8_lossy(&buffer.lock().unwrap()).into_owned())
+}
+
+struct LogWriter(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Fake Groq / OpenAI chat completions endpoint.
+/// Replies with `reply` and records every request body.
+pub async fn fake_groq(reply: &str) -> (String, Arc<Mutex<Vec<Value>>>) {
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let reply = reply.to_string();
+
+    let router = Router::new()
+        .route(
+            "/chat/completions",
+            post(
+                |State((captured, reply)): State<(Arc<Mutex<Vec<Value>>>, String)>,
+                 Json(body): Json<Value>| async move {
+                    captured.lock().unwrap().push(body);
+                    Json(json!({
+                        "choices": [{ "message": { "role": "assistant", "content": reply } }]
+                    }))
+                },
+            ),
+        )
+        .with_state((captured.clone(), reply));
+
+    (serve(router).await, captured)
+}
+
+/// Fake Groq that takes `delay` per completion and records the peak
+/// number of completions in flight at once
+pub async fn fake_ai_slow(reply: &str, delay: Duration) -> (AIService, Arc<AtomicUsize>) {
+    #[derive(Clone)]
+    struct Probe {
+        reply: String,
+        delay: Duration,
+        in_flight: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    }
+
+    let probe = Probe {
+        reply: reply.to_string(),
+        delay,
+        in_flight: Arc::new(AtomicUsize::new(0)),
+        peak: Arc::new(AtomicUsize::new(0)),
+    };
+    let peak = probe.peak.clone();
+
+    let router = Router::new()
+        .route(
+            "/chat/completions",
+            post(|State(probe): State<Probe>| async move {
+                let now = probe.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                probe.peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(probe.delay).await;
+                probe.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                Json(json!({
+                    "choices": [{ "message": { "role": "assistant", "content": probe.reply } }]
+                }))
+            }),
+        )
+        .with_state(probe);
+
+    let url = serve(router).await;
+    let ai = AIService::new("test-model".into(), "test-key".into()).with_api_url(url);
+    (ai, peak)
+}
+
+/// Fake Groq that answers every completion with a 500
+pub async fn fake_ai_failing() -> AIService {
+    fake_ai_rejecting(StatusCode::INTERNAL_SERVER_ERROR).await
+}
+
+/// Fake Groq that answers every completion with `status`
+pub async fn fake_ai_rejecting(status: StatusCode) -> AIService {
+    let router = Router::new().route("/chat/completions", post(move || async move { status }));
+
+    let url = serve(router).await;
+    AIService::new("test-model".into(), "test-key".into()).with_api_url(url)
+}
+
+pub async fn fake_ai(reply: &str) -> (AIService, Arc<Mutex<Vec<Value>>>) {
+    let (url, captured) = fake_groq(reply).await;
+    let ai = AIService::new("test-model".into(), "test-key".into()).with_api_url(url);
+    (ai, captured)
+}
+
+pub type SentForms = Arc<Mutex<Vec<HashMap<String, String>>>>;
+
+/// Fake SignalWire Messages endpoint; records every form body.
+/// The n-th send (0-based) gets the SID `SM_fake_{n}`.
+pub async fn fake_signalwire() -> (SignalWireClient, SentForms) {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+
+    let router = Router::new()
+        .route(
+            "/api/laml/2010-04-01/Accounts/{project}/Messages.json",
+            post(
+                |State(sent): State<SentForms>,
+                 Form(form): Form<HashMap<String, String>>| async move {
+                    let mut sent = sent.lock().unwrap();
+                    let sid = format!("SM_fake_{}", sent.len());
+                    sent.push(form);
+                    Json(json!({ "sid": sid, "status": "queued" }))
+                },
+            ),
+        )
+        .with_state(sent.clone());
+
+    let url = serve(router).await;
+    let client = SignalWireClient::new("project".into(), "token".into(), url, "+15550000000".into());
+    (client, sent)
+}
+
+/// Fake SignalWire that rejects every send with the given error code
+pub async fn fake_signalwire_rejecting(code: u32) -> (SignalWireClient, SentForms) {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+
+    let router = Router::new()
+        .route(
+            "/api/laml/2010-04-01/Accounts/{project}/Messages.json",
+            post(
+                move |State(sent): State<SentForms>,
+                      Form(form): Form<HashMap<String, String>>| async move {
+                    sent.lock().unwrap().push(form);
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({ "code": code, "message": "rejected", "status": 400 })),
+                    )
+                },
+            ),
+        )
+        .with_state(sent.clone());
+
+    let url = serve(router).await;
+    let client = SignalWireClient::new("project".into(), "token".into(), url, "+15550000000".into());
+    (client, sent)
+}
+
+/// -----------------------------
+/// Fake Turso (SQLite-backed)
+/// -----------------------------
+/// Implements enough of the `/v2/pipeline` protocol to run the store
+/// against an in-memory SQLite database.
+#[derive(Clone)]
+pub struct FakeTurso {
+    pub url: String,
+}
+
+impl FakeTurso {
+    pub fn store(&self) -> ConversationStore {
+        ConversationStore::new(self.url.clone(), "test-token".into())
+    }
+}
+
+pub async fn fake_turso() -> FakeTurso {
+    spawn_fake_turso(Vec::new(), StatusCode::OK, None, Duration::ZERO, None).await
+}
+
+/// Fake Turso that answers 503 to the requests at the given (0-based) indexes
+pub async fn fake_turso_failing(fail_at: Vec<usize>) -> FakeTurso {
+    spawn_fake_turso(fail_at, StatusCode::SERVICE_UNAVAILABLE, None, Duration::ZERO, None).await
+}
+
+/// Fake Turso that answers `status` to the requests at the given indexes,
+/// with a `Retry-After` header (in seconds) when one is given
+pub async fn fake_turso_rejecting(
+    fail_at: Vec<usize>,
+    status: StatusCode,
+    retry_after: Option<u64>,
+) -> FakeTurso {
+    spawn_fake_turso(fail_at, status, retry_after, Duration::ZERO, None).await
+}
+
+/// Fake Turso that answers 503 to the first request whose SQL starts with
+/// `sql_prefix` (e.g. `"ALTER TABLE"`), wherever it falls in the sequence
+pub async fn fake_turso_failing_sql(sql_prefix: &'static str) -> FakeTurso {
+    spawn_fake_turso(Vec::new(), StatusCode::SERVICE_UNAVAILABLE, None, Duration::ZERO, Some(sql_prefix)).await
+}
+
+/// Fake Turso that waits `delay` before answering each request
+pub async fn fake_turso_delayed(delay: Duration) -> FakeTurso {
+    spawn_fake_turso(Vec::new(), StatusCode::OK, None, delay, None).await
+}
+
+async fn spawn_fake_turso(
+    fail_at: Vec<usize>,
+    fail_status: StatusCode,
+    retry_after: Option<u64>,
+    delay: Duration,
+    fail_sql: Option<&'static str>,
+) -> FakeTurso {
+    let state = Arc::new(TursoState {
+        db: Mutex::new(Connection::open_in_memory().unwrap()),
+        requests: AtomicUsize::new(0),
+        fail_at,
+        fail_status,
+        retry_after,
+        delay,
+        fail_sql: Mutex::new(fail_sql),
+    });
+
+    let router = Router::new()
+        .route("/v2/pipeline", post(pipeline))
+        .with_state(state);
+
+    FakeTurso {
+        url: serve(router).await,
+    }
+}
+
+struct TursoState {
+    db: Mutex<Connection>,
+    requests: AtomicUsize,
+    fail_at: Vec<usize>,
+    fail_status: StatusCode,
+    retry_after: Option<u64>,
+    delay: Duration,
+    /// Taken by the first request it matches, so it fails only once
+    fail_sql: Mutex<Option<&'static str>>,
+}
+
+/// Fake Turso with an initialized schema and a store pointing at it
+pub async fn fake_store() -> (FakeTurso, ConversationStore) {
+    let turso = fake_turso().await;
+    let store = turso.store();
+    store.initialize().await.unwrap();
+    (turso, store)
+}
+
+async fn pipeline(
+    State(state): State<Arc<TursoState>>,
+    Json(body): Json<Value>,
+) -> Result<Json<Value>, Response> {
+    let index = state.requests.fetch_add(1, Ordering::SeqCst);
+    let sql = body["requests"][0]["stmt"]["sql"].as_str().unwrap_or("");
+    let fails_sql = {
+        let mut fail_sql = state.fail_sql.lock().unwrap();
+        fail_sql.take_if(|prefix| sql.trim_start().starts_with(*prefix)).is_some()
+    };
+    if fails_sql || state.fail_at.contains(&index) {
+        return Err(match state.retry_after {
+            Some(secs) => (state.fail_status, [("retry-after", secs.to_string())]).into_response(),
+            None => state.fail_status.into_response(),
+        });
+    }
+    tokio::time::sleep(state.delay).await;
+
+    let conn = state.db.lock().unwrap();
+
+    let results: Vec<Value> = body["requests"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .iter()
+        .map(|req| match req["type"].as_str() {
+            Some("execute") => match execute(&conn, &req["stmt"]) {
+                Ok(result) => json!({ "type": "ok", "response": { "type": "execute", "result": result } }),
+                Err(e) => json!({ "type": "error", "error": { "message": e.to_string() } }),
+            },
+            Some("batch") => {
+                let result = batch(&conn, &req["batch"]["steps"]);
+                json!({ "type": "ok", "response": { "type": "batch", "result": result } })
+            }
+            _ => json!({ "type": "ok", "response": { "type": "close" } }),
+        })
+        .collect();
+
+    Ok(Json(json!({ "baton": null, "base_url": null, "results": results })))
+}
+
+/// Run the steps of a Hrana batch whose `condition` holds
+fn batch(conn: &Connection, steps: &Value) -> Value {
+    let steps = steps.as_array().cloned().unwrap_or_default();
+    let mut results: Vec<Value> = Vec::new();
+    let mut errors: Vec<Value> = Vec::new();
+
+    for step in &steps {
+        if !condition_holds(&step["condition"], &results, &errors) {
+            results.push(Value::Null);
+            errors.push(Value::Null);
+            continue;
+        }
+        match execute(conn, &step["stmt"]) {
+            Ok(result) => {
+                results.push(result);
+                errors.push(Value::Null);
+            }
+            Err(e) => {
+                results.push(Value::Null);
+                errors.push(json!({ "message": e.to_string() }));
+            }
+        }
+    }
+
+    json!({ "step_results": results, "step_errors": errors })
+}
+
+fn condition_holds(condition: &Value, results: &[Value], errors: &[Value]) -> bool {
+    let step = |c: &Value| c["step"].as_u64().unwrap_or(0) as usize;
+    match condition["type"].as_str() {
+        None => true,
+        Some("ok") => results.get(step(condition)).is_some_and(|r| !r.is_null()),
+        Some("error") => errors.get(step(condition)).is_some_and(|e| !e.is_null()),
+        Some("not") => !condition_holds(&condition["cond"], results, errors),
+        Some(other) => panic!("Unsupported batch condition {other}"),
+    }
+}
+
+/// Decode positional `args` (Turso typed values) into SQLite values
+fn bind_args(stmt: &Value) -> Vec<SqlValue> {
+    stmt["args"]
+        .as_array()
+        .map(|args| {
+            args.iter()
+                .map(|arg| match (arg["type"].as_str(), &arg["value"]) {
+                    (Some("integer"), Value::String(v)) => {
+                        SqlValue::Integer(v.parse().unwrap_or_default())
+                    }
+                    (Some("float"), v) => SqlValue::Real(v.as_f64().unwrap_or_default()),
+                    (Some("text"), Value::String(v)) => SqlValue::Text(v.clone()),
+                    _ => SqlValue::Null,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn execute(conn: &Connection, stmt: &Value) -> rusqlite::Result<Value> {
+    let args = bind_args(stmt);
+    let mut stmt = conn.prepare(stmt["sql"].as_str().unwrap_or(""))?;
+    let cols: Vec<Value> = stmt
+        .column_names()
+        .into_iter()
+        .map(|name| json!({ "name": name }))
+        .collect();
+    let width = cols.len();
+
+    let mut rows = Vec::new();
+    for (i, arg) in args.into_iter().enumerate() {
+        stmt.raw_bind_parameter(i + 1, arg)?;
+    }
+    let mut cursor = stmt.raw_query();
+
+    while let Some(row) = cursor.next()? {
+        let values = (0..width)
+            .map(|i| {
+                Ok(match row.get_ref(i)? {
+                    ValueRef::Null => json!({ "type": "null" }),
+                    ValueRef::Integer(v) => json!({ "type": "integer", "value": v.to_string() }),
+                    ValueRef::Real(v) => json!({ "type": "float", "value": v }),
+                    ValueRef::Text(v) => {
+                        json!({ "type": "text", "value": String::from_utf8_lossy(v) })
+                    }
+                    ValueRef::Blob(_) => json!({ "type": "blob" }),
+                })
+            })
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.push(Value::Array(values));
+    }
+
+    Ok(json!({
+        "cols": cols,
+        "rows": rows,
+        "affected_row_count": conn.changes(),
+        "last_insert_rowid": null,
+    }))
+}
@@ -0,0 +1,151 @@
+use chrono::NaiveDate;
+use tracing::warn;
+
+use crate::clock::Clock;
+use crate::storage::ConversationStorage;
+
+/// -----------------------------
+/// Daily Usage
+/// -----------------------------
+/// What one number consumed on one (UTC) day. A new day starts a new row,
+/// so caps reset at midnight UTC without any cleanup job.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DailyUsage {
+    pub ai_calls: u32,
+    pub sms_sent: u32,
+    /// The cap notice already went out today
+    pub notice_sent: bool,
+}
+
+/// Something that counts against a number's daily cap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageKind {
+    AiCompletion,
+    OutboundSms,
+}
+
+/// The day usage is recorded under, by `clock` (the store's)
+pub fn usage_date(clock: &dyn Clock) -> NaiveDate {
+    clock.now().date_naive()
+}
+
+/// -----------------------------
+/// Usage Caps
+/// -----------------------------
+/// Per-number daily limits on AI completions and outbound SMS. `None`
+/// means unlimited; the default has no limits at all.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsageCaps {
+    pub max_ai_calls: Option<u32>,
+    pub max_sms: Option<u32>,
+    /// Sent once per number and day when a cap is first hit
+    pub notice: Option<String>,
+}
+
+impl UsageCaps {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_ai_calls.is_none() && self.max_sms.is_none()
+    }
+
+    /// No further AI replies for this number today
+    pub fn is_exceeded(&self, usage: &DailyUsage) -> bool {
+        self.max_ai_calls.is_some_and(|max| usage.ai_calls >= max)
+            || self.max_sms.is_some_and(|max| usage.sms_sent >= max)
+    }
+
+    /// Daily cap on `kind`; `None` if unlimited
+    pub fn limit(&self, kind: UsageKind) -> Option<u32> {
+        match kind {
+            UsageKind::AiCompletion => self.max_ai_calls,
+            UsageKind::OutboundSms => self.max_sms,
+        }
+    }
+}
+
+/// Count one `kind` for `number` today unless it already used up that
+/// cap; `false` (nothing counted) when it has. Check and increment are one
+/// conditional update, so concurrent senders can't both take the last one.
+/// Best-effort: a failed count is logged and lets the caller go ahead.
+/// Nothing is tracked while `caps` is unlimited.
+pub async fn claim_usage<S: ConversationStorage>(
+    store: &S,
+    caps: &UsageCaps,
+    number: &str,
+    kind: UsageKind,
+) -> bool {
+    if caps.is_unlimited() {
+        return true;
+    }
+
+    match store.claim_usage(number, usage_date(store.clock()), kind, caps.limit(kind)).await {
+        Ok(claimed) => claimed,
+        Err(e) => {
+            warn!("Failed to count {:?} for {}: {e}", kind, number);
+            true
+        }
+    }
+}
+
+/// Count one `kind` for `number` today whatever the cap, e.g. the cap
+/// notice itself. Best-effort, like `claim_usage`.
+pub async fn record_usage<S: ConversationStorage>(
+    store: &S,
+    caps: &UsageCaps,
+    number: &str,
+    kind: UsageKind,
+) {
+    if caps.is_unlimited() {
+        return;
+    }
+
+    if let Err(e) = store.record_usage(number, usage_date(store.clock()), kind).await {
+        warn!("Failed to record {:?} for {}: {e}", kind, number);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_either_cap_blocks() {
+        let caps = UsageCaps {
+            max_ai_calls: Some(2),
+            max_sms: Some(3),
+            notice: None,
+        };
+
+        let usage = |ai_calls, sms_sent| DailyUsage {
+            ai_calls,
+            sms_sent,
+            notice_sent: false,
+        };
+
+        assert!(!caps.is_exceeded(&usage(1, 1)));
+        assert!(caps.is_exceeded(&usage(2, 0)));
+        assert!(caps.is_exceeded(&usage(0, 3)));
+        assert!(!UsageCaps::default().is_exceeded(&usage(1000, 1000)));
+        assert!(UsageCaps::default().is_unlimited());
+    }
+
+    #[tokio::test]
+    async fn test_usage_is_counted_on_the_store_clock_day() {
+        let late = chrono::DateTime::parse_from_rfc3339("2024-03-01T23:59:00Z").unwrap().to_utc();
+        let clock = std::sync::Arc::new(crate::clock::FixedClock::new(late));
+        let store = crate::InMemoryStore::new().with_clock(clock.clone());
+        let caps = UsageCaps {
+            max_ai_calls: Some(1),
+            max_sms: None,
+            notice: None,
+        };
+
+        assert!(claim_usage(&store, &caps, "+15551230000", UsageKind::AiCompletion).await);
+        assert!(!claim_usage(&store, &caps, "+15551230000", UsageKind::AiCompletion).await);
+
+        // A new day by the store's clock, whatever the wall clock says
+        clock.advance(chrono::Duration::minutes(2));
+        assert!(claim_usage(&store, &caps, "+15551230000", UsageKind::AiCompletion).await);
+        let first_day = store.daily_usage("+15551230000", late.date_naive()).await.unwrap();
+        assert_eq!(first_day.ai_calls, 1);
+    }
+}
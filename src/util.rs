@@ -0,0 +1,25 @@
+/// -----------------------------
+/// Stable Hash
+/// -----------------------------
+/// FNV-1a: unlike `DefaultHasher`, the same in every process and release,
+/// so anything keyed on it (partition routing, sender stickiness) agrees
+/// across producers and restarts.
+pub fn stable_hash(value: &str) -> u64 {
+    value
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stable_hash_is_fnv1a() {
+        // Pinned: changing it would reshuffle partitions and sender numbers
+        assert_eq!(stable_hash(""), 0xcbf29ce484222325);
+        assert_eq!(stable_hash("a"), 0xaf63dc4c8601ec8c);
+    }
+}
@@ -0,0 +1,185 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, KeyInit, Mac};
+use sha1::Sha1;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Headers the signature may arrive in; SignalWire sends the first, and
+/// the second for Twilio (LaML) compatibility
+pub const SIGNATURE_HEADERS: [&str; 2] = ["x-signalwire-signature", "x-twilio-signature"];
+
+/// Largest webhook body read for checking; carrier forms are a few KB
+const MAX_WEBHOOK_BYTES: usize = 64 * 1024;
+
+/// -----------------------------
+/// Webhook Signatures
+/// -----------------------------
+/// Checks that carrier webhooks were signed with the project's auth
+/// token: base64 HMAC-SHA1 of the full request URL followed by every form
+/// parameter's name and value, sorted by name. Unsigned or mis-signed
+/// requests are answered `403` before any handler runs.
+///
+/// The URL must be the one the carrier called. Set `base_url` (e.g.
+/// `https://sms.example.com`) behind proxies that rewrite the host;
+/// otherwise it is rebuilt from `Host` and `X-Forwarded-Proto`.
+///
+/// Use with `axum::middleware::from_fn_with_state(signer, verify_webhook_signature)`.
+#[derive(Debug, Clone)]
+pub struct WebhookSigner {
+    auth_token: Arc<str>,
+    base_url: Option<Arc<str>>,
+}
+
+impl WebhookSigner {
+    pub fn new(auth_token: &str, base_url: Option<&str>) -> Self {
+        Self {
+            auth_token: auth_token.into(),
+            base_url: base_url.map(|url| url.trim_end_matches('/').into()),
+        }
+    }
+
+    fn mac(&self, url: &str, params: &[(String, String)]) -> Hmac<Sha1> {
+        let mut sorted: Vec<&(String, String)> = params.iter().collect();
+        sorted.sort();
+
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.auth_token.as_bytes()).expect("HMAC takes any key length");
+        mac.update(url.as_bytes());
+        for (name, value) in sorted {
+            mac.update(name.as_bytes());
+            mac.update(value.as_bytes());
+        }
+        mac
+    }
+
+    /// Signature the carrier sends for a POST of `params` to `url`
+    pub fn sign(&self, url: &str, params: &[(String, String)]) -> String {
+        STANDARD.encode(self.mac(url, params).finalize().into_bytes())
+    }
+
+    /// Constant-time check of `signature` against `sign(url, params)`
+    pub fn is_valid(&self, url: &str, params: &[(String, String)], signature: &str) -> bool {
+        match STANDARD.decode(signature) {
+            Ok(signature) => self.mac(url, params).verify_slice(&signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// URL the carrier posted to
+    fn request_url(&self, headers: &HeaderMap, path_and_query: &str) -> Option<String> {
+        if let Some(base) = &self.base_url {
+            return Some(format!("{base}{path_and_query}"));
+        }
+
+        let host = headers.get(header::HOST)?.to_str().ok()?;
+        let scheme = headers
+            .get("x-forwarded-proto")
+            .and_then(|proto| proto.to_str().ok())
+            .unwrap_or("https");
+        Some(format!("{scheme}://{host}{path_and_query}"))
+    }
+}
+
+pub async fn verify_webhook_signature(
+    State(signer): State<WebhookSigner>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let path = parts.uri.path().to_string();
+
+    let signature = SIGNATURE_HEADERS
+        .iter()
+        .find_map(|name| parts.headers.get(*name))
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let Some(signature) = signature else {
+        warn!("Rejecting unsigned webhook to {path}");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    let Ok(bytes) = to_bytes(body, MAX_WEBHOOK_BYTES).await else {
+        warn!("Rejecting webhook to {path}: body too large");
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    };
+    // A body that isn't a form is signed over the URL alone
+    let params: Vec<(String, String)> = serde_urlencoded::from_bytes(&bytes).unwrap_or_default();
+
+    let path_and_query = parts.uri.path_and_query().map_or(path.as_str(), |pq| pq.as_str());
+    let valid = signer
+        .request_url(&parts.headers, path_and_query)
+        .is_some_and(|url| signer.is_valid(&url, &params, &signature));
+    if !valid {
+        warn!("Rejecting webhook to {path}: bad signature");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    next.run(Request::from_parts(parts, Body::from(bytes))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::post, Router};
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_signature_matches_the_documented_example() {
+        let signer = WebhookSigner::new("12345", None);
+        let url = "https://mycompany.com/myapp.php?foo=1&bar=2";
+        let params = params(&[
+            ("To", "+18005551212"),
+            ("CallSid", "CA1234567890ABCDE"),
+            ("Caller", "+12349013030"),
+            ("Digits", "1234"),
+            ("From", "+12349013030"),
+        ]);
+
+        assert_eq!(signer.sign(url, &params), "0/KCTR6DLpKmkAf8muzZqo1nDgQ=");
+        assert!(signer.is_valid(url, &params, "0/KCTR6DLpKmkAf8muzZqo1nDgQ="));
+        assert!(!signer.is_valid("https://mycompany.com/myapp.php", &params, "0/KCTR6DLpKmkAf8muzZqo1nDgQ="));
+        assert!(!signer.is_valid(url, &params, "not base64!"));
+    }
+
+    #[tokio::test]
+    async fn test_only_correctly_signed_webhooks_reach_the_handler() {
+        let signer = WebhookSigner::new("token", Some("https://sms.example.com/"));
+        let router = Router::new()
+            .route("/sms/webhook", post(|body: String| async move { body }))
+            .route_layer(middleware::from_fn_with_state(signer.clone(), verify_webhook_signature));
+        let url = crate::test_support::serve(router).await;
+        let client = reqwest::Client::new();
+
+        let form = params(&[("From", "+15551234567"), ("Body", "Hello there!")]);
+        let signature = signer.sign("https://sms.example.com/sms/webhook", &form);
+
+        let post = |header: &'static str, signature: String, form: Vec<(String, String)>| {
+            client.post(format!("{url}/sms/webhook")).header(header, signature).form(&form).send()
+        };
+
+        let accepted = post(SIGNATURE_HEADERS[0], signature.clone(), form.clone()).await.unwrap();
+        assert_eq!(accepted.status(), reqwest::StatusCode::OK);
+        // The handler still sees the whole body
+        assert_eq!(accepted.text().await.unwrap(), "From=%2B15551234567&Body=Hello+there%21");
+
+        let twilio = post(SIGNATURE_HEADERS[1], signature.clone(), form).await.unwrap();
+        assert_eq!(twilio.status(), reqwest::StatusCode::OK);
+
+        let tampered = post(SIGNATURE_HEADERS[0], signature, params(&[("From", "+15559990000")]))
+            .await
+            .unwrap();
+        assert_eq!(tampered.status(), reqwest::StatusCode::FORBIDDEN);
+
+        let unsigned = client.post(format!("{url}/sms/webhook")).send().await.unwrap();
+        assert_eq!(unsigned.status(), reqwest::StatusCode::FORBIDDEN);
+    }
+}
@@ -1,9 +1,11 @@
 use anyhow::{Context, Result};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use crate::message_broker::SMSMessage;
+
 /// -----------------------------
 /// Message batch with indexed access
 /// -----------------------------
@@ -40,33 +42,39 @@ impl MessageBatch {
         }
     }
 
-    /// Zero-copy iterator
+    /// Zero-copy iterator; stops at the first malformed entry (see `get`)
     pub fn iter(&self) -> MessageBatchIterator {
         MessageBatchIterator {
-            indexes: self.indexes.clone(),
-            messages: self.messages.clone(),
+            batch: self.clone(),
             current: 0,
-            count: self.count,
         }
     }
 
-    /// Get message by index (zero-copy)
+    /// Get message by index (zero-copy).
+    ///
+    /// The batch may come off the wire, so nothing is trusted: `None` for
+    /// an index past `count`, a missing index entry, or offsets that run
+    /// backwards or past the end of `messages`.
     pub fn get(&self, index: usize) -> Option<Bytes> {
         if index >= self.count {
             return None;
         }
 
-        let mut idx = self.indexes.clone();
-        idx.advance(index * 4);
-        let start = idx.get_u32_le() as usize;
-
+        let start = self.offset(index)?;
         let end = if index + 1 < self.count {
-            idx.get_u32_le() as usize
+            self.offset(index + 1)?
         } else {
             self.messages.len()
         };
 
-        Some(self.messages.slice(start..end))
+        (start <= end && end <= self.messages.len()).then(|| self.messages.slice(start..end))
+    }
+
+    /// Offset stored for message `index`, if the index buffer has it
+    fn offset(&self, index: usize) -> Option<usize> {
+        let at = index.checked_mul(4)?;
+        let mut entry = self.indexes.get(at..at.checked_add(4)?)?;
+        Some(entry.get_u32_le() as usize)
     }
 
     pub fn total_size(&self) -> usize {
@@ -78,47 +86,27 @@ impl MessageBatch {
 /// Iterator over MessageBatch
 /// -----------------------------
 pub struct MessageBatchIterator {
-    indexes: Bytes,
-    messages: Bytes,
+    batch: MessageBatch,
     current: usize,
-    count: usize,
 }
 
 impl Iterator for MessageBatchIterator {
     type Item = Bytes;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current >= self.count {
-            return None;
-        }
-
-        let start = self.indexes.get_u32_le() as usize;
-
-        let end = if self.current + 1 < self.count {
-            let mut peek = self.indexes.clone();
-            peek.get_u32_le() as usize
-        } else {
-            self.messages.len()
-        };
-
+        let message = self.batch.get(self.current)?;
         self.current += 1;
-        Some(self.messages.slice(start..end))
+        Some(message)
     }
 }
 
 /// -----------------------------
 /// SMS Message View
 /// -----------------------------
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SMSMessageView {
-    pub from: String,
-    pub to: String,
-    pub body: String,
-    pub timestamp: i64,
-    pub conversation_id: String,
-}
+/// The broker payload type itself; kept as a name for existing callers
+pub type SMSMessageView = SMSMessage;
 
-impl SMSMessageView {
+impl SMSMessage {
     pub fn to_bytes(&self) -> Result<Bytes> {
         Ok(Bytes::from(serde_json::to_vec(self)?))
     }
@@ -127,12 +115,17 @@ impl SMSMessageView {
         serde_json::from_slice(bytes).context("Failed to deserialize SMS message")
     }
 
-    /// NOTE: This still parses JSON fully — optimized parsing can be added later
+    /// Reads only `conversation_id`; the rest of the payload is skipped
+    /// without being decoded. Errors, never panics, on malformed bytes.
     pub fn extract_conversation_id(bytes: &[u8]) -> Result<String> {
-        let value: serde_json::Value = serde_json::from_slice(bytes)?;
-        value["conversation_id"]
-            .as_str()
-            .map(|s| s.to_string())
+        #[derive(Deserialize)]
+        struct ConversationIdOnly {
+            conversation_id: Option<String>,
+        }
+
+        serde_json::from_slice::<ConversationIdOnly>(bytes)
+            .context("Failed to decode SMS payload")?
+            .conversation_id
             .context("Missing conversation_id field")
     }
 }
@@ -143,7 +136,7 @@ impl SMSMessageView {
 #[derive(Debug, Clone)]
 pub struct LazyMessage {
     raw: Bytes,
-    cached: Arc<Mutex<Option<SMSMessageView>>>,
+    cached: Arc<Mutex<Option<SMSMessage>>>,
 }
 
 impl LazyMessage {
@@ -158,20 +151,20 @@ impl LazyMessage {
         &self.raw
     }
 
-    pub async fn deserialize(&self) -> Result<SMSMessageView> {
+    pub async fn deserialize(&self) -> Result<SMSMessage> {
         let mut guard = self.cached.lock().await;
 
         if let Some(msg) = guard.as_ref() {
             return Ok(msg.clone());
         }
 
-        let msg = SMSMessageView::from_bytes(&self.raw)?;
+        let msg = SMSMessage::from_bytes(&self.raw)?;
         *guard = Some(msg.clone());
         Ok(msg)
     }
 
     pub async fn conversation_id(&self) -> Result<String> {
-        SMSMessageView::extract_conversation_id(&self.raw)
+        SMSMessage::extract_conversation_id(&self.raw)
     }
 }
 
@@ -203,4 +196,119 @@ mod tests {
         assert_eq!(&collected[1][..], b"bb");
         assert_eq!(&collected[2][..], b"ccc");
     }
+
+    #[tokio::test]
+    async fn test_broker_payload_round_trips_through_lazy_message() {
+        let sms = SMSMessage {
+            id: "m1".into(),
+            from: crate::PhoneNumber::parse("+15550001111").unwrap(),
+            to: crate::PhoneNumber::parse("+15552223333").unwrap(),
+            body: "Hello".into(),
+            timestamp: 1_700_000_000,
+            conversation_id: "conv-1".into(),
+            provider_sid: Some("SM1".into()),
+            in_reply_to: None,
+            received_at: None,
+        };
+
+        // Same encoding `MessageBroker::publish_sms` uses
+        let payload = Bytes::from(serde_json::to_string(&sms).unwrap());
+        let lazy = LazyMessage::new(payload);
+
+        assert_eq!(lazy.deserialize().await.unwrap(), sms);
+        assert_eq!(lazy.conversation_id().await.unwrap(), "conv-1");
+        assert_eq!(SMSMessage::from_bytes(&sms.to_bytes().unwrap()).unwrap(), sms);
+    }
+
+    fn raw_batch(indexes: &[u8], messages: &[u8], count: usize) -> MessageBatch {
+        MessageBatch {
+            indexes: Bytes::copy_from_slice(indexes),
+            messages: Bytes::copy_from_slice(messages),
+            count,
+        }
+    }
+
+    fn offsets(offsets: &[u32]) -> Vec<u8> {
+        offsets.iter().flat_map(|o| o.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_truncated_index_buffer() {
+        // Three messages claimed, one and a half index entries present
+        let mut indexes = offsets(&[0]);
+        indexes.extend_from_slice(&[5, 0]);
+        let batch = raw_batch(&indexes, b"HelloWorld", 3);
+
+        assert_eq!(batch.get(0), None);
+        assert_eq!(batch.get(1), None);
+        assert_eq!(batch.get(2), None);
+        assert_eq!(batch.iter().count(), 0);
+
+        let batch = raw_batch(&offsets(&[0, 5]), b"HelloWorld", 3);
+        assert_eq!(&batch.get(0).unwrap()[..], b"Hello");
+        assert_eq!(batch.get(2), None);
+        assert_eq!(batch.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_out_of_range_offsets() {
+        let batch = raw_batch(&offsets(&[0, 50]), b"HelloWorld", 2);
+        assert_eq!(batch.get(0), None);
+        assert_eq!(batch.get(1), None);
+
+        // Offsets running backwards
+        let batch = raw_batch(&offsets(&[6, 2, 8]), b"HelloWorld", 3);
+        assert_eq!(batch.get(0), None);
+        assert_eq!(&batch.get(1).unwrap()[..], b"lloWor");
+        assert_eq!(&batch.get(2).unwrap()[..], b"ld");
+
+        let batch = raw_batch(&offsets(&[u32::MAX]), b"", 1);
+        assert_eq!(batch.get(0), None);
+        assert_eq!(batch.get(usize::MAX), None);
+    }
+
+    #[test]
+    fn test_malformed_payloads_error_instead_of_panicking() {
+        for bytes in [&b""[..], b"{", b"[]", b"null", b"{\"conversation_id\": 7}", b"\xff\xfe"] {
+            assert!(SMSMessage::extract_conversation_id(bytes).is_err());
+            assert!(SMSMessage::from_bytes(bytes).is_err());
+        }
+        assert!(SMSMessage::extract_conversation_id(b"{}").is_err());
+        assert_eq!(
+            SMSMessage::extract_conversation_id(br#"{"body": [1, {"x": null}], "conversation_id": "c1"}"#).unwrap(),
+            "c1"
+        );
+    }
+
+    /// Random batches and payloads from a fixed seed, so a failure is
+    /// reproducible. Asserts only that decoding never panics and that
+    /// whatever `get` returns lies within `messages`.
+    #[test]
+    fn test_fuzz_random_bytes_never_panic() {
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for _ in 0..5_000 {
+            let mut bytes = |max: u64| -> Vec<u8> { (0..next() % max).map(|_| next() as u8).collect() };
+            let indexes = bytes(64);
+            let messages = bytes(64);
+            let count = (next() % 24) as usize;
+
+            let batch = raw_batch(&indexes, &messages, count);
+            for index in 0..count + 2 {
+                if let Some(message) = batch.get(index) {
+                    assert!(message.len() <= messages.len());
+                }
+            }
+            assert!(batch.iter().count() <= count);
+
+            let _ = SMSMessage::extract_conversation_id(&messages);
+            let _ = SMSMessage::from_bytes(&messages);
+        }
+    }
 }